@@ -0,0 +1,585 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::Memory;
+use crate::ppu::CLOCK_RATE;
+
+mod fifo;
+mod noise;
+mod resampler;
+mod ring_buffer;
+mod square;
+mod wav;
+mod wave;
+
+use fifo::Fifo;
+use noise::NoiseChannel;
+pub use resampler::AudioFilter;
+use resampler::Resampler;
+pub use ring_buffer::SampleRing;
+use square::SquareChannel;
+use wav::WavDump;
+use wave::WaveChannel;
+
+// base PWM rate of the mixer, one sample every 512 CPU cycles; SOUNDBIAS
+// can raise it up to 8x at the cost of amplitude resolution
+pub const SAMPLE_RATE: u32 = 32768;
+const CYCLES_PER_SAMPLE: u64 = 512;
+pub const DEFAULT_OUTPUT_RATE: u32 = 48000;
+// dynamic rate control may stretch the output rate by at most this much
+pub const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+// interleaved samples buffered for the frontend, about 170ms at 48 kHz
+const RING_CAPACITY: usize = 16384;
+// the frame sequencer steps at 512 Hz
+pub const FRAME_SEQUENCER_CYCLES: u64 = CLOCK_RATE / 512;
+// a FIFO asks for a DMA refill once it holds this many bytes or fewer
+const FIFO_REFILL_LEVEL: usize = 16;
+// scales the 10-bit hardware output up to 16 bits
+const OUTPUT_GAIN: i32 = 64;
+
+pub const SOUND1CNT_L: usize = 0x060;
+pub const SOUND1CNT_H: usize = 0x062;
+pub const SOUND1CNT_X: usize = 0x064;
+pub const SOUND2CNT_L: usize = 0x068;
+pub const SOUND2CNT_H: usize = 0x06C;
+pub const SOUND3CNT_L: usize = 0x070;
+pub const SOUND3CNT_H: usize = 0x072;
+pub const SOUND3CNT_X: usize = 0x074;
+pub const SOUND4CNT_L: usize = 0x078;
+pub const SOUND4CNT_H: usize = 0x07C;
+pub const SOUNDCNT_L: usize = 0x080;
+pub const SOUNDCNT_H: usize = 0x082;
+pub const SOUNDCNT_X: usize = 0x084;
+pub const SOUNDBIAS: usize = 0x088;
+pub const WAVE_RAM: usize = 0x090;
+pub const FIFO_A: usize = 0x0A0;
+pub const FIFO_B: usize = 0x0A4;
+
+// The six sound sources, for muting and soloing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundChannel {
+    Tone1,
+    Tone2,
+    Wave,
+    Noise,
+    FifoA,
+    FifoB,
+}
+
+impl SoundChannel {
+    pub const ALL: [SoundChannel; 6] = [
+        SoundChannel::Tone1,
+        SoundChannel::Tone2,
+        SoundChannel::Wave,
+        SoundChannel::Noise,
+        SoundChannel::FifoA,
+        SoundChannel::FifoB,
+    ];
+
+    // PSG channels 0-3, then FIFO A and B
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SoundChannel::Tone1 => "tone1",
+            SoundChannel::Tone2 => "tone2",
+            SoundChannel::Wave => "wave",
+            SoundChannel::Noise => "noise",
+            SoundChannel::FifoA => "fifo_a",
+            SoundChannel::FifoB => "fifo_b",
+        }
+    }
+}
+
+// Everything from muted on is output side state, which save states leave
+// out and loading one keeps.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Apu {
+    pub channel1: SquareChannel,
+    pub channel2: SquareChannel,
+    pub channel3: WaveChannel,
+    pub channel4: NoiseChannel,
+    pub fifos: [Fifo; 2],
+    // refill requests for the sound DMA channels, taken by the Gba
+    fifo_requests: [bool; 2],
+    master_enabled: bool,
+    soundcnt_l: u16,
+    soundcnt_h: u16,
+    soundbias: u16,
+    sequencer_step: u8,
+    // cycle the channels have been run up to
+    last_update: u64,
+    #[serde(skip)]
+    muted: [bool; 6],
+    #[serde(skip)]
+    soloed: [bool; 6],
+    #[serde(skip, default = "new_resampler")]
+    resampler: Resampler,
+    #[serde(skip)]
+    output_rate: u32,
+    #[serde(skip)]
+    rate_adjustment: f64,
+    #[serde(skip)]
+    filter: AudioFilter,
+    // interleaved left/right samples at the output rate
+    #[serde(skip, default = "new_ring")]
+    ring: Arc<SampleRing>,
+    #[serde(skip)]
+    wav_dump: Option<WavDump>,
+}
+
+fn new_resampler() -> Resampler {
+    Resampler::new(SAMPLE_RATE, DEFAULT_OUTPUT_RATE)
+}
+
+fn new_ring() -> Arc<SampleRing> {
+    Arc::new(SampleRing::new(RING_CAPACITY))
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            channel1: SquareChannel::new(true),
+            channel2: SquareChannel::new(false),
+            channel3: WaveChannel::default(),
+            channel4: NoiseChannel::default(),
+            fifos: [Fifo::new(), Fifo::new()],
+            fifo_requests: [false; 2],
+            master_enabled: false,
+            soundcnt_l: 0,
+            soundcnt_h: 0,
+            soundbias: 0x200,
+            sequencer_step: 0,
+            last_update: 0,
+            muted: [false; 6],
+            soloed: [false; 6],
+            resampler: new_resampler(),
+            output_rate: DEFAULT_OUTPUT_RATE,
+            rate_adjustment: 1.0,
+            filter: AudioFilter::default(),
+            ring: new_ring(),
+            wav_dump: None,
+        }
+    }
+
+    // Returns the sound hardware to its power-on state, keeping output
+    // settings, the ring the audio device reads from and any WAV dump.
+    pub fn reset(&mut self) {
+        let mut fresh = Apu::new();
+        fresh.muted = self.muted;
+        fresh.soloed = self.soloed;
+        fresh.output_rate = self.output_rate;
+        fresh.rate_adjustment = self.rate_adjustment;
+        fresh.filter = self.filter;
+        fresh.ring = Arc::clone(&self.ring);
+        fresh.wav_dump = self.wav_dump.take();
+        fresh.update_resampler();
+        *self = fresh;
+    }
+
+    // Takes the sound hardware from a loaded save state, keeping the
+    // output side as reset does.
+    pub fn restore(&mut self, mut state: Apu) {
+        state.muted = self.muted;
+        state.soloed = self.soloed;
+        state.output_rate = self.output_rate;
+        state.rate_adjustment = self.rate_adjustment;
+        state.filter = self.filter;
+        state.ring = Arc::clone(&self.ring);
+        state.wav_dump = self.wav_dump.take();
+        std::mem::swap(&mut state.resampler, &mut self.resampler);
+        state.update_resampler();
+        *self = state;
+    }
+
+    // skips delay cycles spent in STOP mode without running the channels
+    pub fn postpone(&mut self, delay: u64) {
+        self.last_update += delay;
+    }
+
+    // Runs the channels up to cycle now, then applies the register writes
+    // queued since the last call
+    pub fn catch_up(&mut self, memory: &mut Memory, now: u64) {
+        let cycles = now.saturating_sub(self.last_update) as u32;
+        self.last_update = now;
+        if cycles > 0 {
+            self.channel1.advance(cycles);
+            self.channel2.advance(cycles);
+            self.channel3.advance(cycles);
+            self.channel4.advance(cycles);
+        }
+
+        if !memory.sound_writes.is_empty() {
+            let mut writes = std::mem::take(&mut memory.sound_writes);
+            for &(offset, value) in &writes {
+                self.write_register(memory, offset, value);
+            }
+            writes.clear();
+            memory.sound_writes = writes;
+            self.update_status(memory);
+        }
+    }
+
+    // cycles between two samples at the current PWM rate
+    pub fn sample_period(&self) -> u64 {
+        CYCLES_PER_SAMPLE >> self.resolution()
+    }
+
+    // Shared handle for frontends that pull audio from another thread
+    // Grows the ring to hold at least capacity samples, for frontends that
+    // keep more queued than the default allows. Only rings taken with
+    // sample_ring afterwards are the new one.
+    pub fn reserve_ring(&mut self, capacity: usize) {
+        if capacity > self.ring.capacity() {
+            self.ring = Arc::new(SampleRing::new(capacity));
+        }
+    }
+
+    pub fn sample_ring(&self) -> Arc<SampleRing> {
+        Arc::clone(&self.ring)
+    }
+
+    pub fn read_samples(&self, out: &mut [i16]) -> usize {
+        self.ring.pop(out)
+    }
+
+    pub fn set_muted(&mut self, channel: SoundChannel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    pub fn is_muted(&self, channel: SoundChannel) -> bool {
+        self.muted[channel.index()]
+    }
+
+    // while any channel is soloed, only soloed channels are heard
+    pub fn set_soloed(&mut self, channel: SoundChannel, soloed: bool) {
+        self.soloed[channel.index()] = soloed;
+    }
+
+    pub fn is_soloed(&self, channel: SoundChannel) -> bool {
+        self.soloed[channel.index()]
+    }
+
+    fn audible(&self, index: usize) -> bool {
+        if self.soloed.contains(&true) {
+            self.soloed[index]
+        } else {
+            !self.muted[index]
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn set_output_rate(&mut self, rate: u32) {
+        self.output_rate = rate;
+        self.update_resampler();
+    }
+
+    // Dynamic rate control: a frontend synced to video nudges the output
+    // rate to keep its audio buffer from draining or overflowing. The
+    // factor is clamped to within MAX_RATE_ADJUSTMENT of 1.
+    pub fn set_rate_adjustment(&mut self, factor: f64) {
+        self.rate_adjustment = factor.clamp(1.0 - MAX_RATE_ADJUSTMENT, 1.0 + MAX_RATE_ADJUSTMENT);
+        self.update_resampler();
+    }
+
+    pub fn filter(&self) -> AudioFilter {
+        self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: AudioFilter) {
+        self.filter = filter;
+        self.update_resampler();
+    }
+
+    fn update_resampler(&mut self) {
+        let input_rate = self.pwm_rate() as f64;
+        let output_rate = self.output_rate as f64 * self.rate_adjustment;
+        self.resampler.set_rates(input_rate, output_rate);
+        self.resampler.set_filter(self.filter);
+        if let Some(dump) = &mut self.wav_dump {
+            for stem in &mut dump.stems {
+                stem.resampler.set_rates(input_rate, output_rate);
+                stem.resampler.set_filter(self.filter);
+            }
+        }
+    }
+
+    // Records the stereo output to a WAV file at the output rate, and with
+    // stems each channel's own contribution to PATH.<channel>.wav.
+    // Mute and solo apply to the main mix only.
+    pub fn start_wav_dump(&mut self, path: &Path, stems: bool) -> io::Result<()> {
+        self.stop_wav_dump()?;
+        self.wav_dump = Some(WavDump::create(path, self.output_rate, self.pwm_rate(), stems)?);
+        self.update_resampler();
+        Ok(())
+    }
+
+    pub fn dumping_wav(&self) -> bool {
+        self.wav_dump.is_some()
+    }
+
+    // finishes the files, reporting the first error hit while writing
+    pub fn stop_wav_dump(&mut self) -> io::Result<()> {
+        match self.wav_dump.take() {
+            Some(dump) => dump.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // SOUNDBIAS amplitude resolution: 0 is 9 bits at 32 kHz up to 3 for
+    // 6 bits at 262 kHz
+    fn resolution(&self) -> u32 {
+        (self.soundbias >> 14) as u32
+    }
+
+    fn pwm_rate(&self) -> u32 {
+        SAMPLE_RATE << self.resolution()
+    }
+
+    fn write_register(&mut self, memory: &mut Memory, offset: usize, value: u8) {
+        // the next frame sequencer step will not clock the length counters
+        let extra_length_clock = self.sequencer_step % 2 == 1;
+        match offset {
+            SOUND1CNT_L => self.channel1.write_sweep(value),
+            SOUND1CNT_H => self.channel1.write_length_duty(value),
+            0x063 => self.channel1.write_envelope(value),
+            SOUND1CNT_X => self.channel1.write_frequency_low(value),
+            0x065 => self.channel1.write_frequency_high(value, extra_length_clock),
+            SOUND2CNT_L => self.channel2.write_length_duty(value),
+            0x069 => self.channel2.write_envelope(value),
+            SOUND2CNT_H => self.channel2.write_frequency_low(value),
+            0x06D => self.channel2.write_frequency_high(value, extra_length_clock),
+            SOUND3CNT_L => {
+                let old_bank = self.channel3.cpu_bank();
+                self.channel3.write_control(value);
+                let bank = self.channel3.cpu_bank();
+                if bank != old_bank {
+                    // swap the other bank into the CPU's view of wave RAM
+                    memory.io[WAVE_RAM..WAVE_RAM + 16].copy_from_slice(&self.channel3.wave_ram[bank]);
+                }
+            }
+            SOUND3CNT_H => self.channel3.write_length(value),
+            0x073 => self.channel3.write_volume(value),
+            SOUND3CNT_X => self.channel3.write_frequency_low(value),
+            0x075 => self.channel3.write_frequency_high(value, extra_length_clock),
+            SOUND4CNT_L => self.channel4.write_length(value),
+            0x079 => self.channel4.write_envelope(value),
+            SOUND4CNT_H => self.channel4.write_polynomial(value),
+            0x07D => self.channel4.write_control(value, extra_length_clock),
+            SOUNDCNT_L => self.soundcnt_l = (self.soundcnt_l & 0xFF00) | value as u16,
+            0x081 => self.soundcnt_l = (self.soundcnt_l & 0x00FF) | ((value as u16) << 8),
+            SOUNDCNT_H => self.soundcnt_h = (self.soundcnt_h & 0xFF00) | value as u16,
+            0x083 => {
+                self.soundcnt_h = (self.soundcnt_h & 0x00FF) | ((value as u16) << 8);
+                // the FIFO reset bits act on write and always read back as zero
+                for fifo in 0..2 {
+                    if value & (0x08 << (fifo * 4)) != 0 {
+                        self.fifos[fifo].reset();
+                    }
+                }
+                memory.io[0x083] &= !0x88;
+            }
+            SOUNDCNT_X => {
+                let enabled = value & 0x80 != 0;
+                if self.master_enabled && !enabled {
+                    self.power_off(memory);
+                } else if !self.master_enabled && enabled {
+                    // powering on restarts the frame sequencer at step 0
+                    self.sequencer_step = 0;
+                }
+                self.master_enabled = enabled;
+            }
+            SOUNDBIAS => self.soundbias = (self.soundbias & 0xFF00) | value as u16,
+            0x089 => {
+                self.soundbias = (self.soundbias & 0x00FF) | ((value as u16) << 8);
+                self.update_resampler();
+            }
+            0x090..=0x09F => self.channel3.write_wave_ram(offset - WAVE_RAM, value),
+            FIFO_A..=0x0A3 => self.fifos[0].push(value),
+            FIFO_B..=0x0A7 => self.fifos[1].push(value),
+            _ => {}
+        }
+    }
+
+    // Turning the master enable off resets the PSG channels and clears
+    // their registers; Memory ignores PSG writes until it is back on.
+    fn power_off(&mut self, memory: &mut Memory) {
+        let wave_ram = self.channel3.wave_ram;
+        self.channel1 = SquareChannel::new(true);
+        self.channel2 = SquareChannel::new(false);
+        self.channel3 = WaveChannel::default();
+        self.channel3.wave_ram = wave_ram;
+        self.channel4 = NoiseChannel::default();
+        self.soundcnt_l = 0;
+        memory.io[SOUND1CNT_L..=0x081].fill(0);
+    }
+
+    // mirrors the channel on flags into SOUNDCNT_X, which the CPU can only read
+    fn update_status(&self, memory: &mut Memory) {
+        let active = [
+            self.channel1.enabled,
+            self.channel2.enabled,
+            self.channel3.enabled,
+            self.channel4.enabled,
+        ];
+        let mut status = memory.io[SOUNDCNT_X] & 0x80;
+        for (channel, &on) in active.iter().enumerate() {
+            if on {
+                status |= 1 << channel;
+            }
+        }
+        memory.io[SOUNDCNT_X] = status;
+    }
+
+    // called by the timers on overflow; each FIFO follows the timer
+    // selected for it in SOUNDCNT_H
+    pub fn timer_overflow(&mut self, timer: usize) {
+        for fifo in 0..2 {
+            let selected = ((self.soundcnt_h >> (10 + fifo * 4)) & 1) as usize;
+            if selected == timer {
+                self.fifos[fifo].pop();
+                if self.fifos[fifo].len() <= FIFO_REFILL_LEVEL {
+                    self.fifo_requests[fifo] = true;
+                }
+            }
+        }
+    }
+
+    pub fn take_fifo_request(&mut self, fifo: usize) -> bool {
+        std::mem::take(&mut self.fifo_requests[fifo])
+    }
+
+    // Eight steps per cycle: length counters on even steps (256 Hz), the
+    // sweep on steps 2 and 6 (128 Hz) and envelopes on step 7 (64 Hz)
+    pub fn step_frame_sequencer(&mut self, memory: &mut Memory) {
+        let step = self.sequencer_step;
+        self.sequencer_step = (step + 1) % 8;
+
+        if step.is_multiple_of(2) {
+            self.channel1.clock_length();
+            self.channel2.clock_length();
+            self.channel3.clock_length();
+            self.channel4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.channel1.clock_sweep();
+        }
+        if step == 7 {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+        }
+        self.update_status(memory);
+    }
+
+    pub fn output_sample(&mut self) {
+        let levels = [self.channel_levels(1), self.channel_levels(0)];
+        let frame = if self.master_enabled {
+            [self.bias(self.mix_levels(&levels[0])), self.bias(self.mix_levels(&levels[1]))]
+        } else {
+            [0, 0]
+        };
+
+        let ring = &self.ring;
+        let mut dump = self.wav_dump.as_mut().filter(|dump| dump.error.is_none());
+        self.resampler.push(frame, |out| {
+            ring.push_frame(out);
+            if let Some(dump) = dump.as_mut()
+                && let Err(err) = dump.mix.write_samples(&out)
+            {
+                dump.error = Some(err);
+            }
+        });
+
+        if let Some(dump) = dump {
+            for (channel, stem) in dump.stems.iter_mut().enumerate() {
+                let frame = if self.master_enabled {
+                    levels.map(|side| stem_level(&side, self.soundcnt_h, channel))
+                } else {
+                    [0, 0]
+                };
+                let writer = &mut stem.writer;
+                let error = &mut dump.error;
+                stem.resampler.push(frame, |out| {
+                    if error.is_none()
+                        && let Err(err) = writer.write_samples(&out)
+                    {
+                        *error = Some(err);
+                    }
+                });
+            }
+        }
+    }
+
+    // Adds the bias, clips to the 10-bit PWM range and drops the bits the
+    // selected resolution cannot represent. The bias is taken back out
+    // afterwards, as the output capacitor removes the DC offset.
+    fn bias(&self, mixed: i32) -> i16 {
+        let bias = (self.soundbias & 0x3FE) as i32;
+        let level = (mixed + bias).clamp(0, 0x3FF) & !((1 << (self.resolution() + 1)) - 1);
+        ((level - bias) * OUTPUT_GAIN).clamp(-0x8000, 0x7FFF) as i16
+    }
+
+    // Each channel's contribution to one speaker, 0 for right and 1 for
+    // left, with routing and volume applied but not the PSG volume ratio
+    fn channel_levels(&self, side: usize) -> [i32; 6] {
+        let outputs = [
+            self.channel1.output(),
+            self.channel2.output(),
+            self.channel3.output(),
+            self.channel4.output(),
+        ];
+        let enables = self.soundcnt_l >> (8 + side * 4);
+        let volume = ((self.soundcnt_l >> (side * 4)) & 0x7) as i32 + 1;
+
+        let mut levels = [0; 6];
+        for (channel, &output) in outputs.iter().enumerate() {
+            if enables & (1 << channel) != 0 {
+                levels[channel] = output as i32 * volume;
+            }
+        }
+        for fifo in 0..2 {
+            if self.soundcnt_h & (0x100 << (fifo * 4 + side)) != 0 {
+                let sample = self.fifos[fifo].sample as i32;
+                levels[4 + fifo] = if self.soundcnt_h & (0x4 << fifo) != 0 { sample * 2 } else { sample };
+            }
+        }
+        levels
+    }
+
+    // sums the audible channels in the hardware's 10-bit range
+    fn mix_levels(&self, levels: &[i32; 6]) -> i32 {
+        let psg = (0..4).filter(|&channel| self.audible(channel)).map(|channel| levels[channel]).sum();
+        let fifo: i32 = (4..6).filter(|&channel| self.audible(channel)).map(|channel| levels[channel]).sum();
+        psg_ratio(psg, self.soundcnt_h) + fifo
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new()
+    }
+}
+
+// SOUNDCNT_H selects 25%, 50% or 100% PSG volume
+fn psg_ratio(psg: i32, soundcnt_h: u16) -> i32 {
+    match soundcnt_h & 0x3 {
+        0 => psg >> 2,
+        1 => psg >> 1,
+        _ => psg,
+    }
+}
+
+// a lone channel's output level for stem dumps, without bias or clipping
+fn stem_level(levels: &[i32; 6], soundcnt_h: u16, channel: usize) -> i16 {
+    let level = if channel < 4 { psg_ratio(levels[channel], soundcnt_h) } else { levels[channel] };
+    (level * OUTPUT_GAIN).clamp(-0x8000, 0x7FFF) as i16
+}
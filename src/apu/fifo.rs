@@ -0,0 +1,45 @@
+// Direct Sound: 32-byte FIFOs of signed 8-bit PCM, written by the CPU or
+// DMA and consumed one sample per overflow of the selected timer.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+const FIFO_CAPACITY: usize = 32;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fifo {
+    buffer: VecDeque<i8>,
+    // sample currently being output, held until the next timer overflow
+    pub sample: i8,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Fifo {
+            buffer: VecDeque::with_capacity(FIFO_CAPACITY),
+            sample: 0,
+        }
+    }
+
+    pub fn push(&mut self, value: u8) {
+        if self.buffer.len() < FIFO_CAPACITY {
+            self.buffer.push_back(value as i8);
+        }
+    }
+
+    pub fn pop(&mut self) {
+        if let Some(sample) = self.buffer.pop_front() {
+            self.sample = sample;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.sample = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+}
@@ -0,0 +1,95 @@
+// Channel 4: pseudo-random noise from a 15-bit (or 7-bit) LFSR.
+
+use serde::{Deserialize, Serialize};
+
+use super::square::{Envelope, LengthCounter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NoiseChannel {
+    pub enabled: bool,
+    pub envelope: Envelope,
+    divisor_code: usize,
+    short_mode: bool,
+    shift: u8,
+    lfsr: u16,
+    timer: u32,
+    length: LengthCounter<64>,
+}
+
+impl NoiseChannel {
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load((value & 0x3F) as u16);
+    }
+
+    pub fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_polynomial(&mut self, value: u8) {
+        self.divisor_code = (value & 0x7) as usize;
+        self.short_mode = value & 0x8 != 0;
+        self.shift = value >> 4;
+    }
+
+    pub fn write_control(&mut self, value: u8, extra_length_clock: bool) {
+        let trigger = value & 0x80 != 0;
+        if !self.length.write_control(value & 0x40 != 0, trigger, extra_length_clock) {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    // the divisors are in 4 MHz GB cycles, hence the extra factor of 4
+    fn period(&self) -> u32 {
+        (DIVISORS[self.divisor_code] << self.shift) * 4
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.envelope.trigger();
+    }
+
+    pub fn advance(&mut self, mut cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            if self.short_mode {
+                self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // signed output level, -15..=15
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+        let volume = self.envelope.volume as i16;
+        if self.lfsr & 1 == 0 { volume } else { -volume }
+    }
+}
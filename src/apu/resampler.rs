@@ -0,0 +1,104 @@
+// Converts the hardware mix to the host sample rate, run separately for
+// each stereo side. The filter setting picks the interpolation and an
+// optional low-pass stage.
+
+use std::f64::consts::PI;
+
+// cutoff of the low-pass stage, roughly where the GBA's speaker and
+// output circuitry roll off
+const LOW_PASS_CUTOFF: f64 = 8000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioFilter {
+    // nearest sample, keeping the raw stepped PWM output
+    None,
+    Linear,
+    // 4-point cubic Hermite
+    #[default]
+    Cubic,
+    // cubic followed by a one-pole low-pass
+    LowPass,
+}
+
+impl AudioFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(AudioFilter::None),
+            "linear" => Some(AudioFilter::Linear),
+            "cubic" => Some(AudioFilter::Cubic),
+            "lowpass" => Some(AudioFilter::LowPass),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Resampler {
+    // input samples consumed per output sample
+    step: f64,
+    position: f64,
+    history: [[f32; 2]; 4],
+    filter: AudioFilter,
+    low_pass_alpha: f32,
+    low_pass: [f32; 2],
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let mut resampler = Resampler {
+            step: 1.0,
+            position: 0.0,
+            history: [[0.0; 2]; 4],
+            filter: AudioFilter::default(),
+            low_pass_alpha: 1.0,
+            low_pass: [0.0; 2],
+        };
+        resampler.set_rates(input_rate as f64, output_rate as f64);
+        resampler
+    }
+
+    pub fn set_rates(&mut self, input_rate: f64, output_rate: f64) {
+        self.step = input_rate / output_rate;
+        self.low_pass_alpha = (1.0 - (-2.0 * PI * LOW_PASS_CUTOFF / output_rate).exp()) as f32;
+    }
+
+    pub fn set_filter(&mut self, filter: AudioFilter) {
+        self.filter = filter;
+    }
+
+    // feeds one input frame, calling emit for every output frame it completes
+    pub fn push(&mut self, frame: [i16; 2], mut emit: impl FnMut([i16; 2])) {
+        self.history.rotate_left(1);
+        self.history[3] = [frame[0] as f32, frame[1] as f32];
+
+        // output frames fall between history[1] and history[2]
+        while self.position < 1.0 {
+            let t = self.position as f32;
+            let mut out = [0; 2];
+            for (side, sample) in out.iter_mut().enumerate() {
+                let [p0, p1, p2, p3] = self.history.map(|h| h[side]);
+                let value = match self.filter {
+                    AudioFilter::None => if t < 0.5 { p1 } else { p2 },
+                    AudioFilter::Linear => p1 + (p2 - p1) * t,
+                    AudioFilter::Cubic => hermite(p0, p1, p2, p3, t),
+                    AudioFilter::LowPass => {
+                        let state = &mut self.low_pass[side];
+                        *state += (hermite(p0, p1, p2, p3, t) - *state) * self.low_pass_alpha;
+                        *state
+                    }
+                };
+                *sample = value.round().clamp(-32768.0, 32767.0) as i16;
+            }
+            emit(out);
+            self.position += self.step;
+        }
+        self.position -= 1.0;
+    }
+}
+
+fn hermite(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (p2 - p0);
+    let c2 = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+    let c3 = 0.5 * (p3 - p0) + 1.5 * (p1 - p2);
+    ((c3 * t + c2) * t + c1) * t + p1
+}
@@ -0,0 +1,69 @@
+// Single-producer single-consumer ring of interleaved stereo samples. The
+// emulator pushes from its thread while a frontend, possibly on an audio
+// callback thread, pops without either side taking a lock.
+
+use std::sync::atomic::{AtomicI16, AtomicUsize, Ordering};
+
+#[derive(Debug)]
+pub struct SampleRing {
+    buffer: Box<[AtomicI16]>,
+    // total samples ever read and written; indices wrap by capacity
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> Self {
+        SampleRing {
+            buffer: (0..capacity).map(|_| AtomicI16::new(0)).collect(),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn len(&self) -> usize {
+        let write = self.write.load(Ordering::Acquire);
+        let read = self.read.load(Ordering::Acquire);
+        write.wrapping_sub(read)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Producer side. A frame that does not fit is dropped, so a frontend
+    // that stops reading only loses the newest audio.
+    pub fn push_frame(&self, frame: [i16; 2]) -> bool {
+        let write = self.write.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        if write.wrapping_sub(read) + 2 > self.capacity() {
+            return false;
+        }
+        for (i, &sample) in frame.iter().enumerate() {
+            self.buffer[(write + i) % self.capacity()].store(sample, Ordering::Relaxed);
+        }
+        self.write.store(write.wrapping_add(2), Ordering::Release);
+        true
+    }
+
+    // Consumer side. Reads whole frames only, so left and right stay in step.
+    pub fn pop(&self, out: &mut [i16]) -> usize {
+        let read = self.read.load(Ordering::Relaxed);
+        let write = self.write.load(Ordering::Acquire);
+        let count = write.wrapping_sub(read).min(out.len() & !1);
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            *sample = self.buffer[(read + i) % self.capacity()].load(Ordering::Relaxed);
+        }
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    pub fn clear(&self) {
+        let write = self.write.load(Ordering::Acquire);
+        self.read.store(write, Ordering::Release);
+    }
+}
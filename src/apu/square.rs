@@ -0,0 +1,259 @@
+// Tone channels 1 and 2: a square wave with selectable duty, a volume
+// envelope and a length counter. Channel 1 adds a frequency sweep.
+
+use serde::{Deserialize, Serialize};
+
+const DUTY_PATTERNS: [[bool; 8]; 4] = [
+    [false, false, false, false, false, false, false, true],
+    [true, false, false, false, false, false, false, true],
+    [true, false, false, false, false, true, true, true],
+    [false, true, true, true, true, true, true, false],
+];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Envelope {
+    pub volume: u8,
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, value: u8) {
+        self.period = value & 0x7;
+        self.increase = value & 0x8 != 0;
+        self.initial_volume = value >> 4;
+    }
+
+    // the DAC is off when the envelope can only ever produce silence
+    pub fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// Counts down at 256 Hz from MAX and silences its channel on reaching zero
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LengthCounter<const MAX: u16> {
+    counter: u16,
+    enabled: bool,
+}
+
+impl<const MAX: u16> LengthCounter<MAX> {
+    pub fn load(&mut self, value: u16) {
+        self.counter = MAX - value;
+    }
+
+    // Handles the length enable and trigger bits of a channel's control
+    // register, returning false if the channel must be switched off.
+    // extra_clock is set while the frame sequencer's next step does not
+    // clock lengths; enabling the counter then clocks it once right away,
+    // and a trigger reloading it from zero starts it at MAX - 1.
+    pub fn write_control(&mut self, enabled: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+
+        let mut keep_playing = true;
+        if extra_clock && enabled && !was_enabled && self.counter > 0 {
+            self.counter -= 1;
+            keep_playing = self.counter > 0 || trigger;
+        }
+        if trigger && self.counter == 0 {
+            self.counter = MAX;
+            if extra_clock && enabled {
+                self.counter -= 1;
+            }
+        }
+        keep_playing
+    }
+
+    // returns true when the counter just expired
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow_frequency: u16,
+    enabled: bool,
+}
+
+impl Sweep {
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow_frequency >> self.shift;
+        if self.negate {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SquareChannel {
+    pub enabled: bool,
+    has_sweep: bool,
+    sweep: Sweep,
+    pub envelope: Envelope,
+    duty: usize,
+    duty_step: usize,
+    frequency: u16,
+    timer: u32,
+    length: LengthCounter<64>,
+}
+
+impl SquareChannel {
+    pub fn new(has_sweep: bool) -> Self {
+        SquareChannel {
+            has_sweep,
+            ..Default::default()
+        }
+    }
+
+    pub fn write_sweep(&mut self, value: u8) {
+        self.sweep.shift = value & 0x7;
+        self.sweep.negate = value & 0x8 != 0;
+        self.sweep.period = (value >> 4) & 0x7;
+    }
+
+    pub fn write_length_duty(&mut self, value: u8) {
+        self.length.load((value & 0x3F) as u16);
+        self.duty = (value >> 6) as usize;
+    }
+
+    pub fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    pub fn write_frequency_high(&mut self, value: u8, extra_length_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x7) << 8);
+        let trigger = value & 0x80 != 0;
+        if !self.length.write_control(value & 0x40 != 0, trigger, extra_length_clock) {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    // current frequency register value, which the sweep can rewrite
+    pub fn frequency(&self) -> u16 {
+        self.frequency
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 16
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.sweep.shadow_frequency = self.frequency;
+            self.sweep.timer = if self.sweep.period == 0 { 8 } else { self.sweep.period };
+            self.sweep.enabled = self.sweep.period != 0 || self.sweep.shift != 0;
+            if self.sweep.shift != 0 && self.sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    // advances the waveform by a number of CPU cycles
+    pub fn advance(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if !self.has_sweep {
+            return;
+        }
+
+        self.sweep.timer = self.sweep.timer.saturating_sub(1);
+        if self.sweep.timer != 0 {
+            return;
+        }
+        self.sweep.timer = if self.sweep.period == 0 { 8 } else { self.sweep.period };
+
+        if !self.sweep.enabled || self.sweep.period == 0 {
+            return;
+        }
+
+        let next = self.sweep.next_frequency();
+        if next > 2047 {
+            self.enabled = false;
+        } else if self.sweep.shift != 0 {
+            self.sweep.shadow_frequency = next;
+            self.frequency = next;
+            // the new frequency is checked again straight away for overflow
+            if self.sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    // signed output level, -15..=15
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+        let volume = self.envelope.volume as i16;
+        if DUTY_PATTERNS[self.duty][self.duty_step] {
+            volume
+        } else {
+            -volume
+        }
+    }
+}
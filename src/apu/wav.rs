@@ -0,0 +1,118 @@
+// 16-bit PCM WAV output for dumping the mixer. The header's size fields
+// are rewritten on every flush, so a dump cut short by the process exiting
+// is still a valid file up to the last flush.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::resampler::Resampler;
+use super::SoundChannel;
+
+const HEADER_LEN: u32 = 44;
+// samples between header updates, about a second of stereo audio
+const FLUSH_INTERVAL: u32 = 96000;
+
+#[derive(Debug)]
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32,
+    unflushed: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * 2;
+        file.write_all(b"RIFF")?;
+        file.write_u32::<LittleEndian>(HEADER_LEN - 8)?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_u32::<LittleEndian>(16)?;
+        file.write_u16::<LittleEndian>(1)?; // PCM
+        file.write_u16::<LittleEndian>(channels)?;
+        file.write_u32::<LittleEndian>(sample_rate)?;
+        file.write_u32::<LittleEndian>(sample_rate * block_align as u32)?;
+        file.write_u16::<LittleEndian>(block_align)?;
+        file.write_u16::<LittleEndian>(16)?;
+        file.write_all(b"data")?;
+        file.write_u32::<LittleEndian>(0)?;
+        Ok(WavWriter {
+            file,
+            data_len: 0,
+            unflushed: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for &sample in samples {
+            self.file.write_i16::<LittleEndian>(sample)?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        self.unflushed += samples.len() as u32;
+        if self.unflushed >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = 0;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_u32::<LittleEndian>(HEADER_LEN - 8 + self.data_len)?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_u32::<LittleEndian>(self.data_len)?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()
+    }
+}
+
+// Stereo stem for one channel, resampled alongside the main mix
+#[derive(Debug)]
+pub struct Stem {
+    pub writer: WavWriter,
+    pub resampler: Resampler,
+}
+
+#[derive(Debug)]
+pub struct WavDump {
+    pub mix: WavWriter,
+    pub stems: Vec<Stem>,
+    // the first write error; writing stops once one happens
+    pub error: Option<io::Error>,
+}
+
+impl WavDump {
+    // stems go next to the main file as NAME.tone1.wav, NAME.fifo_a.wav, ...
+    pub fn create(path: &Path, sample_rate: u32, input_rate: u32, stems: bool) -> io::Result<Self> {
+        let mix = WavWriter::create(path, sample_rate, 2)?;
+        let mut dump = WavDump {
+            mix,
+            stems: Vec::new(),
+            error: None,
+        };
+        if stems {
+            let base = path.with_extension("");
+            for channel in SoundChannel::ALL {
+                let stem_path = format!("{}.{}.wav", base.display(), channel.name());
+                dump.stems.push(Stem {
+                    writer: WavWriter::create(Path::new(&stem_path), sample_rate, 2)?,
+                    resampler: Resampler::new(input_rate, sample_rate),
+                });
+            }
+        }
+        Ok(dump)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.mix.flush()?;
+        for stem in &mut self.stems {
+            stem.writer.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+// Channel 3: plays 4-bit samples out of two 32-sample wave RAM banks.
+// The CPU sees whichever bank is not selected for playback.
+
+use serde::{Deserialize, Serialize};
+
+use super::square::LengthCounter;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    two_banks: bool,
+    bank: usize,
+    pub wave_ram: [[u8; 16]; 2],
+    position: usize,
+    sample: u8,
+    volume_code: u8,
+    force_75: bool,
+    frequency: u16,
+    timer: u32,
+    length: LengthCounter<256>,
+}
+
+impl WaveChannel {
+    pub fn write_control(&mut self, value: u8) {
+        self.two_banks = value & 0x20 != 0;
+        self.bank = ((value >> 6) & 1) as usize;
+        self.dac_enabled = value & 0x80 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value as u16);
+    }
+
+    pub fn write_volume(&mut self, value: u8) {
+        self.volume_code = (value >> 5) & 0x3;
+        self.force_75 = value & 0x80 != 0;
+    }
+
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    pub fn write_frequency_high(&mut self, value: u8, extra_length_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x7) << 8);
+        let trigger = value & 0x80 != 0;
+        if !self.length.write_control(value & 0x40 != 0, trigger, extra_length_clock) {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    pub fn cpu_bank(&self) -> usize {
+        self.bank ^ 1
+    }
+
+    pub fn write_wave_ram(&mut self, index: usize, value: u8) {
+        let bank = self.cpu_bank();
+        self.wave_ram[bank][index] = value;
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 8
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    pub fn advance(&mut self, mut cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            let length = if self.two_banks { 64 } else { 32 };
+            self.position = (self.position + 1) % length;
+
+            // in two bank mode playback runs from the selected bank into the other one
+            let bank = (self.bank + self.position / 32) & 1;
+            let byte = self.wave_ram[bank][(self.position % 32) / 2];
+            self.sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0xF };
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    // signed output level, -15..=15
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+        let level = self.sample as i16 * 2 - 15;
+        if self.force_75 {
+            return level * 3 / 4;
+        }
+        match self.volume_code {
+            0 => 0,
+            1 => level,
+            2 => level / 2,
+            _ => level / 4,
+        }
+    }
+}
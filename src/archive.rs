@@ -0,0 +1,84 @@
+// ROM files, read straight or out of an archive. Archives give up their
+// first .gba entry; .zip is always supported and .7z with the sevenz
+// feature. unpack_rom does the same for a file already in memory, for
+// frontends with no filesystem to read from.
+
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek};
+use std::path::Path;
+
+const ROM_EXTENSION: &str = ".gba";
+
+pub fn read_rom(path: &Path) -> io::Result<Vec<u8>> {
+    match archive_type(&path.to_string_lossy()) {
+        Some("zip") => read_zip(File::open(path)?),
+        Some("7z") => {
+            let file = File::open(path)?;
+            let length = file.metadata()?.len();
+            read_7z(file, length)
+        }
+        _ => fs::read(path),
+    }
+}
+
+// The ROM in a file's contents, name being the file's name.
+pub fn unpack_rom(name: &str, data: Vec<u8>) -> io::Result<Vec<u8>> {
+    match archive_type(name) {
+        Some("zip") => read_zip(Cursor::new(data)),
+        Some("7z") => {
+            let length = data.len() as u64;
+            read_7z(Cursor::new(data), length)
+        }
+        _ => Ok(data),
+    }
+}
+
+fn archive_type(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_lowercase();
+    ["zip", "7z"].into_iter().find(|extension| name.ends_with(&format!(".{}", extension)))
+}
+
+fn is_rom(name: &str) -> bool {
+    name.to_ascii_lowercase().ends_with(ROM_EXTENSION)
+}
+
+fn no_rom_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "the archive holds no .gba file")
+}
+
+fn read_zip(source: impl Read + Seek) -> io::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(source)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        if entry.is_file() && is_rom(entry.name()) {
+            let mut rom = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut rom)?;
+            return Ok(rom);
+        }
+    }
+    Err(no_rom_found())
+}
+
+#[cfg(feature = "sevenz")]
+fn read_7z(source: impl Read + Seek, length: u64) -> io::Result<Vec<u8>> {
+    let mut archive =
+        sevenz_rust::SevenZReader::new(source, length, sevenz_rust::Password::empty()).map_err(io::Error::other)?;
+    let mut rom = None;
+    archive
+        .for_each_entries(|entry, reader| {
+            if entry.is_directory() || !is_rom(entry.name()) {
+                return Ok(true);
+            }
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            reader.read_to_end(&mut data).map_err(sevenz_rust::Error::io)?;
+            rom = Some(data);
+            Ok(false)
+        })
+        .map_err(io::Error::other)?;
+    rom.ok_or_else(no_rom_found)
+}
+
+#[cfg(not(feature = "sevenz"))]
+fn read_7z(_source: impl Read + Seek, _length: u64) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "built without .7z support; enable the sevenz feature"))
+}
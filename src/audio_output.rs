@@ -0,0 +1,239 @@
+// cpal playback for the bundled frontend. The audio callback pulls from the
+// APU's sample ring on its own thread; the emulation loop uses the ring's
+// fill level to pace itself.
+
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, SampleFormat, Stream, StreamConfig};
+use serde::{Deserialize, Serialize};
+
+use afterimage::apu::{Apu, SampleRing, MAX_RATE_ADJUSTMENT};
+
+pub const DEFAULT_LATENCY_MS: u32 = 60;
+pub const MAX_VOLUME: u32 = 100;
+
+// What the emulation loop waits on between frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    // block until the device has drained enough audio; video timing
+    // follows the sound card's clock
+    Audio,
+    // pace frames by wall clock time and stretch the audio slightly with
+    // dynamic rate control so the buffer neither drains nor overflows
+    Video,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioConfig {
+    // substring of the output device name, or the host default
+    pub device: Option<String>,
+    pub latency_ms: u32,
+    pub sync: SyncMode,
+    // percent of full scale
+    pub volume: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            device: None,
+            latency_ms: DEFAULT_LATENCY_MS,
+            sync: SyncMode::Audio,
+            volume: MAX_VOLUME,
+        }
+    }
+}
+
+pub fn list_devices() -> Vec<String> {
+    let host = cpal::default_host();
+    match host.output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Gain the audio callback applies on the way to the device, so WAV dumps
+// and recordings keep the APU's own level.
+#[derive(Debug, Clone)]
+struct Volume(Arc<AtomicU32>);
+
+impl Volume {
+    fn new(percent: u32) -> Self {
+        let volume = Volume(Arc::new(AtomicU32::new(0)));
+        volume.set(percent);
+        volume
+    }
+
+    fn set(&self, percent: u32) {
+        let gain = percent.min(MAX_VOLUME) as f32 / MAX_VOLUME as f32;
+        self.0.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    fn gain(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+pub struct AudioOutput {
+    // playback stops when the stream is dropped
+    _stream: Stream,
+    ring: Arc<SampleRing>,
+    volume: Volume,
+    sample_rate: u32,
+    latency_samples: usize,
+}
+
+impl AudioOutput {
+    pub fn start(config: &AudioConfig, apu: &mut Apu) -> Result<Self, Box<dyn Error>> {
+        let host = cpal::default_host();
+        let device = match &config.device {
+            Some(name) => host
+                .output_devices()?
+                .find(|device| device.name().is_ok_and(|n| n.contains(name.as_str())))
+                .ok_or_else(|| format!("no audio output device matching \"{}\"", name))?,
+            None => host.default_output_device().ok_or("no default audio output device")?,
+        };
+
+        // stereo at the default rate if the device offers it, or else the
+        // device's own default layout
+        let default = device.default_output_config()?;
+        let supported = device
+            .supported_output_configs()?
+            .filter(|range| {
+                range.channels() == 2 && matches!(range.sample_format(), SampleFormat::I16 | SampleFormat::F32)
+            })
+            .find_map(|range| range.try_with_sample_rate(default.sample_rate()))
+            .unwrap_or(default);
+        let sample_rate = supported.sample_rate().0;
+        let latency_frames = sample_rate * config.latency_ms / 1000;
+        let mut stream_config = StreamConfig {
+            channels: supported.channels(),
+            sample_rate: supported.sample_rate(),
+            buffer_size: BufferSize::Fixed(latency_frames / 2),
+        };
+
+        // room for twice the latency, so wait_for_room always has something
+        // to wait for and a frame made meanwhile still fits
+        apu.reserve_ring(latency_frames as usize * 2 * 2);
+        let ring = apu.sample_ring();
+
+        // drops what was made before the device was ready, while nothing
+        // is reading the ring yet: once the stream exists its callback is
+        // the only consumer
+        ring.clear();
+        let volume = Volume::new(config.volume);
+        // not every backend accepts an explicit buffer size
+        let stream = match build_stream(&device, &stream_config, supported.sample_format(), &ring, &volume) {
+            Ok(stream) => stream,
+            Err(_) => {
+                stream_config.buffer_size = BufferSize::Default;
+                build_stream(&device, &stream_config, supported.sample_format(), &ring, &volume)?
+            }
+        };
+        stream.play()?;
+
+        Ok(AudioOutput {
+            _stream: stream,
+            ring,
+            volume,
+            sample_rate,
+            latency_samples: latency_frames as usize * 2,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // in percent; 0 mutes
+    pub fn set_volume(&self, percent: u32) {
+        self.volume.set(percent);
+    }
+
+    // blocks while more than the configured latency is already queued
+    pub fn wait_for_room(&self) {
+        while self.ring.len() > self.latency_samples {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // Rate factor for Apu::set_rate_adjustment that steers the buffer
+    // towards holding exactly the configured latency: produce slightly
+    // more audio when below it and slightly less when above.
+    pub fn rate_adjustment(&self) -> f64 {
+        let fill = (self.ring.len() as f64 / (2 * self.latency_samples) as f64).min(1.0);
+        1.0 + MAX_RATE_ADJUSTMENT * (1.0 - 2.0 * fill)
+    }
+}
+
+fn build_stream(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    ring: &Arc<SampleRing>,
+    volume: &Volume,
+) -> Result<Stream, Box<dyn Error>> {
+    let on_error = |err| eprintln!("Audio stream error: {}", err);
+    let channels = config.channels as usize;
+    let stream = match format {
+        SampleFormat::I16 => {
+            let ring = Arc::clone(ring);
+            let volume = volume.clone();
+            let mut stereo = Vec::new();
+            device.build_output_stream(
+                config,
+                move |data: &mut [i16], _| {
+                    let gain = volume.gain();
+                    fill(&ring, &mut stereo, data, channels, |sample| (sample as f32 * gain) as i16);
+                },
+                on_error,
+                None,
+            )?
+        }
+        SampleFormat::F32 => {
+            let ring = Arc::clone(ring);
+            let volume = volume.clone();
+            let mut stereo = Vec::new();
+            device.build_output_stream(
+                config,
+                move |data: &mut [f32], _| {
+                    let scale = volume.gain() / 32768.0;
+                    fill(&ring, &mut stereo, data, channels, |sample| sample as f32 * scale);
+                },
+                on_error,
+                None,
+            )?
+        }
+        other => return Err(format!("unsupported audio sample format {:?}", other).into()),
+    };
+    Ok(stream)
+}
+
+// Fills a device buffer of interleaved channels from the ring's stereo
+// samples: a mono device gets the two mixed, and channels past the first
+// two stay silent, as does anything the ring runs short of.
+fn fill<T: Copy + Default>(
+    ring: &SampleRing,
+    stereo: &mut Vec<i16>,
+    data: &mut [T],
+    channels: usize,
+    convert: impl Fn(i16) -> T,
+) {
+    stereo.resize(data.len() / channels * 2, 0);
+    let read = ring.pop(stereo);
+    data.fill(T::default());
+    for (frame, pair) in data.chunks_exact_mut(channels).zip(stereo[..read].chunks_exact(2)) {
+        if channels == 1 {
+            frame[0] = convert(((pair[0] as i32 + pair[1] as i32) / 2) as i16);
+        } else {
+            frame[0] = convert(pair[0]);
+            frame[1] = convert(pair[1]);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+// When battery saves are written out while a game runs. Whatever the
+// policy, a save with changes is also written on exit and before another
+// ROM is swapped in.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_FLUSH_INTERVAL: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlushPolicy {
+    // after every frame the game changed its save in
+    WriteThrough,
+    // at most once per interval
+    Periodic,
+    // only on exit
+    #[default]
+    Exit,
+}
+
+#[derive(Debug)]
+pub struct SaveFlusher {
+    policy: FlushPolicy,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl SaveFlusher {
+    pub fn new(policy: FlushPolicy, interval_seconds: u32) -> Self {
+        SaveFlusher {
+            policy,
+            interval: Duration::from_secs(interval_seconds.into()),
+            last_flush: Instant::now(),
+        }
+    }
+
+    // Whether a save with unwritten changes should be written now; the
+    // caller is expected to write it when this says so.
+    pub fn due(&mut self) -> bool {
+        let due = match self.policy {
+            FlushPolicy::WriteThrough => true,
+            FlushPolicy::Periodic => self.last_flush.elapsed() >= self.interval,
+            FlushPolicy::Exit => false,
+        };
+        if due {
+            self.last_flush = Instant::now();
+        }
+        due
+    }
+}
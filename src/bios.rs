@@ -0,0 +1,116 @@
+// High-level emulation of the BIOS routines games depend on. No BIOS image
+// is loaded, so the IRQ vector and the SWIs below are handled here instead
+// of by running BIOS code.
+
+use crate::cpu::{Cpu, CpuMode};
+use crate::interrupts::IME;
+use crate::memory::Memory;
+
+pub const IRQ_VECTOR: u32 = 0x18;
+// where the BIOS IRQ handler resumes after the user handler returns
+pub const IRQ_RETURN: u32 = 0x138;
+
+const USER_IRQ_HANDLER: u32 = 0x03007FFC;
+// the BIOS copy of IF that IntrWait checks, set by the game's handler
+const INTR_CHECK_FLAGS: u32 = 0x03007FF8;
+const SOFT_RESET_FLAG: u32 = 0x03007FFA;
+
+// BIOS IRQ handler entry:
+//   stmfd sp!, {r0-r3, r12, lr}
+//   mov r0, #0x4000000
+//   add lr, pc, #0
+//   ldr pc, [r0, #-4]
+pub fn irq_entry(cpu: &mut Cpu, memory: &mut Memory) {
+    let saved = [cpu.registers[0], cpu.registers[1], cpu.registers[2], cpu.registers[3], cpu.registers[12], cpu.lr];
+    for value in saved {
+        cpu.sp = cpu.sp.wrapping_sub(4);
+        memory.write_u32(cpu.sp, value);
+    }
+    cpu.registers[0] = 0x0400_0000;
+    cpu.lr = IRQ_RETURN;
+    cpu.pc = memory.read_u32(USER_IRQ_HANDLER) & !3;
+}
+
+// BIOS IRQ handler exit:
+//   ldmfd sp!, {r0-r3, r12, lr}
+//   subs pc, lr, #4
+pub fn irq_return(cpu: &mut Cpu, memory: &mut Memory) {
+    let mut saved = [0; 6];
+    for value in saved.iter_mut().rev() {
+        *value = memory.read_u32(cpu.sp);
+        cpu.sp = cpu.sp.wrapping_add(4);
+    }
+    let [r0, r1, r2, r3, r12, lr] = saved;
+    cpu.registers[..4].copy_from_slice(&[r0, r1, r2, r3]);
+    cpu.registers[12] = r12;
+    cpu.pc = lr.wrapping_sub(4);
+    cpu.restore_cpsr();
+    check_intr_wait(cpu, memory);
+}
+
+pub fn software_interrupt(cpu: &mut Cpu, memory: &mut Memory, function: u32) {
+    match function {
+        // SoftReset
+        0x00 => soft_reset(cpu, memory),
+        // Halt
+        0x02 => cpu.halted = true,
+        // Stop
+        0x03 => memory.stop_requested = true,
+        // IntrWait
+        0x04 => {
+            let (discard, flags) = (cpu.registers[0] != 0, cpu.registers[1] as u16);
+            intr_wait(cpu, memory, discard, flags);
+        }
+        // VBlankIntrWait
+        0x05 => {
+            cpu.registers[0] = 1;
+            cpu.registers[1] = 1;
+            intr_wait(cpu, memory, true, 1);
+        }
+        _ => {
+            // println!("Unimplemented SWI 0x{:02X}", function);
+        }
+    }
+}
+
+// Restarts the game without touching most of memory. The top 512 bytes of
+// IWRAM are cleared, after reading the flag that picks between restarting
+// the cartridge and a multiboot image in EWRAM.
+pub fn soft_reset(cpu: &mut Cpu, memory: &mut Memory) {
+    let from_ewram = memory.read_u8(SOFT_RESET_FLAG) != 0;
+    for address in 0x0300_7E00..0x0300_8000 {
+        memory.write_u8(address, 0);
+    }
+    *cpu = Cpu::new();
+    cpu.pc = if from_ewram { 0x0200_0000 } else { 0x0800_0000 };
+}
+
+// Halts until one of the given interrupts has been handled. With discard
+// set, flags raised before the call don't count.
+fn intr_wait(cpu: &mut Cpu, memory: &mut Memory, discard: bool, flags: u16) {
+    if discard {
+        let pending = memory.read_u16(INTR_CHECK_FLAGS);
+        memory.write_u16(INTR_CHECK_FLAGS, pending & !flags);
+    }
+    memory.write_u8(0x0400_0000 | IME as u32, 1);
+    cpu.intr_wait = Some(flags);
+    check_intr_wait(cpu, memory);
+}
+
+fn check_intr_wait(cpu: &mut Cpu, memory: &mut Memory) {
+    let Some(flags) = cpu.intr_wait else {
+        return;
+    };
+    let pending = memory.read_u16(INTR_CHECK_FLAGS);
+    if pending & flags != 0 {
+        memory.write_u16(INTR_CHECK_FLAGS, pending & !flags);
+        cpu.intr_wait = None;
+    } else {
+        cpu.halted = true;
+    }
+}
+
+// the user handler has returned into the BIOS IRQ handler
+pub fn at_irq_return(cpu: &Cpu) -> bool {
+    cpu.pc == IRQ_RETURN && cpu.mode == CpuMode::Irq
+}
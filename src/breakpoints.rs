@@ -0,0 +1,113 @@
+// Execution breakpoints, checked as each instruction is fetched. A hit
+// stops the Gba before the instruction runs, partway through whatever
+// run_frame or step call reached it, and is left in hit() for the
+// debugger, GDB stub or script that set it to pick up. Running on again
+// carries on from that instruction without stopping at it a second time.
+// A breakpoint with a condition only stops when the condition holds.
+
+pub mod condition;
+
+use std::collections::HashMap;
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+pub use condition::Condition;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstructionSet {
+    Arm,
+    Thumb,
+}
+
+impl InstructionSet {
+    pub fn of(thumb: bool) -> Self {
+        if thumb { InstructionSet::Thumb } else { InstructionSet::Arm }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub address: u32,
+    pub set: InstructionSet,
+    // the cycle it was reached on
+    pub cycle: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    // the instruction set it stops in, None for either
+    pub set: Option<InstructionSet>,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    addresses: HashMap<u32, Breakpoint>,
+    // let through once, being the instruction stopped before
+    resume_at: Option<u32>,
+    hit: Option<BreakpointHit>,
+}
+
+impl Breakpoints {
+    // Stops at address when running code of the given set, or either, and
+    // the condition holds if there is one. Replaces any breakpoint there.
+    pub fn add(&mut self, address: u32, set: Option<InstructionSet>, condition: Option<Condition>) {
+        self.addresses.insert(address, Breakpoint { set, condition });
+    }
+
+    pub fn remove(&mut self, address: u32) -> bool {
+        self.addresses.remove(&address).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    // by address
+    pub fn list(&self) -> Vec<(u32, &Breakpoint)> {
+        let mut list: Vec<_> = self.addresses.iter().map(|(&address, breakpoint)| (address, breakpoint)).collect();
+        list.sort_unstable_by_key(|&(address, _)| address);
+        list
+    }
+
+    // The breakpoint the last run stopped at, if it stopped at one.
+    pub fn hit(&self) -> Option<BreakpointHit> {
+        self.hit
+    }
+
+    // Lets the instruction at address run once without stopping, for
+    // stepping off a breakpoint the Gba was stopped at some other way.
+    pub fn skip(&mut self, address: u32) {
+        self.resume_at = Some(address);
+    }
+
+    // Called as each run starts, which carries on from any hit.
+    pub(crate) fn resume(&mut self) {
+        self.hit = None;
+    }
+
+    // Called before each instruction; true if it should stop there.
+    pub(crate) fn check(&mut self, cpu: &Cpu, memory: &Memory, cycle: u64) -> bool {
+        let address = cpu.pc;
+        if self.resume_at.take() == Some(address) {
+            return false;
+        }
+        let set = InstructionSet::of(cpu.thumb_mode);
+        match self.addresses.get(&address) {
+            Some(breakpoint)
+                if breakpoint.set.is_none_or(|wanted| wanted == set)
+                    && breakpoint.condition.as_ref().is_none_or(|condition| condition.holds(cpu, memory)) =>
+            {
+                self.hit = Some(BreakpointHit { address, set, cycle });
+                self.resume_at = Some(address);
+                true
+            }
+            _ => false,
+        }
+    }
+}
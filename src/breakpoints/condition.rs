@@ -0,0 +1,326 @@
+// Breakpoint conditions, C-like expressions on 32-bit unsigned values,
+// checked each time the breakpoint is reached:
+//
+//     r0 == 0x42 && [0x03001234]u16 > 100
+//
+// Values are r0-r15, sp, lr, pc, cpsr, decimal or 0x hex numbers, and
+// memory as [ADDRESS] for a word or [ADDRESS]u8 / [ADDRESS]u16 for less,
+// where ADDRESS is itself an expression. The operators, loosest first:
+//
+//     ||   &&   == != < <= > >=   |   ^   &   << >>   + -   * / %   ! ~ -
+//
+// Comparisons and ! give 1 or 0, and the breakpoint stops when the whole
+// expression isn't 0. pc is the address of the instruction stopped before.
+
+use std::fmt;
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Number(u32),
+    Register(usize),
+    Cpsr,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+    // the width following a closing bracket
+    Width(u32),
+    Operator(Operator),
+    Not,
+    Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl Operator {
+    // higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Equal
+            | Operator::NotEqual
+            | Operator::Less
+            | Operator::LessEqual
+            | Operator::Greater
+            | Operator::GreaterEqual => 3,
+            Operator::BitOr => 4,
+            Operator::BitXor => 5,
+            Operator::BitAnd => 6,
+            Operator::ShiftLeft | Operator::ShiftRight => 7,
+            Operator::Add | Operator::Subtract => 8,
+            Operator::Multiply | Operator::Divide | Operator::Remainder => 9,
+        }
+    }
+
+    fn apply(self, left: u32, right: u32) -> u32 {
+        match self {
+            Operator::Or => (left != 0 || right != 0) as u32,
+            Operator::And => (left != 0 && right != 0) as u32,
+            Operator::Equal => (left == right) as u32,
+            Operator::NotEqual => (left != right) as u32,
+            Operator::Less => (left < right) as u32,
+            Operator::LessEqual => (left <= right) as u32,
+            Operator::Greater => (left > right) as u32,
+            Operator::GreaterEqual => (left >= right) as u32,
+            Operator::BitOr => left | right,
+            Operator::BitXor => left ^ right,
+            Operator::BitAnd => left & right,
+            Operator::ShiftLeft => left.checked_shl(right).unwrap_or(0),
+            Operator::ShiftRight => left.checked_shr(right).unwrap_or(0),
+            Operator::Add => left.wrapping_add(right),
+            Operator::Subtract => left.wrapping_sub(right),
+            Operator::Multiply => left.wrapping_mul(right),
+            // dividing by zero gives 0 rather than stopping the game
+            Operator::Divide => left.checked_div(right).unwrap_or(0),
+            Operator::Remainder => left.checked_rem(right).unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(u32),
+    // r0-r12, then sp, lr and pc
+    Register(usize),
+    Cpsr,
+    Memory(Box<Expr>, u32),
+    Not(Box<Expr>),
+    Complement(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, cpu: &Cpu, memory: &Memory) -> u32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(index @ 0..=12) => cpu.registers[*index],
+            Expr::Register(13) => cpu.sp,
+            Expr::Register(14) => cpu.lr,
+            Expr::Register(_) => cpu.pc,
+            Expr::Cpsr => cpu.cpsr,
+            Expr::Memory(address, width) => {
+                let address = address.eval(cpu, memory);
+                match width {
+                    1 => memory.read_u8(address) as u32,
+                    2 => memory.read_u16(address) as u32,
+                    _ => memory.read_u32(address),
+                }
+            }
+            Expr::Not(operand) => (operand.eval(cpu, memory) == 0) as u32,
+            Expr::Complement(operand) => !operand.eval(cpu, memory),
+            Expr::Negate(operand) => operand.eval(cpu, memory).wrapping_neg(),
+            Expr::Binary(operator, left, right) => operator.apply(left.eval(cpu, memory), right.eval(cpu, memory)),
+        }
+    }
+}
+
+// A parsed condition, shown as it was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+        let expr = parser.expression(0)?;
+        if parser.position != tokens.len() {
+            return Err(format!("unexpected {} in condition", describe(tokens[parser.position])));
+        }
+        Ok(Condition { text: text.trim().to_string(), expr })
+    }
+
+    // Side effect free: memory is read the way the debugger reads it.
+    pub fn holds(&self, cpu: &Cpu, memory: &Memory) -> bool {
+        self.expr.eval(cpu, memory) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let word_length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let (token, length) = if c.is_ascii_alphanumeric() {
+            (word(&rest[..word_length], tokens.last())?, word_length)
+        } else {
+            let two = rest.get(..2).unwrap_or("");
+            let operator = match two {
+                "||" => Some(Operator::Or),
+                "&&" => Some(Operator::And),
+                "==" => Some(Operator::Equal),
+                "!=" => Some(Operator::NotEqual),
+                "<=" => Some(Operator::LessEqual),
+                ">=" => Some(Operator::GreaterEqual),
+                "<<" => Some(Operator::ShiftLeft),
+                ">>" => Some(Operator::ShiftRight),
+                _ => None,
+            };
+            match operator {
+                Some(operator) => (Token::Operator(operator), 2),
+                None => {
+                    let token = match c {
+                        '(' => Token::Open,
+                        ')' => Token::Close,
+                        '[' => Token::OpenBracket,
+                        ']' => Token::CloseBracket,
+                        '!' => Token::Not,
+                        '~' => Token::Complement,
+                        '<' => Token::Operator(Operator::Less),
+                        '>' => Token::Operator(Operator::Greater),
+                        '|' => Token::Operator(Operator::BitOr),
+                        '^' => Token::Operator(Operator::BitXor),
+                        '&' => Token::Operator(Operator::BitAnd),
+                        '+' => Token::Operator(Operator::Add),
+                        '-' => Token::Operator(Operator::Subtract),
+                        '*' => Token::Operator(Operator::Multiply),
+                        '/' => Token::Operator(Operator::Divide),
+                        '%' => Token::Operator(Operator::Remainder),
+                        _ => return Err(format!("unexpected \"{}\" in condition", c)),
+                    };
+                    (token, c.len_utf8())
+                }
+            }
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// A number, register, or the width of the memory access just closed.
+fn word(word: &str, last: Option<&Token>) -> Result<Token, String> {
+    let lower = word.to_ascii_lowercase();
+    if last == Some(&Token::CloseBracket) {
+        match lower.as_str() {
+            "u8" => return Ok(Token::Width(1)),
+            "u16" => return Ok(Token::Width(2)),
+            "u32" => return Ok(Token::Width(4)),
+            _ => {}
+        }
+    }
+    let register = match lower.as_str() {
+        "sp" => Some(13),
+        "lr" => Some(14),
+        "pc" => Some(15),
+        "cpsr" => return Ok(Token::Cpsr),
+        _ => lower.strip_prefix('r').and_then(|index| index.parse().ok()).filter(|&index| index < 16),
+    };
+    if let Some(index) = register {
+        return Ok(Token::Register(index));
+    }
+    let number = match lower.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => lower.parse(),
+    };
+    number.map(Token::Number).map_err(|_| format!("\"{}\" is not a number or register", word))
+}
+
+fn describe(token: Token) -> String {
+    match token {
+        Token::Number(value) => format!("number {}", value),
+        Token::Register(_) | Token::Cpsr => "register".to_string(),
+        Token::Width(_) => "width".to_string(),
+        Token::Operator(_) | Token::Not | Token::Complement => "operator".to_string(),
+        Token::Open | Token::OpenBracket => "opening bracket".to_string(),
+        Token::Close | Token::CloseBracket => "closing bracket".to_string(),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).copied();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, wanted: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == wanted => Ok(()),
+            Some(token) => Err(format!("{} where {} was expected", describe(token), describe(wanted))),
+            None => Err(format!("condition ends where {} was expected", describe(wanted))),
+        }
+    }
+
+    // Binary operators binding tighter than min_precedence, by precedence
+    // climbing; all of them group left to right.
+    fn expression(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(&Token::Operator(operator)) = self.tokens.get(self.position)
+            && operator.precedence() > min_precedence
+        {
+            self.position += 1;
+            let right = self.expression(operator.precedence())?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Complement) => Ok(Expr::Complement(Box::new(self.unary()?))),
+            Some(Token::Operator(Operator::Subtract)) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Register(index)) => Ok(Expr::Register(index)),
+            Some(Token::Cpsr) => Ok(Expr::Cpsr),
+            Some(Token::Open) => {
+                let inner = self.expression(0)?;
+                self.expect(Token::Close)?;
+                Ok(inner)
+            }
+            Some(Token::OpenBracket) => {
+                let address = self.expression(0)?;
+                self.expect(Token::CloseBracket)?;
+                let width = match self.tokens.get(self.position) {
+                    Some(&Token::Width(width)) => {
+                        self.position += 1;
+                        width
+                    }
+                    _ => 4,
+                };
+                Ok(Expr::Memory(Box::new(address), width))
+            }
+            Some(token) => Err(format!("unexpected {} in condition", describe(token))),
+            None => Err("condition ends too soon".to_string()),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// Heuristic call stack tracking, for debuggers. Nothing in the hardware
+// marks a call, so one is taken to be any branch that leaves LR pointing
+// just past the branching instruction, which covers BL in both states and
+// the mov lr, pc / bx pattern, and a return is a branch to a return
+// address still on the stack. Frames are also dropped once SP rises above
+// where it was at the call, for code that unwinds the stack without
+// returning. Interrupts show up as frames of their own, returning to the
+// interrupted instruction.
+//
+// Tracking costs a little on every instruction, so it is off until
+// enabled, and only knows of calls made since.
+
+use crate::cpu::{Cpu, CpuMode};
+use crate::memory::Memory;
+
+// frames kept; the oldest go first, for code that never returns
+const MAX_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    // the branch, or the instruction an interrupt came before
+    pub call_site: u32,
+    // the function or interrupt handler entered
+    pub target: u32,
+    pub return_address: u32,
+    // the CPU state and SP at the call
+    pub thumb: bool,
+    pub sp: u32,
+    pub mode: CpuMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallEvent {
+    // with the depth of the new frame, 1 being the outermost
+    Enter { frame: Frame, depth: usize, cycle: u64 },
+    // the depth the frame had
+    Leave { frame: Frame, depth: usize, cycle: u64 },
+}
+
+#[derive(Debug, Default)]
+pub struct CallStack {
+    enabled: bool,
+    // outermost first
+    frames: Vec<Frame>,
+    // calls and returns since the last take_trace, while tracing
+    trace: Option<Vec<CallEvent>>,
+}
+
+impl CallStack {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Turning tracking off forgets the frames.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.frames.clear();
+        }
+    }
+
+    // Starts or stops keeping calls and returns for take_trace; tracing
+    // also turns tracking on.
+    pub fn set_tracing(&mut self, tracing: bool) {
+        if tracing {
+            self.enabled = true;
+            self.trace.get_or_insert_with(Vec::new);
+        } else {
+            self.trace = None;
+        }
+    }
+
+    pub fn take_trace(&mut self) -> Vec<CallEvent> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // Called after each instruction, given the address and width of the one
+    // that ran.
+    pub(crate) fn instruction_ran(&mut self, address: u32, width: u32, cpu: &Cpu, memory: &Memory, cycle: u64) {
+        let next = address.wrapping_add(width);
+        if cpu.pc != next {
+            if cpu.lr & !1 == next {
+                // the call site of a Thumb BL is its first half
+                let call_site = if width == 2 && memory.read_u16(address) >> 11 == 0x1F {
+                    address.wrapping_sub(2)
+                } else {
+                    address
+                };
+                self.enter(FrameKind::Call, call_site, next, cpu, cycle);
+                return;
+            }
+            self.returned_to(cpu.pc, cycle);
+        }
+        self.unwind(cpu, cycle);
+    }
+
+    // Called once the CPU has entered the handler for an interrupt that came
+    // before the instruction at address.
+    pub(crate) fn interrupted(&mut self, address: u32, cpu: &Cpu, cycle: u64) {
+        self.enter(FrameKind::Interrupt, address, address, cpu, cycle);
+    }
+
+    // Pops the frame returning to address and any called from it.
+    pub(crate) fn returned_to(&mut self, address: u32, cycle: u64) {
+        if let Some(index) = self.frames.iter().rposition(|frame| frame.return_address == address & !1) {
+            self.leave_to(index, cycle);
+        }
+    }
+
+    fn enter(&mut self, kind: FrameKind, call_site: u32, return_address: u32, cpu: &Cpu, cycle: u64) {
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        let frame = Frame {
+            kind,
+            call_site,
+            target: cpu.pc,
+            return_address,
+            thumb: cpu.thumb_mode,
+            sp: cpu.sp,
+            mode: cpu.mode,
+        };
+        self.frames.push(frame);
+        if let Some(trace) = &mut self.trace {
+            trace.push(CallEvent::Enter { frame, depth: self.frames.len(), cycle });
+        }
+    }
+
+    // Drops the frames the stack has been unwound past in the mode they
+    // were made in; SP in other modes is banked, so says nothing.
+    fn unwind(&mut self, cpu: &Cpu, cycle: u64) {
+        let kept = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.mode != cpu.mode || cpu.sp <= frame.sp)
+            .map_or(0, |index| index + 1);
+        if kept < self.frames.len() {
+            self.leave_to(kept, cycle);
+        }
+    }
+
+    // Pops the frames from index up.
+    fn leave_to(&mut self, index: usize, cycle: u64) {
+        while self.frames.len() > index {
+            let depth = self.frames.len();
+            let frame = self.frames.pop().unwrap();
+            if let Some(trace) = &mut self.trace {
+                trace.push(CallEvent::Leave { frame, depth, cycle });
+            }
+        }
+    }
+}
+
+// Return addresses found by looking through the words on the stack, for
+// when tracking wasn't on for the calls: each word just past a BL, as
+// (where it is on the stack, the address), innermost first. Thumb ones are
+// odd, as pushed.
+pub fn scan_stack(cpu: &Cpu, memory: &Memory, words: u32) -> Vec<(u32, u32)> {
+    (0..words)
+        .map(|index| cpu.sp.wrapping_add(index * 4))
+        .map(|slot| (slot, memory.read_u32(slot)))
+        .filter(|&(_, value)| follows_bl(memory, value))
+        .collect()
+}
+
+fn follows_bl(memory: &Memory, return_address: u32) -> bool {
+    // somewhere code runs from: the BIOS, work RAM or the cartridge
+    let code = matches!(return_address >> 24, 0x00 | 0x02 | 0x03 | 0x08..=0x0D);
+    if !code || return_address < 4 {
+        return false;
+    }
+    if return_address & 1 != 0 {
+        let address = return_address & !1;
+        memory.read_u16(address - 4) >> 11 == 0x1E && memory.read_u16(address - 2) >> 11 == 0x1F
+    } else {
+        return_address & 3 == 0 && memory.read_u32(return_address - 4) & 0x0F00_0000 == 0x0B00_0000
+    }
+}
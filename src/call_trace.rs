@@ -0,0 +1,60 @@
+// The --call-trace log: every call and return the core's call stack
+// tracking sees, one to a line, indented by depth and led by the cycle it
+// happened on:
+//
+//          1234  call 08001000 from 08000124 (Thumb)
+//          1300    interrupt 03000100 at 08001010
+//          1420    return to 08001010
+//          1500  return to 08000128
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use afterimage::Gba;
+use afterimage::call_stack::{CallEvent, FrameKind};
+
+#[derive(Debug)]
+pub struct CallTrace {
+    out: BufWriter<File>,
+}
+
+impl CallTrace {
+    // Turns on tracing in the core.
+    pub fn create(path: &Path, gba: &mut Gba) -> io::Result<Self> {
+        let out = BufWriter::new(File::create(path)?);
+        gba.call_stack.set_tracing(true);
+        Ok(CallTrace { out })
+    }
+
+    // Writes out what has happened since the last call.
+    pub fn write(&mut self, gba: &mut Gba) -> io::Result<()> {
+        for event in gba.call_stack.take_trace() {
+            match event {
+                CallEvent::Enter { frame, depth, cycle } => {
+                    let indent = "  ".repeat(depth - 1);
+                    match frame.kind {
+                        FrameKind::Call => {
+                            let state = if frame.thumb { "Thumb" } else { "ARM" };
+                            writeln!(
+                                self.out,
+                                "{:>12}  {}call {:08X} from {:08X} ({})",
+                                cycle, indent, frame.target, frame.call_site, state
+                            )?;
+                        }
+                        FrameKind::Interrupt => writeln!(
+                            self.out,
+                            "{:>12}  {}interrupt {:08X} at {:08X}",
+                            cycle, indent, frame.target, frame.call_site
+                        )?,
+                    }
+                }
+                CallEvent::Leave { frame, depth, cycle } => {
+                    let indent = "  ".repeat(depth - 1);
+                    writeln!(self.out, "{:>12}  {}return to {:08X}", cycle, indent, frame.return_address)?;
+                }
+            }
+        }
+        self.out.flush()
+    }
+}
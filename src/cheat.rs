@@ -0,0 +1,355 @@
+// Cheat codes for GameShark and Action Replay devices, read from a TOML
+// file beside the ROM (game.cht) or given with --cheats:
+//
+//     [[cheat]]
+//     name = "999 coins"
+//     format = "gameshark-v1"
+//     encrypted = false
+//     code = """
+//     13007FF0 000003E7
+//     """
+//
+// format is "gameshark-v1" for GameShark Advance and Action Replay v1/v2
+// codes, or "gameshark-v3" for GameShark SP and Action Replay v3 ones.
+// Codes are taken as printed, encrypted; `encrypted = false` takes ones
+// already decrypted, and `enabled = false` loads a cheat switched off.
+//
+// The devices hook the game's VBlank interrupt and write their codes from
+// there, so enabled cheats run right after each frame, as the console
+// enters VBlank.
+
+mod action_replay;
+mod gameshark;
+
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use afterimage::Gba;
+use serde::{Deserialize, Serialize};
+
+pub const CHEAT_EXTENSION: &str = "cht";
+
+// decrypted second word of the game ID line in master codes, which only
+// matters to the device itself
+const ID_CODE: u32 = 0x001D_C0DE;
+// decrypted first word of a line that reseeds the encryption for the
+// lines after it
+const RESEED_CODE: u32 = 0xDEAD_FACE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CodeFormat {
+    #[serde(alias = "gameshark-v2", alias = "action-replay-v1", alias = "action-replay-v2")]
+    GamesharkV1,
+    #[serde(alias = "action-replay-v3")]
+    GamesharkV3,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheatFile {
+    #[serde(default)]
+    cheat: Vec<CheatEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheatEntry {
+    name: String,
+    format: CodeFormat,
+    code: String,
+    #[serde(default = "enabled_by_default")]
+    encrypted: bool,
+    #[serde(default = "enabled_by_default")]
+    enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Width {
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(Width::Byte),
+            16 => Some(Width::Half),
+            32 => Some(Width::Word),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> u32 {
+        match self {
+            Width::Byte => 1,
+            Width::Half => 2,
+            Width::Word => 4,
+        }
+    }
+
+    pub fn read(self, gba: &Gba, address: u32) -> u32 {
+        match self {
+            Width::Byte => gba.memory.read_u8(address) as u32,
+            Width::Half => gba.memory.read_u16(address) as u32,
+            Width::Word => gba.memory.read_u32(address),
+        }
+    }
+
+    fn write(self, gba: &mut Gba, address: u32, value: u32) {
+        match self {
+            Width::Byte => gba.memory.write_u8(address, value as u8),
+            Width::Half => gba.memory.write_u16(address, value as u16),
+            Width::Word => gba.memory.write_u32(address, value),
+        }
+    }
+
+    // value read at this width as a signed number
+    fn signed(self, value: u32) -> i32 {
+        match self {
+            Width::Byte => value as u8 as i8 as i32,
+            Width::Half => value as u16 as i16 as i32,
+            Width::Word => value as i32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Test {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessSigned,
+    GreaterSigned,
+    // any of the value's bits set
+    And,
+}
+
+// One code as the device runs it, whatever line or lines it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Code {
+    Write { addresses: Vec<u32>, width: Width, value: u32 },
+    Add { address: u32, width: Width, value: u32 },
+    // a halfword of the cartridge ROM, by offset into it
+    RomPatch { offset: usize, value: u16 },
+    // the next `then` codes only run when the memory passes the test
+    If { address: u32, width: Width, test: Test, value: u32, then: usize },
+}
+
+#[derive(Debug)]
+pub struct Cheat {
+    pub name: String,
+    enabled: bool,
+    codes: Vec<Code>,
+    // ROM halfwords the patches replaced, to put back when turned off
+    rom_backup: Vec<(usize, u16)>,
+}
+
+impl Cheat {
+    // Parses the code text of one cheat into the codes it runs.
+    fn parse(name: String, format: CodeFormat, text: &str, encrypted: bool) -> Result<Self, String> {
+        let lines = read_lines(text, format, encrypted)?;
+        let codes = match format {
+            CodeFormat::GamesharkV1 => gameshark::parse(&lines)?,
+            CodeFormat::GamesharkV3 => action_replay::parse(&lines)?,
+        };
+        Ok(Cheat {
+            name,
+            enabled: true,
+            codes,
+            rom_backup: Vec::new(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn run(&mut self, gba: &mut Gba) {
+        let mut index = 0;
+        while let Some(code) = self.codes.get(index) {
+            index += 1;
+            match *code {
+                Code::Write { ref addresses, width, value } => {
+                    for &address in addresses {
+                        width.write(gba, address, value);
+                    }
+                }
+                Code::Add { address, width, value } => {
+                    let sum = width.read(gba, address).wrapping_add(value);
+                    width.write(gba, address, sum);
+                }
+                Code::RomPatch { offset, value } => {
+                    let Some(bytes) = gba.memory.rom.get_mut(offset..offset + 2) else {
+                        continue;
+                    };
+                    let original = u16::from_le_bytes([bytes[0], bytes[1]]);
+                    if !self.rom_backup.iter().any(|&(patched, _)| patched == offset) {
+                        self.rom_backup.push((offset, original));
+                    }
+                    bytes.copy_from_slice(&value.to_le_bytes());
+                }
+                Code::If { address, width, test, value, then } => {
+                    let memory = width.read(gba, address);
+                    let passed = match test {
+                        Test::Equal => memory == value,
+                        Test::NotEqual => memory != value,
+                        Test::Less => memory < value,
+                        Test::Greater => memory > value,
+                        Test::LessSigned => width.signed(memory) < width.signed(value),
+                        Test::GreaterSigned => width.signed(memory) > width.signed(value),
+                        Test::And => memory & value != 0,
+                    };
+                    if !passed {
+                        index += then;
+                    }
+                }
+            }
+        }
+    }
+
+    // Puts back the ROM as it was before any of the cheat's patches.
+    fn restore_rom(&mut self, gba: &mut Gba) {
+        for (offset, original) in self.rom_backup.drain(..) {
+            if let Some(bytes) = gba.memory.rom.get_mut(offset..offset + 2) {
+                bytes.copy_from_slice(&original.to_le_bytes());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Cheats {
+    pub cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    // A missing file is no cheats. A cheat whose codes can't be used is
+    // reported and left out, without holding up the rest.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Cheats::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let file: CheatFile = toml::from_str(&text)?;
+        let mut cheats = Vec::new();
+        for entry in file.cheat {
+            match Cheat::parse(entry.name.clone(), entry.format, &entry.code, entry.encrypted) {
+                Ok(cheat) => cheats.push(Cheat {
+                    enabled: entry.enabled,
+                    ..cheat
+                }),
+                Err(err) => println!("Skipping cheat \"{}\": {}", entry.name, err),
+            }
+        }
+        Ok(Cheats { cheats })
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    // Adds a cheat holding value at address, found with a RAM search, and
+    // writes it to the end of the cheat file as a decrypted GameShark code.
+    pub fn add_write(&mut self, path: &Path, name: &str, address: u32, width: Width, value: u32) -> Result<(), Box<dyn Error>> {
+        let address = address & 0x0FFF_FFFF;
+        let code = match width {
+            Width::Byte => format!("0{:07X} {:08X}", address, value & 0xFF),
+            Width::Half => format!("1{:07X} {:08X}", address, value & 0xFFFF),
+            Width::Word => format!("2{:07X} {:08X}", address, value),
+        };
+        let entry = CheatEntry {
+            name: name.to_string(),
+            format: CodeFormat::GamesharkV1,
+            code,
+            encrypted: false,
+            enabled: true,
+        };
+        let cheat = Cheat::parse(entry.name.clone(), entry.format, &entry.code, false)?;
+        let text = toml::to_string(&CheatFile { cheat: vec![entry] })?;
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        write!(file, "\n{}", text)?;
+        self.cheats.push(cheat);
+        Ok(())
+    }
+
+    // Runs the enabled cheats, once the frame has been run.
+    pub fn apply(&mut self, gba: &mut Gba) {
+        for cheat in self.cheats.iter_mut().filter(|cheat| cheat.enabled) {
+            cheat.run(gba);
+        }
+    }
+
+    // Turns a cheat on or off. Turning one off undoes its ROM patches;
+    // its RAM writes stay until the game changes the values back.
+    pub fn toggle(&mut self, index: usize, gba: &mut Gba) {
+        let cheat = &mut self.cheats[index];
+        cheat.enabled = !cheat.enabled;
+        if !cheat.enabled {
+            cheat.restore_rom(gba);
+        }
+    }
+}
+
+// The code text as address and value word pairs, decrypted. A line holds
+// one pair of eight digit hex words, with or without a space between.
+fn read_lines(text: &str, format: CodeFormat, encrypted: bool) -> Result<Vec<(u32, u32)>, String> {
+    let seeds = match format {
+        CodeFormat::GamesharkV1 => gameshark::SEEDS,
+        CodeFormat::GamesharkV3 => action_replay::SEEDS,
+    };
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let digits: String = line.split_whitespace().collect();
+        if digits.is_empty() {
+            continue;
+        }
+        let word = |digits: &str| u32::from_str_radix(digits, 16).ok();
+        let (Some(mut address), Some(mut value)) = (digits.get(..8).and_then(word), digits.get(8..).and_then(word))
+        else {
+            return Err(format!("\"{}\" is not a code", line.trim()));
+        };
+        if digits.len() != 16 {
+            return Err(format!("\"{}\" is not a code", line.trim()));
+        }
+        if encrypted {
+            decrypt(&mut address, &mut value, &seeds);
+            if address == RESEED_CODE {
+                // the new seeds come from tables in the device's firmware
+                return Err("codes that change the encryption (DEADFACE) are not supported".to_string());
+            }
+        }
+        lines.push((address, value));
+    }
+    if lines.is_empty() {
+        return Err("no codes".to_string());
+    }
+    Ok(lines)
+}
+
+// TEA, which both generations of the devices encrypt codes with, each with
+// their own key.
+fn decrypt(address: &mut u32, value: &mut u32, seeds: &[u32; 4]) {
+    const DELTA: u32 = 0x9E37_79B9;
+    let mut sum = DELTA.wrapping_mul(32);
+    for _ in 0..32 {
+        *value = value.wrapping_sub(
+            (*address << 4).wrapping_add(seeds[2]) ^ address.wrapping_add(sum) ^ (*address >> 5).wrapping_add(seeds[3]),
+        );
+        *address = address.wrapping_sub(
+            (*value << 4).wrapping_add(seeds[0]) ^ value.wrapping_add(sum) ^ (*value >> 5).wrapping_add(seeds[1]),
+        );
+        sum = sum.wrapping_sub(DELTA);
+    }
+}
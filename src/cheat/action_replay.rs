@@ -0,0 +1,85 @@
+// Codes for the GameShark SP and Action Replay v3. The top byte of the
+// address word picks the code type, and the rest packs the address: its
+// region digit in bits 20-23 and the offset into the region below.
+//
+//     00aaaaaa nnnnnnxx   write the byte xx to nnnnnn + 1 bytes from aaaaaa
+//     02aaaaaa nnnnxxxx   write the halfword to nnnn + 1 halfwords
+//     04aaaaaa xxxxxxxx   write a word
+//     80aaaaaa 000000xx   add to a byte; 82 and 84 add to a halfword or word
+//     08aaaaaa 000000xx   run the next code if the byte is xx; 0A and 0C
+//                         test a halfword or word, and each eight up from
+//                         08 is another test: not equal, signed less,
+//                         signed greater, less, greater, any bits of xx
+//     C4aaaaaa xxxxxxxx   master code hook, which the device needs and we don't
+//     C6aaaaaa 0000xxxx   write the halfword to I/O register aaaaaa
+//     C7aaaaaa xxxxxxxx   write a word to an I/O register
+
+use super::{Code, ID_CODE, Test, Width};
+
+pub const SEEDS: [u32; 4] = [0x7AA9_648F, 0x7FAE_6994, 0xC0EF_AAD5, 0x4271_2C57];
+
+const TESTS: [Test; 7] = [
+    Test::Equal,
+    Test::NotEqual,
+    Test::LessSigned,
+    Test::GreaterSigned,
+    Test::Less,
+    Test::Greater,
+    Test::And,
+];
+const IO_BASE: u32 = 0x0400_0000;
+
+pub fn parse(lines: &[(u32, u32)]) -> Result<Vec<Code>, String> {
+    let mut codes = Vec::new();
+    for &(address, value) in lines {
+        if value == ID_CODE {
+            continue;
+        }
+        let target = ((address & 0x00F0_0000) << 4) | (address & 0x000F_FFFF);
+        let code = match address >> 24 {
+            0x00 if address != 0 => fill(target, Width::Byte, value & 0xFF, value >> 8)?,
+            0x02 => fill(target, Width::Half, value & 0xFFFF, value >> 16)?,
+            0x04 => fill(target, Width::Word, value, 0)?,
+            kind @ (0x80 | 0x82 | 0x84) => Code::Add {
+                address: target,
+                width: width(kind),
+                value,
+            },
+            kind @ 0x08..=0x3F if kind & 1 == 0 && kind & 0x06 != 0x06 => Code::If {
+                address: target,
+                width: width(kind),
+                test: TESTS[(kind as usize - 0x08) / 8],
+                value,
+                then: 1,
+            },
+            0xC4 => continue,
+            0xC6 => fill(IO_BASE | (address & 0x3FF), Width::Half, value & 0xFFFF, 0)?,
+            0xC7 => fill(IO_BASE | (address & 0x3FF), Width::Word, value, 0)?,
+            _ => return Err(format!("unsupported code {:08X} {:08X}", address, value)),
+        };
+        codes.push(code);
+    }
+    Ok(codes)
+}
+
+// the width given by bits 1-2 of a code type
+fn width(kind: u32) -> Width {
+    match kind & 0x06 {
+        0x00 => Width::Byte,
+        0x02 => Width::Half,
+        _ => Width::Word,
+    }
+}
+
+// value written to address and the extra elements after it
+fn fill(address: u32, width: Width, value: u32, extra: u32) -> Result<Code, String> {
+    // the largest region, EWRAM, is 256 KiB
+    if extra >= 0x40000 / width.bytes() {
+        return Err(format!("a fill of {} values is longer than any memory it could be in", extra + 1));
+    }
+    Ok(Code::Write {
+        addresses: (0..=extra).map(|index| address + index * width.bytes()).collect(),
+        width,
+        value,
+    })
+}
@@ -0,0 +1,77 @@
+// Codes for the first GameShark Advance and Action Replay v1/v2, which
+// share a format. The top digit of the address word picks the code type:
+//
+//     0aaaaaaa 000000xx   write the byte xx to aaaaaaa
+//     1aaaaaaa 0000xxxx   write a halfword
+//     2aaaaaaa xxxxxxxx   write a word
+//     3000cccc xxxxxxxx   write the word to the cccc addresses on the lines
+//                         after, two to a line
+//     6aaaaaaa 0000xxxx   patch the ROM halfword at aaaaaaa * 2
+//     Daaaaaaa 0000xxxx   run the next code if the halfword at aaaaaaa is xxxx
+//     E0zzxxxx 0aaaaaaa   run the next zz codes if the halfword at aaaaaaa is xxxx
+//     Faaaaaaa xxxxxxxx   master code hook, which the device needs and we don't
+
+use super::{Code, ID_CODE, Test, Width};
+
+pub const SEEDS: [u32; 4] = [0x09F4_FBBD, 0x9681_884A, 0x3520_27E9, 0xF3DE_E5A7];
+
+pub fn parse(lines: &[(u32, u32)]) -> Result<Vec<Code>, String> {
+    let mut codes = Vec::new();
+    let mut lines = lines.iter();
+    while let Some(&(address, value)) = lines.next() {
+        let target = address & 0x0FFF_FFFF;
+        let code = match address >> 28 {
+            _ if value == ID_CODE => continue,
+            0x0 => write(target, Width::Byte, value & 0xFF),
+            0x1 => write(target, Width::Half, value & 0xFFFF),
+            0x2 => write(target, Width::Word, value),
+            0x3 if target >> 16 == 0 => {
+                let count = (address & 0xFFFF) as usize;
+                let addresses: Vec<u32> = lines
+                    .by_ref()
+                    .take(count.div_ceil(2))
+                    .flat_map(|&(first, second)| [first, second])
+                    .take(count)
+                    .collect();
+                if addresses.len() < count {
+                    return Err("a group write is missing some of its addresses".to_string());
+                }
+                Code::Write {
+                    addresses,
+                    width: Width::Word,
+                    value,
+                }
+            }
+            0x6 if value >> 16 == 0 => Code::RomPatch {
+                offset: ((address << 1) & 0x01FF_FFFE) as usize,
+                value: value as u16,
+            },
+            0xD if value >> 16 == 0 => Code::If {
+                address: target,
+                width: Width::Half,
+                test: Test::Equal,
+                value: value & 0xFFFF,
+                then: 1,
+            },
+            0xE if target >> 24 == 0 => Code::If {
+                address: value & 0x0FFF_FFFF,
+                width: Width::Half,
+                test: Test::Equal,
+                value: address & 0xFFFF,
+                then: ((address >> 16) & 0xFF) as usize,
+            },
+            0xF => continue,
+            _ => return Err(format!("unsupported code {:08X} {:08X}", address, value)),
+        };
+        codes.push(code);
+    }
+    Ok(codes)
+}
+
+fn write(address: u32, width: Width, value: u32) -> Code {
+    Code::Write {
+        addresses: vec![address],
+        width,
+        value,
+    }
+}
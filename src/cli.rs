@@ -0,0 +1,433 @@
+// Command line options. Settings from the config file fill in whatever was
+// not given here, so a flag always wins.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+
+#[cfg(feature = "audio")]
+use crate::audio_output::{AudioConfig, SyncMode, DEFAULT_LATENCY_MS, MAX_VOLUME};
+use afterimage::apu::AudioFilter;
+use crate::battery::{FlushPolicy, DEFAULT_FLUSH_INTERVAL};
+use crate::cheat::CHEAT_EXTENSION;
+use crate::config::Config;
+use crate::frontend::filter::VideoFilter;
+use crate::frontend::orientation::Orientation;
+use crate::frontend::{WindowOptions, DEFAULT_SCALE};
+use crate::pacing::FrameSkip;
+use crate::rewind::DEFAULT_REWIND_BUFFER_MB;
+
+#[derive(Debug, Parser)]
+#[command(name = "afterimage", version, about = "Game Boy Advance emulator")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(help = "ROM image to run, or a .zip or .7z holding one; without one the cartridge slot is empty")]
+    pub rom: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Config file to use [default: afterimage.toml here, else in the user config directory]")]
+    pub config: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "BIOS image to map at address 0; BIOS calls are still emulated")]
+    pub bios: Option<PathBuf>,
+
+    #[arg(long, value_name = "DIR", help = "Where battery saves are kept [default: next to the ROM]")]
+    pub save_dir: Option<PathBuf>,
+
+    #[arg(long, value_enum, value_name = "POLICY", default_value = "exit", help = "When battery saves are written while playing")]
+    pub save_flush: FlushPolicy,
+
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_FLUSH_INTERVAL, help = "How often --save-flush periodic writes a changed save")]
+    pub save_interval: u32,
+
+    #[arg(long, value_name = "DIR", default_value = "screenshots", help = "Where screenshots are saved")]
+    pub screenshot_dir: PathBuf,
+
+    #[arg(long, value_name = "DIR", default_value = "recordings", help = "Where recordings started by hotkey are saved")]
+    pub recording_dir: PathBuf,
+
+    #[arg(long, value_name = "PATH", help = "Record video and audio through ffmpeg from the start, e.g. to run.mp4")]
+    pub record: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Record the buttons pressed from power-on to a movie file")]
+    pub record_movie: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "record_movie", help = "Play back a movie from --record-movie, reporting the frame it goes out of sync on if it does; the movie read-only hotkey switches to recording over it from the current frame")]
+    pub play_movie: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Cheat code file to use instead of the one beside the ROM [default: ROM name with .cht]")]
+    pub cheats: Option<PathBuf>,
+
+    #[arg(long, help = "Take RAM search, watch and cheat commands typed into the terminal while the game runs; type help for a list")]
+    pub console: bool,
+
+    #[arg(long, help = "Start stopped in the debugger, before the first instruction; implies --console")]
+    pub debug: bool,
+
+    #[arg(long, value_name = "PATH", help = "Log every function call and return the debugger can see to PATH, indented by call depth")]
+    pub call_trace: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Record which ROM addresses run as ARM or Thumb code, writing the map to PATH on exit: address ranges as text, or with a .bin extension a byte per ROM halfword, 1 for ARM and 2 for Thumb")]
+    pub coverage: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Count the cycles spent at each address from power-on, writing a report of the hot spots to PATH on exit")]
+    pub profile: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Symbol table naming the functions in profiler reports: nm output or a no$gba .sym file")]
+    pub symbols: Option<PathBuf>,
+
+    #[cfg(feature = "gdb")]
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["record_movie", "play_movie"], help = "Wait for GDB to attach on this TCP port before running, with target remote localhost:PORT")]
+    pub gdb: Option<u16>,
+
+    #[cfg(feature = "lua")]
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["link_host", "link_connect"], help = "Run a Lua script alongside the game, with callbacks each frame and on memory reads, writes and code addresses, and functions to read and write memory, press buttons and put text on the screen")]
+    pub script: Option<PathBuf>,
+
+    #[arg(long, value_name = "SECONDS", default_value_t = 10, help = "Length of the clip the clip hotkey saves as an animated PNG; 0 turns it off")]
+    pub clip_seconds: u32,
+
+    #[arg(long, default_value_t = DEFAULT_SCALE, value_parser = clap::value_parser!(u32).range(1..=6), help = "Window size as a multiple of 240x160")]
+    pub scale: u32,
+
+    #[arg(long, value_enum, help = "Upscaling filter for the picture [default: nearest]")]
+    pub filter: Option<VideoFilter>,
+
+    #[arg(long, value_name = "DEGREES", default_value_t = 0, value_parser = parse_rotation, help = "Turn the picture clockwise by 90, 180 or 270 degrees, for a monitor on its side")]
+    pub rotation: u32,
+
+    #[arg(long, overrides_with = "no_mirror", help = "Mirror the picture left to right")]
+    pub mirror: bool,
+
+    #[arg(long, overrides_with = "mirror", help = "Don't mirror the picture, whatever the config file says")]
+    no_mirror: bool,
+
+    #[arg(long, overrides_with = "no_integer_scaling", help = "Only scale the picture by whole numbers, keeping pixels even")]
+    pub integer_scaling: bool,
+
+    #[arg(long, overrides_with = "integer_scaling", help = "Scale the picture to fill the window, whatever the config file says")]
+    no_integer_scaling: bool,
+
+    #[arg(long, overrides_with = "no_fullscreen", help = "Start in borderless fullscreen")]
+    pub fullscreen: bool,
+
+    #[arg(long, overrides_with = "fullscreen", help = "Start in a window, whatever the config file says")]
+    no_fullscreen: bool,
+
+    #[arg(long, help = "Run without a window or audio device")]
+    pub headless: bool,
+
+    #[arg(long, value_name = "N", help = "Run N frames as fast as possible, then exit; implies --headless")]
+    pub frames: Option<u32>,
+
+    #[arg(long, value_name = "PATH", requires = "frames", help = "Save the last of the --frames frames as a PNG")]
+    pub screenshot: Option<PathBuf>,
+
+    #[arg(long, help = "Run as fast as possible instead of at the GBA's frame rate")]
+    pub unthrottled: bool,
+
+    #[arg(long, value_name = "N", default_value = "0", value_parser = str::parse::<FrameSkip>, help = "Frames left undrawn between each one shown, 0 to 4, or auto to skip only when running behind")]
+    pub frame_skip: FrameSkip,
+
+    #[arg(long, value_name = "N", default_value_t = 4, help = "Speed limit while fast-forwarding, as a multiple of normal speed; 0 for none")]
+    pub fast_forward_speed: u32,
+
+    #[arg(long, value_name = "N", default_value_t = 0, help = "Frames left undrawn between each one shown while fast-forwarding")]
+    pub fast_forward_skip: u32,
+
+    #[arg(long, overrides_with = "no_show_fps", help = "Show the frame rate and emulation speed over the picture")]
+    pub show_fps: bool,
+
+    #[arg(long, overrides_with = "show_fps", help = "Hide the frame rate, whatever the config file says")]
+    no_show_fps: bool,
+
+    #[arg(long, overrides_with = "no_auto_save", help = "Save a state when the window is closed and offer to resume from it next time")]
+    pub auto_save: bool,
+
+    #[arg(long, overrides_with = "auto_save", help = "Don't save a state on exit, whatever the config file says")]
+    no_auto_save: bool,
+
+    #[arg(long, overrides_with = "no_rewind", help = "Keep recent states so holding the rewind key steps back in time")]
+    pub rewind: bool,
+
+    #[arg(long, overrides_with = "rewind", help = "Don't keep states for rewinding, whatever the config file says")]
+    no_rewind: bool,
+
+    #[arg(long, value_name = "MB", default_value_t = DEFAULT_REWIND_BUFFER_MB, help = "Memory kept for rewinding; the oldest states are dropped past it")]
+    pub rewind_buffer: usize,
+
+    #[arg(long, overrides_with = "idle_skip", help = "Run idle loops instruction by instruction")]
+    pub no_idle_skip: bool,
+
+    #[arg(long, overrides_with = "no_idle_skip", help = "Skip idle loops, whatever the config file says")]
+    idle_skip: bool,
+
+    #[arg(long, value_name = "FILTER", value_parser = ["none", "linear", "cubic", "lowpass"], help = "Resampling filter for the audio output")]
+    pub audio_filter: Option<String>,
+
+    #[arg(long, value_name = "PATH", help = "Record the audio output to a WAV file")]
+    pub dump_wav: Option<PathBuf>,
+
+    #[arg(long, requires = "dump_wav", help = "Also record one WAV file per sound channel")]
+    pub dump_stems: bool,
+
+    #[arg(long, value_name = "ADDR", help = "Wait for a link cable partner on ADDR, as player 1")]
+    pub link_host: Option<String>,
+
+    #[arg(long, value_name = "ADDR", conflicts_with = "link_host", help = "Join the link cable partner hosting on ADDR")]
+    pub link_connect: Option<String>,
+
+    #[arg(long, help = "Pick a key or button for each input in turn and save them to the config file")]
+    pub rebind: bool,
+
+    #[cfg(feature = "audio")]
+    #[arg(long, value_name = "NAME", help = "Audio output device, matched by part of its name")]
+    pub audio_device: Option<String>,
+
+    #[cfg(feature = "audio")]
+    #[arg(long, value_name = "MS", default_value_t = DEFAULT_LATENCY_MS, help = "Audio buffer length")]
+    pub audio_latency: u32,
+
+    #[cfg(feature = "audio")]
+    #[arg(long, value_name = "PERCENT", default_value_t = MAX_VOLUME, value_parser = clap::value_parser!(u32).range(0..=MAX_VOLUME as i64), help = "Output volume")]
+    pub volume: u32,
+
+    #[cfg(feature = "audio")]
+    #[arg(long, value_enum, default_value = "audio", help = "What paces the frames: the sound card or the wall clock")]
+    pub sync: SyncMode,
+
+    #[cfg(feature = "audio")]
+    #[arg(long, help = "List the audio output devices and exit")]
+    pub list_audio_devices: bool,
+
+    // ids of the arguments given on the command line, which the config file
+    // leaves alone
+    #[arg(skip)]
+    given: BTreeSet<String>,
+}
+
+fn parse_rotation(text: &str) -> Result<u32, String> {
+    match text.parse() {
+        Ok(degrees @ (0 | 90 | 180 | 270)) => Ok(degrees),
+        _ => Err("expected 0, 90, 180 or 270".to_string()),
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "List recently played ROMs, or run one by its number")]
+    Recent {
+        #[arg(help = "Number of the ROM in the list to run")]
+        number: Option<usize>,
+    },
+}
+
+impl Cli {
+    pub fn parse_args() -> Self {
+        let matches = Cli::command().get_matches();
+        let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        cli.given = matches
+            .ids()
+            .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.to_string())
+            .collect();
+        cli
+    }
+
+    fn given(&self, id: &str) -> bool {
+        self.given.contains(id)
+    }
+
+    // the config file's value for a switch, unless the switch or its
+    // opposite was given
+    fn switch(&self, id: &str, opposite: &str, value: Option<bool>) -> Option<bool> {
+        value.filter(|_| !self.given(id) && !self.given(opposite))
+    }
+
+    // Takes each setting from the config file unless its flag was given.
+    pub fn apply_config(&mut self, config: &Config) {
+        let video = &config.video;
+        if !self.given("scale")
+            && let Some(scale) = video.scale
+        {
+            self.scale = scale.clamp(1, 6);
+        }
+        if !self.given("rotation")
+            && let Some(degrees) = video.rotation
+        {
+            match Orientation::new(degrees, false) {
+                Some(_) => self.rotation = degrees % 360,
+                None => println!("Ignoring rotation {} in the config file: not a multiple of 90", degrees),
+            }
+        }
+        if let Some(on) = self.switch("mirror", "no_mirror", video.mirror) {
+            self.mirror = on;
+        }
+        if let Some(on) = self.switch("integer_scaling", "no_integer_scaling", video.integer_scaling) {
+            self.integer_scaling = on;
+        }
+        if let Some(on) = self.switch("fullscreen", "no_fullscreen", video.fullscreen) {
+            self.fullscreen = on;
+        }
+        if let Some(on) = self.switch("show_fps", "no_show_fps", video.show_fps) {
+            self.show_fps = on;
+        }
+        if !self.given("clip_seconds")
+            && let Some(seconds) = video.clip_seconds
+        {
+            self.clip_seconds = seconds;
+        }
+
+        let paths = &config.paths;
+        if self.bios.is_none() {
+            self.bios = paths.bios.clone();
+        }
+        if self.save_dir.is_none() {
+            self.save_dir = paths.save_dir.clone();
+        }
+        if !self.given("screenshot_dir")
+            && let Some(dir) = &paths.screenshot_dir
+        {
+            self.screenshot_dir = dir.clone();
+        }
+        if !self.given("recording_dir")
+            && let Some(dir) = &paths.recording_dir
+        {
+            self.recording_dir = dir.clone();
+        }
+
+        let emulation = &config.emulation;
+        if !self.given("frame_skip")
+            && let Some(text) = &emulation.frame_skip
+        {
+            match text.parse() {
+                Ok(frame_skip) => self.frame_skip = frame_skip,
+                Err(err) => println!("Ignoring frame_skip {:?} in the config file: {}", text, err),
+            }
+        }
+        if let Some(on) = self.switch("idle_skip", "no_idle_skip", emulation.idle_skip) {
+            self.no_idle_skip = !on;
+        }
+        if !self.given("save_flush")
+            && let Some(policy) = emulation.save_flush
+        {
+            self.save_flush = policy;
+        }
+        if !self.given("save_interval")
+            && let Some(seconds) = emulation.save_interval
+        {
+            self.save_interval = seconds;
+        }
+        if let Some(on) = self.switch("auto_save", "no_auto_save", emulation.auto_save) {
+            self.auto_save = on;
+        }
+        if let Some(on) = self.switch("rewind", "no_rewind", emulation.rewind) {
+            self.rewind = on;
+        }
+        if !self.given("rewind_buffer")
+            && let Some(megabytes) = emulation.rewind_buffer
+        {
+            self.rewind_buffer = megabytes;
+        }
+        if !self.given("fast_forward_speed")
+            && let Some(speed) = emulation.fast_forward_speed
+        {
+            self.fast_forward_speed = speed;
+        }
+        if !self.given("fast_forward_skip")
+            && let Some(skip) = emulation.fast_forward_skip
+        {
+            self.fast_forward_skip = skip;
+        }
+
+        let audio = &config.audio;
+        if self.audio_filter.is_none()
+            && let Some(name) = &audio.filter
+        {
+            match AudioFilter::from_name(name) {
+                Some(_) => self.audio_filter = Some(name.clone()),
+                None => println!("Ignoring unknown audio filter {:?} in the config file", name),
+            }
+        }
+        #[cfg(feature = "audio")]
+        {
+            if self.audio_device.is_none() {
+                self.audio_device = audio.device.clone();
+            }
+            if !self.given("audio_latency")
+                && let Some(latency) = audio.latency_ms
+            {
+                self.audio_latency = latency;
+            }
+            if !self.given("volume")
+                && let Some(volume) = audio.volume
+            {
+                self.volume = volume.min(MAX_VOLUME);
+            }
+            if !self.given("sync")
+                && let Some(sync) = audio.sync
+            {
+                self.sync = sync;
+            }
+        }
+    }
+
+    pub fn headless(&self) -> bool {
+        self.headless || self.frames.is_some()
+    }
+
+    pub fn window_options(&self) -> WindowOptions {
+        WindowOptions {
+            scale: self.scale,
+            integer_scaling: self.integer_scaling,
+            fullscreen: self.fullscreen,
+            filter: self.filter.unwrap_or_default(),
+            orientation: Orientation::new(self.rotation, self.mirror).unwrap_or_default(),
+        }
+    }
+
+    pub fn fast_forward_speed(&self) -> f64 {
+        match self.fast_forward_speed {
+            0 => f64::INFINITY,
+            speed => speed as f64,
+        }
+    }
+
+    // battery save file for the ROM, if one is loaded
+    pub fn save_path(&self) -> Option<PathBuf> {
+        let rom = self.rom.as_ref()?;
+        let file = rom.with_extension("sav");
+        match &self.save_dir {
+            Some(dir) => Some(dir.join(file.file_name()?)),
+            None => Some(file),
+        }
+    }
+
+    // save state file for a slot, kept with the battery save
+    pub fn state_path(&self, slot: u32) -> Option<PathBuf> {
+        Some(self.save_path()?.with_extension(format!("ss{}", slot)))
+    }
+
+    // cheat codes for the ROM, kept beside it unless --cheats says otherwise
+    pub fn cheat_path(&self) -> Option<PathBuf> {
+        self.cheats.clone().or_else(|| Some(self.rom.as_ref()?.with_extension(CHEAT_EXTENSION)))
+    }
+
+    // state saved on exit by --auto-save
+    pub fn resume_path(&self) -> Option<PathBuf> {
+        Some(self.save_path()?.with_extension("resume"))
+    }
+
+    #[cfg(feature = "audio")]
+    pub fn audio_config(&self) -> AudioConfig {
+        AudioConfig {
+            device: self.audio_device.clone(),
+            latency_ms: self.audio_latency,
+            sync: self.sync,
+            volume: self.volume,
+        }
+    }
+}
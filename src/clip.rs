@@ -0,0 +1,90 @@
+// The last few seconds of frames, kept so a hotkey can save them as an
+// animated PNG. Frames are held as BGR555, 75 KiB each, so ten seconds
+// take about 45 MiB.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use afterimage::ppu::debug::Rgb;
+use afterimage::ppu::{FRAME_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// one GBA frame, 280896 / 16777216 s, within a nanosecond
+const FRAME_DELAY: (u16, u16) = (400, 23891);
+
+#[derive(Debug)]
+pub struct ClipBuffer {
+    // each drawn frame with the number of frames it stayed on screen for,
+    // counting the undrawn ones after it
+    frames: VecDeque<(Vec<u16>, u16)>,
+    // frames covered, drawn or not, and the most kept
+    length: usize,
+    capacity: usize,
+}
+
+impl ClipBuffer {
+    pub fn new(seconds: u32) -> Self {
+        let capacity = (seconds as f64 * FRAME_RATE).round() as usize;
+        ClipBuffer {
+            frames: VecDeque::with_capacity(capacity),
+            length: 0,
+            capacity,
+        }
+    }
+
+    // Adds a drawn frame, dropping the oldest once the buffer is full.
+    pub fn push(&mut self, frame: &[u16]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut buffer = None;
+        while self.length >= self.capacity {
+            let (oldest, shown) = self.frames.pop_front().unwrap();
+            self.length -= shown as usize;
+            buffer = Some(oldest);
+        }
+        let mut buffer = buffer.unwrap_or_else(|| Vec::with_capacity(frame.len()));
+        buffer.clear();
+        buffer.extend_from_slice(frame);
+        self.frames.push_back((buffer, 1));
+        self.length += 1;
+    }
+
+    // A frame ran without being drawn, leaving the last one on screen.
+    pub fn skipped(&mut self) {
+        if let Some((_, shown)) = self.frames.back_mut() {
+            *shown = shown.saturating_add(1);
+            self.length += 1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Writes the buffered frames, oldest first, as a looping APNG.
+    pub fn save_apng(&self, path: &Path) -> io::Result<()> {
+        if self.frames.is_empty() {
+            return Err(io::Error::other("no frames to save"));
+        }
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        let mut writer = encoder.write_header()?;
+        let mut data = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        for (frame, shown) in &self.frames {
+            writer.set_frame_delay(FRAME_DELAY.0.saturating_mul(*shown), FRAME_DELAY.1)?;
+            data.clear();
+            for &color in frame {
+                let Rgb { r, g, b } = Rgb::from_bgr555(color);
+                data.extend([r, g, b]);
+            }
+            writer.write_image_data(&data)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
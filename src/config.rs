@@ -0,0 +1,236 @@
+// User settings, kept in afterimage.toml. The file is looked for in the
+// working directory first, for portable setups, then in the platform config
+// directory (~/.config/afterimage on Linux). Missing fields take their
+// defaults, so the file only needs what a user changes, and a command line
+// flag beats the file:
+//
+//     [video]
+//     scale = 4
+//     filter = "bilinear"
+//
+//     [paths]
+//     save_dir = "/home/me/gba/saves"
+//
+// A [games.<key>] section, keyed by the game code from the ROM header or
+// the ROM's file name, overrides settings for that game alone:
+//
+//     [games.BPEE]
+//     rtc = true
+//     filter = "scale2x"
+//     input.keyboard.a = ["Space"]
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::battery::FlushPolicy;
+use crate::frontend::bindings::{Action, Bindings};
+use crate::frontend::filter::VideoFilter;
+use afterimage::memory::SaveType;
+#[cfg(feature = "audio")]
+use crate::audio_output::SyncMode;
+
+pub const CONFIG_FILE: &str = "afterimage.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // where the file was read from, and where it is saved back to
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub video: VideoSettings,
+    pub audio: AudioSettings,
+    pub paths: PathSettings,
+    pub emulation: EmulationSettings,
+    pub input: Bindings,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub games: BTreeMap<String, GameConfig>,
+}
+
+// The settings below mirror command line flags; an unset one leaves the
+// flag's default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<VideoFilter>,
+    // clockwise, in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integer_scaling: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fullscreen: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_fps: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip_seconds: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    // resampling filter, by the same names as --audio-filter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u32>,
+    // percent of full scale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u32>,
+    #[cfg(feature = "audio")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync: Option<SyncMode>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bios: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshot_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recording_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmulationSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_skip: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_save: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewind: Option<bool>,
+    // megabytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewind_buffer: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_flush: Option<FlushPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_interval: Option<u32>,
+    // "auto" or a number of frames, as for --frame-skip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_skip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fast_forward_speed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fast_forward_skip: Option<u32>,
+    // whether cartridges have a real time clock; unset to go by the ROM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtc: Option<bool>,
+}
+
+// Settings for one game; anything left out follows the global settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub save_type: Option<SaveType>,
+    pub rtc: Option<bool>,
+    pub filter: Option<VideoFilter>,
+    #[serde(skip_serializing_if = "InputOverrides::is_empty")]
+    pub input: InputOverrides,
+}
+
+// bindings replacing the global ones for the actions listed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputOverrides {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keyboard: BTreeMap<Action, Vec<String>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub gamepad: BTreeMap<Action, Vec<String>>,
+}
+
+impl InputOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.keyboard.is_empty() && self.gamepad.is_empty()
+    }
+}
+
+// The config file to use: the one given with --config, else one in the
+// working directory, else the one in the platform config directory.
+pub fn config_path(given: Option<&Path>) -> PathBuf {
+    if let Some(path) = given {
+        return path.to_path_buf();
+    }
+    let local = PathBuf::from(CONFIG_FILE);
+    if local.exists() {
+        return local;
+    }
+    match dirs::config_dir() {
+        Some(dir) => dir.join("afterimage").join(CONFIG_FILE),
+        None => local,
+    }
+}
+
+impl Config {
+    // A missing file gives the defaults; a broken one is an error.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::empty(path)),
+            Err(err) => return Err(err.into()),
+        };
+        let mut config: Config = toml::from_str(&text)?;
+        config.path = path.to_path_buf();
+        config.input.fill_defaults();
+        Ok(config)
+    }
+
+    // the defaults, to be saved at path
+    pub fn empty(path: &Path) -> Self {
+        Config { path: path.to_path_buf(), ..Config::default() }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = self.path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // a file kept beside the config file
+    pub fn sibling(&self, name: &str) -> PathBuf {
+        self.path.with_file_name(name)
+    }
+
+    // The section for a game, looked up by game code and then file name;
+    // an empty one if it has neither. Its filter and clock setting fall
+    // back to the global ones.
+    pub fn game(&self, code: Option<&str>, rom: Option<&Path>) -> GameConfig {
+        let file_name = rom.and_then(|rom| rom.file_name()).map(|name| name.to_string_lossy());
+        let mut game = code
+            .and_then(|code| self.games.get(code))
+            .or_else(|| file_name.and_then(|name| self.games.get(name.as_ref())))
+            .cloned()
+            .unwrap_or_default();
+        game.filter = game.filter.or(self.video.filter);
+        game.rtc = game.rtc.or(self.emulation.rtc);
+        game
+    }
+
+    // the global bindings with a game's overrides applied
+    pub fn bindings(&self, game: &GameConfig) -> Bindings {
+        let mut bindings = self.input.clone();
+        bindings.keyboard.extend(game.input.keyboard.clone());
+        bindings.gamepad.extend(game.input.gamepad.clone());
+        bindings
+    }
+}
@@ -0,0 +1,227 @@
+// Commands typed into the terminal while the game runs, with --console:
+// a RAM search, addresses watched on screen, turning what a search finds
+// into cheats, and the debugger. Lines are read on a thread of their own
+// and run between frames.
+
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use afterimage::Gba;
+
+use crate::cheat::{Cheats, Width};
+use crate::debugger::{self, Debugger};
+use crate::ram_search::{Comparison, RamSearch};
+
+// search results printed by list
+const LIST_LENGTH: usize = 20;
+
+const HELP: &str = "\
+search [8|16|32]          start a search of EWRAM and IWRAM, 8-bit by default
+changed, unchanged, increased, decreased
+                          keep the addresses that did since the last step
+= VALUE                   keep the addresses holding VALUE
+list                      show the addresses left
+watch ADDRESS [NAME]      show the value at ADDRESS on screen
+unwatch ADDRESS|all       stop showing one or all watched addresses
+cheat ADDRESS VALUE [NAME]
+                          hold ADDRESS at VALUE, adding it to the cheat file
+
+ADDRESS is hex, or #N for the Nth address in the last list; ADDRESS/16 or
+ADDRESS/32 reads it as a halfword or word instead of as the search does.
+
+Debugger, where ADDRESS is hex:";
+
+#[derive(Debug)]
+struct Watch {
+    address: u32,
+    width: Width,
+    name: String,
+}
+
+#[derive(Debug)]
+pub struct Console {
+    lines: Receiver<String>,
+    search: Option<RamSearch>,
+    // addresses shown by the last list, for #N
+    listed: Vec<u32>,
+    watches: Vec<Watch>,
+    pub debugger: Debugger,
+}
+
+impl Console {
+    pub fn start() -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("Console ready; type help for commands");
+        Console {
+            lines,
+            search: None,
+            listed: Vec::new(),
+            watches: Vec::new(),
+            debugger: Debugger::default(),
+        }
+    }
+
+    // Runs whatever has been typed since the last call.
+    pub fn run_pending(&mut self, gba: &mut Gba, cheats: &mut Cheats, cheat_path: Option<&Path>) {
+        while let Ok(line) = self.lines.try_recv() {
+            if let Err(err) = self.run(line.trim(), gba, cheats, cheat_path) {
+                println!("{}", err);
+            }
+        }
+    }
+
+    // The watched values, a line each, for the on-screen display.
+    pub fn watch_lines(&self, gba: &Gba) -> Vec<String> {
+        self.watches
+            .iter()
+            .map(|watch| format!("{} {}", watch.name, watch.width.read(gba, watch.address)))
+            .collect()
+    }
+
+    // Drops the search and watches, which belong to the game before.
+    pub fn forget_game(&mut self) {
+        self.search = None;
+        self.listed.clear();
+        self.watches.clear();
+        self.debugger = Debugger::default();
+    }
+
+    fn run(&mut self, line: &str, gba: &mut Gba, cheats: &mut Cheats, cheat_path: Option<&Path>) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(());
+        };
+        let rest: Vec<&str> = words.collect();
+        if self.debugger.run(command, &rest, gba)? {
+            return Ok(());
+        }
+        match (command, rest.as_slice()) {
+            ("help", _) => println!("{}\n{}", HELP, debugger::HELP),
+            ("search", []) => self.start_search(gba, Width::Byte),
+            ("search", [bits]) => {
+                let width = bits.parse().ok().and_then(Width::from_bits).ok_or("search takes 8, 16 or 32")?;
+                self.start_search(gba, width);
+            }
+            ("list", []) => {
+                let search = self.search.as_ref().ok_or("no search running")?;
+                let results = search.results(gba, LIST_LENGTH);
+                for (index, (address, now, before)) in results.iter().enumerate() {
+                    println!("#{:<3} {:08X}  {} (was {})", index + 1, address, now, before);
+                }
+                if search.len() > results.len() {
+                    println!("...and {} more", search.len() - results.len());
+                }
+                self.listed = results.iter().map(|&(address, _, _)| address).collect();
+            }
+            ("watch", [address, name @ ..]) => {
+                let (address, width) = self.address(address)?;
+                let name = if name.is_empty() { format!("{:08X}", address) } else { name.join(" ") };
+                self.watches.push(Watch { address, width, name });
+            }
+            ("unwatch", ["all"]) => self.watches.clear(),
+            ("unwatch", [address]) => {
+                let (address, _) = self.address(address)?;
+                self.watches.retain(|watch| watch.address != address);
+            }
+            ("cheat", [address, value, name @ ..]) => {
+                let path = cheat_path.ok_or("no game loaded to keep cheats for")?;
+                let (address, width) = self.address(address)?;
+                let value = number(value)?;
+                let name = if name.is_empty() { format!("{:08X} = {}", address, value) } else { name.join(" ") };
+                cheats
+                    .add_write(path, &name, address, width, value)
+                    .map_err(|err| format!("Could not add the cheat: {}", err))?;
+                println!("Added cheat \"{}\" to {}", name, path.display());
+            }
+            (comparison, words) => {
+                let value = words.first().map(|word| number(word)).transpose()?;
+                let comparison = Comparison::parse(comparison, value)
+                    .filter(|_| words.len() <= 1)
+                    .ok_or_else(|| format!("Unknown command \"{}\"; type help for a list", line))?;
+                let search = self.search.as_mut().ok_or("no search running")?;
+                search.narrow(gba, comparison);
+                println!("{} addresses left", search.len());
+            }
+        }
+        Ok(())
+    }
+
+    fn start_search(&mut self, gba: &Gba, width: Width) {
+        let search = RamSearch::new(gba, width);
+        println!("Searching {} addresses", search.len());
+        self.search = Some(search);
+        self.listed.clear();
+    }
+
+    // An address as typed, and the width to read it at: the search's
+    // unless a /BITS suffix gives one.
+    fn address(&self, text: &str) -> Result<(u32, Width), String> {
+        let (text, bits) = match text.split_once('/') {
+            Some((text, bits)) => (text, Some(bits)),
+            None => (text, None),
+        };
+        let address = match text.strip_prefix('#') {
+            Some(index) => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| self.listed.get(index.checked_sub(1)?))
+                .copied()
+                .ok_or_else(|| format!("{} is not in the last list", text))?,
+            None => u32::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("{} is not an address", text))?,
+        };
+        let width = match bits {
+            Some(bits) => bits.parse().ok().and_then(Width::from_bits).ok_or("widths are 8, 16 or 32")?,
+            None => self.search.as_ref().map_or(Width::Byte, |search| search.width),
+        };
+        Ok((address, width))
+    }
+}
+
+// decimal, or hex with 0x
+pub fn number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("{} is not a number", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn console() -> Console {
+        Console {
+            lines: mpsc::channel().1,
+            search: None,
+            listed: Vec::new(),
+            watches: Vec::new(),
+            debugger: Debugger::default(),
+        }
+    }
+
+    #[test]
+    fn listed_results_can_be_watched_by_number() {
+        let mut console = console();
+        let mut gba = Gba::new();
+        let mut cheats = Cheats::default();
+        console.run("search", &mut gba, &mut cheats, None).unwrap();
+        gba.memory.ewram[0x10] = 5;
+        gba.memory.iwram[0x20] = 7;
+        console.run("increased", &mut gba, &mut cheats, None).unwrap();
+        console.run("list", &mut gba, &mut cheats, None).unwrap();
+        console.run("watch #2 LIVES", &mut gba, &mut cheats, None).unwrap();
+        console.run("watch #1/16", &mut gba, &mut cheats, None).unwrap();
+        assert_eq!(console.watch_lines(&gba), ["LIVES 7", "02000010 5"]);
+        assert!(console.run("watch #3", &mut gba, &mut cheats, None).is_err());
+    }
+}
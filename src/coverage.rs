@@ -0,0 +1,117 @@
+// Which parts of the cartridge ROM have run as code, in which state, for
+// telling code from data and for seeing how much of a test ROM the CPU
+// gets through. The map has a byte of flags for each halfword of the ROM,
+// an ARM instruction marking both of its halfwords; code run from RAM or
+// the BIOS isn't counted, and the ROM's mirrors all count as the first.
+
+use std::io::{self, Write};
+
+use crate::breakpoints::InstructionSet;
+
+// flags in each byte of the map
+pub const ARM: u8 = 1;
+pub const THUMB: u8 = 2;
+
+const ROM_START: u32 = 0x0800_0000;
+const ROM_END: u32 = 0x0DFF_FFFF;
+const ROM_MASK: u32 = 0x01FF_FFFF;
+
+// A run of halfwords with the same flags, as ROM addresses at 0x08000000,
+// end inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoveredRange {
+    pub start: u32,
+    pub end: u32,
+    pub flags: u8,
+}
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    // None while not recording
+    map: Option<Vec<u8>>,
+}
+
+impl Coverage {
+    // Starts recording afresh for a ROM of rom_length bytes.
+    pub fn start(&mut self, rom_length: usize) {
+        self.map = Some(vec![0; rom_length.div_ceil(2)]);
+    }
+
+    pub fn stop(&mut self) {
+        self.map = None;
+    }
+
+    pub fn recording(&self) -> bool {
+        self.map.is_some()
+    }
+
+    // a byte of flags per ROM halfword, empty while not recording
+    pub fn map(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or_default()
+    }
+
+    pub(crate) fn record(&mut self, address: u32, set: InstructionSet) {
+        let Some(map) = &mut self.map else {
+            return;
+        };
+        if !(ROM_START..=ROM_END).contains(&address) {
+            return;
+        }
+        let index = ((address & ROM_MASK) >> 1) as usize;
+        let (flag, halfwords) = match set {
+            InstructionSet::Arm => (ARM, 2),
+            InstructionSet::Thumb => (THUMB, 1),
+        };
+        for flags in map.iter_mut().skip(index).take(halfwords) {
+            *flags |= flag;
+        }
+    }
+
+    // Bytes of ROM run as ARM code and as Thumb code; a halfword run both
+    // ways counts in both.
+    pub fn totals(&self) -> (usize, usize) {
+        let count = |flag| self.map().iter().filter(|&&flags| flags & flag != 0).count() * 2;
+        (count(ARM), count(THUMB))
+    }
+
+    // The runs of code, in address order.
+    pub fn ranges(&self) -> Vec<CoveredRange> {
+        let mut ranges: Vec<CoveredRange> = Vec::new();
+        for (index, &flags) in self.map().iter().enumerate() {
+            let address = ROM_START + index as u32 * 2;
+            match ranges.last_mut() {
+                Some(last) if last.flags == flags && last.end + 1 == address => last.end = address + 1,
+                _ if flags != 0 => ranges.push(CoveredRange { start: address, end: address + 1, flags }),
+                _ => {}
+            }
+        }
+        ranges
+    }
+
+    // The ranges as text, after a summary line:
+    //
+    //     # 1024 of 4194304 ROM bytes run, 768 as ARM and 256 as Thumb
+    //     08000000-080000BF arm
+    //     080000C0-080000FF thumb
+    pub fn write_ranges(&self, out: &mut impl Write) -> io::Result<()> {
+        let (arm, thumb) = self.totals();
+        let run = self.map().iter().filter(|&&flags| flags != 0).count() * 2;
+        writeln!(
+            out,
+            "# {} of {} ROM bytes run, {} as ARM and {} as Thumb",
+            run,
+            self.map().len() * 2,
+            arm,
+            thumb
+        )?;
+        for range in self.ranges() {
+            let state = match range.flags {
+                ARM => "arm",
+                THUMB => "thumb",
+                _ => "arm+thumb",
+            };
+            writeln!(out, "{:08X}-{:08X} {}", range.start, range.end, state)?;
+        }
+        Ok(())
+    }
+}
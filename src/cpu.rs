@@ -1,6 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bios;
 use crate::memory::Memory;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Cpu {
     pub registers: [u32; 13],
     pub sp: u32,
@@ -10,9 +13,17 @@ pub struct Cpu {
     pub spsr: [u32; 5],
     pub mode: CpuMode,
     pub thumb_mode: bool,
+    // SP and LR of the modes not currently active, by bank_index
+    pub banked: [(u32, u32); 6],
+    // stopped until an enabled interrupt is requested
+    pub halted: bool,
+    // IRQ flags an IntrWait call is waiting for
+    pub intr_wait: Option<u16>,
+    // cycles spent on the instruction being executed beyond its fetch
+    step_cycles: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CpuMode {
     User = 0x10,
     Fiq = 0x11,
@@ -23,6 +34,21 @@ pub enum CpuMode {
     System = 0x1F,
 }
 
+impl CpuMode {
+    // invalid mode bits are treated as System mode
+    pub fn from_bits(cpsr: u32) -> Self {
+        match cpsr & 0x1F {
+            0x10 => CpuMode::User,
+            0x11 => CpuMode::Fiq,
+            0x12 => CpuMode::Irq,
+            0x13 => CpuMode::Supervisor,
+            0x17 => CpuMode::Abort,
+            0x1B => CpuMode::Undefined,
+            _ => CpuMode::System,
+        }
+    }
+}
+
 impl Cpu {
     pub fn new() -> Self {
         Cpu {
@@ -34,23 +60,75 @@ impl Cpu {
             spsr: [0; 5],
             mode: CpuMode::System,
             thumb_mode: false,
+            // stack pointers as the BIOS leaves them for IRQ and SVC mode
+            banked: [(0, 0), (0, 0), (0x03007FA0, 0), (0x03007FE0, 0), (0, 0), (0, 0)],
+            halted: false,
+            intr_wait: None,
+            step_cycles: 0,
         }
     }
 
-    pub fn step(&mut self, memory: &mut Memory) {
+    pub fn irq_enabled(&self) -> bool {
+        self.cpsr & 0x80 == 0
+    }
+
+    // Takes the IRQ exception: the return address goes to LR_irq and the
+    // old CPSR to SPSR_irq, then execution continues at the IRQ vector.
+    pub fn enter_irq(&mut self) {
+        let cpsr = (self.cpsr & !0x20) | if self.thumb_mode { 0x20 } else { 0 };
+        // pc already points at the next instruction; handlers return with
+        // subs pc, lr, #4
+        let return_address = self.pc + 4;
+
+        self.switch_mode(CpuMode::Irq);
+        self.spsr[spsr_index(CpuMode::Irq)] = cpsr;
+        self.lr = return_address;
+        self.cpsr = (self.cpsr | 0x80) & !0x20;
+        self.thumb_mode = false;
+        self.pc = bios::IRQ_VECTOR;
+    }
+
+    // returns from an exception by restoring CPSR from the current SPSR
+    pub fn restore_cpsr(&mut self) {
+        let cpsr = self.spsr[spsr_index(self.mode)];
+        self.switch_mode(CpuMode::from_bits(cpsr));
+        self.cpsr = cpsr;
+        self.thumb_mode = cpsr & 0x20 != 0;
+    }
+
+    pub fn switch_mode(&mut self, mode: CpuMode) {
+        self.banked[bank_index(self.mode)] = (self.sp, self.lr);
+        (self.sp, self.lr) = self.banked[bank_index(mode)];
+        self.mode = mode;
+        self.cpsr = (self.cpsr & !0x1F) | mode as u32;
+    }
+
+    // Executes one instruction and returns the cycles it took, counting
+    // memory wait states and pipeline refills.
+    pub fn step(&mut self, memory: &mut Memory) -> u32 {
+        let width = if self.thumb_mode { 2 } else { 4 };
         let instruction = if self.thumb_mode {
             memory.read_u16(self.pc) as u32
         } else {
             memory.read_u32(self.pc)
         };
+        self.step_cycles = memory.access_cycles(self.pc, width, true);
 
-        self.pc += if self.thumb_mode { 2 } else { 4 };
+        self.pc += width;
+        let next_pc = self.pc;
         
         if self.thumb_mode {
             self.execute_thumb(instruction as u16, memory);
         } else {
             self.execute_arm(instruction, memory);
         }
+
+        // a taken branch flushes the pipeline, refetching from the target
+        if self.pc != next_pc {
+            let width = if self.thumb_mode { 2 } else { 4 };
+            self.step_cycles += memory.access_cycles(self.pc, width, false) + memory.access_cycles(self.pc, width, true);
+        }
+        self.step_cycles
     }
 
     fn execute_arm(&mut self, instruction: u32, memory: &mut Memory) {
@@ -63,6 +141,19 @@ impl Cpu {
             return;
         }
 
+        if (instruction >> 24) & 0xF == 0xF {
+            bios::software_interrupt(self, memory, (instruction >> 16) & 0xFF);
+            return;
+        }
+
+        // BX
+        if instruction & 0x0FFF_FFF0 == 0x012F_FF10 {
+            let target = self.get_register((instruction & 0xF) as usize);
+            self.thumb_mode = target & 1 != 0;
+            self.pc = if self.thumb_mode { target & !1 } else { target & !3 };
+            return;
+        }
+
         if (instruction >> 26) & 0x3 == 0x1 {
             self.execute_single_data_transfer(instruction, memory);
             return;
@@ -134,15 +225,21 @@ impl Cpu {
             base
         };
 
+        // loads spend an extra internal cycle writing the register
+        let width = if byte { 1 } else { 4 };
+        self.step_cycles += memory.access_cycles(address, width, false) + load as u32;
+
         if load {
             let value = if byte {
                 memory.read_u8(address) as u32
             } else {
                 memory.read_u32(address)
             };
+            memory.watch_read(address, width, value);
             self.set_register(rd, value);
         } else {
             let value = self.get_register(rd);
+            memory.watch_write(address, width, if byte { value & 0xFF } else { value });
             if byte {
                 memory.write_u8(address, value as u8);
             } else {
@@ -238,10 +335,14 @@ impl Cpu {
         }
     }
 
-    fn execute_thumb(&mut self, instruction: u16, _memory: &mut Memory) {
+    fn execute_thumb(&mut self, instruction: u16, memory: &mut Memory) {
         let opcode = (instruction >> 11) & 0x1F;
         
         match opcode {
+            // SWI shares its encoding space with condition 0xF
+            0x1B if instruction >> 8 == 0xDF => {
+                bios::software_interrupt(self, memory, (instruction & 0xFF) as u32);
+            }
             0x1C => {
                 let mut offset = instruction & 0x7FF;
                 // Sign extend 11-bit offset
@@ -289,3 +390,32 @@ impl Cpu {
         }
     }
 }
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
+}
+
+// User and System mode share registers
+fn bank_index(mode: CpuMode) -> usize {
+    match mode {
+        CpuMode::User | CpuMode::System => 0,
+        CpuMode::Fiq => 1,
+        CpuMode::Irq => 2,
+        CpuMode::Supervisor => 3,
+        CpuMode::Abort => 4,
+        CpuMode::Undefined => 5,
+    }
+}
+
+// spsr slots for the exception modes; User and System have none
+fn spsr_index(mode: CpuMode) -> usize {
+    match mode {
+        CpuMode::Fiq => 0,
+        CpuMode::Supervisor => 1,
+        CpuMode::Abort => 2,
+        CpuMode::Irq => 3,
+        _ => 4,
+    }
+}
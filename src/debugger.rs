@@ -0,0 +1,382 @@
+// The debugger, taking commands from the console: --debug, the debugger
+// hotkey, a breakpoint or a watchpoint stops the game, and from there it
+// runs an instruction or a frame at a time, or on to a breakpoint, showing
+// the registers, memory and the code around the PC. Frames the debugger runs
+// itself skip the frontend's work after a frame, like cheats and movie
+// recording.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use afterimage::Gba;
+use afterimage::breakpoints::{Condition, InstructionSet};
+use afterimage::call_stack::{self, FrameKind};
+use afterimage::disasm;
+use afterimage::watchpoints::{AccessKind, WatchKind, WatchpointHit};
+
+use crate::console::number;
+use crate::profile;
+
+// instructions shown either side of the PC by disassemble
+const LISTING_CONTEXT: u32 = 4;
+// instructions shown by disassemble ADDRESS
+const LISTING_LENGTH: u32 = 10;
+// bytes shown by memory ADDRESS
+const DUMP_LENGTH: u32 = 64;
+// stack words backtrace looks through when no calls have been tracked
+const STACK_SCAN_WORDS: u32 = 256;
+// functions and instructions listed by profile
+const PROFILE_ROWS: usize = 16;
+
+pub const HELP: &str = "\
+stop                      stop the game to debug it
+continue, c               let it run again, until a breakpoint
+step, s [N]               run one or N instructions
+frame, f [N]              run one or N frames, staying stopped
+break, b [ADDRESS [arm|thumb] [if CONDITION]]
+                          stop when the PC reaches ADDRESS, in either state
+                          or only the one given, and CONDITION holds, e.g.
+                          r0 == 0x42 && [0x03001234]u16 > 100; alone, list
+                          them
+delete ADDRESS|all        remove one or all breakpoints
+watchpoint, wp [RANGE [read|write|access|change]]
+                          stop after the CPU or DMA writes RANGE, or reads
+                          it, either, or writes it a new value; alone, list
+                          them. RANGE is ADDRESS or ADDRESS-END
+wpdelete RANGE|all        remove the watchpoints on RANGE, or all of them
+backtrace, bt [stack]     show the calls made to get to the PC, as tracked
+                          since the console started; with stack, or when
+                          none were, guess them from the stack instead
+registers, r              show the CPU registers
+coverage [PATH]           show how much of the ROM has run as code, or
+                          write the map to PATH as --coverage does; starts
+                          recording if it wasn't
+profile [start|stop|reset]
+                          show where the cycles have gone since the
+                          profiler started, or start, stop or clear it
+memory, x ADDRESS [LENGTH]
+                          show LENGTH bytes from ADDRESS, 64 by default
+disassemble, d [ADDRESS] [COUNT]
+                          show COUNT instructions from ADDRESS, in the CPU's
+                          current state; alone, the ones around the PC";
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    // the game only runs as commands say while set
+    pub stopped: bool,
+}
+
+impl Debugger {
+    pub fn stop(&mut self, gba: &Gba) {
+        self.stopped = true;
+        println!("Stopped at {}", instruction_line(gba, gba.cpu.pc, gba.cpu.thumb_mode).0);
+    }
+
+    // Stops at the breakpoint or watchpoint the last run reached, if it
+    // reached one.
+    pub fn check_hit(&mut self, gba: &Gba) -> bool {
+        if let Some(hit) = gba.memory.watchpoints.hit() {
+            println!("Watchpoint: {}", describe_hit(&hit));
+        } else if gba.breakpoints.hit().is_some() {
+            print!("Breakpoint: ");
+        } else {
+            return false;
+        }
+        self.stop(gba);
+        true
+    }
+
+    // Runs command if it is one of the debugger's, returning false if it
+    // isn't.
+    pub fn run(&mut self, command: &str, args: &[&str], gba: &mut Gba) -> Result<bool, String> {
+        if matches!(command, "step" | "s" | "frame" | "f") && !self.stopped {
+            return Err("stop the game first".to_string());
+        }
+        match (command, args) {
+            ("stop", []) => self.stop(gba),
+            ("continue" | "c", []) => self.stopped = false,
+            ("step" | "s", args) => {
+                for _ in 0..count(args)? {
+                    // runs the instruction even if it has a breakpoint
+                    gba.breakpoints.skip(gba.cpu.pc);
+                    gba.step_instruction();
+                    if self.check_hit(gba) {
+                        return Ok(true);
+                    }
+                }
+                println!("{}", instruction_line(gba, gba.cpu.pc, gba.cpu.thumb_mode).0);
+            }
+            ("frame" | "f", args) => {
+                for _ in 0..count(args)? {
+                    gba.run_frame();
+                    if self.check_hit(gba) {
+                        return Ok(true);
+                    }
+                }
+                println!("At {}", instruction_line(gba, gba.cpu.pc, gba.cpu.thumb_mode).0);
+            }
+            ("break" | "b", []) => {
+                let list = gba.breakpoints.list();
+                if list.is_empty() {
+                    println!("No breakpoints");
+                }
+                for (address, breakpoint) in list {
+                    let mut line = format!("{:08X}", address);
+                    if let Some(set) = breakpoint.set {
+                        line += &format!(" ({:?} only)", set);
+                    }
+                    if let Some(condition) = &breakpoint.condition {
+                        line += &format!(" if {}", condition);
+                    }
+                    println!("{}", line);
+                }
+            }
+            ("break" | "b", [address, rest @ ..]) => {
+                let (set, condition) = match rest.iter().position(|&arg| arg == "if") {
+                    Some(index) => (&rest[..index], Some(Condition::parse(&rest[index + 1..].join(" "))?)),
+                    None => (rest, None),
+                };
+                let set = match set {
+                    [] => None,
+                    ["arm"] => Some(InstructionSet::Arm),
+                    ["thumb"] => Some(InstructionSet::Thumb),
+                    _ => return Err("a breakpoint is for arm or thumb code, or either".to_string()),
+                };
+                gba.breakpoints.add(address_arg(address)?, set, condition);
+            }
+            ("delete", ["all"]) => gba.breakpoints.clear(),
+            ("delete", [address]) => {
+                let address = address_arg(address)?;
+                if !gba.breakpoints.remove(address) {
+                    return Err(format!("no breakpoint at {:08X}", address));
+                }
+            }
+            ("watchpoint" | "wp", []) => {
+                let list = gba.memory.watchpoints.list();
+                if list.is_empty() {
+                    println!("No watchpoints");
+                }
+                for watchpoint in list {
+                    println!("{}  {:?}", range_text(watchpoint.start, watchpoint.end), watchpoint.kind);
+                }
+            }
+            ("watchpoint" | "wp", [range, kind @ ..]) => {
+                let kind = match kind {
+                    [] | ["write"] => WatchKind::Write,
+                    ["read"] => WatchKind::Read,
+                    ["access"] => WatchKind::Access,
+                    ["change"] => WatchKind::Change,
+                    _ => return Err("a watchpoint is for read, write, access or change".to_string()),
+                };
+                let (start, end) = range_arg(range)?;
+                gba.memory.watchpoints.add(start, end, kind);
+            }
+            ("wpdelete", ["all"]) => gba.memory.watchpoints.clear(),
+            ("wpdelete", [range]) => {
+                let (start, end) = range_arg(range)?;
+                if !gba.memory.watchpoints.remove(start, end, None) {
+                    return Err(format!("no watchpoint on {}", range_text(start, end)));
+                }
+            }
+            ("backtrace" | "bt", []) if !gba.call_stack.frames().is_empty() => print_backtrace(gba),
+            ("backtrace" | "bt", [] | ["stack"]) => print_stack_scan(gba),
+            ("registers" | "r", []) => print_registers(gba),
+            ("coverage", []) if !gba.coverage.recording() => {
+                gba.coverage.start(gba.memory.rom.len());
+                println!("Recording coverage from now");
+            }
+            ("coverage", []) => {
+                let (arm, thumb) = gba.coverage.totals();
+                let rom = gba.memory.rom.len().max(1);
+                println!(
+                    "{} bytes run as ARM and {} as Thumb, {:.1}% of the ROM",
+                    arm,
+                    thumb,
+                    (arm + thumb) as f64 * 100.0 / rom as f64
+                );
+            }
+            ("profile", []) => {
+                if !gba.profiler.running() {
+                    println!("The profiler is stopped; profile start starts it");
+                }
+                profile::write_report(gba, &mut io::stdout().lock(), PROFILE_ROWS).map_err(|err| err.to_string())?;
+            }
+            ("profile", ["start"]) => gba.profiler.start(),
+            ("profile", ["stop"]) => gba.profiler.stop(),
+            ("profile", ["reset"]) => gba.profiler.reset(),
+            ("coverage", [path]) => {
+                if !gba.coverage.recording() {
+                    return Err("coverage isn't being recorded".to_string());
+                }
+                save_coverage(gba, Path::new(path)).map_err(|err| format!("could not write {}: {}", path, err))?;
+                println!("Saved coverage to {}", path);
+            }
+            ("memory" | "x", [address]) => print_memory(gba, address_arg(address)?, DUMP_LENGTH),
+            ("memory" | "x", [address, length]) => print_memory(gba, address_arg(address)?, number(length)?),
+            ("disassemble" | "d", []) => {
+                let width = if gba.cpu.thumb_mode { 2 } else { 4 };
+                let start = gba.cpu.pc.saturating_sub(LISTING_CONTEXT * width);
+                print_listing(gba, start, LISTING_CONTEXT * 2 + 1);
+            }
+            ("disassemble" | "d", [address]) => print_listing(gba, address_arg(address)?, LISTING_LENGTH),
+            ("disassemble" | "d", [address, count]) => print_listing(gba, address_arg(address)?, number(count)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+// The instruction at address as a listing line, and its length.
+fn instruction_line(gba: &Gba, address: u32, thumb: bool) -> (String, u32) {
+    let (text, length) = disasm::disassemble(&gba.memory, address, thumb);
+    let raw = match (thumb, length) {
+        (false, _) => format!("{:08X} ", gba.memory.read_u32(address)),
+        (true, 2) => format!("{:04X}     ", gba.memory.read_u16(address)),
+        (true, _) => format!(
+            "{:04X}{:04X} ",
+            gba.memory.read_u16(address),
+            gba.memory.read_u16(address.wrapping_add(2))
+        ),
+    };
+    (format!("{:08X}  {} {}", address, raw, text), length)
+}
+
+// Writes the coverage map, as a byte of flags per halfword for a .bin path
+// and as address ranges otherwise.
+pub fn save_coverage(gba: &Gba, path: &Path) -> io::Result<()> {
+    if path.extension().is_some_and(|extension| extension == "bin") {
+        return fs::write(path, gba.coverage.map());
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    gba.coverage.write_ranges(&mut out)?;
+    out.flush()
+}
+
+// innermost first, like GDB
+fn print_backtrace(gba: &Gba) {
+    println!("#0  {:08X}", gba.cpu.pc);
+    for (depth, frame) in gba.call_stack.frames().iter().rev().enumerate() {
+        let state = if frame.thumb { "Thumb" } else { "ARM" };
+        match frame.kind {
+            FrameKind::Call => println!(
+                "#{:<2} {:08X}  {}, called from {:08X}",
+                depth + 1,
+                frame.target,
+                state,
+                frame.call_site
+            ),
+            FrameKind::Interrupt => println!(
+                "#{:<2} {:08X}  interrupt handler, interrupting {:08X}",
+                depth + 1,
+                frame.target,
+                frame.call_site
+            ),
+        }
+    }
+}
+
+fn print_stack_scan(gba: &Gba) {
+    let found = call_stack::scan_stack(&gba.cpu, &gba.memory, STACK_SCAN_WORDS);
+    if found.is_empty() {
+        println!("No return addresses on the stack");
+        return;
+    }
+    println!("Return addresses on the stack, innermost first:");
+    for (slot, address) in found {
+        let call_site = if address & 1 != 0 { (address & !1) - 4 } else { address - 4 };
+        println!("sp+{:<4X} {:08X}  after the BL at {:08X}", slot - gba.cpu.sp, address, call_site);
+    }
+}
+
+// in the state the CPU is in now
+fn print_listing(gba: &Gba, start: u32, count: u32) {
+    let mut address = start;
+    for _ in 0..count {
+        let (line, length) = instruction_line(gba, address, gba.cpu.thumb_mode);
+        let marker = if address == gba.cpu.pc { "=>" } else { "  " };
+        println!("{} {}", marker, line);
+        address = address.wrapping_add(length);
+    }
+}
+
+fn print_registers(gba: &Gba) {
+    let cpu = &gba.cpu;
+    let values: Vec<u32> = cpu.registers.iter().copied().chain([cpu.sp, cpu.lr, cpu.pc]).collect();
+    for (row, chunk) in values.chunks(4).enumerate() {
+        let line: Vec<String> = chunk
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("{:<4}{:08X}", disasm::register((row * 4 + column) as u32), value))
+            .collect();
+        println!("{}", line.join("  "));
+    }
+    let flags: String = [(31, 'N'), (30, 'Z'), (29, 'C'), (28, 'V'), (7, 'I'), (6, 'F')]
+        .iter()
+        .map(|&(bit, name)| if cpu.cpsr >> bit & 1 != 0 { name } else { '-' })
+        .collect();
+    let state = if cpu.thumb_mode { "Thumb" } else { "ARM" };
+    println!("cpsr {:08X}  {}  {:?} mode, {}", cpu.cpsr, flags, cpu.mode, state);
+}
+
+// 16 bytes a line, with the ASCII beside them
+fn print_memory(gba: &Gba, address: u32, length: u32) {
+    for line in (0..length).step_by(16) {
+        let start = address.wrapping_add(line);
+        let bytes: Vec<u8> = (0..16.min(length - line))
+            .map(|offset| gba.memory.read_u8(start.wrapping_add(offset)))
+            .collect();
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let text: String = bytes
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        println!("{:08X}  {:<47}  {}", start, hex.join(" "), text);
+    }
+}
+
+// hex, with or without 0x
+fn address_arg(text: &str) -> Result<u32, String> {
+    u32::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("{} is not an address", text))
+}
+
+// ADDRESS or ADDRESS-END, inclusive
+fn range_arg(text: &str) -> Result<(u32, u32), String> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (address_arg(start)?, address_arg(end)?),
+        None => {
+            let address = address_arg(text)?;
+            (address, address)
+        }
+    };
+    if end < start {
+        return Err(format!("{} ends before it starts", text));
+    }
+    Ok((start, end))
+}
+
+fn range_text(start: u32, end: u32) -> String {
+    if start == end { format!("{:08X}", start) } else { format!("{:08X}-{:08X}", start, end) }
+}
+
+// e.g. "write of 0042 (was 0000), 16-bit at 03001000, by 08000124"
+fn describe_hit(hit: &WatchpointHit) -> String {
+    let digits = hit.size as usize * 2;
+    let access = match (hit.access, hit.old_value) {
+        (AccessKind::Write, Some(old)) => format!("write of {:0digits$X} (was {:0digits$X})", hit.value, old),
+        (AccessKind::Write, None) => format!("write of {:0digits$X}", hit.value),
+        (AccessKind::Read, _) => format!("read of {:0digits$X}", hit.value),
+    };
+    format!("{}, {}-bit at {:08X}, by {:08X}", access, hit.size * 8, hit.address, hit.pc)
+}
+
+// the optional repeat count of step and frame
+fn count(args: &[&str]) -> Result<u32, String> {
+    match args {
+        [] => Ok(1),
+        [count] => number(count),
+        _ => Err("too many arguments".to_string()),
+    }
+}
+
+
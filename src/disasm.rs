@@ -0,0 +1,310 @@
+// ARMv4T disassembly, for debuggers: both instruction sets in the usual
+// assembler syntax, with branch targets and PC-relative loads given as the
+// addresses they reach. Encodings the ARM7TDMI doesn't have come out as a
+// .word or .hword of the raw value.
+
+use crate::memory::Memory;
+
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "cs", "cc", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "", "nv",
+];
+const DATA_PROCESSING: [&str; 16] = [
+    "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc", "tst", "teq", "cmp", "cmn", "orr", "mov", "bic", "mvn",
+];
+const THUMB_ALU: [&str; 16] = [
+    "and", "eor", "lsl", "lsr", "asr", "adc", "sbc", "ror", "tst", "neg", "cmp", "cmn", "orr", "mul", "bic", "mvn",
+];
+const SHIFTS: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+// The instruction at address as text, and its length in bytes: 4 for ARM,
+// 2 for Thumb, or 4 for a Thumb BL, whose two halves are read together.
+pub fn disassemble(memory: &Memory, address: u32, thumb_state: bool) -> (String, u32) {
+    if !thumb_state {
+        return (arm(memory.read_u32(address), address), 4);
+    }
+    let instruction = memory.read_u16(address);
+    let second = memory.read_u16(address.wrapping_add(2));
+    if instruction >> 11 == 0x1E && second >> 11 == 0x1F {
+        let offset = sign_extend(instruction as u32 & 0x7FF, 11) << 12 | (second as u32 & 0x7FF) << 1;
+        let target = address.wrapping_add(4).wrapping_add(offset);
+        return (format!("bl 0x{:08X}", target), 4);
+    }
+    (thumb(instruction, address), 2)
+}
+
+pub fn register(index: u32) -> &'static str {
+    const NAMES: [&str; 16] = [
+        "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc",
+    ];
+    NAMES[index as usize & 0xF]
+}
+
+pub fn arm(instruction: u32, address: u32) -> String {
+    let i = instruction;
+    let cond = CONDITIONS[(i >> 28) as usize];
+    let bit = |n: u32| i >> n & 1 != 0;
+    let reg = |shift: u32| register(i >> shift & 0xF);
+
+    if i & 0x0FFF_FFF0 == 0x012F_FF10 {
+        return format!("bx{} {}", cond, reg(0));
+    }
+    if i & 0x0FC0_00F0 == 0x0000_0090 {
+        let s = if bit(20) { "s" } else { "" };
+        return if bit(21) {
+            format!("mla{}{} {}, {}, {}, {}", cond, s, reg(16), reg(0), reg(8), reg(12))
+        } else {
+            format!("mul{}{} {}, {}, {}", cond, s, reg(16), reg(0), reg(8))
+        };
+    }
+    if i & 0x0F80_00F0 == 0x0080_0090 {
+        let sign = if bit(22) { "s" } else { "u" };
+        let op = if bit(21) { "mlal" } else { "mull" };
+        let s = if bit(20) { "s" } else { "" };
+        return format!("{}{}{}{} {}, {}, {}, {}", sign, op, cond, s, reg(12), reg(16), reg(0), reg(8));
+    }
+    if i & 0x0FB0_0FF0 == 0x0100_0090 {
+        let b = if bit(22) { "b" } else { "" };
+        return format!("swp{}{} {}, {}, [{}]", cond, b, reg(12), reg(0), reg(16));
+    }
+    if i & 0x0E00_0090 == 0x0000_0090 && i & 0x60 != 0 {
+        let op = match (bit(20), i >> 5 & 3) {
+            (false, 1) => "strh",
+            (true, 1) => "ldrh",
+            (true, 2) => "ldrsb",
+            (true, 3) => "ldrsh",
+            _ => return format!(".word 0x{:08X}", i),
+        };
+        let offset = if bit(22) {
+            immediate_offset((i >> 4 & 0xF0) | (i & 0xF), bit(23))
+        } else {
+            format!("{}{}", if bit(23) { "" } else { "-" }, reg(0))
+        };
+        return format!("{}{} {}, {}", op, cond, reg(12), address_mode(reg(16), &offset, bit(24), bit(21)));
+    }
+    if i & 0x0FBF_0FFF == 0x010F_0000 {
+        return format!("mrs{} {}, {}", cond, reg(12), if bit(22) { "spsr" } else { "cpsr" });
+    }
+    if i & 0x0DB0_F000 == 0x0120_F000 {
+        let fields: String = [(19, 'f'), (18, 's'), (17, 'x'), (16, 'c')]
+            .iter()
+            .filter(|&&(n, _)| bit(n))
+            .map(|&(_, name)| name)
+            .collect();
+        let psr = if bit(22) { "spsr" } else { "cpsr" };
+        let source = if bit(25) { immediate((i & 0xFF).rotate_right((i >> 8 & 0xF) * 2)) } else { reg(0).to_string() };
+        return format!("msr{} {}_{}, {}", cond, psr, fields, source);
+    }
+    if i & 0x0C00_0000 == 0 {
+        let opcode = i >> 21 & 0xF;
+        let name = DATA_PROCESSING[opcode as usize];
+        let operand = if bit(25) {
+            immediate((i & 0xFF).rotate_right((i >> 8 & 0xF) * 2))
+        } else {
+            shifted_register(i)
+        };
+        return match opcode {
+            // the compares always set flags, without an s
+            0x8..=0xB => format!("{}{} {}, {}", name, cond, reg(16), operand),
+            0xD | 0xF => format!("{}{}{} {}, {}", name, cond, if bit(20) { "s" } else { "" }, reg(12), operand),
+            _ => format!("{}{}{} {}, {}, {}", name, cond, if bit(20) { "s" } else { "" }, reg(12), reg(16), operand),
+        };
+    }
+    if i & 0x0E00_0010 == 0x0600_0010 {
+        return format!(".word 0x{:08X}", i);
+    }
+    if i & 0x0C00_0000 == 0x0400_0000 {
+        let op = if bit(20) { "ldr" } else { "str" };
+        let b = if bit(22) { "b" } else { "" };
+        // writeback on a post-indexed access makes it a user mode one
+        let t = if !bit(24) && bit(21) { "t" } else { "" };
+        let text = if bit(25) {
+            let offset = format!("{}{}", if bit(23) { "" } else { "-" }, shifted_register(i));
+            address_mode(reg(16), &offset, bit(24), bit(21))
+        } else if i >> 16 & 0xF == 15 && bit(24) && !bit(21) {
+            let offset = i & 0xFFF;
+            let base = address.wrapping_add(8);
+            let target = if bit(23) { base.wrapping_add(offset) } else { base.wrapping_sub(offset) };
+            format!("[0x{:08X}]", target)
+        } else {
+            address_mode(reg(16), &immediate_offset(i & 0xFFF, bit(23)), bit(24), bit(21))
+        };
+        return format!("{}{}{}{} {}, {}", op, cond, b, t, reg(12), text);
+    }
+    if i & 0x0E00_0000 == 0x0800_0000 {
+        let op = if bit(20) { "ldm" } else { "stm" };
+        let mode = ["da", "ia", "db", "ib"][(i >> 23 & 3) as usize];
+        let writeback = if bit(21) { "!" } else { "" };
+        let user = if bit(22) { "^" } else { "" };
+        return format!("{}{}{} {}{}, {}{}", op, cond, mode, reg(16), writeback, register_list(i & 0xFFFF), user);
+    }
+    if i & 0x0E00_0000 == 0x0A00_0000 {
+        let op = if bit(24) { "bl" } else { "b" };
+        let target = address.wrapping_add(8).wrapping_add(sign_extend(i & 0xFF_FFFF, 24) << 2);
+        return format!("{}{} 0x{:08X}", op, cond, target);
+    }
+    if i & 0x0F00_0000 == 0x0F00_0000 {
+        return format!("swi{} {}", cond, immediate(i & 0xFF_FFFF));
+    }
+    format!(".word 0x{:08X}", i)
+}
+
+// A single Thumb halfword. The halves of a BL are only shown as one by
+// disassemble, which can read both.
+pub fn thumb(instruction: u16, address: u32) -> String {
+    let i = instruction as u32;
+    let low = |shift: u32| register(i >> shift & 7);
+    match i >> 13 {
+        0 if i >> 11 & 3 != 3 => {
+            let amount = i >> 6 & 0x1F;
+            let op = SHIFTS[(i >> 11 & 3) as usize];
+            // a shift right by 0 encodes one by 32
+            let amount = if amount == 0 && op != "lsl" { 32 } else { amount };
+            format!("{} {}, {}, #{}", op, low(0), low(3), amount)
+        }
+        0 => {
+            let op = if i >> 9 & 1 != 0 { "sub" } else { "add" };
+            let operand = if i >> 10 & 1 != 0 { format!("#{}", i >> 6 & 7) } else { low(6).to_string() };
+            format!("{} {}, {}, {}", op, low(0), low(3), operand)
+        }
+        1 => {
+            let op = ["mov", "cmp", "add", "sub"][(i >> 11 & 3) as usize];
+            format!("{} {}, {}", op, low(8), immediate(i & 0xFF))
+        }
+        2 if i >> 10 == 0x10 => format!("{} {}, {}", THUMB_ALU[(i >> 6 & 0xF) as usize], low(0), low(3)),
+        2 if i >> 10 == 0x11 => {
+            let rd = register((i >> 4 & 8) | (i & 7));
+            let rs = register(i >> 3 & 0xF);
+            match i >> 8 & 3 {
+                0 => format!("add {}, {}", rd, rs),
+                1 => format!("cmp {}, {}", rd, rs),
+                2 => format!("mov {}, {}", rd, rs),
+                _ => format!("bx {}", rs),
+            }
+        }
+        2 if i >> 11 == 0x09 => {
+            let target = (address.wrapping_add(4) & !2).wrapping_add((i & 0xFF) << 2);
+            format!("ldr {}, [0x{:08X}]", low(8), target)
+        }
+        2 => {
+            let op = if i >> 9 & 1 == 0 {
+                ["str", "strb", "ldr", "ldrb"][(i >> 10 & 3) as usize]
+            } else {
+                ["strh", "ldrsb", "ldrh", "ldrsh"][(i >> 10 & 3) as usize]
+            };
+            format!("{} {}, [{}, {}]", op, low(0), low(3), low(6))
+        }
+        3 => {
+            let (op, scale) = match i >> 11 & 3 {
+                0 => ("str", 4),
+                1 => ("ldr", 4),
+                2 => ("strb", 1),
+                _ => ("ldrb", 1),
+            };
+            format!("{} {}, [{}, {}]", op, low(0), low(3), immediate((i >> 6 & 0x1F) * scale))
+        }
+        4 if i >> 12 & 1 == 0 => {
+            let op = if i >> 11 & 1 != 0 { "ldrh" } else { "strh" };
+            format!("{} {}, [{}, {}]", op, low(0), low(3), immediate((i >> 6 & 0x1F) * 2))
+        }
+        4 => {
+            let op = if i >> 11 & 1 != 0 { "ldr" } else { "str" };
+            format!("{} {}, [sp, {}]", op, low(8), immediate((i & 0xFF) * 4))
+        }
+        5 if i >> 12 & 1 == 0 => {
+            let base = if i >> 11 & 1 != 0 { "sp" } else { "pc" };
+            format!("add {}, {}, {}", low(8), base, immediate((i & 0xFF) * 4))
+        }
+        5 if i >> 8 & 0xF == 0 => {
+            let op = if i >> 7 & 1 != 0 { "sub" } else { "add" };
+            format!("{} sp, {}", op, immediate((i & 0x7F) * 4))
+        }
+        5 if i >> 9 & 3 == 2 => {
+            let pop = i >> 11 & 1 != 0;
+            let extra = if i >> 8 & 1 == 0 {
+                0
+            } else if pop {
+                1 << 15
+            } else {
+                1 << 14
+            };
+            format!("{} {}", if pop { "pop" } else { "push" }, register_list((i & 0xFF) | extra))
+        }
+        6 if i >> 12 & 1 == 0 => {
+            let op = if i >> 11 & 1 != 0 { "ldmia" } else { "stmia" };
+            format!("{} {}!, {}", op, low(8), register_list(i & 0xFF))
+        }
+        6 if i >> 8 & 0xF == 0xF => format!("swi {}", immediate(i & 0xFF)),
+        6 if i >> 8 & 0xF != 0xE => {
+            let target = address.wrapping_add(4).wrapping_add(sign_extend(i & 0xFF, 8) << 1);
+            format!("b{} 0x{:08X}", CONDITIONS[(i >> 8 & 0xF) as usize], target)
+        }
+        7 if i >> 11 & 3 == 0 => {
+            let target = address.wrapping_add(4).wrapping_add(sign_extend(i & 0x7FF, 11) << 1);
+            format!("b 0x{:08X}", target)
+        }
+        // a half of a BL on its own
+        7 if i >> 11 & 3 == 2 => format!("bl.hi {}", immediate(sign_extend(i & 0x7FF, 11) << 12)),
+        7 if i >> 11 & 3 == 3 => format!("bl.lo {}", immediate((i & 0x7FF) << 1)),
+        _ => format!(".hword 0x{:04X}", i),
+    }
+}
+
+// The register and shift of an ARM data processing or transfer operand.
+fn shifted_register(i: u32) -> String {
+    let rm = register(i & 0xF);
+    let kind = (i >> 5 & 3) as usize;
+    if i >> 4 & 1 != 0 {
+        return format!("{}, {} {}", rm, SHIFTS[kind], register(i >> 8 & 0xF));
+    }
+    match (kind, i >> 7 & 0x1F) {
+        (0, 0) => rm.to_string(),
+        (3, 0) => format!("{}, rrx", rm),
+        // shifts right by 0 encode ones by 32
+        (1 | 2, 0) => format!("{}, {} #32", rm, SHIFTS[kind]),
+        (_, amount) => format!("{}, {} #{}", rm, SHIFTS[kind], amount),
+    }
+}
+
+fn immediate_offset(offset: u32, up: bool) -> String {
+    format!("#{}{}", if up { "" } else { "-" }, &immediate(offset)[1..])
+}
+
+// [rn, offset] with ! for writeback when pre-indexed, else [rn], offset
+fn address_mode(base: &str, offset: &str, pre: bool, writeback: bool) -> String {
+    match (pre, offset) {
+        (true, "#0") => format!("[{}]{}", base, if writeback { "!" } else { "" }),
+        (true, _) => format!("[{}, {}]{}", base, offset, if writeback { "!" } else { "" }),
+        (false, _) => format!("[{}], {}", base, offset),
+    }
+}
+
+// {r0-r3, lr}
+fn register_list(list: u32) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut index = 0;
+    while index < 16 {
+        if list >> index & 1 == 0 {
+            index += 1;
+            continue;
+        }
+        let start = index;
+        while index < 16 && list >> index & 1 != 0 {
+            index += 1;
+        }
+        parts.push(match index - start {
+            1 => register(start).to_string(),
+            2 => format!("{}, {}", register(start), register(start + 1)),
+            _ => format!("{}-{}", register(start), register(index - 1)),
+        });
+    }
+    format!("{{{}}}", parts.join(", "))
+}
+
+// small numbers in decimal, the rest in hex
+fn immediate(value: u32) -> String {
+    if value < 10 { format!("#{}", value) } else { format!("#0x{:X}", value) }
+}
+
+fn sign_extend(value: u32, bits: u32) -> u32 {
+    ((value << (32 - bits)) as i32 >> (32 - bits)) as u32
+}
@@ -0,0 +1,294 @@
+// DMA controller. Register values are latched into internal state when a
+// channel's enable bit is set, as the hardware does.
+
+use serde::{Deserialize, Serialize};
+
+use crate::interrupts::Interrupt;
+use crate::memory::Memory;
+
+pub const DMA0SAD: usize = 0x0B0;
+const CHANNEL_STRIDE: usize = 12;
+
+const FIFO_ADDRESSES: [u32; 2] = [0x040000A0, 0x040000A4];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressControl {
+    Increment,
+    Decrement,
+    Fixed,
+    IncrementReload,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartTiming {
+    Immediate,
+    VBlank,
+    HBlank,
+    Special,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DmaControl(pub u16);
+
+impl DmaControl {
+    pub fn read(memory: &Memory, channel: usize) -> Self {
+        DmaControl(memory.io_u16(DMA0SAD + channel * CHANNEL_STRIDE + 10))
+    }
+
+    pub fn dest_control(self) -> AddressControl {
+        address_control(self.0 >> 5)
+    }
+
+    pub fn source_control(self) -> AddressControl {
+        address_control(self.0 >> 7)
+    }
+
+    pub fn repeat(self) -> bool {
+        self.0 & (1 << 9) != 0
+    }
+
+    pub fn word_transfer(self) -> bool {
+        self.0 & (1 << 10) != 0
+    }
+
+    pub fn start_timing(self) -> StartTiming {
+        match (self.0 >> 12) & 0x3 {
+            0 => StartTiming::Immediate,
+            1 => StartTiming::VBlank,
+            2 => StartTiming::HBlank,
+            _ => StartTiming::Special,
+        }
+    }
+
+    pub fn irq(self) -> bool {
+        self.0 & (1 << 14) != 0
+    }
+
+    pub fn enabled(self) -> bool {
+        self.0 & (1 << 15) != 0
+    }
+}
+
+// only DMA0 is limited to internal memory as a source, and only DMA3 can
+// write to the cartridge bus
+fn source_mask(channel: usize) -> u32 {
+    if channel == 0 { 0x07FF_FFFF } else { 0x0FFF_FFFF }
+}
+
+fn dest_mask(channel: usize) -> u32 {
+    if channel == 3 { 0x0FFF_FFFF } else { 0x07FF_FFFF }
+}
+
+fn is_gamepak(address: u32) -> bool {
+    (0x0800_0000..0x0E00_0000).contains(&address)
+}
+
+fn word_count(memory: &Memory, channel: usize) -> u32 {
+    let count = memory.io_u16(DMA0SAD + channel * CHANNEL_STRIDE + 8) as u32;
+    let max = if channel == 3 { 0x10000 } else { 0x4000 };
+    match count & (max - 1) {
+        0 => max,
+        count => count,
+    }
+}
+
+fn step_address(address: u32, control: AddressControl, unit: u32) -> u32 {
+    match control {
+        AddressControl::Decrement => address.wrapping_sub(unit),
+        AddressControl::Fixed => address,
+        _ => address.wrapping_add(unit),
+    }
+}
+
+fn address_control(bits: u16) -> AddressControl {
+    match bits & 0x3 {
+        0 => AddressControl::Increment,
+        1 => AddressControl::Decrement,
+        2 => AddressControl::Fixed,
+        _ => AddressControl::IncrementReload,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct DmaChannel {
+    source: u32,
+    dest: u32,
+    // units per transfer, with 0 already expanded to the channel maximum
+    count: u32,
+    // last value moved by the channel, read back in place of open bus
+    latch: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Dma {
+    channels: [DmaChannel; 4],
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma::default()
+    }
+
+    // Latches newly enabled channels and runs immediate transfers, lowest
+    // channel first. Returns the bus cycles taken from the CPU.
+    pub fn step(&mut self, memory: &mut Memory) -> u64 {
+        let mut cycles = 0;
+        for channel in 0..4 {
+            if memory.dma_started[channel] {
+                memory.dma_started[channel] = false;
+                self.latch(memory, channel);
+                if DmaControl::read(memory, channel).start_timing() == StartTiming::Immediate {
+                    cycles += self.transfer(memory, channel);
+                }
+            }
+        }
+        cycles
+    }
+
+    // Runs the enabled channels waiting on a VBlank or HBlank start.
+    pub fn trigger(&mut self, memory: &mut Memory, timing: StartTiming) -> u64 {
+        let mut cycles = 0;
+        for channel in 0..4 {
+            let control = DmaControl::read(memory, channel);
+            if control.enabled() && control.start_timing() == timing {
+                cycles += self.transfer(memory, channel);
+            }
+        }
+        cycles
+    }
+
+    // DMA3 in special timing mode copies one line of captured video at the
+    // start of lines 2 to 161 and turns itself off at line 162.
+    pub fn video_capture(&mut self, memory: &mut Memory, vcount: u16) -> u64 {
+        let control = DmaControl::read(memory, 3);
+        if !control.enabled() || control.start_timing() != StartTiming::Special {
+            return 0;
+        }
+        match vcount {
+            2..=161 => self.transfer(memory, 3),
+            162 => {
+                memory.set_io_u16(DMA0SAD + 3 * CHANNEL_STRIDE + 10, control.0 & !0x8000);
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    fn latch(&mut self, memory: &Memory, channel: usize) {
+        let base = DMA0SAD + channel * CHANNEL_STRIDE;
+        let state = &mut self.channels[channel];
+        state.source = memory.io_u32(base) & source_mask(channel);
+        state.dest = memory.io_u32(base + 4) & dest_mask(channel);
+        state.count = word_count(memory, channel);
+    }
+
+    fn transfer(&mut self, memory: &mut Memory, channel: usize) -> u64 {
+        let control = DmaControl::read(memory, channel);
+        let unit = if control.word_transfer() { 4 } else { 2 };
+        let count = self.channels[channel].count;
+        let cycles = self.copy(memory, channel, control, unit, count, control.dest_control());
+        self.finish(memory, channel, control);
+        cycles
+    }
+
+    // Moves count units for the channel, returning the bus cycles taken.
+    fn copy(
+        &mut self,
+        memory: &mut Memory,
+        channel: usize,
+        control: DmaControl,
+        unit: u32,
+        count: u32,
+        dest_control: AddressControl,
+    ) -> u64 {
+        let state = &mut self.channels[channel];
+        // two internal cycles to start up, then a read and a write per unit
+        let mut cycles = 2;
+
+        // DMA3 carries whole EEPROM requests, their length giving away the
+        // chip's size
+        if channel == 3 && memory.is_eeprom(state.dest) {
+            memory.eeprom.begin_request(count);
+        }
+
+        // the cartridge bus can only count upwards
+        let source_control = match control.source_control() {
+            AddressControl::Decrement if is_gamepak(state.source) => AddressControl::Increment,
+            source_control => source_control,
+        };
+
+        for index in 0..count {
+            // addresses are forced to the transfer unit's alignment
+            let (source, dest) = (state.source & !(unit - 1), state.dest & !(unit - 1));
+            let sequential = index != 0;
+            cycles += (memory.access_cycles(source, unit, sequential) + memory.access_cycles(dest, unit, sequential)) as u64;
+            // the BIOS and unused regions can't be read by DMA, which sees
+            // the value left on its own latch instead
+            let readable = source >= 0x0200_0000;
+            if unit == 4 {
+                if readable {
+                    state.latch = memory.read_u32(source);
+                    memory.watch_read(source, 4, state.latch);
+                }
+                memory.watch_write(dest, 4, state.latch);
+                memory.write_u32(dest, state.latch);
+            } else {
+                if readable {
+                    let half = memory.read_u16(source) as u32;
+                    memory.watch_read(source, 2, half);
+                    state.latch = half | (half << 16);
+                }
+                let half = (state.latch >> ((dest & 2) * 8)) as u16;
+                memory.watch_write(dest, 2, half as u32);
+                memory.write_u16(dest, half);
+            }
+            state.source = step_address(state.source, source_control, unit);
+            state.dest = step_address(state.dest, dest_control, unit);
+        }
+        // the last value moved stays on the bus, for the CPU's reads of
+        // unused addresses
+        memory.open_bus = state.latch;
+        cycles
+    }
+
+    // The end of a transfer: the IRQ, then either reloading for the next
+    // repeat or turning the channel off.
+    fn finish(&mut self, memory: &mut Memory, channel: usize, control: DmaControl) {
+        if control.irq() {
+            memory.request_interrupt(Interrupt::dma(channel));
+        }
+
+        let state = &mut self.channels[channel];
+        let base = DMA0SAD + channel * CHANNEL_STRIDE;
+        if control.repeat() && control.start_timing() != StartTiming::Immediate {
+            state.count = word_count(memory, channel);
+            if control.dest_control() == AddressControl::IncrementReload {
+                state.dest = memory.io_u32(base + 4) & dest_mask(channel);
+            }
+        } else {
+            memory.set_io_u16(base + 10, control.0 & !0x8000);
+        }
+    }
+
+    // Sound FIFO refill: DMA1/DMA2 in special timing mode transfer four
+    // words to a fixed FIFO address whenever the FIFO runs half empty.
+    // Returns the bus cycles taken from the CPU.
+    pub fn sound_fifo_request(&mut self, memory: &mut Memory, fifo: usize) -> u64 {
+        let mut cycles = 0;
+        for channel in 1..=2 {
+            let control = DmaControl::read(memory, channel);
+            if !control.enabled()
+                || control.start_timing() != StartTiming::Special
+                || self.channels[channel].dest != FIFO_ADDRESSES[fifo]
+            {
+                continue;
+            }
+
+            // always four words, with the FIFO held as the destination
+            // whatever the word count and destination control say
+            cycles += self.copy(memory, channel, control, 4, 4, AddressControl::Fixed);
+            self.finish(memory, channel, control);
+        }
+        cycles
+    }
+}
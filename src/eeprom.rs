@@ -0,0 +1,149 @@
+// The serial EEPROM some cartridges save to in place of SRAM. Games talk to
+// it a bit at a time through DMA3, in bit 0 of each halfword, at the top of
+// the ROM area. A read request is "11", the block address and a stop bit,
+// after which 68 bits can be read back: 4 junk bits, then the block's 64
+// bits. A write is "10", the address, 64 bits of data and a stop bit. The
+// 512 byte chip takes 6-bit addresses and the 8KB one 14-bit addresses,
+// told apart by the length of the DMA carrying the request. The contents
+// live in the memory's battery save buffer, as SRAM's do.
+
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+
+pub const EEPROM_SIZE: usize = 0x2000;
+
+// bits read back for a read request, the first 4 of them junk
+const READ_BITS: u32 = 68;
+// the longest request, a write to the 8KB chip
+const MAX_REQUEST_BITS: u32 = 2 + 14 + 64 + 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Eeprom {
+    // 6 or 14, once a request's length has shown which chip this is
+    address_bits: Option<u32>,
+    // the bits of the request being sent, the first in the highest place
+    request: u128,
+    request_length: u32,
+    // the block a read request asked for, and how many of its bits have
+    // been read back; READ_BITS when there is nothing to read
+    read_data: u64,
+    read_position: Cell<u32>,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Eeprom {
+            address_bits: None,
+            request: 0,
+            request_length: 0,
+            read_data: 0,
+            read_position: Cell::new(READ_BITS),
+        }
+    }
+}
+
+impl Eeprom {
+    // A DMA of length halfwords is about to send a request.
+    pub fn begin_request(&mut self, length: u32) {
+        match length {
+            9 | 73 => self.address_bits = Some(6),
+            17 | 81 => self.address_bits = Some(14),
+            _ => {}
+        }
+        self.request = 0;
+        self.request_length = 0;
+    }
+
+    // Takes the next bit of a request, carrying it out once complete.
+    // Returns true if save was written to.
+    pub fn write_bit(&mut self, bit: bool, save: &mut [u8]) -> bool {
+        // bits past any request are noise; start again from them
+        if self.request_length == MAX_REQUEST_BITS {
+            self.request_length = 0;
+        }
+        self.request = (self.request << 1) | bit as u128;
+        self.request_length += 1;
+        if self.request_length < 2 {
+            return false;
+        }
+        // chips whose size hasn't been seen yet are taken to be the larger
+        let address_bits = self.address_bits.unwrap_or(14);
+        let command = (self.request >> (self.request_length - 2)) & 0b11;
+        match (self.request_length, command) {
+            (length, 0b11) if length == 2 + address_bits + 1 => {
+                let block = block(self.request >> 1, address_bits);
+                self.read_data = u64::from_be_bytes(save[block..block + 8].try_into().unwrap());
+                self.read_position.set(0);
+                self.request_length = 0;
+                false
+            }
+            (length, 0b10) if length == 2 + address_bits + 64 + 1 => {
+                let block = block(self.request >> 65, address_bits);
+                save[block..block + 8].copy_from_slice(&((self.request >> 1) as u64).to_be_bytes());
+                self.request_length = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // The next bit of a read, or 1 for ready when none is under way, as
+    // writes finish at once.
+    pub fn read_bit(&self) -> bool {
+        let position = self.read_position.get();
+        if position >= READ_BITS {
+            return true;
+        }
+        self.read_position.set(position + 1);
+        position >= 4 && (self.read_data >> (63 - (position - 4))) & 1 != 0
+    }
+}
+
+// offset in the save of the addressed 8 byte block; the 8KB chip only
+// decodes the low 10 of its 14 address bits
+fn block(address: u128, address_bits: u32) -> usize {
+    let address = (address as usize) & ((1 << address_bits) - 1);
+    (address * 8) % EEPROM_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(eeprom: &mut Eeprom, save: &mut [u8], bits: &[u64], widths: &[u32]) -> bool {
+        let length = widths.iter().sum::<u32>() + 1;
+        eeprom.begin_request(length);
+        let mut written = false;
+        for (&value, &width) in bits.iter().zip(widths) {
+            for bit in (0..width).rev() {
+                written |= eeprom.write_bit((value >> bit) & 1 != 0, save);
+            }
+        }
+        // the stop bit
+        written | eeprom.write_bit(false, save)
+    }
+
+    fn read_block(eeprom: &Eeprom) -> u64 {
+        let bits: Vec<bool> = (0..READ_BITS).map(|_| eeprom.read_bit()).collect();
+        assert!(bits[..4].iter().all(|&bit| !bit));
+        bits[4..].iter().fold(0, |block, &bit| (block << 1) | bit as u64)
+    }
+
+    #[test]
+    fn written_blocks_read_back() {
+        for address_bits in [6, 14] {
+            let mut eeprom = Eeprom::default();
+            let mut save = vec![0xFF; EEPROM_SIZE];
+            let data = 0x0123_4567_89AB_CDEF;
+            assert!(send(&mut eeprom, &mut save, &[0b10, 5, data], &[2, address_bits, 64]));
+            assert_eq!(save[40..48], data.to_be_bytes());
+            // ready straight after the write
+            assert!(eeprom.read_bit());
+
+            assert!(!send(&mut eeprom, &mut save, &[0b11, 5], &[2, address_bits]));
+            assert_eq!(read_block(&eeprom), data);
+            assert!(eeprom.read_bit());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Windowed frontends. They only show frames and report input; the loop in
+// main.rs drives the Gba and decides when frames are presented. Which ones
+// are built is chosen by cargo feature: sdl (the default) or winit.
+
+pub mod bindings;
+pub mod filter;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+pub mod keyboard;
+pub mod orientation;
+pub mod osd;
+#[cfg(feature = "sdl")]
+mod sdl;
+#[cfg(all(feature = "winit", not(feature = "sdl")))]
+mod winit_pixels;
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use filter::VideoFilter;
+use keyboard::Keyboard;
+use orientation::Orientation;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use afterimage::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const DEFAULT_SCALE: u32 = 3;
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+pub const WINDOW_TITLE: &str = "afterimage";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowOptions {
+    // initial window size as a multiple of the GBA screen
+    pub scale: u32,
+    // only scale by whole numbers, leaving a wider border in between sizes
+    pub integer_scaling: bool,
+    // borderless, covering the desktop
+    pub fullscreen: bool,
+    pub filter: VideoFilter,
+    pub orientation: Orientation,
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        WindowOptions {
+            scale: DEFAULT_SCALE,
+            integer_scaling: false,
+            fullscreen: false,
+            filter: VideoFilter::default(),
+            orientation: Orientation::default(),
+        }
+    }
+}
+
+pub trait Frontend {
+    // Handles pending window events, returning false once the window has
+    // been closed.
+    fn poll_events(&mut self) -> bool;
+
+    // keys held on the host's keyboard, fed by poll_events
+    fn keyboard(&mut self) -> &mut Keyboard;
+
+    // the last file dropped onto the window since the previous call
+    fn take_dropped_file(&mut self) -> Option<PathBuf>;
+
+    // Switches between a window and borderless fullscreen, which hides the
+    // mouse cursor.
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), Box<dyn Error>>;
+
+    fn fullscreen(&self) -> bool;
+
+    fn set_filter(&mut self, filter: VideoFilter) -> Result<(), Box<dyn Error>>;
+
+    // Shows a frame of BGR555 pixels as produced by the PPU.
+    fn present(&mut self, frame: &[u16]) -> Result<(), Box<dyn Error>>;
+}
+
+// Opens a window with the first frontend built in, SDL being preferred.
+pub fn open(options: WindowOptions) -> Result<Box<dyn Frontend>, Box<dyn Error>> {
+    #[cfg(feature = "sdl")]
+    return Ok(Box::new(sdl::SdlFrontend::open(options)?));
+    #[cfg(all(feature = "winit", not(feature = "sdl")))]
+    return Ok(Box::new(winit_pixels::WinitFrontend::open(options)?));
+    #[cfg(not(any(feature = "sdl", feature = "winit")))]
+    {
+        let _ = options;
+        Err("built without a window frontend; enable the sdl or winit feature".into())
+    }
+}
+
+// the GBA screen's size once turned
+#[cfg(any(feature = "sdl", feature = "winit"))]
+pub fn screen_size(orientation: Orientation) -> (u32, u32) {
+    let (width, height) = orientation.size((SCREEN_WIDTH, SCREEN_HEIGHT));
+    (width as u32, height as u32)
+}
+
+// window size for a whole-number scale of the GBA screen
+#[cfg(any(feature = "sdl", feature = "winit"))]
+pub fn window_size(scale: u32, orientation: Orientation) -> (u32, u32) {
+    let (width, height) = screen_size(orientation);
+    (width * scale, height * scale)
+}
+
+// Where the picture goes in a window of the given size, as x, y, width and
+// height: as large as fits at the GBA's 3:2 aspect ratio, or 2:3 turned on
+// its side, centred, with black bars filling the rest.
+#[cfg(feature = "sdl")]
+pub fn viewport(window: (u32, u32), orientation: Orientation, integer_scaling: bool) -> (u32, u32, u32, u32) {
+    let (window_width, window_height) = window;
+    let (screen_width, screen_height) = screen_size(orientation);
+    let (width, height) = if integer_scaling {
+        // never smaller than 1x, even if that means cropping
+        let scale = (window_width / screen_width).min(window_height / screen_height).max(1);
+        (screen_width * scale, screen_height * scale)
+    } else if window_width * screen_height > window_height * screen_width {
+        (window_height * screen_width / screen_height, window_height)
+    } else {
+        (window_width, window_width * screen_height / screen_width)
+    };
+    let x = window_width.saturating_sub(width) / 2;
+    let y = window_height.saturating_sub(height) / 2;
+    (x, y, width, height)
+}
@@ -0,0 +1,289 @@
+// What each host key and controller button does. Keyboard keys go by SDL's
+// key names ("Up", "Z", "Return"), controller buttons by gilrs' button
+// names ("South", "DPadUp", "LeftTrigger"). An action can have any number
+// of bindings on each device.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use afterimage::keypad::KeyState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    A,
+    B,
+    L,
+    R,
+    Start,
+    Select,
+    Up,
+    Down,
+    Left,
+    Right,
+    TurboA,
+    TurboB,
+    FastForward,
+    Rewind,
+    Pause,
+    FrameAdvance,
+    SaveState,
+    LoadState,
+    Reset,
+    Fullscreen,
+    Screenshot,
+    Record,
+    SaveClip,
+    SwapRom,
+    MovieReadOnly,
+    NextCheat,
+    ToggleCheat,
+    Debug,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    Slot1,
+    Slot2,
+    Slot3,
+    Slot4,
+    Slot5,
+    Slot6,
+    Slot7,
+    Slot8,
+    Slot9,
+}
+
+impl Action {
+    pub const ALL: [Action; 40] = [
+        Action::A,
+        Action::B,
+        Action::L,
+        Action::R,
+        Action::Start,
+        Action::Select,
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::TurboA,
+        Action::TurboB,
+        Action::FastForward,
+        Action::Rewind,
+        Action::Pause,
+        Action::FrameAdvance,
+        Action::SaveState,
+        Action::LoadState,
+        Action::Reset,
+        Action::Fullscreen,
+        Action::Screenshot,
+        Action::Record,
+        Action::SaveClip,
+        Action::SwapRom,
+        Action::MovieReadOnly,
+        Action::NextCheat,
+        Action::ToggleCheat,
+        Action::Debug,
+        Action::VolumeUp,
+        Action::VolumeDown,
+        Action::Mute,
+        Action::Slot1,
+        Action::Slot2,
+        Action::Slot3,
+        Action::Slot4,
+        Action::Slot5,
+        Action::Slot6,
+        Action::Slot7,
+        Action::Slot8,
+        Action::Slot9,
+    ];
+
+    // the GBA button pressed by the action, None for frontend hotkeys
+    pub fn button(self) -> Option<KeyState> {
+        match self {
+            Action::A => Some(KeyState::A),
+            Action::B => Some(KeyState::B),
+            Action::L => Some(KeyState::L),
+            Action::R => Some(KeyState::R),
+            Action::Start => Some(KeyState::START),
+            Action::Select => Some(KeyState::SELECT),
+            Action::Up => Some(KeyState::UP),
+            Action::Down => Some(KeyState::DOWN),
+            Action::Left => Some(KeyState::LEFT),
+            Action::Right => Some(KeyState::RIGHT),
+            _ => None,
+        }
+    }
+
+    // the GBA button an auto-fire action presses every other frame
+    pub fn turbo_button(self) -> Option<KeyState> {
+        match self {
+            Action::TurboA => Some(KeyState::A),
+            Action::TurboB => Some(KeyState::B),
+            _ => None,
+        }
+    }
+
+    // the save state slot picked by the action
+    pub fn slot(self) -> Option<u32> {
+        let slot = match self {
+            Action::Slot1 => 1,
+            Action::Slot2 => 2,
+            Action::Slot3 => 3,
+            Action::Slot4 => 4,
+            Action::Slot5 => 5,
+            Action::Slot6 => 6,
+            Action::Slot7 => 7,
+            Action::Slot8 => 8,
+            Action::Slot9 => 9,
+            _ => return None,
+        };
+        Some(slot)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(slot) = self.slot() {
+            return write!(f, "Save state slot {}", slot);
+        }
+        let name = match self {
+            Action::TurboA => "Turbo A",
+            Action::TurboB => "Turbo B",
+            Action::FastForward => "Fast forward",
+            Action::Rewind => "Rewind",
+            Action::Pause => "Pause",
+            Action::FrameAdvance => "Frame advance",
+            Action::SaveState => "Save state",
+            Action::LoadState => "Load state",
+            Action::Reset => "Reset",
+            Action::Fullscreen => "Fullscreen",
+            Action::Screenshot => "Screenshot",
+            Action::Record => "Start or stop recording",
+            Action::SaveClip => "Save the last few seconds",
+            Action::SwapRom => "Switch to the last game played",
+            Action::MovieReadOnly => "Toggle movie read-only",
+            Action::NextCheat => "Pick the next cheat",
+            Action::ToggleCheat => "Turn the picked cheat on or off",
+            Action::Debug => "Stop in the debugger",
+            Action::VolumeUp => "Volume up",
+            Action::VolumeDown => "Volume down",
+            Action::Mute => "Mute",
+            Action::Start => "Start",
+            Action::Select => "Select",
+            Action::Up => "Up",
+            Action::Down => "Down",
+            Action::Left => "Left",
+            Action::Right => "Right",
+            button => return write!(f, "{:?} button", button),
+        };
+        f.write_str(name)
+    }
+}
+
+// GBA buttons held down by a set of actions. Turbo buttons count as held
+// only on frames where turbo_phase is set.
+pub fn buttons(actions: &BTreeSet<Action>, turbo_phase: bool) -> KeyState {
+    actions
+        .iter()
+        .filter_map(|action| action.button().or(action.turbo_button().filter(|_| turbo_phase)))
+        .fold(KeyState::NONE, |keys, button| keys | button)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub keyboard: BTreeMap<Action, Vec<String>>,
+    pub gamepad: BTreeMap<Action, Vec<String>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keyboard = [
+            (Action::A, "X"),
+            (Action::B, "Z"),
+            (Action::L, "A"),
+            (Action::R, "S"),
+            (Action::Start, "Return"),
+            (Action::Select, "Backspace"),
+            (Action::Up, "Up"),
+            (Action::Down, "Down"),
+            (Action::Left, "Left"),
+            (Action::Right, "Right"),
+            (Action::FastForward, "Tab"),
+            (Action::Rewind, "R"),
+            (Action::Pause, "P"),
+            (Action::FrameAdvance, "N"),
+            (Action::SaveState, "F5"),
+            (Action::LoadState, "F9"),
+            (Action::Reset, "F10"),
+            (Action::Fullscreen, "F11"),
+            (Action::Screenshot, "F12"),
+            (Action::Record, "F8"),
+            (Action::SaveClip, "F7"),
+            (Action::SwapRom, "F6"),
+            (Action::MovieReadOnly, "F3"),
+            (Action::NextCheat, "F1"),
+            (Action::ToggleCheat, "F2"),
+            (Action::Debug, "F4"),
+            (Action::VolumeUp, "="),
+            (Action::VolumeDown, "-"),
+            (Action::Mute, "M"),
+            (Action::Slot1, "1"),
+            (Action::Slot2, "2"),
+            (Action::Slot3, "3"),
+            (Action::Slot4, "4"),
+            (Action::Slot5, "5"),
+            (Action::Slot6, "6"),
+            (Action::Slot7, "7"),
+            (Action::Slot8, "8"),
+            (Action::Slot9, "9"),
+        ];
+        // by position: the right face button is A and the bottom one B, as
+        // on the GBA, with turbo A and B above and left of them. Hats report
+        // as the D-pad buttons.
+        let gamepad = [
+            (Action::A, &["East"][..]),
+            (Action::B, &["South"]),
+            (Action::L, &["LeftTrigger", "LeftTrigger2"]),
+            (Action::R, &["RightTrigger", "RightTrigger2"]),
+            (Action::Start, &["Start"]),
+            (Action::Select, &["Select"]),
+            (Action::Up, &["DPadUp"]),
+            (Action::Down, &["DPadDown"]),
+            (Action::Left, &["DPadLeft"]),
+            (Action::Right, &["DPadRight"]),
+            (Action::TurboA, &["North"]),
+            (Action::TurboB, &["West"]),
+        ];
+        Bindings {
+            keyboard: keyboard.iter().map(|&(action, key)| (action, vec![key.to_string()])).collect(),
+            gamepad: gamepad
+                .iter()
+                .map(|&(action, buttons)| (action, buttons.iter().map(|button| button.to_string()).collect()))
+                .collect(),
+        }
+    }
+}
+
+impl Bindings {
+    // Gives actions missing from a config file their default bindings, so
+    // a file only needs to list what it changes. An empty list unbinds.
+    pub fn fill_defaults(&mut self) {
+        let defaults = Bindings::default();
+        for (action, keys) in defaults.keyboard {
+            self.keyboard.entry(action).or_insert(keys);
+        }
+        for (action, buttons) in defaults.gamepad {
+            self.gamepad.entry(action).or_insert(buttons);
+        }
+    }
+
+    // actions bound to a host key or button on one device
+    pub fn actions<'a>(map: &'a BTreeMap<Action, Vec<String>>, name: &'a str) -> impl Iterator<Item = Action> + 'a {
+        map.iter()
+            .filter(move |(_, names)| names.iter().any(|bound| bound.eq_ignore_ascii_case(name)))
+            .map(|(&action, _)| action)
+    }
+}
@@ -0,0 +1,225 @@
+// Upscaling filters, run on the CPU before a frame is handed to the
+// window. Each turns the BGR555 frame into RGBA8888 pixels at a whole
+// multiple of the GBA resolution, which the window then scales to fit.
+// The upscaler also turns the result to the window's orientation.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use super::orientation::Orientation;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use afterimage::ppu::debug::Rgb;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+use afterimage::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoFilter {
+    // square pixels
+    #[default]
+    Nearest,
+    // smooth interpolation between pixels
+    Bilinear,
+    // EPX / AdvMAME2x: rounds off diagonal edges
+    Scale2x,
+    // AdvMAME3x
+    Scale3x,
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+impl VideoFilter {
+    pub fn factor(self) -> usize {
+        match self {
+            VideoFilter::Nearest => 1,
+            // enough detail for the smoothing to survive the window's
+            // own nearest-neighbour scaling
+            VideoFilter::Bilinear => 4,
+            VideoFilter::Scale2x => 2,
+            VideoFilter::Scale3x => 3,
+        }
+    }
+
+    // size of the filtered picture
+    pub fn size(self) -> (usize, usize) {
+        (SCREEN_WIDTH * self.factor(), SCREEN_HEIGHT * self.factor())
+    }
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+#[derive(Debug)]
+pub struct Upscaler {
+    filter: VideoFilter,
+    orientation: Orientation,
+    // the frame scaled up in BGR555, for the edge filters
+    scaled: Vec<u16>,
+    // the filtered frame before it is turned, unless it stays upright
+    unturned: Vec<u8>,
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+impl Upscaler {
+    pub fn new(filter: VideoFilter, orientation: Orientation) -> Self {
+        let (width, height) = filter.size();
+        let unturned = if orientation.is_upright() { 0 } else { width * height * 4 };
+        Upscaler {
+            filter,
+            orientation,
+            scaled: vec![0; width * height],
+            unturned: vec![0; unturned],
+        }
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn filter(&self) -> VideoFilter {
+        self.filter
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    // size of the output picture
+    pub fn size(&self) -> (usize, usize) {
+        self.orientation.size(self.filter.size())
+    }
+
+    // Filters frame into out, which holds size() RGBA pixels.
+    pub fn apply(&mut self, frame: &[u16], out: &mut [u8]) {
+        if self.orientation.is_upright() {
+            self.filter_into(frame, out);
+        } else {
+            let mut unturned = std::mem::take(&mut self.unturned);
+            self.filter_into(frame, &mut unturned);
+            self.orientation.apply(&unturned, self.filter.size(), out);
+            self.unturned = unturned;
+        }
+    }
+
+    fn filter_into(&mut self, frame: &[u16], out: &mut [u8]) {
+        match self.filter {
+            VideoFilter::Nearest => write_rgba(frame, out),
+            VideoFilter::Bilinear => bilinear(frame, out),
+            VideoFilter::Scale2x => {
+                scale2x(frame, &mut self.scaled);
+                write_rgba(&self.scaled, out);
+            }
+            VideoFilter::Scale3x => {
+                scale3x(frame, &mut self.scaled);
+                write_rgba(&self.scaled, out);
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn write_rgba(frame: &[u16], out: &mut [u8]) {
+    for (rgba, &color) in out.chunks_exact_mut(4).zip(frame) {
+        let Rgb { r, g, b } = Rgb::from_bgr555(color);
+        rgba.copy_from_slice(&[r, g, b, 0xFF]);
+    }
+}
+
+// neighbours of a pixel, repeating the edge pixels beyond the screen
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn pixel(frame: &[u16], x: usize, y: usize, dx: isize, dy: isize) -> u16 {
+    let x = x.saturating_add_signed(dx).min(SCREEN_WIDTH - 1);
+    let y = y.saturating_add_signed(dy).min(SCREEN_HEIGHT - 1);
+    frame[y * SCREEN_WIDTH + x]
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn scale2x(frame: &[u16], out: &mut [u16]) {
+    let width = SCREEN_WIDTH * 2;
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let p = pixel(frame, x, y, 0, 0);
+            let a = pixel(frame, x, y, 0, -1);
+            let b = pixel(frame, x, y, 1, 0);
+            let c = pixel(frame, x, y, -1, 0);
+            let d = pixel(frame, x, y, 0, 1);
+            let top = 2 * y * width + 2 * x;
+            let bottom = top + width;
+            out[top] = if c == a && c != d && a != b { a } else { p };
+            out[top + 1] = if a == b && a != c && b != d { b } else { p };
+            out[bottom] = if d == c && d != b && c != a { c } else { p };
+            out[bottom + 1] = if b == d && b != a && d != c { d } else { p };
+        }
+    }
+}
+
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn scale3x(frame: &[u16], out: &mut [u16]) {
+    let width = SCREEN_WIDTH * 3;
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            // a b c
+            // d e f
+            // g h i
+            let [a, b, c, d, e, f, g, h, i] = [
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (0, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ]
+            .map(|(dx, dy)| pixel(frame, x, y, dx, dy));
+            let block = [
+                if d == b && b != f && d != h { d } else { e },
+                if (d == b && b != f && d != h && e != c) || (b == f && b != d && f != h && e != a) { b } else { e },
+                if b == f && b != d && f != h { f } else { e },
+                if (d == b && b != f && d != h && e != g) || (d == h && d != b && h != f && e != a) { d } else { e },
+                e,
+                if (b == f && b != d && f != h && e != i) || (h == f && d != h && b != f && e != c) { f } else { e },
+                if d == h && d != b && h != f { d } else { e },
+                if (d == h && d != b && h != f && e != i) || (h == f && d != h && b != f && e != g) { h } else { e },
+                if h == f && d != h && b != f { f } else { e },
+            ];
+            for row in 0..3 {
+                let start = (3 * y + row) * width + 3 * x;
+                out[start..start + 3].copy_from_slice(&block[row * 3..row * 3 + 3]);
+            }
+        }
+    }
+}
+
+// Bilinear interpolation in 8.8 fixed point, sampling at pixel centres.
+#[cfg(any(feature = "sdl", feature = "winit"))]
+fn bilinear(frame: &[u16], out: &mut [u8]) {
+    let factor = VideoFilter::Bilinear.factor();
+    let rgb: Vec<Rgb> = frame.iter().map(|&color| Rgb::from_bgr555(color)).collect();
+    // source pixels either side of each output column or row, and the
+    // weight of the second
+    let taps = |length: usize| -> Vec<(usize, usize, u32)> {
+        (0..length * factor)
+            .map(|position| {
+                let centre = ((2 * position + 1) * 256 / (2 * factor)).saturating_sub(128);
+                let first = (centre / 256).min(length - 1);
+                (first, (first + 1).min(length - 1), (centre % 256) as u32)
+            })
+            .collect()
+    };
+    let columns = taps(SCREEN_WIDTH);
+    let rows = taps(SCREEN_HEIGHT);
+
+    let mut pixels = out.chunks_exact_mut(4);
+    for &(top, bottom, y_weight) in &rows {
+        for &(left, right, x_weight) in &columns {
+            let sample = |row: usize| {
+                let (p, q) = (rgb[row * SCREEN_WIDTH + left], rgb[row * SCREEN_WIDTH + right]);
+                let mix = |a: u8, b: u8| a as u32 * (256 - x_weight) + b as u32 * x_weight;
+                [mix(p.r, q.r), mix(p.g, q.g), mix(p.b, q.b)]
+            };
+            let (upper, lower) = (sample(top), sample(bottom));
+            let rgba = pixels.next().unwrap();
+            for channel in 0..3 {
+                let value = upper[channel] * (256 - y_weight) + lower[channel] * y_weight;
+                rgba[channel] = (value >> 16) as u8;
+            }
+            rgba[3] = 0xFF;
+        }
+    }
+}
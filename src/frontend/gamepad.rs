@@ -0,0 +1,101 @@
+// Game controllers through gilrs, with plugging and unplugging handled as
+// the events come in. Buttons are bound by name in the input bindings; the
+// left stick always steers the D-pad.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+
+use gilrs::{Axis, Button, EventType, Gamepad, Gilrs};
+
+use super::bindings::{Action, Bindings};
+
+// how far the stick must be pushed to count as a D-pad press
+const STICK_THRESHOLD: f32 = 0.5;
+
+const BUTTONS: [Button; 19] = [
+    Button::South,
+    Button::East,
+    Button::North,
+    Button::West,
+    Button::C,
+    Button::Z,
+    Button::LeftTrigger,
+    Button::LeftTrigger2,
+    Button::RightTrigger,
+    Button::RightTrigger2,
+    Button::Select,
+    Button::Start,
+    Button::Mode,
+    Button::LeftThumb,
+    Button::RightThumb,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+// bindings use the names gilrs gives its buttons
+fn button_named(name: &str) -> Option<Button> {
+    BUTTONS.iter().copied().find(|button| format!("{:?}", button).eq_ignore_ascii_case(name))
+}
+
+pub struct Gamepads {
+    pub bindings: Bindings,
+    gilrs: Gilrs,
+    // most recent button press, for rebinding
+    last_pressed: Option<String>,
+}
+
+impl Gamepads {
+    pub fn new(bindings: Bindings) -> Result<Self, Box<dyn Error>> {
+        let gilrs = Gilrs::new().map_err(|err| err.to_string())?;
+        for (_, pad) in gilrs.gamepads() {
+            println!("Controller found: {}", pad.name());
+        }
+        Ok(Gamepads {
+            bindings,
+            gilrs,
+            last_pressed: None,
+        })
+    }
+
+    // Takes in controller events and returns the actions held across every
+    // connected controller.
+    pub fn poll(&mut self) -> BTreeSet<Action> {
+        while let Some(event) = self.gilrs.next_event() {
+            let name = self.gilrs.gamepad(event.id).name().to_string();
+            match event.event {
+                EventType::Connected => println!("Controller connected: {}", name),
+                EventType::Disconnected => println!("Controller disconnected: {}", name),
+                EventType::ButtonPressed(button, _) => self.last_pressed = Some(format!("{:?}", button)),
+                _ => {}
+            }
+        }
+        self.gilrs.gamepads().flat_map(|(_, pad)| self.pad_actions(&pad)).collect()
+    }
+
+    pub fn take_pressed(&mut self) -> Option<String> {
+        self.last_pressed.take()
+    }
+
+    fn pad_actions(&self, pad: &Gamepad) -> BTreeSet<Action> {
+        let mut actions: BTreeSet<Action> = self
+            .bindings
+            .gamepad
+            .iter()
+            .filter(|(_, names)| names.iter().filter_map(|name| button_named(name)).any(|button| pad.is_pressed(button)))
+            .map(|(&action, _)| action)
+            .collect();
+
+        let (x, y) = (pad.value(Axis::LeftStickX), pad.value(Axis::LeftStickY));
+        // gilrs has up as positive
+        let directions = [
+            (x < -STICK_THRESHOLD, Action::Left),
+            (x > STICK_THRESHOLD, Action::Right),
+            (y > STICK_THRESHOLD, Action::Up),
+            (y < -STICK_THRESHOLD, Action::Down),
+        ];
+        actions.extend(directions.iter().filter(|(pushed, _)| *pushed).map(|&(_, direction)| direction));
+        actions
+    }
+}
@@ -0,0 +1,44 @@
+// Host keys held down, as reported by a frontend's window events, and the
+// actions they are bound to.
+
+use std::collections::{BTreeSet, HashSet};
+
+use super::bindings::{Action, Bindings};
+
+#[derive(Debug, Clone, Default)]
+pub struct Keyboard {
+    pub bindings: Bindings,
+    pressed: HashSet<String>,
+    // most recent key press, for rebinding
+    last_pressed: Option<String>,
+}
+
+impl Keyboard {
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn press(&mut self, key: &str) {
+        self.pressed.insert(key.to_string());
+        self.last_pressed = Some(key.to_string());
+    }
+
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn release(&mut self, key: &str) {
+        self.pressed.remove(key);
+    }
+
+    // the window lost focus and will miss the key releases
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn release_all(&mut self) {
+        self.pressed.clear();
+    }
+
+    pub fn held(&self) -> BTreeSet<Action> {
+        self.pressed
+            .iter()
+            .flat_map(|key| Bindings::actions(&self.bindings.keyboard, key))
+            .collect()
+    }
+
+    pub fn take_pressed(&mut self) -> Option<String> {
+        self.last_pressed.take()
+    }
+}
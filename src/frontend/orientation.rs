@@ -0,0 +1,51 @@
+// Turning and mirroring the picture, for monitors stood on their side and
+// cabinets that show the screen through a mirror. Applied to the filtered
+// RGBA frame, so the filters only ever see the GBA's own layout.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Orientation {
+    // clockwise quarter turns, 0 to 3
+    pub turns: u32,
+    // flipped left to right, before turning
+    pub mirror: bool,
+}
+
+impl Orientation {
+    // None unless degrees is a multiple of 90
+    pub fn new(degrees: u32, mirror: bool) -> Option<Self> {
+        degrees.is_multiple_of(90).then_some(Orientation {
+            turns: degrees / 90 % 4,
+            mirror,
+        })
+    }
+
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn is_upright(self) -> bool {
+        self.turns == 0 && !self.mirror
+    }
+
+    // size of a width by height picture once oriented
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn size(self, (width, height): (usize, usize)) -> (usize, usize) {
+        if self.turns % 2 == 1 { (height, width) } else { (width, height) }
+    }
+
+    // Copies the RGBA picture src, size pixels large, into out oriented.
+    #[cfg(any(feature = "sdl", feature = "winit"))]
+    pub fn apply(self, src: &[u8], (width, height): (usize, usize), out: &mut [u8]) {
+        let (out_width, _) = self.size((width, height));
+        for (y, row) in src.chunks_exact(width * 4).enumerate() {
+            for (x, pixel) in row.chunks_exact(4).enumerate() {
+                let x = if self.mirror { width - 1 - x } else { x };
+                let (out_x, out_y) = match self.turns {
+                    1 => (height - 1 - y, x),
+                    2 => (width - 1 - x, height - 1 - y),
+                    3 => (y, width - 1 - x),
+                    _ => (x, y),
+                };
+                let start = (out_y * out_width + out_x) * 4;
+                out[start..start + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+}
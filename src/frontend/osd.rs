@@ -0,0 +1,267 @@
+// On-screen display: an FPS and speed readout, short-lived messages, lines
+// such as watched RAM values kept up until replaced, text a script puts
+// anywhere on the screen, and a picture such as a save state's thumbnail,
+// drawn over a copy of the frame at the GBA's resolution so the emulated
+// frame buffer is never touched. Text is upper case in a built-in 5x7
+// font on a darkened box.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use afterimage::ppu::{FRAME_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+const MAX_MESSAGES: usize = 4;
+// watch lines shown at most, leaving room for the messages below
+const MAX_WATCHES: usize = 8;
+// how often the readout is recalculated
+const STATS_INTERVAL: Duration = Duration::from_millis(500);
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 3;
+const MARGIN: usize = 2;
+const WHITE: u16 = 0x7FFF;
+
+#[derive(Debug)]
+pub struct Osd {
+    pub show_fps: bool,
+    // shown under the readout every frame
+    pub watches: Vec<String>,
+    // left and top of each, placed by a script and replaced every frame
+    pub texts: Vec<(usize, usize, String)>,
+    messages: VecDeque<(String, Instant)>,
+    stats_start: Instant,
+    frames_shown: u32,
+    frames_emulated: u32,
+    // the readout: frames presented per second and speed against the GBA
+    fps: f64,
+    speed: f64,
+    // pixels and width of a picture shown in the top right corner
+    picture: Option<(Vec<u16>, usize, Instant)>,
+    buffer: Vec<u16>,
+}
+
+impl Osd {
+    pub fn new(show_fps: bool) -> Self {
+        Osd {
+            show_fps,
+            watches: Vec::new(),
+            texts: Vec::new(),
+            messages: VecDeque::new(),
+            stats_start: Instant::now(),
+            frames_shown: 0,
+            frames_emulated: 0,
+            fps: 0.0,
+            speed: 0.0,
+            picture: None,
+            buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    // Shows a message for a few seconds, under any still showing.
+    pub fn message(&mut self, text: impl Into<String>) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((text.into(), Instant::now()));
+    }
+
+    // Shows a picture width pixels wide for as long as a message, in place
+    // of any picture already showing.
+    pub fn picture(&mut self, pixels: Vec<u16>, width: usize) {
+        self.picture = Some((pixels, width, Instant::now()));
+    }
+
+    pub fn clear_picture(&mut self) {
+        self.picture = None;
+    }
+
+    pub fn frame_emulated(&mut self) {
+        self.frames_emulated += 1;
+    }
+
+    // Draws the overlay over a copy of frame, counting it as presented.
+    pub fn compose(&mut self, frame: &[u16]) -> &[u16] {
+        let now = Instant::now();
+        self.frames_shown += 1;
+        let elapsed = now - self.stats_start;
+        if elapsed >= STATS_INTERVAL {
+            self.fps = self.frames_shown as f64 / elapsed.as_secs_f64();
+            self.speed = self.frames_emulated as f64 / elapsed.as_secs_f64() / FRAME_RATE;
+            self.stats_start = now;
+            self.frames_shown = 0;
+            self.frames_emulated = 0;
+        }
+        self.messages.retain(|(_, shown)| now - *shown < MESSAGE_DURATION);
+        if self.picture.as_ref().is_some_and(|(_, _, shown)| now - *shown >= MESSAGE_DURATION) {
+            self.picture = None;
+        }
+
+        self.buffer.copy_from_slice(frame);
+        let mut y = MARGIN;
+        if self.show_fps {
+            let readout = format!("{:.1} FPS {:.2}X", self.fps, self.speed);
+            draw_text(&mut self.buffer, MARGIN, y, &readout);
+            y += LINE_HEIGHT;
+        }
+        for line in self.watches.iter().take(MAX_WATCHES) {
+            draw_text(&mut self.buffer, MARGIN, y, line);
+            y += LINE_HEIGHT;
+        }
+        for (x, y, text) in &self.texts {
+            // kept inside the screen along with the box drawn around it
+            let x = (*x).clamp(1, SCREEN_WIDTH - 1);
+            let y = (*y).min(SCREEN_HEIGHT - GLYPH_HEIGHT - 1);
+            draw_text(&mut self.buffer, x, y, text);
+        }
+        let top = SCREEN_HEIGHT - MARGIN - self.messages.len() * LINE_HEIGHT;
+        for (index, (text, _)) in self.messages.iter().enumerate() {
+            draw_text(&mut self.buffer, MARGIN, top + index * LINE_HEIGHT, text);
+        }
+        if let Some((pixels, width, _)) = &self.picture {
+            draw_picture(&mut self.buffer, pixels, *width);
+        }
+        &self.buffer
+    }
+}
+
+// a picture in the top right corner with a white border, cut off at the
+// edges of the screen
+fn draw_picture(buffer: &mut [u16], pixels: &[u16], width: usize) {
+    let height = pixels.len() / width;
+    let left = SCREEN_WIDTH.saturating_sub(MARGIN + 1 + width);
+    let top = MARGIN + 1;
+    for row in top - 1..(top + height + 1).min(SCREEN_HEIGHT) {
+        for column in left.saturating_sub(1)..(left + width + 1).min(SCREEN_WIDTH) {
+            let inside = row >= top && row < top + height && column >= left && column < left + width;
+            buffer[row * SCREEN_WIDTH + column] = if inside {
+                pixels[(row - top) * width + column - left]
+            } else {
+                WHITE
+            };
+        }
+    }
+}
+
+// one line of text with its top-left corner at x, y, cut off at the right
+// edge of the screen and left out if it would run off the bottom
+fn draw_text(buffer: &mut [u16], x: usize, y: usize, text: &str) {
+    if y + GLYPH_HEIGHT > SCREEN_HEIGHT {
+        return;
+    }
+    let width = text.chars().count() * ADVANCE + 1;
+    for row in y.saturating_sub(1)..(y + GLYPH_HEIGHT + 1).min(SCREEN_HEIGHT) {
+        for column in x.saturating_sub(1)..(x - 1 + width).min(SCREEN_WIDTH) {
+            let pixel = &mut buffer[row * SCREEN_WIDTH + column];
+            *pixel = (*pixel >> 1) & 0x3DEF;
+        }
+    }
+
+    for (index, c) in text.chars().enumerate() {
+        let left = x + index * ADVANCE;
+        if left + GLYPH_WIDTH > SCREEN_WIDTH {
+            break;
+        }
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> column) != 0 {
+                    buffer[(y + row) * SCREEN_WIDTH + left + column] = WHITE;
+                }
+            }
+        }
+    }
+}
+
+// rows from top to bottom, bit 4 the leftmost pixel
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '?' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        // anything else shows as a hollow box
+        _ => [0x1F, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1F],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn many_watches_stay_on_screen() {
+        let mut osd = Osd::new(true);
+        osd.watches = (0..40).map(|index| format!("WATCH {}", index)).collect();
+        osd.message("MESSAGE");
+        let frame = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let composed = osd.compose(&frame);
+        assert_eq!(composed.len(), frame.len());
+
+        // on black only the letters show; the lowest watch line has to end
+        // above the box of the highest message there can be
+        osd.messages.clear();
+        let composed = osd.compose(&frame);
+        let lowest = (0..SCREEN_HEIGHT)
+            .rev()
+            .find(|row| composed[row * SCREEN_WIDTH..(row + 1) * SCREEN_WIDTH].contains(&WHITE))
+            .unwrap();
+        let message_top = SCREEN_HEIGHT - MARGIN - MAX_MESSAGES * LINE_HEIGHT;
+        assert!(lowest > MARGIN + LINE_HEIGHT, "no watch lines drawn");
+        assert!(lowest + 1 < message_top - 1, "watch line drawn at row {}, in the message area", lowest);
+    }
+
+    #[test]
+    fn text_past_the_bottom_is_left_out() {
+        let mut buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        draw_text(&mut buffer, MARGIN, SCREEN_HEIGHT - 2, "CUT");
+        assert!(buffer.iter().all(|&pixel| pixel == 0));
+    }
+}
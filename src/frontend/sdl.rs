@@ -0,0 +1,139 @@
+// SDL2 window showing the PPU framebuffer. Frames go through the video
+// filter into an RGBA texture, which SDL stretches over the viewport.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use sdl2::event::{Event, WindowEvent};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::mouse::MouseUtil;
+use sdl2::video::{FullscreenType, Window};
+use sdl2::EventPump;
+
+use super::filter::{Upscaler, VideoFilter};
+use super::keyboard::Keyboard;
+use super::{viewport, window_size, Frontend, WindowOptions, WINDOW_TITLE};
+
+pub struct SdlFrontend {
+    canvas: Canvas<Window>,
+    texture: Texture,
+    events: EventPump,
+    mouse: MouseUtil,
+    keyboard: Keyboard,
+    dropped: Option<PathBuf>,
+    integer_scaling: bool,
+    upscaler: Upscaler,
+    // the filtered frame, for the texture upload
+    pixels: Vec<u8>,
+}
+
+impl SdlFrontend {
+    pub fn open(options: WindowOptions) -> Result<Self, Box<dyn Error>> {
+        let sdl = sdl2::init()?;
+        let video = sdl.video()?;
+        let (width, height) = window_size(options.scale, options.orientation);
+        let window = video
+            .window(WINDOW_TITLE, width, height)
+            .position_centered()
+            .resizable()
+            .build()?;
+        let canvas = window.into_canvas().accelerated().build()?;
+        let upscaler = Upscaler::new(options.filter, options.orientation);
+        let texture = create_texture(&canvas, upscaler.size())?;
+        let (texture_width, texture_height) = upscaler.size();
+        let events = sdl.event_pump()?;
+        let mut frontend = SdlFrontend {
+            canvas,
+            texture,
+            events,
+            mouse: sdl.mouse(),
+            keyboard: Keyboard::default(),
+            dropped: None,
+            integer_scaling: options.integer_scaling,
+            upscaler,
+            pixels: vec![0; texture_width * texture_height * 4],
+        };
+        frontend.set_fullscreen(options.fullscreen)?;
+        Ok(frontend)
+    }
+}
+
+// streaming texture the size of the upscaler's output
+fn create_texture(canvas: &Canvas<Window>, (width, height): (usize, usize)) -> Result<Texture, Box<dyn Error>> {
+    let texture = canvas
+        .texture_creator()
+        .create_texture_streaming(PixelFormatEnum::RGBA32, width as u32, height as u32)?;
+    Ok(texture)
+}
+
+impl Frontend for SdlFrontend {
+    fn poll_events(&mut self) -> bool {
+        let mut open = true;
+        for event in self.events.poll_iter() {
+            match event {
+                Event::Quit { .. } => open = false,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => self.keyboard.press(&key.name()),
+                Event::KeyUp { keycode: Some(key), .. } => self.keyboard.release(&key.name()),
+                Event::DropFile { filename, .. } => self.dropped = Some(PathBuf::from(filename)),
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => self.keyboard.release_all(),
+                _ => {}
+            }
+        }
+        open
+    }
+
+    fn keyboard(&mut self) -> &mut Keyboard {
+        &mut self.keyboard
+    }
+
+    fn take_dropped_file(&mut self) -> Option<PathBuf> {
+        self.dropped.take()
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), Box<dyn Error>> {
+        let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        self.canvas.window_mut().set_fullscreen(mode)?;
+        self.mouse.show_cursor(!fullscreen);
+        Ok(())
+    }
+
+    fn fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+    fn set_filter(&mut self, filter: VideoFilter) -> Result<(), Box<dyn Error>> {
+        if filter == self.upscaler.filter() {
+            return Ok(());
+        }
+        let upscaler = Upscaler::new(filter, self.upscaler.orientation());
+        let old = std::mem::replace(&mut self.texture, create_texture(&self.canvas, upscaler.size())?);
+        // with unsafe_textures, freeing a texture is left to us
+        unsafe { old.destroy() };
+        let (width, height) = upscaler.size();
+        self.upscaler = upscaler;
+        self.pixels = vec![0; width * height * 4];
+        Ok(())
+    }
+
+    fn present(&mut self, frame: &[u16]) -> Result<(), Box<dyn Error>> {
+        self.upscaler.apply(frame, &mut self.pixels);
+        let (width, _) = self.upscaler.size();
+        self.texture.update(None, &self.pixels, width * 4)?;
+        let orientation = self.upscaler.orientation();
+        let (x, y, width, height) = viewport(self.canvas.output_size()?, orientation, self.integer_scaling);
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, Rect::new(x as i32, y as i32, width, height))?;
+        self.canvas.present();
+        Ok(())
+    }
+}
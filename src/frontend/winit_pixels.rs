@@ -0,0 +1,192 @@
+// Pure Rust window for systems without SDL2: winit for the window and
+// events, pixels to scale the framebuffer on the GPU. The event loop is
+// pumped from the emulation loop rather than owning it. pixels only scales
+// by whole numbers, so the picture is always integer scaled here, and the
+// window can't be made smaller than the video filter's output.
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::application::ApplicationHandler;
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{ElementState, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::{Fullscreen, Window, WindowId};
+
+use super::filter::{Upscaler, VideoFilter};
+use super::keyboard::Keyboard;
+use super::{window_size, Frontend, WindowOptions, WINDOW_TITLE};
+
+// window state, created once the event loop first resumes
+struct App {
+    options: WindowOptions,
+    window: Option<Arc<Window>>,
+    pixels: Option<Pixels<'static>>,
+    closed: bool,
+    keyboard: Keyboard,
+    dropped: Option<PathBuf>,
+    error: Option<Box<dyn Error>>,
+}
+
+impl App {
+    fn create_window(&mut self, event_loop: &ActiveEventLoop) -> Result<(), Box<dyn Error>> {
+        let factor = self.options.filter.factor() as u32;
+        let (width, height) = window_size(self.options.scale.max(factor), self.options.orientation);
+        let (buffer_width, buffer_height) = self.options.orientation.size(self.options.filter.size());
+        let attributes = Window::default_attributes()
+            .with_title(WINDOW_TITLE)
+            .with_inner_size(LogicalSize::new(width, height))
+            .with_min_inner_size(PhysicalSize::new(buffer_width as u32, buffer_height as u32))
+            .with_fullscreen(self.options.fullscreen.then_some(Fullscreen::Borderless(None)));
+        let window = Arc::new(event_loop.create_window(attributes)?);
+        window.set_cursor_visible(!self.options.fullscreen);
+        let size = window.inner_size();
+        let surface = SurfaceTexture::new(size.width, size.height, Arc::clone(&window));
+        self.pixels = Some(Pixels::new(buffer_width as u32, buffer_height as u32, surface)?);
+        self.window = Some(window);
+        Ok(())
+    }
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+        if let Err(err) = self.create_window(event_loop) {
+            self.error = Some(err);
+            event_loop.exit();
+        }
+    }
+
+    fn window_event(&mut self, _event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => self.closed = true,
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return;
+                };
+                match event.state {
+                    ElementState::Pressed if !event.repeat => self.keyboard.press(&key_name(code)),
+                    ElementState::Released => self.keyboard.release(&key_name(code)),
+                    _ => {}
+                }
+            }
+            WindowEvent::Focused(false) => self.keyboard.release_all(),
+            WindowEvent::DroppedFile(path) => self.dropped = Some(path),
+            WindowEvent::Resized(size) => {
+                if let Some(pixels) = &mut self.pixels {
+                    // a minimised window has no surface to resize
+                    let _ = pixels.resize_surface(size.width.max(1), size.height.max(1));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// SDL's name for a key, which bindings are written in. Letters, digits and
+// arrows differ only by a prefix; other keys keep winit's name.
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::Enter => return "Return".to_string(),
+        KeyCode::Equal => return "=".to_string(),
+        KeyCode::Minus => return "-".to_string(),
+        _ => {}
+    }
+    let name = format!("{:?}", code);
+    match ["Key", "Digit", "Arrow"].iter().find_map(|prefix| name.strip_prefix(prefix)) {
+        Some(short) => short.to_string(),
+        None => name,
+    }
+}
+
+pub struct WinitFrontend {
+    event_loop: EventLoop<()>,
+    app: App,
+    upscaler: Upscaler,
+}
+
+impl WinitFrontend {
+    pub fn open(options: WindowOptions) -> Result<Self, Box<dyn Error>> {
+        let mut event_loop = EventLoop::new()?;
+        let mut app = App {
+            options,
+            window: None,
+            pixels: None,
+            closed: false,
+            keyboard: Keyboard::default(),
+            dropped: None,
+            error: None,
+        };
+        event_loop.pump_app_events(Some(Duration::ZERO), &mut app);
+        if let Some(err) = app.error.take() {
+            return Err(err);
+        }
+        if app.pixels.is_none() {
+            return Err("the window was not created".into());
+        }
+        Ok(WinitFrontend {
+            event_loop,
+            app,
+            upscaler: Upscaler::new(options.filter, options.orientation),
+        })
+    }
+}
+
+impl Frontend for WinitFrontend {
+    fn poll_events(&mut self) -> bool {
+        match self.event_loop.pump_app_events(Some(Duration::ZERO), &mut self.app) {
+            PumpStatus::Exit(_) => false,
+            PumpStatus::Continue => !self.app.closed,
+        }
+    }
+
+    fn keyboard(&mut self) -> &mut Keyboard {
+        &mut self.app.keyboard
+    }
+
+    fn take_dropped_file(&mut self) -> Option<PathBuf> {
+        self.app.dropped.take()
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), Box<dyn Error>> {
+        if let Some(window) = &self.app.window {
+            window.set_fullscreen(fullscreen.then_some(Fullscreen::Borderless(None)));
+            window.set_cursor_visible(!fullscreen);
+        }
+        Ok(())
+    }
+
+    fn fullscreen(&self) -> bool {
+        self.app.window.as_ref().is_some_and(|window| window.fullscreen().is_some())
+    }
+
+    fn set_filter(&mut self, filter: VideoFilter) -> Result<(), Box<dyn Error>> {
+        let upscaler = Upscaler::new(filter, self.upscaler.orientation());
+        let (width, height) = upscaler.size();
+        if let Some(pixels) = &mut self.app.pixels {
+            pixels.resize_buffer(width as u32, height as u32)?;
+        }
+        if let Some(window) = &self.app.window {
+            window.set_min_inner_size(Some(PhysicalSize::new(width as u32, height as u32)));
+        }
+        self.app.options.filter = filter;
+        self.upscaler = upscaler;
+        Ok(())
+    }
+
+    fn present(&mut self, frame: &[u16]) -> Result<(), Box<dyn Error>> {
+        let Some(pixels) = &mut self.app.pixels else {
+            return Ok(());
+        };
+        self.upscaler.apply(frame, pixels.frame_mut());
+        pixels.render()?;
+        Ok(())
+    }
+}
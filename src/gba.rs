@@ -1,42 +1,563 @@
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::apu::{Apu, FRAME_SEQUENCER_CYCLES};
+use crate::archive;
+use crate::bios;
+use crate::breakpoints::{Breakpoints, InstructionSet};
+use crate::call_stack::CallStack;
+use crate::coverage::Coverage;
 use crate::cpu::Cpu;
-use crate::memory::Memory;
-use crate::ppu::Ppu;
+use crate::dma::{Dma, StartTiming};
+use crate::idle_loop::IdleLoopDetector;
+use crate::interrupts::Interrupt;
+use crate::keypad::{self, KeyState, KEYINPUT};
+use crate::memory::{Memory, SaveType};
+use crate::ppu::{Ppu, CLOCK_RATE, FRAME_CYCLES, HDRAW_CYCLES, SCANLINE_CYCLES, SCREEN_HEIGHT};
+use crate::profiler::Profiler;
+use crate::scheduler::{EventKind, Scheduler};
+use crate::serial::{self, Serial, SerialDevice};
+
+// Save states start with this, then the format version as a little endian
+// u32, then the machine in bincode. Bump the version whenever a change to
+// any serialized struct would make older states load wrong.
+const STATE_MAGIC: &[u8; 8] = b"AFTRIMGS";
+pub const STATE_VERSION: u32 = 3;
 
+#[derive(Serialize, Deserialize)]
 pub struct Gba {
     pub cpu: Cpu,
     pub memory: Memory,
     pub ppu: Ppu,
+    pub apu: Apu,
+    pub dma: Dma,
+    pub serial: Serial,
+    #[serde(skip)]
+    pub idle_loop: IdleLoopDetector,
+    #[serde(skip)]
+    pub breakpoints: Breakpoints,
+    #[serde(skip)]
+    pub call_stack: CallStack,
+    #[serde(skip)]
+    pub coverage: Coverage,
+    #[serde(skip)]
+    pub profiler: Profiler,
+    // set when the PPU enters VBlank, consumed by run_frame
+    frame_ready: bool,
+    // cycle STOP mode was entered on, while the system is stopped
+    stopped_since: Option<u64>,
+    pub scheduler: Scheduler,
     pub cycles: u64,
 }
 
 impl Gba {
     pub fn new() -> Self {
-        Gba {
+        let mut gba = Gba {
             cpu: Cpu::new(),
             memory: Memory::new(),
             ppu: Ppu::new(),
+            apu: Apu::new(),
+            dma: Dma::new(),
+            serial: Serial::new(),
+            idle_loop: IdleLoopDetector::new(),
+            breakpoints: Breakpoints::default(),
+            call_stack: CallStack::default(),
+            coverage: Coverage::default(),
+            profiler: Profiler::default(),
+            frame_ready: false,
+            stopped_since: None,
+            scheduler: Scheduler::new(),
             cycles: 0,
+        };
+
+        gba.ppu.begin_line(&mut gba.memory, 0);
+        gba.scheduler.schedule(HDRAW_CYCLES, EventKind::HBlank);
+        gba.scheduler.schedule(gba.apu.sample_period(), EventKind::ApuSample);
+        gba.scheduler.schedule(FRAME_SEQUENCER_CYCLES, EventKind::FrameSequencer);
+        gba
+    }
+
+    // Power cycles the console, keeping the cartridge with its save and
+    // clock, the BIOS image, the audio output and frontend settings.
+    pub fn reset(&mut self) {
+        let rom = std::mem::take(&mut self.memory.rom);
+        let sram = std::mem::take(&mut self.memory.sram);
+        let sram_dirty = self.memory.sram_dirty;
+        let save_type = self.memory.save_type;
+        let has_rtc = self.memory.has_rtc;
+        // the clock runs on through a power cycle, which starts the cycle
+        // count over
+        let mut rtc = std::mem::take(&mut self.memory.rtc);
+        rtc.start += self.memory.now / CLOCK_RATE;
+        let bios = std::mem::take(&mut self.memory.bios);
+        let link_id = self.memory.link_id;
+        let keys = self.keys();
+        let idle_skip = self.idle_loop.enabled;
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let mut call_stack = std::mem::take(&mut self.call_stack);
+        call_stack.clear();
+        let coverage = std::mem::take(&mut self.coverage);
+        let profiler = std::mem::take(&mut self.profiler);
+        let watchpoints = std::mem::take(&mut self.memory.watchpoints);
+        let layers = self.ppu.layers;
+        let device = self.serial.detach(&mut self.memory);
+        let mut apu = std::mem::take(&mut self.apu);
+        apu.reset();
+
+        *self = Gba::new();
+        self.memory.rom = rom;
+        self.memory.sram = sram;
+        self.memory.sram_dirty = sram_dirty;
+        self.memory.save_type = save_type;
+        self.memory.has_rtc = has_rtc;
+        self.memory.rtc = rtc;
+        self.memory.bios = bios;
+        self.memory.link_id = link_id;
+        match device {
+            Some(device) => self.serial.attach(&mut self.memory, device),
+            None => serial::update_lines(&mut self.memory),
         }
+        self.set_keys(keys);
+        self.idle_loop.enabled = idle_skip;
+        self.breakpoints = breakpoints;
+        self.call_stack = call_stack;
+        self.coverage = coverage;
+        self.profiler = profiler;
+        self.memory.watchpoints = watchpoints;
+        self.ppu.layers = layers;
+        self.apu = apu;
+    }
+
+    // What the BIOS SoftReset call does, for frontends offering a reset that
+    // keeps RAM intact.
+    pub fn soft_reset(&mut self) {
+        bios::soft_reset(&mut self.cpu, &mut self.memory);
     }
 
     pub fn load_rom(&mut self, path: &str) -> Result<(), std::io::Error> {
         self.memory.load_rom(path)
     }
 
+    // for ROMs that don't come from a file
+    pub fn load_rom_data(&mut self, rom: Vec<u8>) {
+        self.memory.rom = rom;
+    }
+
+    // Puts a different cartridge in and power cycles, first writing the
+    // old game's save to save_path if it has changed. The old game keeps
+    // running if the new ROM can't be read or the save can't be written.
+    // Loading the new game's save is up to the caller, and any coverage
+    // being recorded starts over for the new game.
+    pub fn swap_rom(&mut self, rom: &Path, save_path: Option<&Path>) -> io::Result<()> {
+        let rom = archive::read_rom(rom)?;
+        if let Some(path) = save_path
+            && self.memory.save_type != SaveType::None
+            && self.memory.sram_dirty
+        {
+            self.memory.save_sram(path)?;
+        }
+        if self.coverage.recording() {
+            self.coverage.start(rom.len());
+        }
+        self.memory.rom = rom;
+        self.memory.sram.fill(0xFF);
+        self.memory.sram_dirty = false;
+        self.memory.save_type = SaveType::default();
+        self.memory.has_rtc = false;
+        self.reset();
+        Ok(())
+    }
+
+    // Snapshots the whole machine, to be restored by load_state. The
+    // cartridge ROM and BIOS are not included, but SRAM is.
+    pub fn save_state(&self) -> io::Result<Vec<u8>> {
+        let mut state = STATE_MAGIC.to_vec();
+        state.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut state, self).map_err(io::Error::other)?;
+        Ok(state)
+    }
+
+    // Restores a snapshot from save_state, keeping the cartridge, BIOS,
+    // serial device and frontend settings like reset does. The machine is
+    // left untouched if the state can't be read.
+    pub fn load_state(&mut self, state: &[u8]) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let state = state
+            .strip_prefix(STATE_MAGIC.as_slice())
+            .ok_or_else(|| invalid("not a save state".to_string()))?;
+        let (version, payload) = state
+            .split_first_chunk::<4>()
+            .ok_or_else(|| invalid("save state is truncated".to_string()))?;
+        let version = u32::from_le_bytes(*version);
+        if version != STATE_VERSION {
+            return Err(invalid(format!(
+                "save state is version {}, this build reads version {}",
+                version, STATE_VERSION
+            )));
+        }
+        let mut loaded: Gba =
+            bincode::deserialize(payload).map_err(|e| invalid(format!("corrupt save state: {}", e)))?;
+
+        let sram_changed = loaded.memory.sram != self.memory.sram;
+        loaded.memory.rom = std::mem::take(&mut self.memory.rom);
+        loaded.memory.bios = std::mem::take(&mut self.memory.bios);
+        loaded.memory.save_type = self.memory.save_type;
+        loaded.memory.has_rtc = self.memory.has_rtc;
+        loaded.memory.link_id = self.memory.link_id;
+        loaded.memory.sram_dirty = self.memory.sram_dirty || sram_changed;
+        match self.serial.detach(&mut self.memory) {
+            Some(device) => loaded.serial.attach(&mut loaded.memory, device),
+            None => serial::update_lines(&mut loaded.memory),
+        }
+        loaded.idle_loop.enabled = self.idle_loop.enabled;
+        loaded.breakpoints = std::mem::take(&mut self.breakpoints);
+        // the calls the state was saved in aren't known
+        loaded.call_stack = std::mem::take(&mut self.call_stack);
+        loaded.call_stack.clear();
+        loaded.coverage = std::mem::take(&mut self.coverage);
+        loaded.profiler = std::mem::take(&mut self.profiler);
+        loaded.memory.watchpoints = std::mem::take(&mut self.memory.watchpoints);
+        loaded.ppu.layers = self.ppu.layers;
+        loaded.ppu.skip_drawing = self.ppu.skip_drawing;
+        let apu = std::mem::take(&mut loaded.apu);
+        self.apu.restore(apu);
+        loaded.apu = std::mem::take(&mut self.apu);
+        *self = loaded;
+        Ok(())
+    }
+
+    // Runs the CPU up to the next scheduled event, then handles every
+    // event that has come due. A breakpoint or watchpoint stops it sooner.
     pub fn step(&mut self) {
-        self.cpu.step(&mut self.memory);
-        
-        self.ppu.step(&self.memory);
-        
-        self.cycles += 1;
-        
-        // TODO: Handle interrupts, timers, DMA, etc.
+        self.advance(u64::MAX);
     }
 
+    // Runs until cycle target, finishing the instruction in flight when
+    // it is reached, so the clock may end a few cycles past it. A
+    // breakpoint or watchpoint stops it sooner.
+    pub fn run_until(&mut self, target: u64) {
+        while self.cycles < target {
+            self.advance(target);
+            if self.debug_stopped() {
+                return;
+            }
+        }
+    }
+
+    pub fn run_for(&mut self, cycles: u64) {
+        self.run_until(self.cycles + cycles);
+    }
+
+    // Whether the last run stopped at a breakpoint or watchpoint, which
+    // breakpoints.hit() and memory.watchpoints.hit() tell apart.
+    pub fn debug_stopped(&self) -> bool {
+        self.breakpoints.hit().is_some() || self.memory.watchpoints.hit().is_some()
+    }
+
+    // Runs a single instruction, for debuggers, unless it is one a
+    // breakpoint stops before. A halted CPU instead waits out the time to
+    // the next event, so stepping never hangs on it.
+    pub fn step_instruction(&mut self) {
+        let limit = if self.cpu.halted && !self.memory.interrupt_requested() {
+            self.scheduler.next_time()
+        } else {
+            self.cycles + 1
+        };
+        self.advance(limit);
+    }
+
+    // Like step, but stops the CPU at limit if that comes first.
+    fn advance(&mut self, limit: u64) {
+        self.breakpoints.resume();
+        self.memory.watchpoints.resume();
+        if !self.wake_from_stop(limit) {
+            return;
+        }
+
+        // the CPU may schedule earlier events, e.g. by starting a timer
+        while self.cycles < self.scheduler.next_time().min(limit) {
+            // keeps mid-line video writes pixel accurate; cheap when idle
+            self.ppu.catch_up(&mut self.memory, self.cycles);
+
+            if self.cpu.halted {
+                if !self.memory.interrupt_requested() {
+                    // nothing can happen before the next event
+                    let wake = self.scheduler.next_time().min(limit);
+                    if self.profiler.running() {
+                        self.profiler.record_halted(wake - self.cycles);
+                    }
+                    self.cycles = wake;
+                    continue;
+                }
+                self.cpu.halted = false;
+            }
+            if self.memory.interrupt_pending() && self.cpu.irq_enabled() {
+                let interrupted = self.cpu.pc;
+                self.cpu.enter_irq();
+                bios::irq_entry(&mut self.cpu, &mut self.memory);
+                if self.call_stack.enabled() {
+                    self.call_stack.interrupted(interrupted, &self.cpu, self.cycles);
+                }
+            } else if bios::at_irq_return(&self.cpu) {
+                bios::irq_return(&mut self.cpu, &mut self.memory);
+                if self.call_stack.enabled() {
+                    self.call_stack.returned_to(self.cpu.pc, self.cycles);
+                }
+                continue;
+            }
+            if !self.breakpoints.is_empty() && self.breakpoints.check(&self.cpu, &self.memory, self.cycles) {
+                return;
+            }
+            self.memory.now = self.cycles;
+            let pc = self.cpu.pc;
+            let thumb = self.cpu.thumb_mode;
+            let width = if thumb { 2 } else { 4 };
+            self.coverage.record(pc, InstructionSet::of(thumb));
+            self.memory.watchpoints.pc = pc;
+            let started = self.cycles;
+            self.cycles += self.cpu.step(&mut self.memory) as u64;
+            if self.call_stack.enabled() {
+                self.call_stack.instruction_ran(pc, width, &self.cpu, &self.memory, self.cycles);
+            }
+            if self.idle_loop.check(&self.cpu, &self.memory, pc) {
+                // only an event can break the loop
+                self.cycles = self.cycles.max(self.scheduler.next_time().min(limit));
+            }
+            // the loop's own address gets the time it skips
+            if self.profiler.running() {
+                self.profiler.record(pc, thumb, self.cycles - started);
+            }
+
+            if !self.memory.sound_writes.is_empty() {
+                self.apu.catch_up(&mut self.memory, self.cycles);
+            }
+            if self.memory.dma_started.contains(&true) {
+                // the CPU is stalled while the transfer owns the bus
+                let stall = self.dma.step(&mut self.memory);
+                self.stall_for_dma(stall);
+            }
+            if self.memory.timers.rescheduled.contains(&true) {
+                self.reschedule_timers();
+            }
+            if self.memory.serial_started {
+                self.memory.serial_started = false;
+                if let Some(duration) = self.serial.start(&mut self.memory) {
+                    self.scheduler.cancel(EventKind::SerialTransfer);
+                    self.scheduler.schedule(self.cycles + duration, EventKind::SerialTransfer);
+                }
+            }
+            if self.memory.halt_requested {
+                self.memory.halt_requested = false;
+                self.cpu.halted = true;
+            }
+            if self.memory.stop_requested {
+                self.memory.stop_requested = false;
+                self.stopped_since = Some(self.cycles);
+                return;
+            }
+            if self.memory.watchpoints.hit().is_some() {
+                return;
+            }
+        }
+
+        while let Some((time, kind)) = self.scheduler.pop_due(self.cycles) {
+            self.handle_event(time, kind);
+        }
+    }
+
+    fn stall_for_dma(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        if self.profiler.running() {
+            self.profiler.record_dma(cycles);
+        }
+    }
+
+    fn handle_event(&mut self, time: u64, kind: EventKind) {
+        match kind {
+            EventKind::HBlank => {
+                self.ppu.hblank(&mut self.memory);
+                // HBlank DMA only fires on visible lines
+                if (self.ppu.vcount as usize) < SCREEN_HEIGHT {
+                    let stall = self.dma.trigger(&mut self.memory, StartTiming::HBlank);
+                    self.stall_for_dma(stall);
+                }
+                self.scheduler.schedule(time + SCANLINE_CYCLES - HDRAW_CYCLES, EventKind::LineEnd);
+            }
+            EventKind::LineEnd => {
+                self.ppu.end_line(&mut self.memory, time);
+                if self.ppu.vcount as usize == SCREEN_HEIGHT {
+                    self.frame_ready = true;
+                    let stall = self.dma.trigger(&mut self.memory, StartTiming::VBlank);
+                    self.stall_for_dma(stall);
+                }
+                let stall = self.dma.video_capture(&mut self.memory, self.ppu.vcount);
+                self.stall_for_dma(stall);
+                self.service_serial(time);
+                self.scheduler.schedule(time + HDRAW_CYCLES, EventKind::HBlank);
+            }
+            EventKind::ApuSample => {
+                self.apu.catch_up(&mut self.memory, time);
+                self.apu.output_sample();
+                self.scheduler.schedule(time + self.apu.sample_period(), EventKind::ApuSample);
+            }
+            EventKind::FrameSequencer => {
+                self.apu.catch_up(&mut self.memory, time);
+                self.apu.step_frame_sequencer(&mut self.memory);
+                self.scheduler.schedule(time + FRAME_SEQUENCER_CYCLES, EventKind::FrameSequencer);
+            }
+            EventKind::SerialTransfer => self.serial.complete(&mut self.memory),
+            EventKind::TimerOverflow(timer) => {
+                self.memory.timers.overflow(timer, time);
+                self.schedule_timer(timer);
+                self.timer_overflowed(timer, time);
+            }
+        }
+    }
+
+    // In STOP mode the video, sound and timers are frozen and only a keypad,
+    // serial or cartridge interrupt wakes the system. Time still passes, a
+    // frame at a time, so frontends keep presenting and polling input.
+    // Returns whether the system is running again.
+    fn wake_from_stop(&mut self, limit: u64) -> bool {
+        let Some(since) = self.stopped_since else {
+            return true;
+        };
+        // an external clock still drives the serial port
+        self.serial.sync_lines(&mut self.memory);
+        if self.serial.poll_clock().is_some() {
+            self.serial.complete(&mut self.memory);
+        }
+
+        if self.memory.stop_wake_requested() {
+            self.stopped_since = None;
+            let delay = self.cycles - since;
+            self.scheduler.postpone(delay);
+            self.memory.timers.postpone(delay);
+            self.ppu.postpone(delay);
+            self.apu.postpone(delay);
+            return true;
+        }
+        self.cycles = limit.min(self.cycles + FRAME_CYCLES);
+        self.frame_ready = true;
+        false
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped_since.is_some()
+    }
+
+    // devices change their lines and clock transfers on their own time;
+    // once a scanline is often enough to notice
+    fn service_serial(&mut self, time: u64) {
+        self.serial.sync_lines(&mut self.memory);
+        if let Some(duration) = self.serial.poll_clock() {
+            self.scheduler.schedule(time + duration, EventKind::SerialTransfer);
+        }
+    }
+
+    fn reschedule_timers(&mut self) {
+        for timer in 0..4 {
+            if std::mem::take(&mut self.memory.timers.rescheduled[timer]) {
+                self.scheduler.cancel(EventKind::TimerOverflow(timer));
+                self.schedule_timer(timer);
+            }
+        }
+    }
+
+    fn schedule_timer(&mut self, timer: usize) {
+        if let Some(time) = self.memory.timers.next_overflow(timer) {
+            self.scheduler.schedule(time, EventKind::TimerOverflow(timer));
+        }
+    }
+
+    // side effects of a timer overflow on the rest of the system
+    fn timer_overflowed(&mut self, timer: usize, time: u64) {
+        if self.memory.timers.irq_enabled(timer) {
+            self.memory.request_interrupt(Interrupt::timer(timer));
+        }
+        // timers 0 and 1 clock the Direct Sound FIFOs
+        if timer < 2 {
+            self.apu.catch_up(&mut self.memory, time);
+            self.apu.timer_overflow(timer);
+            self.service_fifo_requests();
+        }
+        if timer < 3 && self.memory.timers.count_up(timer + 1) {
+            self.timer_overflowed(timer + 1, time);
+        }
+    }
+
+    // hands FIFO refill requests raised by the APU to the sound DMA channels
+    fn service_fifo_requests(&mut self) {
+        for fifo in 0..2 {
+            if self.apu.take_fifo_request(fifo) {
+                let stall = self.dma.sound_fifo_request(&mut self.memory, fifo);
+                self.stall_for_dma(stall);
+            }
+        }
+    }
+
+    // Sets which keys are held; the game sees them from its next read of KEYINPUT.
+    pub fn set_keys(&mut self, keys: KeyState) {
+        self.memory.set_io_u16(KEYINPUT, keys.register());
+        keypad::check_keypad_irq(&mut self.memory);
+    }
+
+    pub fn keys(&self) -> KeyState {
+        KeyState(!self.memory.io_u16(KEYINPUT) & KeyState::ALL.0)
+    }
+
+    // Plugs a peripheral into the serial port, replacing any already there.
+    pub fn attach_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.attach(&mut self.memory, device);
+    }
+
+    pub fn detach_serial_device(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.serial.detach(&mut self.memory)
+    }
+
+    // Pulls interleaved stereo samples at the APU's output rate, returning
+    // how many were written. Only whole left/right frames are read.
+    pub fn read_audio_samples(&mut self, out: &mut [i16]) -> usize {
+        self.apu.read_samples(out)
+    }
+
+    // The last complete frame, SCREEN_WIDTH x SCREEN_HEIGHT BGR555 pixels
+    // in rows from the top.
+    pub fn frame_buffer(&self) -> &[u16] {
+        &self.ppu.frame_buffer
+    }
+
+    // Saves the last complete frame as a PNG.
+    pub fn screenshot(&self, path: &Path) -> io::Result<()> {
+        self.ppu.frame_image().save_png(path)
+    }
+
+    // Runs until the PPU enters VBlank, when the frame buffer holds a
+    // complete picture, or a breakpoint or watchpoint stops it partway. The
+    // next call finishes a frame stopped partway.
     pub fn run_frame(&mut self) {
-        let target_cycles = self.cycles + 280_896;
-        while self.cycles < target_cycles {
+        self.frame_ready = false;
+        while !self.frame_ready {
             self.step();
+            if self.debug_stopped() {
+                return;
+            }
         }
     }
+
+    // Runs a frame without drawing it, for frame skipping. Timing,
+    // interrupts and DMA are exactly as in run_frame.
+    pub fn skip_frame(&mut self) {
+        self.ppu.skip_drawing = true;
+        self.run_frame();
+        self.ppu.skip_drawing = false;
+    }
+}
+
+impl Default for Gba {
+    fn default() -> Self {
+        Gba::new()
+    }
 }
@@ -0,0 +1,406 @@
+// A GDB remote stub, with --gdb PORT: the game waits for GDB to attach
+// before it runs, e.g. with
+//
+//     arm-none-eabi-gdb game.elf -ex "target remote localhost:PORT"
+//
+// and GDB can then read and write the registers and memory, step
+// instructions, and set breakpoints and watchpoints. Detaching lets
+// the game carry on as usual. While GDB has the game stopped the window
+// isn't redrawn and takes no input.
+
+use std::marker::PhantomData;
+use std::net::{TcpListener, TcpStream};
+use std::num::NonZeroUsize;
+
+use afterimage::Gba;
+use afterimage::breakpoints::InstructionSet;
+use afterimage::cpu::CpuMode;
+use afterimage::watchpoints;
+use gdbstub::arch::{Arch, RegId, Registers};
+use gdbstub::common::Signal;
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::run_blocking::{BlockingEventLoop, Event, WaitForStopReasonError};
+use gdbstub::stub::{DisconnectReason, GdbStub, SingleThreadStopReason};
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep, SingleThreadSingleStepOps,
+};
+use gdbstub::target::ext::breakpoints::{
+    Breakpoints, BreakpointsOps, HwWatchpoint, HwWatchpointOps, SwBreakpoint, SwBreakpointOps, WatchKind,
+};
+use gdbstub::target::{Target, TargetResult};
+
+use crate::frontend::bindings;
+use crate::frontend::Frontend;
+use crate::pacing::FramePacer;
+
+// runs between checks for a Ctrl-C from GDB
+const POLL_INTERVAL: u32 = 4096;
+// the line the PPU enters VBlank on, when a frame is ready to show
+const VBLANK_LINE: u16 = 160;
+const ROM_BASE: u32 = 0x0800_0000;
+const ROM_END: u32 = 0x0E00_0000;
+const THUMB_BIT: u32 = 0x20;
+
+// r0-r12, sp, lr, pc and cpsr, in the order they go over the wire
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>armv4t</architecture>
+  <feature name="org.gnu.gdb.arm.core">
+    <reg name="r0" bitsize="32"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="lr" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="cpsr" bitsize="32"/>
+  </feature>
+</target>
+"#;
+
+// The ARM7TDMI as GDB sees it. gdbstub_arch has one, but not for the
+// gdbstub we use.
+enum Armv4t {}
+
+impl Arch for Armv4t {
+    type Usize = u32;
+    type Registers = ArmRegisters;
+    // 2 for a Thumb breakpoint and 4 for an ARM one
+    type BreakpointKind = usize;
+    type RegId = ArmRegId;
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(TARGET_XML)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct ArmRegisters {
+    // r0-r15 of the current mode
+    r: [u32; 16],
+    cpsr: u32,
+}
+
+impl Registers for ArmRegisters {
+    type ProgramCounter = u32;
+
+    fn pc(&self) -> u32 {
+        self.r[15]
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for register in self.r.iter().chain([&self.cpsr]) {
+            register.to_le_bytes().into_iter().for_each(|byte| write_byte(Some(byte)));
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() != 17 * 4 {
+            return Err(());
+        }
+        let mut words = bytes.chunks_exact(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        for register in self.r.iter_mut().chain([&mut self.cpsr]) {
+            *register = words.next().ok_or(())?;
+        }
+        Ok(())
+    }
+}
+
+// Any of the registers in TARGET_XML. Registers are only read and written
+// all together, so which one isn't kept.
+#[derive(Debug)]
+struct ArmRegId;
+
+impl RegId for ArmRegId {
+    fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+        (id <= 16).then(|| (ArmRegId, NonZeroUsize::new(4)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Step,
+    Continue,
+}
+
+struct Session<'a> {
+    gba: &'a mut Gba,
+    window: Option<&'a mut dyn Frontend>,
+    pacer: FramePacer,
+    resume: Resume,
+}
+
+impl Session<'_> {
+    // Runs an instruction, or on to the next event, and the reason to stop
+    // after it if there is one.
+    fn run(&mut self, single_step: bool) -> Option<SingleThreadStopReason<u32>> {
+        let line = self.gba.ppu.vcount;
+        if single_step {
+            self.gba.breakpoints.skip(self.gba.cpu.pc);
+            self.gba.step_instruction();
+        } else {
+            self.gba.step();
+        }
+        if line != VBLANK_LINE && self.gba.ppu.vcount == VBLANK_LINE && !self.show_frame() {
+            return Some(SingleThreadStopReason::Exited(0));
+        }
+        if self.gba.breakpoints.hit().is_some() {
+            return Some(SingleThreadStopReason::SwBreak(()));
+        }
+        let hit = self.gba.memory.watchpoints.hit()?;
+        let kind = match hit.watchpoint.kind {
+            watchpoints::WatchKind::Read => WatchKind::Read,
+            watchpoints::WatchKind::Write | watchpoints::WatchKind::Change => WatchKind::Write,
+            watchpoints::WatchKind::Access => WatchKind::ReadWrite,
+        };
+        // the first watched byte the access touched
+        let addr = hit.address.max(hit.watchpoint.start);
+        Some(SingleThreadStopReason::Watch { tid: (), kind, addr })
+    }
+
+    // Shows the finished frame and takes the buttons held, at the usual
+    // speed. False once the window is closed.
+    fn show_frame(&mut self) -> bool {
+        let Some(window) = &mut self.window else {
+            return true;
+        };
+        if !window.poll_events() {
+            return false;
+        }
+        self.gba.set_keys(bindings::buttons(&window.keyboard().held(), false));
+        if let Err(err) = window.present(self.gba.frame_buffer()) {
+            println!("Could not draw the frame: {}", err);
+        }
+        self.pacer.wait();
+        true
+    }
+}
+
+impl Target for Session<'_> {
+    type Arch = Armv4t;
+    type Error = &'static str;
+
+    fn base_ops(&mut self) -> BaseOps<'_, Armv4t, &'static str> {
+        BaseOps::SingleThread(self)
+    }
+
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for Session<'_> {
+    fn read_registers(&mut self, regs: &mut ArmRegisters) -> TargetResult<(), Self> {
+        let cpu = &self.gba.cpu;
+        regs.r[..13].copy_from_slice(&cpu.registers);
+        regs.r[13] = cpu.sp;
+        regs.r[14] = cpu.lr;
+        regs.r[15] = cpu.pc;
+        regs.cpsr = if cpu.thumb_mode { cpu.cpsr | THUMB_BIT } else { cpu.cpsr & !THUMB_BIT };
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &ArmRegisters) -> TargetResult<(), Self> {
+        let cpu = &mut self.gba.cpu;
+        // the mode first, so sp and lr land in the new mode's bank
+        let mode = CpuMode::from_bits(regs.cpsr);
+        if mode != cpu.mode {
+            cpu.switch_mode(mode);
+        }
+        cpu.registers.copy_from_slice(&regs.r[..13]);
+        cpu.sp = regs.r[13];
+        cpu.lr = regs.r[14];
+        cpu.pc = regs.r[15];
+        cpu.cpsr = regs.cpsr;
+        cpu.thumb_mode = regs.cpsr & THUMB_BIT != 0;
+        Ok(())
+    }
+
+    fn read_addrs(&mut self, start_addr: u32, data: &mut [u8]) -> TargetResult<usize, Self> {
+        for (byte, address) in data.iter_mut().zip(start_addr..) {
+            *byte = self.gba.memory.read_u8(address);
+        }
+        Ok(data.len())
+    }
+
+    // Writes go through the bus as the CPU's would, except to the
+    // cartridge ROM, which GDB can patch.
+    fn write_addrs(&mut self, start_addr: u32, data: &[u8]) -> TargetResult<(), Self> {
+        for (&byte, address) in data.iter().zip(start_addr..) {
+            match address {
+                ROM_BASE..ROM_END => {
+                    let offset = ((address - ROM_BASE) & 0x01FF_FFFF) as usize;
+                    if let Some(rom) = self.gba.memory.rom.get_mut(offset) {
+                        *rom = byte;
+                    }
+                }
+                _ => self.gba.memory.write_u8(address, byte),
+            }
+        }
+        Ok(())
+    }
+
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadResume for Session<'_> {
+    fn resume(&mut self, _signal: Option<Signal>) -> Result<(), &'static str> {
+        self.resume = Resume::Continue;
+        Ok(())
+    }
+
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for Session<'_> {
+    fn step(&mut self, _signal: Option<Signal>) -> Result<(), &'static str> {
+        self.resume = Resume::Step;
+        Ok(())
+    }
+}
+
+impl Breakpoints for Session<'_> {
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+
+    fn support_hw_watchpoint(&mut self) -> Option<HwWatchpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+// The core's breakpoints, rather than breakpoint instructions written into
+// memory, shared with the console debugger.
+impl SwBreakpoint for Session<'_> {
+    fn add_sw_breakpoint(&mut self, addr: u32, kind: usize) -> TargetResult<bool, Self> {
+        let set = match kind {
+            2 => Some(InstructionSet::Thumb),
+            4 => Some(InstructionSet::Arm),
+            _ => None,
+        };
+        self.gba.breakpoints.add(addr, set, None);
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u32, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(self.gba.breakpoints.remove(addr))
+    }
+}
+
+// The core's watchpoints too, seeing every load and store the CPU and DMA
+// make, shared with the console debugger like the breakpoints.
+impl HwWatchpoint for Session<'_> {
+    fn add_hw_watchpoint(&mut self, addr: u32, len: u32, kind: WatchKind) -> TargetResult<bool, Self> {
+        if len == 0 {
+            return Ok(false);
+        }
+        self.gba.memory.watchpoints.add(addr, addr.saturating_add(len - 1), core_kind(kind));
+        Ok(true)
+    }
+
+    fn remove_hw_watchpoint(&mut self, addr: u32, len: u32, kind: WatchKind) -> TargetResult<bool, Self> {
+        let end = addr.saturating_add(len.max(1) - 1);
+        Ok(self.gba.memory.watchpoints.remove(addr, end, Some(core_kind(kind))))
+    }
+}
+
+fn core_kind(kind: WatchKind) -> watchpoints::WatchKind {
+    match kind {
+        WatchKind::Write => watchpoints::WatchKind::Write,
+        WatchKind::Read => watchpoints::WatchKind::Read,
+        WatchKind::ReadWrite => watchpoints::WatchKind::Access,
+    }
+}
+
+struct EventLoop<'a>(PhantomData<&'a mut Gba>);
+
+impl<'a> BlockingEventLoop for EventLoop<'a> {
+    type Target = Session<'a>;
+    type Connection = TcpStream;
+    type StopReason = SingleThreadStopReason<u32>;
+
+    fn wait_for_stop_reason(
+        session: &mut Session<'a>,
+        conn: &mut TcpStream,
+    ) -> Result<Event<Self::StopReason>, WaitForStopReasonError<&'static str, std::io::Error>> {
+        if session.resume == Resume::Step {
+            let stop = session.run(true);
+            return Ok(Event::TargetStopped(stop.unwrap_or(SingleThreadStopReason::DoneStep)));
+        }
+        let mut count = 0u32;
+        loop {
+            if let Some(stop) = session.run(false) {
+                return Ok(Event::TargetStopped(stop));
+            }
+            count = count.wrapping_add(1);
+            if count.is_multiple_of(POLL_INTERVAL)
+                && conn.peek().map_err(WaitForStopReasonError::Connection)?.is_some()
+            {
+                let byte = conn.read().map_err(WaitForStopReasonError::Connection)?;
+                return Ok(Event::IncomingData(byte));
+            }
+        }
+    }
+
+    fn on_interrupt(_session: &mut Session<'a>) -> Result<Option<Self::StopReason>, &'static str> {
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
+
+// Waits for GDB to attach on port and hands it the game until it detaches.
+// False when the session ended the emulator: the window was closed or GDB
+// killed it.
+pub fn serve<'a>(gba: &'a mut Gba, port: u16, window: Option<&'a mut dyn Frontend>) -> bool {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("Could not listen for GDB on port {}: {}", port, err);
+            return true;
+        }
+    };
+    println!("Waiting for GDB on port {}", port);
+    let stream = match listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(err) => {
+            println!("GDB could not connect: {}", err);
+            return true;
+        }
+    };
+    println!("GDB attached");
+    let mut session = Session {
+        gba,
+        window,
+        pacer: FramePacer::new(false),
+        resume: Resume::Continue,
+    };
+    match GdbStub::new(stream).run_blocking::<EventLoop>(&mut session) {
+        Ok(DisconnectReason::Disconnect) => {
+            println!("GDB detached");
+            true
+        }
+        Ok(DisconnectReason::TargetExited(_) | DisconnectReason::TargetTerminated(_)) => false,
+        Ok(DisconnectReason::Kill) => {
+            println!("GDB ended the session");
+            false
+        }
+        Err(err) => {
+            println!("GDB connection lost: {}", err);
+            true
+        }
+    }
+}
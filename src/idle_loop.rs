@@ -0,0 +1,66 @@
+// Idle loop detection. A short backward branch whose loop body changes no
+// registers, flags or memory between two passes can only be broken by
+// something the scheduler does, so the CPU can skip straight to the next
+// event. This covers branch-to-self and loops polling RAM an interrupt
+// handler sets, or registers like DISPSTAT, VCOUNT and IF that only change
+// at events. Loops reading a timer's counter never count as idle, as it can
+// read the same twice and then move on before the next event.
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+// longest loop body considered, in bytes
+const MAX_LOOP_BYTES: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LoopPass {
+    target: u32,
+    registers: [u32; 15],
+    cpsr: u32,
+    bus_writes: u64,
+    timer_reads: u64,
+}
+
+#[derive(Debug)]
+pub struct IdleLoopDetector {
+    pub enabled: bool,
+    last_pass: Option<LoopPass>,
+}
+
+impl IdleLoopDetector {
+    pub fn new() -> Self {
+        IdleLoopDetector {
+            enabled: true,
+            last_pass: None,
+        }
+    }
+
+    // Called after each instruction with the address it was fetched from.
+    // Returns true when the CPU is spinning in an idle loop.
+    pub fn check(&mut self, cpu: &Cpu, memory: &Memory, branch_pc: u32) -> bool {
+        if !self.enabled || cpu.pc > branch_pc || branch_pc - cpu.pc > MAX_LOOP_BYTES {
+            return false;
+        }
+
+        let mut registers = [0; 15];
+        registers[..13].copy_from_slice(&cpu.registers);
+        registers[13] = cpu.sp;
+        registers[14] = cpu.lr;
+        let pass = LoopPass {
+            target: cpu.pc,
+            registers,
+            cpsr: cpu.cpsr,
+            bus_writes: memory.bus_writes,
+            timer_reads: memory.timer_reads.get(),
+        };
+        let idle = self.last_pass == Some(pass);
+        self.last_pass = Some(pass);
+        idle
+    }
+}
+
+impl Default for IdleLoopDetector {
+    fn default() -> Self {
+        IdleLoopDetector::new()
+    }
+}
@@ -0,0 +1,40 @@
+// Interrupt sources, numbered by their bit in IE and IF.
+
+pub const IE: usize = 0x200;
+pub const IF: usize = 0x202;
+pub const IME: usize = 0x208;
+
+// IE and IF only have bits for the fourteen sources
+pub const INTERRUPT_MASK: u16 = 0x3FFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interrupt {
+    VBlank = 0,
+    HBlank = 1,
+    VCount = 2,
+    Timer0 = 3,
+    Timer1 = 4,
+    Timer2 = 5,
+    Timer3 = 6,
+    Serial = 7,
+    Dma0 = 8,
+    Dma1 = 9,
+    Dma2 = 10,
+    Dma3 = 11,
+    Keypad = 12,
+    GamePak = 13,
+}
+
+impl Interrupt {
+    pub fn timer(timer: usize) -> Self {
+        [Interrupt::Timer0, Interrupt::Timer1, Interrupt::Timer2, Interrupt::Timer3][timer]
+    }
+
+    pub fn dma(channel: usize) -> Self {
+        [Interrupt::Dma0, Interrupt::Dma1, Interrupt::Dma2, Interrupt::Dma3][channel]
+    }
+
+    pub fn mask(self) -> u16 {
+        1 << self as u16
+    }
+}
@@ -0,0 +1,108 @@
+// Keypad state as seen by KEYINPUT. KeyState holds pressed keys as set
+// bits; the register itself is active low.
+
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::interrupts::Interrupt;
+use crate::memory::Memory;
+
+pub const KEYINPUT: usize = 0x130;
+pub const KEYCNT: usize = 0x132;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct KeyState(pub u16);
+
+impl KeyState {
+    pub const A: KeyState = KeyState(1 << 0);
+    pub const B: KeyState = KeyState(1 << 1);
+    pub const SELECT: KeyState = KeyState(1 << 2);
+    pub const START: KeyState = KeyState(1 << 3);
+    pub const RIGHT: KeyState = KeyState(1 << 4);
+    pub const LEFT: KeyState = KeyState(1 << 5);
+    pub const UP: KeyState = KeyState(1 << 6);
+    pub const DOWN: KeyState = KeyState(1 << 7);
+    pub const R: KeyState = KeyState(1 << 8);
+    pub const L: KeyState = KeyState(1 << 9);
+
+    // held together, asks most games to soft reset
+    pub const RESET_COMBO: KeyState = KeyState(0x000F);
+
+    pub const NONE: KeyState = KeyState(0);
+    pub const ALL: KeyState = KeyState(0x3FF);
+
+    pub fn contains(self, keys: KeyState) -> bool {
+        self.0 & keys.0 == keys.0
+    }
+
+    pub fn insert(&mut self, keys: KeyState) {
+        self.0 |= keys.0;
+    }
+
+    pub fn remove(&mut self, keys: KeyState) {
+        self.0 &= !keys.0;
+    }
+
+    pub fn set(&mut self, keys: KeyState, pressed: bool) {
+        if pressed { self.insert(keys) } else { self.remove(keys) }
+    }
+
+    // KEYINPUT value: a cleared bit means pressed
+    pub fn register(self) -> u16 {
+        !self.0 & Self::ALL.0
+    }
+}
+
+impl BitOr for KeyState {
+    type Output = KeyState;
+
+    fn bitor(self, rhs: KeyState) -> KeyState {
+        KeyState(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for KeyState {
+    fn bitor_assign(&mut self, rhs: KeyState) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyCnt(pub u16);
+
+impl KeyCnt {
+    pub fn read(memory: &Memory) -> Self {
+        KeyCnt(memory.io_u16(KEYCNT))
+    }
+
+    pub fn keys(self) -> KeyState {
+        KeyState(self.0 & KeyState::ALL.0)
+    }
+
+    pub fn irq(self) -> bool {
+        self.0 & (1 << 14) != 0
+    }
+
+    // all selected keys must be held rather than any of them
+    pub fn and_mode(self) -> bool {
+        self.0 & (1 << 15) != 0
+    }
+
+    pub fn matches(self, pressed: KeyState) -> bool {
+        let selected = KeyState(pressed.0 & self.keys().0);
+        if self.and_mode() {
+            self.keys() != KeyState::NONE && selected == self.keys()
+        } else {
+            selected != KeyState::NONE
+        }
+    }
+}
+
+// The keypad interrupt is level triggered, so this runs whenever the keys,
+// KEYCNT or the acknowledged IF bits change.
+pub fn check_keypad_irq(memory: &mut Memory) {
+    let keycnt = KeyCnt::read(memory);
+    let pressed = KeyState(!memory.io_u16(KEYINPUT) & KeyState::ALL.0);
+    if keycnt.irq() && keycnt.matches(pressed) {
+        memory.request_interrupt(Interrupt::Keypad);
+    }
+}
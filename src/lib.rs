@@ -0,0 +1,53 @@
+// The emulator core: a Game Boy Advance with no window or audio device of
+// its own, for the afterimage frontend and anything else that embeds it.
+// Everything runs through Gba:
+//
+//     let mut gba = afterimage::Gba::new();
+//     gba.load_rom("game.gba")?;
+//     loop {
+//         gba.set_keys(keys);
+//         gba.run_frame();
+//         show(gba.frame_buffer());
+//         gba.read_audio_samples(&mut samples);
+//     }
+//
+// Frames are 240x160 BGR555 pixels and audio is interleaved stereo i16.
+// reset, save_sram/load_sram on gba.memory and save_state/load_state cover
+// the rest of a frontend's needs. The modules stay public for debuggers and
+// tools that need to look at the hardware directly.
+//
+// Nothing on the way from load_rom_data to a frame touches the filesystem,
+// so the core also builds for wasm32-unknown-unknown; the web feature adds
+// the bindings the browser frontend in web/ uses.
+
+pub mod apu;
+pub mod archive;
+pub mod bios;
+pub mod breakpoints;
+pub mod call_stack;
+pub mod coverage;
+pub mod cpu;
+pub mod disasm;
+pub mod dma;
+pub mod eeprom;
+pub mod gba;
+pub mod idle_loop;
+pub mod interrupts;
+pub mod keypad;
+pub mod link;
+pub mod memory;
+pub mod ppu;
+pub mod profiler;
+pub mod rtc;
+pub mod scheduler;
+pub mod serial;
+pub mod symbols;
+pub mod timers;
+pub mod watchpoints;
+#[cfg(feature = "web")]
+pub mod web;
+
+pub use gba::Gba;
+pub use keypad::KeyState;
+pub use memory::SaveType;
+pub use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
@@ -0,0 +1,87 @@
+// Link cable between Gba instances in the same process. The cable owns the
+// units and runs them in lockstep so multiplayer SIO transfers started by
+// the parent reach every child at the same emulated time.
+
+mod net;
+
+pub use net::NetLink;
+
+use crate::gba::Gba;
+use crate::ppu::{FRAME_CYCLES, SCANLINE_CYCLES};
+use crate::serial::{self, SerialMode, SIOMLT_SEND};
+
+// how far one unit may run ahead of the others, in cycles: a scanline
+const SLICE_CYCLES: u64 = SCANLINE_CYCLES;
+
+pub struct LinkCable {
+    units: Vec<Gba>,
+}
+
+impl LinkCable {
+    // Unit 0 is the parent. The cable has room for two to four units.
+    pub fn new(mut units: Vec<Gba>) -> Self {
+        assert!((2..=4).contains(&units.len()), "a link cable connects 2 to 4 units");
+        for (id, unit) in units.iter_mut().enumerate() {
+            unit.memory.link_id = Some(id);
+            serial::update_lines(&mut unit.memory);
+        }
+        LinkCable { units }
+    }
+
+    pub fn units(&self) -> &[Gba] {
+        &self.units
+    }
+
+    pub fn unit_mut(&mut self, id: usize) -> &mut Gba {
+        &mut self.units[id]
+    }
+
+    // unplugs the cable, handing back the units
+    pub fn disconnect(mut self) -> Vec<Gba> {
+        for unit in &mut self.units {
+            unit.memory.link_id = None;
+            serial::update_lines(&mut unit.memory);
+        }
+        self.units
+    }
+
+    pub fn run_frame(&mut self) {
+        let end = self.units[0].cycles + FRAME_CYCLES;
+        while self.units[0].cycles < end {
+            let target = (self.units[0].cycles + SLICE_CYCLES).min(end);
+            for unit in &mut self.units {
+                unit.run_until(target);
+            }
+            self.exchange();
+        }
+    }
+
+    fn exchange(&mut self) {
+        let (parent, children) = self.units.split_first_mut().unwrap();
+        if parent.serial.transfer_in_flight() {
+            for child in children.iter_mut() {
+                if SerialMode::read(&child.memory) == SerialMode::Multiplayer {
+                    child.serial.mark_busy(&mut child.memory);
+                }
+            }
+        }
+        if !parent.serial.take_exchange() {
+            return;
+        }
+
+        // units not in multiplayer mode don't drive the data line
+        let words: Vec<u16> = self
+            .units
+            .iter()
+            .map(|unit| match SerialMode::read(&unit.memory) {
+                SerialMode::Multiplayer => unit.memory.io_u16(SIOMLT_SEND),
+                _ => 0xFFFF,
+            })
+            .collect();
+        for unit in &mut self.units {
+            if SerialMode::read(&unit.memory) == SerialMode::Multiplayer {
+                unit.serial.finish_multiplayer(&mut unit.memory, &words);
+            }
+        }
+    }
+}
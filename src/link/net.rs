@@ -0,0 +1,266 @@
+// Link cable over TCP between two afterimage instances. The host is the
+// multiplayer parent and the connecting side its only child.
+//
+// To hide network latency neither side waits on the other per transfer:
+// each unit streams its SIOMLT_SEND word whenever it changes, so the parent
+// finishes a transfer at once with the last word it heard from the child,
+// and the child finishes when the parent's word arrives. Emulated time is
+// kept loosely in step by exchanging frame numbers and only blocking when
+// one side gets more than MAX_LEAD_FRAMES ahead.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use super::SLICE_CYCLES;
+use crate::gba::Gba;
+use crate::ppu::FRAME_CYCLES;
+use crate::serial::{self, SerialMode, SIOMLT_SEND};
+
+const MAGIC: &[u8; 4] = b"AFLK";
+const PROTOCOL_VERSION: u8 = 1;
+const MAX_LEAD_FRAMES: u64 = 2;
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Message {
+    // magic and version, then the id the host assigns the client
+    Hello { version: u8, id: u8 },
+    // the sender's SIOMLT_SEND changed
+    Send(u16),
+    // the parent finished a multiplayer transfer carrying this word
+    Transfer(u16),
+    // the sender finished emulating this frame
+    Frame(u64),
+}
+
+impl Message {
+    fn encode(self, out: &mut Vec<u8>) {
+        match self {
+            Message::Hello { version, id } => {
+                out.push(0);
+                out.extend_from_slice(MAGIC);
+                out.push(version);
+                out.push(id);
+            }
+            Message::Send(word) => {
+                out.push(1);
+                out.write_u16::<LittleEndian>(word).unwrap();
+            }
+            Message::Transfer(word) => {
+                out.push(2);
+                out.write_u16::<LittleEndian>(word).unwrap();
+            }
+            Message::Frame(frame) => {
+                out.push(3);
+                out.write_u64::<LittleEndian>(frame).unwrap();
+            }
+        }
+    }
+
+    // Decodes one message from the front of buf, returning it and its
+    // length, or None if it hasn't fully arrived yet.
+    fn decode(buf: &[u8]) -> io::Result<Option<(Message, usize)>> {
+        let Some(&tag) = buf.first() else {
+            return Ok(None);
+        };
+        let len = match tag {
+            0 => 7,
+            1 | 2 => 3,
+            3 => 9,
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown link message {}", tag))),
+        };
+        if buf.len() < len {
+            return Ok(None);
+        }
+        let message = match tag {
+            0 => {
+                if &buf[1..5] != MAGIC {
+                    return Err(io::Error::new(ErrorKind::InvalidData, "peer is not an afterimage link"));
+                }
+                Message::Hello { version: buf[5], id: buf[6] }
+            }
+            1 => Message::Send(LittleEndian::read_u16(&buf[1..])),
+            2 => Message::Transfer(LittleEndian::read_u16(&buf[1..])),
+            _ => Message::Frame(LittleEndian::read_u64(&buf[1..])),
+        };
+        Ok(Some((message, len)))
+    }
+}
+
+pub struct NetLink {
+    stream: TcpStream,
+    id: usize,
+    incoming: Vec<u8>,
+    // latest SIOMLT_SEND of the other side, and the last one we sent
+    remote_send: u16,
+    local_send: Option<u16>,
+    // parent words of transfers the child hasn't finished yet
+    transfers: Vec<u16>,
+    frame: u64,
+    remote_frame: u64,
+}
+
+impl NetLink {
+    // Waits for one client to connect and becomes the parent.
+    pub fn host(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        let mut link = NetLink::new(stream, 0)?;
+        link.send(Message::Hello { version: PROTOCOL_VERSION, id: 1 })?;
+        match link.receive_blocking()? {
+            Message::Hello { version: PROTOCOL_VERSION, .. } => Ok(link),
+            message => Err(handshake_error(message)),
+        }
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        let mut link = NetLink::new(stream, 0)?;
+        match link.receive_blocking()? {
+            Message::Hello { version: PROTOCOL_VERSION, id } => link.id = id as usize,
+            message => return Err(handshake_error(message)),
+        }
+        link.send(Message::Hello { version: PROTOCOL_VERSION, id: link.id as u8 })?;
+        Ok(link)
+    }
+
+    fn new(stream: TcpStream, id: usize) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(PEER_TIMEOUT))?;
+        Ok(NetLink {
+            stream,
+            id,
+            incoming: Vec::new(),
+            remote_send: 0xFFFF,
+            local_send: None,
+            transfers: Vec::new(),
+            frame: 0,
+            remote_frame: 0,
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // Plugs the Gba into this end of the link.
+    pub fn attach(&self, gba: &mut Gba) {
+        gba.memory.link_id = Some(self.id);
+        serial::update_lines(&mut gba.memory);
+    }
+
+    pub fn detach(&self, gba: &mut Gba) {
+        gba.memory.link_id = None;
+        serial::update_lines(&mut gba.memory);
+    }
+
+    pub fn run_frame(&mut self, gba: &mut Gba) -> io::Result<()> {
+        let end = gba.cycles + FRAME_CYCLES;
+        while gba.cycles < end {
+            let target = (gba.cycles + SLICE_CYCLES).min(end);
+            gba.run_until(target);
+            self.poll()?;
+            self.exchange(gba)?;
+        }
+
+        self.frame += 1;
+        self.send(Message::Frame(self.frame))?;
+        while self.frame > self.remote_frame + MAX_LEAD_FRAMES {
+            let message = self.receive_blocking()?;
+            self.handle(message);
+        }
+        Ok(())
+    }
+
+    fn exchange(&mut self, gba: &mut Gba) -> io::Result<()> {
+        let multiplayer = SerialMode::read(&gba.memory) == SerialMode::Multiplayer;
+        let send = if multiplayer { gba.memory.io_u16(SIOMLT_SEND) } else { 0xFFFF };
+        if self.local_send != Some(send) {
+            self.local_send = Some(send);
+            self.send(Message::Send(send))?;
+        }
+
+        if self.id == 0 {
+            if gba.serial.take_exchange() {
+                gba.serial.finish_multiplayer(&mut gba.memory, &[send, self.remote_send]);
+                self.send(Message::Transfer(send))?;
+            }
+        } else {
+            for parent_word in std::mem::take(&mut self.transfers) {
+                if multiplayer {
+                    gba.serial.finish_multiplayer(&mut gba.memory, &[parent_word, send]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, message: Message) {
+        match message {
+            Message::Send(word) => self.remote_send = word,
+            Message::Transfer(word) => self.transfers.push(word),
+            Message::Frame(frame) => self.remote_frame = frame,
+            // a repeated hello is harmless
+            Message::Hello { .. } => {}
+        }
+    }
+
+    fn send(&mut self, message: Message) -> io::Result<()> {
+        let mut out = Vec::with_capacity(9);
+        message.encode(&mut out);
+        self.stream.write_all(&out)
+    }
+
+    // handles whatever has arrived without waiting for more
+    fn poll(&mut self) -> io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0; 256];
+        let result = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err(io::Error::new(ErrorKind::ConnectionAborted, "link peer disconnected")),
+                Ok(len) => self.incoming.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        while let Some(message) = self.next_message()? {
+            self.handle(message);
+        }
+        Ok(())
+    }
+
+    fn receive_blocking(&mut self) -> io::Result<Message> {
+        let mut buf = [0; 256];
+        loop {
+            if let Some(message) = self.next_message()? {
+                return Ok(message);
+            }
+            let len = self.stream.read(&mut buf)?;
+            if len == 0 {
+                return Err(io::Error::new(ErrorKind::ConnectionAborted, "link peer disconnected"));
+            }
+            self.incoming.extend_from_slice(&buf[..len]);
+        }
+    }
+
+    fn next_message(&mut self) -> io::Result<Option<Message>> {
+        let Some((message, len)) = Message::decode(&self.incoming)? else {
+            return Ok(None);
+        };
+        self.incoming.drain(..len);
+        Ok(Some(message))
+    }
+}
+
+fn handshake_error(message: Message) -> io::Error {
+    let reason = match message {
+        Message::Hello { version, .. } => format!("link protocol version {} is not supported", version),
+        _ => "link peer skipped the handshake".to_string(),
+    };
+    io::Error::new(ErrorKind::InvalidData, reason)
+}
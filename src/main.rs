@@ -1,43 +1,1250 @@
-mod cpu;
-mod memory;
-mod ppu;
-mod gba;
+// The afterimage frontend: command line, config file, window, audio and
+// hotkeys around the emulator core in the library.
 
-use gba::Gba;
+#[cfg(feature = "audio")]
+mod audio_output;
+mod battery;
+mod call_trace;
+mod cheat;
+mod cli;
+mod clip;
+mod config;
+mod console;
+mod debugger;
+mod frontend;
+#[cfg(feature = "gdb")]
+mod gdb;
+mod movie;
+mod pacing;
+mod profile;
+mod ram_search;
+mod recent;
+mod recording;
+mod rewind;
+#[cfg(feature = "lua")]
+mod script;
+mod state_slot;
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use afterimage::{apu, keypad, link, Gba, SaveType};
+
+use call_trace::CallTrace;
+use cheat::Cheats;
+use cli::{Cli, Command};
+use clip::ClipBuffer;
+use console::Console;
+use config::{Config, GameConfig};
+use frontend::bindings::{self, Action};
+use frontend::osd::Osd;
+use movie::Movie;
+use recent::RecentRoms;
+use recording::Recorder;
+#[cfg(feature = "lua")]
+use script::Script;
+
+// percent the volume hotkeys change the volume by
+#[cfg(feature = "audio")]
+const VOLUME_STEP: u32 = 10;
 
 fn main() {
+    let mut cli = Cli::parse_args();
+    let config_path = config::config_path(cli.config.as_deref());
+    let mut config = Config::load(&config_path).unwrap_or_else(|err| {
+        println!("Could not read {}: {}", config_path.display(), err);
+        Config::empty(&config_path)
+    });
+    cli.apply_config(&config);
+
+    #[cfg(feature = "audio")]
+    if cli.list_audio_devices {
+        for name in audio_output::list_devices() {
+            println!("{}", name);
+        }
+        return;
+    }
+
+    if let Some(Command::Recent { number }) = cli.command {
+        let recent_path = config.sibling(recent::RECENT_FILE);
+        let recent = RecentRoms::load(&recent_path).unwrap_or_else(|err| {
+            println!("Could not read {}: {}", recent_path.display(), err);
+            RecentRoms::default()
+        });
+        let Some(number) = number else {
+            for (index, rom) in recent.roms.iter().enumerate() {
+                println!("{:>2}. {}", index + 1, rom.display());
+            }
+            return;
+        };
+        match number.checked_sub(1).and_then(|index| recent.roms.get(index)) {
+            Some(rom) => cli.rom = Some(rom.clone()),
+            None => {
+                println!("There is no recent ROM number {}", number);
+                return;
+            }
+        }
+    }
+
+    if cli.rebind {
+        match rebind(&mut config, cli.window_options()) {
+            Ok(()) => println!("Bindings saved to {}", config.path.display()),
+            Err(err) => println!("Rebinding failed: {}", err),
+        }
+        return;
+    }
+
     let mut gba = Gba::new();
-    
-    let rom_paths = ["pokemon_emerald.gba", "test.gba", "game.gba"];
-    let mut rom_loaded = false;
-    
-    for path in &rom_paths {
-        match gba.load_rom(path) {
-            Ok(_) => {
-                println!("ROM loaded successfully: {}", path);
-                rom_loaded = true;
+    // a cartridge clock starts from the host's
+    gba.memory.rtc.start = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    if let Some(path) = &cli.bios
+        && let Err(err) = gba.memory.load_bios(path)
+    {
+        println!("Could not load the BIOS from {}: {}", path.display(), err);
+        return;
+    }
+    match &cli.rom {
+        Some(path) => {
+            if let Err(err) = gba.load_rom(&path.to_string_lossy()) {
+                println!("Could not load {}: {}", path.display(), err);
+                return;
+            }
+            recent::remember(&config.sibling(recent::RECENT_FILE), path);
+        }
+        None => println!("No ROM given; running with an empty cartridge slot."),
+    }
+    let game = game_config(&mut gba, &cli, &config);
+    load_save(&mut gba, &cli);
+
+    if let Some(filter) = cli.audio_filter.as_deref().and_then(apu::AudioFilter::from_name) {
+        gba.apu.set_filter(filter);
+    }
+    gba.idle_loop.enabled = !cli.no_idle_skip;
+    if cli.coverage.is_some() {
+        gba.coverage.start(gba.memory.rom.len());
+    }
+    if let Some(path) = &cli.symbols {
+        match profile::load_symbols(&mut gba, path) {
+            Ok(count) => println!("Loaded {} symbols from {}", count, path.display()),
+            Err(err) => println!("Could not read symbols from {}: {}", path.display(), err),
+        }
+    }
+    if cli.profile.is_some() {
+        gba.profiler.start();
+    }
+
+    if cli.headless() {
+        run_headless(&mut gba, &cli);
+    } else {
+        // a movie starts from power-on
+        if cli.auto_save && cli.record_movie.is_none() && cli.play_movie.is_none() {
+            offer_resume(&mut gba, &cli);
+        }
+        play(&mut gba, &mut cli, &config, game);
+        if cli.auto_save {
+            save_resume_state(&gba, &cli);
+        }
+    }
+
+    if let Err(err) = gba.apu.stop_wav_dump() {
+        println!("WAV dump failed: {}", err);
+    }
+    write_save(&mut gba, &cli);
+    write_coverage(&gba, &cli);
+    write_profile(&gba, &cli);
+}
+
+// Looks up the loaded game's section of the config and applies its save
+// type and clock, or what the ROM says; the rest is for the frontend.
+fn game_config(gba: &mut Gba, cli: &Cli, config: &Config) -> GameConfig {
+    let game = config.game(gba.memory.game_code().as_deref(), cli.rom.as_deref());
+    gba.memory.save_type = game.save_type.unwrap_or_else(|| gba.memory.detect_save_type());
+    gba.memory.has_rtc = game.rtc.unwrap_or_else(|| gba.memory.detect_rtc());
+    game
+}
+
+fn load_save(gba: &mut Gba, cli: &Cli) {
+    if gba.memory.save_type == SaveType::None {
+        return;
+    }
+    if let Some(path) = cli.save_path() {
+        match gba.memory.load_sram(&path) {
+            Ok(()) => println!("Loaded save {}", path.display()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => println!("Could not load save {}: {}", path.display(), err),
+        }
+    }
+}
+
+// Writes the battery save if the game has changed it.
+fn write_save(gba: &mut Gba, cli: &Cli) {
+    let Some(path) = cli.save_path() else {
+        return;
+    };
+    if gba.memory.save_type != SaveType::None
+        && gba.memory.sram_dirty
+        && let Err(err) = gba.memory.save_sram(&path)
+    {
+        println!("Could not write save {}: {}", path.display(), err);
+    }
+}
+
+// writes the save while the game runs, as often as --save-flush asks
+fn flush_save(gba: &mut Gba, cli: &Cli, flusher: &mut battery::SaveFlusher) {
+    if gba.memory.sram_dirty && flusher.due() {
+        write_save(gba, cli);
+    }
+}
+
+// Runs --frames frames as fast as possible, or until killed without it,
+// then reports the speed and saves any --screenshot.
+fn run_headless(gba: &mut Gba, cli: &Cli) {
+    #[cfg(feature = "gdb")]
+    if let Some(port) = cli.gdb
+        && !gdb::serve(gba, port, None)
+    {
+        return;
+    }
+    let Some(session) = start_session(gba, cli) else {
+        return;
+    };
+    let Session {
+        mut link,
+        mut recorder,
+        mut flusher,
+        mut movie,
+        mut cheats,
+        mut call_trace,
+        #[cfg(feature = "lua")]
+        mut script,
+    } = session;
+    let mut combo_held = false;
+    let mut frame = 0;
+    let start = Instant::now();
+    while cli.frames.is_none_or(|frames| frame < frames) {
+        if let Some(playing) = &movie
+            && playing.read_only
+            && !movie_input(gba, playing)
+        {
+            println!("Movie finished after {} frames", playing.position());
+            movie = None;
+            // without --frames, a movie runs until it ends
+            if cli.frames.is_none() {
                 break;
             }
-            Err(_) => continue,
         }
+        #[cfg(feature = "lua")]
+        if movie.is_none() {
+            gba.set_keys(script_keys(&mut script));
+        }
+        frame += 1;
+        check_reset_combo(gba, &mut combo_held);
+        if let Some(recording) = &mut movie
+            && !recording.read_only
+        {
+            recording.record(gba.keys(), false);
+        }
+        match &mut link {
+            Some(net) => {
+                if let Err(err) = net.run_frame(gba) {
+                    println!("Link dropped: {}", err);
+                    net.detach(gba);
+                    link = None;
+                }
+            }
+            None => {
+                gba.run_frame();
+                // the script's hooks stop the frame for their callbacks
+                #[cfg(feature = "lua")]
+                while gba.debug_stopped() && script_hit(gba, &mut script) {
+                    gba.run_frame();
+                }
+            }
+        }
+        write_call_trace(gba, &mut call_trace);
+        cheats.apply(gba);
+        #[cfg(feature = "lua")]
+        script_frame(gba, &mut script);
+        if let Some(playing) = &mut movie {
+            check_movie(gba, playing);
+        }
+        record_frame(gba, &mut recorder);
+        flush_save(gba, cli, &mut flusher);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("Ran {} frames in {:.2}s, {:.1} fps", frame, elapsed, frame as f64 / elapsed);
+
+    Session {
+        link,
+        recorder,
+        flusher,
+        movie,
+        cheats,
+        call_trace,
+        #[cfg(feature = "lua")]
+        script,
+    }
+    .finish(gba);
+    if let Some(path) = &cli.screenshot {
+        match gba.screenshot(path) {
+            Ok(()) => println!("Saved screenshot {}", path.display()),
+            Err(err) => println!("Could not save the screenshot: {}", err),
+        }
+    }
+}
+
+// What a run of the game sets up besides the window, shared by the window
+// and headless loops so an option works the same in both.
+struct Session {
+    link: Option<link::NetLink>,
+    recorder: Option<Recorder>,
+    flusher: battery::SaveFlusher,
+    movie: Option<Movie>,
+    cheats: Cheats,
+    call_trace: Option<CallTrace>,
+    #[cfg(feature = "lua")]
+    script: Option<Script>,
+}
+
+// Connects the link cable first, as hosting waits for the partner. None if
+// it couldn't be.
+fn start_session(gba: &mut Gba, cli: &Cli) -> Option<Session> {
+    let link = match connect_link(cli) {
+        Ok(link) => link,
+        Err(err) => {
+            println!("Link failed: {}", err);
+            return None;
+        }
+    };
+    if let Some(link) = &link {
+        link.attach(gba);
+        println!("Linked as player {}", link.id() + 1);
+    }
+    start_wav_dump(gba, cli);
+    Some(Session {
+        link,
+        recorder: cli.record.as_deref().and_then(|path| start_recording(gba, path)),
+        flusher: battery::SaveFlusher::new(cli.save_flush, cli.save_interval),
+        movie: match (&cli.record_movie, &cli.play_movie) {
+            (Some(path), _) => start_movie(gba, path),
+            (None, Some(path)) => start_playback(gba, path),
+            (None, None) => None,
+        },
+        cheats: load_cheats(cli),
+        call_trace: start_call_trace(gba, cli),
+        #[cfg(feature = "lua")]
+        script: load_script(gba, cli),
+    })
+}
+
+impl Session {
+    // Finishes the video recording and writes out a movie being recorded.
+    fn finish(self, gba: &mut Gba) {
+        if let Some(recorder) = self.recorder {
+            stop_recording(gba, recorder);
+        }
+        if let Some(movie) = self.movie {
+            finish_movie(movie);
+        }
+    }
+}
+
+fn connect_link(cli: &Cli) -> std::io::Result<Option<link::NetLink>> {
+    if let Some(address) = &cli.link_host {
+        println!("Waiting for a link partner on {}", address);
+        return link::NetLink::host(address).map(Some);
+    }
+    if let Some(address) = &cli.link_connect {
+        return link::NetLink::connect(address).map(Some);
+    }
+    Ok(None)
+}
+
+// A+B+Select+Start soft resets, once per press of the combo
+fn check_reset_combo(gba: &mut Gba, held: &mut bool) {
+    let pressed = gba.keys().contains(keypad::KeyState::RESET_COMBO);
+    if pressed && !*held {
+        gba.soft_reset();
+    }
+    *held = pressed;
+}
+
+fn start_wav_dump(gba: &mut Gba, cli: &Cli) {
+    if let Some(path) = &cli.dump_wav {
+        match gba.apu.start_wav_dump(path, cli.dump_stems) {
+            Ok(()) => println!("Dumping audio to {}", path.display()),
+            Err(err) => println!("Could not start WAV dump: {}", err),
+        }
+    }
+}
+
+// Runs the game in a window until it is closed
+fn play(gba: &mut Gba, cli: &mut Cli, config: &Config, game: GameConfig) {
+    // before the window opens, which would stop responding while hosting
+    // waits for the partner
+    let Some(session) = start_session(gba, cli) else {
+        return;
+    };
+    let mut options = cli.window_options();
+    options.filter = cli.filter.or(game.filter).unwrap_or_default();
+    let mut window = match frontend::open(options) {
+        Ok(window) => window,
+        Err(err) => {
+            println!("Could not open a window: {}", err);
+            session.finish(gba);
+            return;
+        }
+    };
+    window.keyboard().bindings = config.bindings(&game);
+    #[cfg(feature = "gdb")]
+    if let Some(port) = cli.gdb
+        && !gdb::serve(gba, port, Some(window.as_mut()))
+    {
+        session.finish(gba);
+        return;
+    }
+    let Session {
+        mut link,
+        mut recorder,
+        mut flusher,
+        mut movie,
+        mut cheats,
+        mut call_trace,
+        #[cfg(feature = "lua")]
+        mut script,
+    } = session;
+    #[cfg(feature = "audio")]
+    let audio = start_audio(gba, cli);
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = frontend::gamepad::Gamepads::new(config.bindings(&game))
+        .inspect_err(|err| println!("Controllers unavailable: {}", err))
+        .ok();
+    let mut clip = ClipBuffer::new(cli.clip_seconds);
+    let mut osd = Osd::new(cli.show_fps);
+    let mut rewind = cli.rewind.then(|| rewind::Rewind::new(cli.rewind_buffer));
+    // the cheat the toggle hotkey acts on, once one has been picked
+    let mut cheat_picked: Option<usize> = None;
+    let mut console = (cli.console || cli.debug).then(Console::start);
+    if let Some(console) = &mut console {
+        gba.call_stack.set_enabled(true);
+        if cli.debug {
+            console.debugger.stop(gba);
+        }
+    }
+    // the frame a breakpoint stopped partway, already recorded to the movie
+    let mut frame_cut = false;
+    // a reset to note in the movie with the next frame run
+    let mut reset_pending = false;
+    // a read-only movie has run out and paused the game; carrying on
+    // without making it recordable ends playback
+    let mut movie_ended = false;
+
+    // unthrottled lets audio underrun
+    let mut pacer = pacing::FramePacer::new(cli.unthrottled);
+    let mut combo_held = false;
+    let mut held = BTreeSet::new();
+    // frames run without being shown since the last one that was, while
+    // fast-forwarding
+    let mut skipped = 0;
+    let mut frame_skip = pacing::FrameSkipper::new(cli.frame_skip);
+    let mut paused = false;
+    // turbo buttons are pressed on the frames this is set, every other one
+    let mut turbo_phase = false;
+    // save state slot the save and load hotkeys use
+    let mut slot = 1;
+    #[cfg(feature = "audio")]
+    let (mut volume, mut muted) = (cli.volume, false);
+    while window.poll_events() {
+        let previous = std::mem::replace(&mut held, window.keyboard().held());
+        #[cfg(feature = "gamepad")]
+        if let Some(gamepads) = &mut gamepads {
+            held.extend(gamepads.poll());
+        }
+        if let Some(console) = &mut console {
+            console.run_pending(gba, &mut cheats, cli.cheat_path().as_deref());
+            osd.watches = console.watch_lines(gba);
+        }
+        // a movie being played back has the buttons
+        if !movie.as_ref().is_some_and(|movie| movie.read_only) {
+            let keys = bindings::buttons(&held, turbo_phase);
+            #[cfg(feature = "lua")]
+            let keys = keys | script_keys(&mut script);
+            gba.set_keys(keys);
+        }
+        // a ROM dropped onto the window or picked by hotkey
+        let mut swap_to = window.take_dropped_file();
+        // frame advance runs one frame and leaves the game paused
+        let mut advance = false;
+        for action in held.difference(&previous) {
+            if let Some(picked) = action.slot() {
+                slot = picked;
+                show_slot(cli, slot, &mut osd);
+            }
+            match action {
+                Action::Reset => {
+                    gba.reset();
+                    reset_pending = true;
+                    osd.message("Reset");
+                }
+                Action::Pause => {
+                    paused = !paused;
+                    osd.message(if paused { "Paused" } else { "Resumed" });
+                }
+                Action::Screenshot => take_screenshot(gba, cli, &mut osd),
+                Action::Record => match recorder.take() {
+                    Some(recorder) => {
+                        stop_recording(gba, recorder);
+                        osd.message("Recording stopped");
+                    }
+                    None => match next_numbered_path(cli, &cli.recording_dir, "mp4") {
+                        Ok(path) => {
+                            recorder = start_recording(gba, &path);
+                            osd.message(if recorder.is_some() { "Recording" } else { "Recording failed" });
+                        }
+                        Err(err) => println!("Could not start recording: {}", err),
+                    },
+                },
+                Action::SaveClip => save_clip(&clip, cli, &mut osd),
+                #[cfg(feature = "audio")]
+                Action::VolumeUp | Action::VolumeDown | Action::Mute => {
+                    match action {
+                        Action::VolumeUp => volume = (volume + VOLUME_STEP).min(audio_output::MAX_VOLUME),
+                        Action::VolumeDown => volume = volume.saturating_sub(VOLUME_STEP),
+                        _ => muted = !muted,
+                    }
+                    // changing the volume unmutes
+                    muted &= *action == Action::Mute;
+                    if let Some((output, _)) = &audio {
+                        output.set_volume(if muted { 0 } else { volume });
+                    }
+                    osd.message(if muted { "Muted".to_string() } else { format!("Volume {}%", volume) });
+                }
+                Action::SaveState => save_state(gba, cli, slot, &movie, &mut osd),
+                Action::LoadState => {
+                    load_state(gba, cli, slot, &mut movie, &mut osd);
+                    movie_ended = false;
+                }
+                Action::MovieReadOnly => match &mut movie {
+                    Some(movie) => {
+                        movie.read_only = !movie.read_only;
+                        movie_ended = false;
+                        osd.message(if movie.read_only { "Movie read-only" } else { "Movie recording" });
+                    }
+                    None => osd.message("No movie"),
+                },
+                Action::NextCheat if cheats.is_empty() => osd.message("No cheats"),
+                Action::NextCheat => {
+                    let index = cheat_picked.map_or(0, |index| (index + 1) % cheats.len());
+                    cheat_picked = Some(index);
+                    show_cheat(&cheats, index, &mut osd);
+                }
+                Action::ToggleCheat => match cheat_picked {
+                    Some(index) => {
+                        cheats.toggle(index, gba);
+                        show_cheat(&cheats, index, &mut osd);
+                    }
+                    None => osd.message("Pick a cheat first"),
+                },
+                Action::SwapRom => match previous_rom(cli, config) {
+                    Some(path) => swap_to = Some(path),
+                    None => osd.message("No other game played yet"),
+                },
+                Action::FrameAdvance => {
+                    paused = true;
+                    advance = true;
+                }
+                Action::Debug => {
+                    gba.call_stack.set_enabled(true);
+                    console.get_or_insert_with(Console::start).debugger.stop(gba);
+                    osd.message("Stopped in the debugger");
+                }
+                Action::Fullscreen => {
+                    let fullscreen = !window.fullscreen();
+                    if let Err(err) = window.set_fullscreen(fullscreen) {
+                        println!("Could not toggle fullscreen: {}", err);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(path) = swap_to
+            && let Some(game) = switch_rom(gba, cli, config, path, &mut osd)
+        {
+            if let Some(rewind) = &mut rewind {
+                rewind.clear();
+            }
+            stop_movie(&mut movie, &mut osd);
+            cheats = load_cheats(cli);
+            cheat_picked = None;
+            if let Some(console) = &mut console {
+                console.forget_game();
+            }
+            gba.breakpoints.clear();
+            gba.memory.watchpoints.clear();
+            // the script was written for the game before
+            #[cfg(feature = "lua")]
+            if script.take().is_some() {
+                println!("Script stopped for the new game");
+            }
+            window.keyboard().bindings = config.bindings(&game);
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = &mut gamepads {
+                gamepads.bindings = config.bindings(&game);
+            }
+            if let Err(err) = window.set_filter(cli.filter.or(game.filter).unwrap_or_default()) {
+                println!("Could not change the video filter: {}", err);
+            }
+        }
+        let debugging = console.as_ref().is_some_and(|console| console.debugger.stopped);
+        if (paused && !advance) || debugging {
+            // keep redrawing and taking input at the usual rate
+            if let Err(err) = window.present(osd.compose(gba.frame_buffer())) {
+                println!("Could not draw the frame: {}", err);
+                break;
+            }
+            pacer.speed = 1.0;
+            pacer.wait();
+            continue;
+        }
+        if let Some(history) = &mut rewind
+            && held.contains(&Action::Rewind)
+        {
+            match history.step_back(gba) {
+                // holding on at the oldest state just stays there
+                Ok(false) if !previous.contains(&Action::Rewind) => osd.message("Can't rewind further"),
+                Ok(true) => stop_movie(&mut movie, &mut osd),
+                Ok(false) => {}
+                Err(err) => {
+                    println!("Rewinding failed: {}", err);
+                    history.clear();
+                }
+            }
+            if let Err(err) = window.present(osd.compose(gba.frame_buffer())) {
+                println!("Could not draw the frame: {}", err);
+                break;
+            }
+            pacer.speed = 1.0;
+            pacer.wait();
+            continue;
+        }
+
+        if let Some(playing) = &movie
+            && playing.read_only
+            && !movie_input(gba, playing)
+        {
+            if movie_ended {
+                stop_movie(&mut movie, &mut osd);
+                gba.set_keys(bindings::buttons(&held, turbo_phase));
+            } else {
+                // left paused at the end, to be made recordable and carried on
+                println!("Movie finished after {} frames", playing.position());
+                osd.message("Movie finished");
+                movie_ended = true;
+                paused = true;
+                continue;
+            }
+        }
+        check_reset_combo(gba, &mut combo_held);
+        let fast_forward = held.contains(&Action::FastForward) && !advance;
+        pacer.speed = if fast_forward { cli.fast_forward_speed() } else { 1.0 };
+
+        let started = Instant::now();
+        let skip = if fast_forward { skipped < cli.fast_forward_skip } else { frame_skip.should_skip() };
+        if let Some(recording) = &mut movie
+            && !recording.read_only
+            && !frame_cut
+        {
+            recording.record(gba.keys(), reset_pending);
+        }
+        reset_pending = false;
+        // a recording needs every frame drawn
+        let draw = !skip || recorder.is_some();
+        let run = |gba: &mut Gba| if draw { gba.run_frame() } else { gba.skip_frame() };
+        match &mut link {
+            Some(net) => {
+                gba.ppu.skip_drawing = !draw;
+                let linked = net.run_frame(gba);
+                gba.ppu.skip_drawing = false;
+                if let Err(err) = linked {
+                    println!("Link dropped: {}", err);
+                    osd.message("Link dropped");
+                    net.detach(gba);
+                    link = None;
+                }
+            }
+            None => {
+                run(gba);
+                // the script's hooks stop the frame for their callbacks
+                #[cfg(feature = "lua")]
+                while gba.debug_stopped() && script_hit(gba, &mut script) {
+                    run(gba);
+                }
+            }
+        }
+        write_call_trace(gba, &mut call_trace);
+        // the rest of the frame runs once the debugger carries on
+        frame_cut = gba.debug_stopped();
+        if frame_cut {
+            console.get_or_insert_with(Console::start).debugger.check_hit(gba);
+            match gba.memory.watchpoints.hit() {
+                Some(hit) => osd.message(format!("Watchpoint at {:08X}", hit.address)),
+                None => osd.message(format!("Breakpoint at {:08X}", gba.cpu.pc)),
+            }
+            continue;
+        }
+        cheats.apply(gba);
+        #[cfg(feature = "lua")]
+        {
+            osd.texts = script_frame(gba, &mut script);
+        }
+        osd.frame_emulated();
+        turbo_phase = !turbo_phase;
+        if let Some(playing) = &mut movie
+            && check_movie(gba, playing)
+        {
+            osd.message(format!("Movie desynced at frame {}", playing.position()));
+        }
+        if let Some(history) = &mut rewind
+            && let Err(err) = history.frame_ran(gba)
+        {
+            println!("Rewinding turned off: {}", err);
+            rewind = None;
+        }
+        flush_save(gba, cli, &mut flusher);
+        record_frame(gba, &mut recorder);
+        if draw {
+            clip.push(gba.frame_buffer());
+        } else {
+            clip.skipped();
+        }
+        if fast_forward && skip {
+            skipped += 1;
+        } else {
+            skipped = 0;
+        }
+        if !skip && let Err(err) = window.present(osd.compose(gba.frame_buffer())) {
+            println!("Could not draw the frame: {}", err);
+            break;
+        }
+        frame_skip.frame_took(started.elapsed());
+
+        #[cfg(feature = "audio")]
+        if let Some((output, config)) = &audio {
+            match config.sync {
+                // the pacer sets the speed; extra audio is dropped, missing audio is silence
+                _ if pacer.unlocked || fast_forward || paused => {}
+                // the sound card's clock paces the frames
+                audio_output::SyncMode::Audio => {
+                    output.wait_for_room();
+                    continue;
+                }
+                audio_output::SyncMode::Video => gba.apu.set_rate_adjustment(output.rate_adjustment()),
+            }
+        }
+        pacer.wait();
+    }
+    Session {
+        link,
+        recorder,
+        flusher,
+        movie,
+        cheats,
+        call_trace,
+        #[cfg(feature = "lua")]
+        script,
+    }
+    .finish(gba);
+}
+
+// Asks on the terminal whether to pick up from the state --auto-save left
+// when the game was last closed.
+fn offer_resume(gba: &mut Gba, cli: &Cli) {
+    let Some(path) = cli.resume_path().filter(|path| path.exists()) else {
+        return;
+    };
+    if !io::stdin().is_terminal() {
+        return;
+    }
+    print!("Resume from where you left off? [Y/n] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return;
+    }
+    let answer = answer.trim().to_ascii_lowercase();
+    if !answer.is_empty() && !"yes".starts_with(&answer) {
+        return;
+    }
+    match state_slot::load(&path, gba) {
+        Ok(_) => println!("Resumed from {}", path.display()),
+        Err(err) => println!("Could not resume from {}: {}", path.display(), err),
+    }
+}
+
+fn save_resume_state(gba: &Gba, cli: &Cli) {
+    let Some(path) = cli.resume_path() else {
+        return;
+    };
+    match state_slot::save(&path, gba, None) {
+        Ok(()) => println!("Saved {} to resume from", path.display()),
+        Err(err) => println!("Could not save a state to resume from: {}", err),
+    }
+}
+
+// A state saved during a movie keeps its input so far, which also goes
+// out to the movie file if it is being recorded.
+fn save_state(gba: &Gba, cli: &Cli, slot: u32, movie: &Option<Movie>, osd: &mut Osd) {
+    let Some(path) = cli.state_path(slot) else {
+        osd.message("No game to save");
+        return;
+    };
+    if let Some(recording) = movie.as_ref().filter(|movie| !movie.read_only)
+        && let Err(err) = recording.save()
+    {
+        println!("Could not write the movie: {}", err);
+    }
+    match state_slot::save(&path, gba, movie.as_ref().map(Movie::log)) {
+        Ok(()) => osd.message(format!("Saved state {}", slot)),
+        Err(err) => {
+            println!("Could not save state {}: {}", path.display(), err);
+            osd.message("Could not save state");
+        }
+    }
+}
+
+// Loading a state during a movie takes the movie back to where the state
+// was saved: recording, the input after it is dropped and recorded over;
+// read-only, playback carries on from there. A state from outside the
+// movie, or one that doesn't fit a read-only movie, ends it.
+fn load_state(gba: &mut Gba, cli: &Cli, slot: u32, movie: &mut Option<Movie>, osd: &mut Osd) {
+    let Some(path) = cli.state_path(slot) else {
+        osd.message("No game to load");
+        return;
+    };
+    match state_slot::load(&path, gba) {
+        Ok((info, log)) => {
+            osd.clear_picture();
+            osd.message(format!("Loaded state {} from {}", slot, info.age()));
+            if let Some(current) = movie
+                && log.is_some_and(|log| current.rewind_to(&log))
+            {
+                osd.message(if current.read_only {
+                    format!("Movie at frame {}", current.position())
+                } else {
+                    format!("Re-recording from frame {}", current.position())
+                });
+            } else {
+                stop_movie(movie, osd);
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => osd.message(format!("Slot {} is empty", slot)),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => osd.message(format!("Slot {} is {}", slot, err)),
+        Err(err) => {
+            println!("Could not load state {}: {}", path.display(), err);
+            osd.message("Could not load state");
+        }
+    }
+}
+
+// Names a newly picked slot along with its age and thumbnail.
+fn show_slot(cli: &Cli, slot: u32, osd: &mut Osd) {
+    match cli.state_path(slot).map(|path| state_slot::info(&path)) {
+        Some(Ok(info)) => {
+            osd.message(format!("Slot {} - {}", slot, info.age()));
+            osd.picture(info.thumbnail, state_slot::THUMBNAIL_WIDTH);
+        }
+        Some(Err(err)) if err.kind() == io::ErrorKind::InvalidData => {
+            osd.message(format!("Slot {} is {}", slot, err));
+            osd.clear_picture();
+        }
+        _ => {
+            osd.message(format!("Slot {} - empty", slot));
+            osd.clear_picture();
+        }
+    }
+}
+
+// Swaps in another ROM without closing the window or audio device,
+// writing the old game's save first. The new ROM takes the command line
+// one's place for naming saves and screenshots. Returns its config section
+// once it is running.
+fn switch_rom(gba: &mut Gba, cli: &mut Cli, config: &Config, path: PathBuf, osd: &mut Osd) -> Option<GameConfig> {
+    // the new game's coverage starts over
+    write_coverage(gba, cli);
+    if let Err(err) = gba.swap_rom(&path, cli.save_path().as_deref()) {
+        println!("Could not switch to {}: {}", path.display(), err);
+        osd.message("Could not load the ROM");
+        return None;
     }
-    
-    if !rom_loaded {
-        println!("No ROM file found. Testing with empty ROM...");
-        println!("Place a GBA ROM file in the current directory as 'pokemon_emerald.gba' to test with actual ROM data.");
+    recent::remember(&config.sibling(recent::RECENT_FILE), &path);
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    cli.rom = Some(path);
+    // a --cheats file was for the game before
+    cli.cheats = None;
+    let game = game_config(gba, cli, config);
+    load_save(gba, cli);
+    osd.message(format!("Loaded {}", name));
+    Some(game)
+}
+
+// the most recently played ROM other than the running one
+fn previous_rom(cli: &Cli, config: &Config) -> Option<PathBuf> {
+    let recent = RecentRoms::load(&config.sibling(recent::RECENT_FILE)).ok()?;
+    let current = cli.rom.as_ref().map(|rom| fs::canonicalize(rom).unwrap_or_else(|_| rom.clone()));
+    recent.roms.into_iter().find(|rom| Some(rom) != current.as_ref())
+}
+
+fn take_screenshot(gba: &Gba, cli: &Cli, osd: &mut Osd) {
+    match next_numbered_path(cli, &cli.screenshot_dir, "png").and_then(|path| gba.screenshot(&path).map(|()| path)) {
+        Ok(path) => {
+            println!("Saved screenshot {}", path.display());
+            osd.message("Screenshot saved");
+        }
+        Err(err) => {
+            println!("Could not save the screenshot: {}", err);
+            osd.message("Screenshot failed");
+        }
     }
-    
-    println!("Starting emulator test...");
-    println!("Initial CPU state:");
-    println!("  PC: 0x{:08X}", gba.cpu.pc);
-    println!("  SP: 0x{:08X}", gba.cpu.sp);
-    
-    for step in 0..5 {
-        let old_pc = gba.cpu.pc;
-        gba.step();
-        println!("Step {}: PC 0x{:08X} -> 0x{:08X}, Cycles: {}", 
-                step + 1, old_pc, gba.cpu.pc, gba.cycles);
+}
+
+fn save_clip(clip: &ClipBuffer, cli: &Cli, osd: &mut Osd) {
+    if clip.is_empty() {
+        return;
+    }
+    match next_numbered_path(cli, &cli.recording_dir, "png").and_then(|path| clip.save_apng(&path).map(|()| path)) {
+        Ok(path) => {
+            println!("Saved clip {}", path.display());
+            osd.message("Clip saved");
+        }
+        Err(err) => {
+            println!("Could not save the clip: {}", err);
+            osd.message("Clip failed");
+        }
+    }
+}
+
+// First unused ROM-NNNN.ext in dir, which is created if needed.
+fn next_numbered_path(cli: &Cli, dir: &Path, extension: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let stem = cli.rom.as_ref().and_then(|rom| rom.file_stem()).map_or("afterimage".into(), |stem| stem.to_string_lossy());
+    let path = (1..)
+        .map(|number| dir.join(format!("{}-{:04}.{}", stem, number, extension)))
+        .find(|path| !path.exists())
+        .unwrap();
+    Ok(path)
+}
+
+fn start_recording(gba: &mut Gba, path: &Path) -> Option<Recorder> {
+    match Recorder::start(gba, path) {
+        Ok(recorder) => {
+            println!("Recording to {}", path.display());
+            Some(recorder)
+        }
+        Err(err) => {
+            println!("Could not start recording: {}", err);
+            None
+        }
+    }
+}
+
+// Sends the frame just run to the recording, ending it if ffmpeg has gone.
+fn record_frame(gba: &mut Gba, recorder: &mut Option<Recorder>) {
+    if let Some(active) = recorder
+        && let Err(err) = active.push_frame(gba.frame_buffer())
+    {
+        println!("Recording stopped: {}", err);
+        stop_recording(gba, recorder.take().unwrap());
+    }
+}
+
+fn stop_recording(gba: &mut Gba, recorder: Recorder) {
+    let path = recorder.output().to_path_buf();
+    println!("Finishing {}", path.display());
+    match recorder.finish(gba) {
+        Ok(()) => println!("Saved recording {}", path.display()),
+        Err(err) => println!("Recording failed: {}", err),
+    }
+}
+
+// Writes the --coverage map, if there is one.
+fn write_coverage(gba: &Gba, cli: &Cli) {
+    if let Some(path) = &cli.coverage
+        && gba.coverage.recording()
+    {
+        match debugger::save_coverage(gba, path) {
+            Ok(()) => println!("Saved coverage to {}", path.display()),
+            Err(err) => println!("Could not write the coverage to {}: {}", path.display(), err),
+        }
+    }
+}
+
+// Writes the --profile report, if there is one.
+fn write_profile(gba: &Gba, cli: &Cli) {
+    let Some(path) = &cli.profile else {
+        return;
+    };
+    let written = fs::File::create(path).and_then(|file| {
+        let mut out = io::BufWriter::new(file);
+        profile::write_report(gba, &mut out, profile::FILE_ROWS)?;
+        out.flush()
+    });
+    match written {
+        Ok(()) => println!("Saved the profile to {}", path.display()),
+        Err(err) => println!("Could not write the profile to {}: {}", path.display(), err),
+    }
+}
+
+fn start_call_trace(gba: &mut Gba, cli: &Cli) -> Option<CallTrace> {
+    let path = cli.call_trace.as_deref()?;
+    match CallTrace::create(path, gba) {
+        Ok(trace) => Some(trace),
+        Err(err) => {
+            println!("Could not start the call trace {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// Logs the calls made over the frame just run, ending the trace on an error.
+fn write_call_trace(gba: &mut Gba, trace: &mut Option<CallTrace>) {
+    if let Some(active) = trace
+        && let Err(err) = active.write(gba)
+    {
+        println!("Call trace stopped: {}", err);
+        gba.call_stack.set_tracing(false);
+        *trace = None;
+    }
+}
+
+#[cfg(feature = "lua")]
+fn load_script(gba: &mut Gba, cli: &Cli) -> Option<Script> {
+    let path = cli.script.as_deref()?;
+    match Script::load(path, gba) {
+        Ok(script) => {
+            println!("Running script {}", path.display());
+            Some(script)
+        }
+        Err(err) => {
+            println!("Could not run the script {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// The buttons the script holds down for the frame about to run.
+#[cfg(feature = "lua")]
+fn script_keys(script: &mut Option<Script>) -> keypad::KeyState {
+    script.as_mut().map_or(keypad::KeyState::NONE, Script::take_keys)
+}
+
+// Runs the script's hooks for where the frame stopped. False if it stopped
+// for the debugger instead.
+#[cfg(feature = "lua")]
+fn script_hit(gba: &mut Gba, script: &mut Option<Script>) -> bool {
+    let Some(running) = script else {
+        return false;
+    };
+    match running.handle_hit(gba) {
+        Ok(handled) => handled,
+        Err(err) => {
+            stop_script(gba, script, &err);
+            true
+        }
+    }
+}
+
+// Runs the script's frame callbacks, returning the text it put up.
+#[cfg(feature = "lua")]
+fn script_frame(gba: &mut Gba, script: &mut Option<Script>) -> Vec<(usize, usize, String)> {
+    let Some(running) = script else {
+        return Vec::new();
+    };
+    match running.frame_done(gba) {
+        Ok(()) => running.take_texts(),
+        Err(err) => {
+            stop_script(gba, script, &err);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(feature = "lua")]
+fn stop_script(gba: &mut Gba, script: &mut Option<Script>, err: &str) {
+    println!("Script stopped: {}", err);
+    if let Some(stopped) = script.take() {
+        stopped.remove_hooks(gba);
+    }
+}
+
+fn start_movie(gba: &Gba, path: &Path) -> Option<Movie> {
+    match Movie::create(path, gba) {
+        Ok(movie) => {
+            println!("Recording a movie to {}", path.display());
+            Some(movie)
+        }
+        Err(err) => {
+            println!("Could not start the movie: {}", err);
+            None
+        }
+    }
+}
+
+// Ends the movie, writing it out if it is being recorded. Rewinding,
+// switching games or loading a state from outside the movie ends it too,
+// as the inputs after would no longer follow from the ones before.
+fn stop_movie(movie: &mut Option<Movie>, osd: &mut Osd) {
+    let Some(movie) = movie.take() else {
+        return;
+    };
+    if movie.read_only {
+        osd.message("Movie playback stopped");
+        return;
+    }
+    finish_movie(movie);
+    osd.message("Movie recording stopped");
+}
+
+// Writes out a movie being recorded.
+fn finish_movie(movie: Movie) {
+    if movie.read_only {
+        return;
+    }
+    match movie.save() {
+        Ok(()) => println!(
+            "Recorded {} frames to {} with {} re-records",
+            movie.len(),
+            movie.path().display(),
+            movie.header.rerecords
+        ),
+        Err(err) => println!("Could not write the movie: {}", err),
+    }
+}
+
+// Readies gba to play the movie at path back read-only from its first
+// frame.
+fn start_playback(gba: &mut Gba, path: &Path) -> Option<Movie> {
+    match Movie::open(path).and_then(|movie| movie.start(gba).map(|()| movie)) {
+        Ok(movie) => {
+            println!("Playing back {}", path.display());
+            Some(movie)
+        }
+        Err(err) => {
+            println!("Could not play back {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// Sets the movie's input for the frame about to run, resetting first if
+// the movie did. False once the movie has run out.
+fn movie_input(gba: &mut Gba, movie: &Movie) -> bool {
+    let Some((keys, reset)) = movie.next_frame() else {
+        return false;
+    };
+    if reset {
+        gba.reset();
+    }
+    gba.set_keys(keys);
+    true
+}
+
+// Moves the movie past the frame just run, checking it against the movie
+// when read-only. True when the game has just gone out of sync with it,
+// somewhere after the last frame that was checked and matched.
+fn check_movie(gba: &Gba, movie: &mut Movie) -> bool {
+    let desynced = movie.frame_done(gba);
+    if desynced {
+        println!(
+            "Movie desynced at frame {}, after last matching at frame {}",
+            movie.position(),
+            movie.last_match()
+        );
+    }
+    desynced
+}
+
+// Reads the cheats for the loaded game, if it has any.
+fn load_cheats(cli: &Cli) -> Cheats {
+    let Some(path) = cli.cheat_path() else {
+        return Cheats::default();
+    };
+    match Cheats::load(&path) {
+        Ok(cheats) if cheats.is_empty() => cheats,
+        Ok(cheats) => {
+            let enabled = cheats.cheats.iter().filter(|cheat| cheat.enabled()).count();
+            println!("Loaded {} cheats from {}, {} on", cheats.len(), path.display(), enabled);
+            cheats
+        }
+        Err(err) => {
+            println!("Could not read cheats from {}: {}", path.display(), err);
+            Cheats::default()
+        }
+    }
+}
+
+fn show_cheat(cheats: &Cheats, index: usize, osd: &mut Osd) {
+    let cheat = &cheats.cheats[index];
+    let state = if cheat.enabled() { "on" } else { "off" };
+    osd.message(format!("Cheat {}/{}: {} ({})", index + 1, cheats.len(), cheat.name, state));
+}
+
+// Asks for a key or controller button for each action in turn
+// and saves the bindings to the config file.
+fn rebind(config: &mut Config, options: frontend::WindowOptions) -> Result<(), Box<dyn Error>> {
+    let mut window = frontend::open(options)?;
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = frontend::gamepad::Gamepads::new(config.input.clone()).ok();
+
+    println!("Press a key or controller button for each action; Escape keeps the current binding.");
+    for action in Action::ALL {
+        println!("{}?", action);
+        window.keyboard().take_pressed();
+        #[cfg(feature = "gamepad")]
+        if let Some(pads) = &mut gamepads {
+            pads.poll();
+            pads.take_pressed();
+        }
+        loop {
+            if !window.poll_events() {
+                return Err("the window was closed before every action was bound".into());
+            }
+            if let Some(key) = window.keyboard().take_pressed() {
+                if key != "Escape" {
+                    config.input.keyboard.insert(action, vec![key]);
+                }
+                break;
+            }
+            #[cfg(feature = "gamepad")]
+            if let Some(button) = gamepads.as_mut().and_then(|pads| {
+                pads.poll();
+                pads.take_pressed()
+            }) {
+                config.input.gamepad.insert(action, vec![button]);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    config.save()
+}
+
+// Opens the audio device; without one the game still runs, silently.
+#[cfg(feature = "audio")]
+fn start_audio(gba: &mut Gba, cli: &Cli) -> Option<(audio_output::AudioOutput, audio_output::AudioConfig)> {
+    let config = cli.audio_config();
+    match audio_output::AudioOutput::start(&config, &mut gba.apu) {
+        Ok(output) => {
+            gba.apu.set_output_rate(output.sample_rate());
+            println!("Playing audio at {} Hz", output.sample_rate());
+            Some((output, config))
+        }
+        Err(err) => {
+            println!("Audio unavailable: {}", err);
+            None
+        }
     }
-    
-    println!("\nSTILL GOT IT BABYYYYYYY");
 }
@@ -1,20 +1,98 @@
-use std::fs::File;
-use std::io::Read;
+use std::cell::Cell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 
-#[derive(Debug)]
+use serde::{Deserialize, Serialize};
+
+use crate::archive;
+use crate::eeprom::{Eeprom, EEPROM_SIZE};
+use crate::interrupts::{Interrupt, IE, IF, IME, INTERRUPT_MASK};
+use crate::keypad::{self, KeyState, KEYINPUT};
+use crate::rtc::Rtc;
+use crate::serial::{self, SerialLines};
+use crate::timers::{Timers, TM0CNT_L};
+use crate::watchpoints::Watchpoints;
+
+// What backs the cartridge's save area. Battery SRAM and serial EEPROM are
+// emulated; carts with no save leave the area unmapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SaveType {
+    #[default]
+    Sram,
+    Eeprom,
+    None,
+}
+
+// The cartridge, BIOS image and link cable position are left out of save
+// states; loading one keeps whatever is plugged in.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Memory {
+    #[serde(skip)]
     pub bios: Vec<u8>,
     pub ewram: Vec<u8>,
     pub iwram: Vec<u8>, 
     pub vram: Vec<u8>,
     pub palette_ram: Vec<u8>,
     pub oam: Vec<u8>,
+    #[serde(skip)]
     pub rom: Vec<u8>,
+    // battery backed cartridge SRAM, or the EEPROM's contents
+    pub sram: Vec<u8>,
+    // set by writes to SRAM, cleared once it has been saved
+    pub sram_dirty: bool,
+    #[serde(skip)]
+    pub save_type: SaveType,
+    pub eeprom: Eeprom,
+    // whether the cartridge has a clock on its GPIO port; like the save
+    // type, up to the frontend
+    #[serde(skip)]
+    pub has_rtc: bool,
+    pub rtc: Rtc,
+    pub io: Vec<u8>,
+    // bumped whenever a write changes memory the PPU renders from
+    pub video_generation: u64,
+    // bumped on every CPU or DMA write, to spot loops that change nothing
+    pub bus_writes: u64,
+    // bumped on every read of a timer's counter, which moves between two
+    // passes of a loop without it or any event writing anything
+    #[serde(skip)]
+    pub timer_reads: Cell<u64>,
+    // set when the CPU writes BG2X/BG2Y or BG3X/BG3Y, cleared by the PPU
+    pub bg_ref_written: [bool; 2],
+    // sound register writes in order, drained by the APU
+    pub sound_writes: Vec<(usize, u8)>,
+    // set when the CPU turns on a DMA channel's enable bit, cleared by the DMA controller
+    pub dma_started: [bool; 4],
+    pub timers: Timers,
+    // set when the CPU sets SIOCNT's start bit, cleared by the Gba
+    pub serial_started: bool,
+    // position on a link cable, 0 being the parent; None when unplugged
+    #[serde(skip)]
+    pub link_id: Option<usize>,
+    // SI and SD as driven by the attached serial device
+    #[serde(skip)]
+    pub serial_lines: SerialLines,
+    // undocumented register at 0x4000800, mirrored every 64KB of the I/O area
+    pub memory_control: u32,
+    // set by a write to HALTCNT, cleared by the Gba as it halts the CPU
+    pub halt_requested: bool,
+    // likewise for STOP, which also freezes the video, sound and timers
+    pub stop_requested: bool,
+    // what reads of unused addresses see: the last value a DMA moved, the
+    // CPU's own fetches not being modelled as driving the bus
+    pub open_bus: u32,
+    // current cycle, kept up to date by the Gba so timer reads are live
+    pub now: u64,
+    // set by the debugger, like the Gba's breakpoints
+    #[serde(skip)]
+    pub watchpoints: Watchpoints,
 }
 
 impl Memory {
     pub fn new() -> Self {
-        Memory {
+        let mut memory = Memory {
             bios: vec![0; 0x4000],        // 16KB
             ewram: vec![0; 0x40000],      // 256KB  
             iwram: vec![0; 0x8000],       // 32KB
@@ -22,43 +100,158 @@ impl Memory {
             palette_ram: vec![0; 0x400],  // 1KB
             oam: vec![0; 0x400],          // 1KB
             rom: Vec::new(),
+            sram: vec![0xFF; SRAM_SIZE],
+            sram_dirty: false,
+            save_type: SaveType::default(),
+            eeprom: Eeprom::default(),
+            has_rtc: false,
+            rtc: Rtc::default(),
+            io: vec![0; 0x400],           // 1KB of I/O registers
+            video_generation: 0,
+            bus_writes: 0,
+            timer_reads: Cell::new(0),
+            bg_ref_written: [false; 2],
+            sound_writes: Vec::new(),
+            dma_started: [false; 4],
+            timers: Timers::new(),
+            serial_started: false,
+            link_id: None,
+            serial_lines: SerialLines::PULLED_UP,
+            memory_control: MEMORY_CONTROL_DEFAULT,
+            halt_requested: false,
+            stop_requested: false,
+            open_bus: 0xFFFF_FFFF,
+            now: 0,
+            watchpoints: Watchpoints::default(),
+        };
+
+        // the BIOS leaves the BG2/BG3 affine matrices at identity
+        for pa_offset in [0x020, 0x026, 0x030, 0x036] {
+            memory.set_io_u16(pa_offset, 0x100);
         }
+        // and SOUNDBIAS at the midpoint of the PWM range
+        memory.set_io_u16(0x088, 0x200);
+        memory.set_io_u16(KEYINPUT, KeyState::NONE.register());
+        serial::update_lines(&mut memory);
+        // POSTFLG: the BIOS has finished booting
+        memory.io[0x300] = 1;
+
+        memory
     }
 
     pub fn load_rom(&mut self, path: &str) -> Result<(), std::io::Error> {
-        let mut file = File::open(path)?;
-        self.rom.clear();
-        file.read_to_end(&mut self.rom)?;
+        self.rom = archive::read_rom(Path::new(path))?;
         println!("Loaded ROM: {} bytes", self.rom.len());
         Ok(())
     }
 
+    // The save type the ROM names, as the SDK's save libraries leave their
+    // version strings in the games built with them.
+    pub fn detect_save_type(&self) -> SaveType {
+        if self.rom.windows(8).any(|window| window == b"EEPROM_V") {
+            SaveType::Eeprom
+        } else {
+            SaveType::Sram
+        }
+    }
+
+    // Whether the ROM carries the driver for the real time clock, as
+    // games that have one do.
+    pub fn detect_rtc(&self) -> bool {
+        self.rom.windows(8).any(|window| window == b"SIIRTC_V")
+    }
+
+    // four character code from the ROM header, such as BPEE
+    pub fn game_code(&self) -> Option<String> {
+        let code = self.rom.get(0xAC..0xB0)?;
+        code.iter().all(u8::is_ascii_alphanumeric).then(|| String::from_utf8_lossy(code).into_owned())
+    }
+
+    pub fn load_bios(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.load_bios_data(&fs::read(path)?)
+    }
+
+    pub fn load_bios_data(&mut self, image: &[u8]) -> Result<(), io::Error> {
+        if image.len() != self.bios.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "a GBA BIOS image is 16KB"));
+        }
+        self.bios.copy_from_slice(image);
+        Ok(())
+    }
+
+    // Fills SRAM from a .sav file. Shorter files leave the rest erased.
+    pub fn load_sram(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.load_sram_data(&fs::read(path)?);
+        Ok(())
+    }
+
+    // Fills SRAM from a save's contents, as load_sram does from a file.
+    pub fn load_sram_data(&mut self, data: &[u8]) {
+        let len = data.len().min(SRAM_SIZE);
+        self.sram.fill(0xFF);
+        self.sram[..len].copy_from_slice(&data[..len]);
+        self.sram_dirty = false;
+    }
+
+    // the part of the save buffer the cartridge's save chip holds
+    pub fn save_data(&self) -> &[u8] {
+        match self.save_type {
+            SaveType::Eeprom => &self.sram[..EEPROM_SIZE],
+            _ => &self.sram,
+        }
+    }
+
+    // Writes to a temporary file and renames it over the old save, so a
+    // crash part way through never leaves a truncated save behind.
+    pub fn save_sram(&mut self, path: &Path) -> Result<(), io::Error> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(self.save_data())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        self.sram_dirty = false;
+        Ok(())
+    }
+
     pub fn read_u8(&self, address: u32) -> u8 {
         match address {
             0x00000000..=0x00003FFF => self.bios[(address & 0x3FFF) as usize],
-            0x02000000..=0x0203FFFF => self.ewram[(address & 0x3FFFF) as usize],
+            0x02000000..=0x0203FFFF => match self.ewram_mapping() {
+                WramMapping::Normal => self.ewram[(address & 0x3FFFF) as usize],
+                WramMapping::Iwram => self.iwram[(address & 0x7FFF) as usize],
+                WramMapping::Disabled => 0xFF,
+            },
+            0x03000000..=0x03007FFF if self.wram_disabled() => 0xFF,
             0x03000000..=0x03007FFF => self.iwram[(address & 0x7FFF) as usize],
-            0x06000000..=0x06017FFF => self.vram[(address & 0x17FFF) as usize],
+            0x06000000..=0x06FFFFFF => self.vram[vram_offset(address)],
             0x05000000..=0x050003FF => self.palette_ram[(address & 0x3FF) as usize],
             0x07000000..=0x070003FF => self.oam[(address & 0x3FF) as usize],
-            0x08000000..=0x09FFFFFF => {
-                let rom_addr = (address - 0x08000000) as usize;
+            _ if self.is_eeprom(address) => (address & 1 == 0 && self.eeprom.read_bit()) as u8,
+            0x080000C4..=0x080000C9 if self.has_rtc && self.rtc.readable() => self.rtc.read(address & 0xFF),
+            // the ROM is mirrored in each of the three wait state regions
+            0x08000000..=0x0DFFFFFF => {
+                let rom_addr = (address & 0x01FFFFFF) as usize;
                 if rom_addr < self.rom.len() {
                     self.rom[rom_addr]
                 } else {
                     0xFF
                 }
             }
-            // return 0 for now
-            0x04000000..=0x040003FF => {
-                // TODO: Implement I/O register handling
-                // This includes graphics, sound, timers, DMA, etc.
-                0
+            // SRAM sits on an 8-bit bus, mirrored through both regions
+            0x0E000000..=0x0FFFFFFF if self.save_type == SaveType::Sram => {
+                self.sram[(address as usize) & (SRAM_SIZE - 1)]
+            }
+            0x04000100..=0x0400010F => {
+                self.timer_reads.set(self.timer_reads.get() + 1);
+                self.timers.read_u8((address & 0x3FF) as usize - TM0CNT_L, self.now)
             }
+            0x04000000..=0x040003FF => self.io[(address & 0x3FF) as usize],
+            _ if is_memory_control(address) => (self.memory_control >> ((address & 3) * 8)) as u8,
             _ => {
                 // another debug
                 // println!("Unhandled memory read at 0x{:08X}", address);
-                0xFF
+                (self.open_bus >> ((address & 3) * 8)) as u8
             }
         }
     }
@@ -76,14 +269,39 @@ impl Memory {
     }
 
     pub fn write_u8(&mut self, address: u32, value: u8) {
+        self.bus_writes += 1;
         match address {
-            0x02000000..=0x0203FFFF => self.ewram[(address & 0x3FFFF) as usize] = value,
+            0x02000000..=0x0203FFFF => match self.ewram_mapping() {
+                WramMapping::Normal => self.ewram[(address & 0x3FFFF) as usize] = value,
+                WramMapping::Iwram => self.iwram[(address & 0x7FFF) as usize] = value,
+                WramMapping::Disabled => {}
+            },
+            0x03000000..=0x03007FFF if self.wram_disabled() => {}
             0x03000000..=0x03007FFF => self.iwram[(address & 0x7FFF) as usize] = value,
-            0x06000000..=0x06017FFF => self.vram[(address & 0x17FFF) as usize] = value,
-            0x05000000..=0x050003FF => self.palette_ram[(address & 0x3FF) as usize] = value,
-            0x07000000..=0x070003FF => self.oam[(address & 0x3FF) as usize] = value,
-            0x04000000..=0x040003FF => {
-                // TODO: Implement I/O register handling
+            0x06000000..=0x06FFFFFF => {
+                store_video(&mut self.vram, vram_offset(address) as u32, value, &mut self.video_generation)
+            }
+            0x05000000..=0x050003FF => {
+                store_video(&mut self.palette_ram, address & 0x3FF, value, &mut self.video_generation)
+            }
+            0x07000000..=0x070003FF => {
+                store_video(&mut self.oam, address & 0x3FF, value, &mut self.video_generation)
+            }
+            0x04000000..=0x040003FF => self.write_io(address & 0x3FF, value),
+            0x080000C4..=0x080000C9 if self.has_rtc => self.rtc.write(address & 0xFF, value, self.now),
+            // each halfword sends one bit, in its low byte
+            _ if self.is_eeprom(address) && address & 1 == 0 => {
+                self.sram_dirty |= self.eeprom.write_bit(value & 1 != 0, &mut self.sram);
+            }
+            _ if self.is_eeprom(address) => {}
+            0x0E000000..=0x0FFFFFFF if self.save_type == SaveType::Sram => {
+                self.sram[(address as usize) & (SRAM_SIZE - 1)] = value;
+                self.sram_dirty = true;
+            }
+            _ if is_memory_control(address) => {
+                let shift = (address & 3) * 8;
+                let control = (self.memory_control & !(0xFF << shift)) | ((value as u32) << shift);
+                self.memory_control = control & MEMORY_CONTROL_MASK;
             }
             _ => {
                 // Remove Insect
@@ -92,6 +310,164 @@ impl Memory {
         }
     }
 
+    fn write_io(&mut self, offset: u32, value: u8) {
+        match offset {
+            // DISPSTAT: the low three bits are status flags owned by the PPU
+            0x004 => self.io[0x004] = (self.io[0x004] & 0x07) | (value & !0x07),
+            // VCOUNT is read-only
+            0x006 | 0x007 => {}
+            // remaining LCD registers feed the renderer
+            0x000..=0x003 | 0x008..=0x055 => {
+                if let 0x028..=0x02F | 0x038..=0x03F = offset {
+                    self.bg_ref_written[((offset - 0x028) / 0x10) as usize] = true;
+                }
+                store_video(&mut self.io, offset, value, &mut self.video_generation)
+            }
+            // PSG registers are read-only while the master enable in SOUNDCNT_X is off
+            0x060..=0x081 if self.io[0x084] & 0x80 == 0 => {}
+            0x060..=0x0A7 => {
+                if offset == 0x084 {
+                    // the low nibble holds read-only channel status
+                    self.io[0x084] = (self.io[0x084] & 0x0F) | (value & 0x80);
+                } else {
+                    self.io[offset as usize] = value;
+                }
+                self.sound_writes.push((offset as usize, value));
+            }
+            // DMAxCNT_H high byte holds the enable bit
+            0x0BB | 0x0C7 | 0x0D3 | 0x0DF => {
+                let channel = ((offset - 0x0BB) / 12) as usize;
+                if value & 0x80 != 0 && self.io[offset as usize] & 0x80 == 0 {
+                    self.dma_started[channel] = true;
+                }
+                self.io[offset as usize] = value;
+            }
+            0x100..=0x10F => {
+                self.timers.write_u8(offset as usize - TM0CNT_L, value, self.now);
+                self.io[offset as usize] = value;
+            }
+            0x200 => self.io[0x200] = value,
+            0x201 => self.io[0x201] = value & (INTERRUPT_MASK >> 8) as u8,
+            // IF bits are acknowledged by writing 1 to them
+            0x202 | 0x203 => {
+                self.io[offset as usize] &= !value;
+                keypad::check_keypad_irq(self);
+            }
+            // only the master enable bit of IME exists
+            0x208 => self.io[0x208] = value & 1,
+            0x209..=0x20B => {}
+            // SIOCNT and RCNT line states are driven by the port
+            0x128 | 0x129 | 0x134 | 0x135 => {
+                if offset == 0x128 && value & 0x80 != 0 && self.io[0x128] & 0x80 == 0 {
+                    self.serial_started = true;
+                }
+                self.io[offset as usize] = value;
+                serial::update_lines(self);
+            }
+            // KEYINPUT is read-only
+            0x130 | 0x131 => {}
+            0x132 | 0x133 => {
+                self.io[offset as usize] = value;
+                keypad::check_keypad_irq(self);
+            }
+            // POSTFLG only has the boot flag
+            0x300 => self.io[0x300] = value & 1,
+            // HALTCNT: bit 7 clear halts, set stops
+            0x301 if value & 0x80 == 0 => self.halt_requested = true,
+            0x301 => self.stop_requested = true,
+            _ => self.io[offset as usize] = value,
+        }
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let flags = self.io_u16(IF) | interrupt.mask();
+        self.set_io_u16(IF, flags);
+    }
+
+    // wakes a halted CPU, whether or not IME lets the IRQ through
+    pub fn interrupt_requested(&self) -> bool {
+        self.io_u16(IE) & self.io_u16(IF) != 0
+    }
+
+    // only the keypad, serial port and cartridge run during STOP
+    pub fn stop_wake_requested(&self) -> bool {
+        let sources = Interrupt::Keypad.mask() | Interrupt::Serial.mask() | Interrupt::GamePak.mask();
+        self.io_u16(IE) & self.io_u16(IF) & sources != 0
+    }
+
+    // the IRQ line into the CPU: an enabled source has raised its flag and
+    // the master enable is on
+    pub fn interrupt_pending(&self) -> bool {
+        self.io[IME] & 1 != 0 && self.interrupt_requested()
+    }
+
+    // Cycles one access of width bytes takes, including wait states.
+    // Sequential accesses follow on from the previous address.
+    pub fn access_cycles(&self, address: u32, width: u32, sequential: bool) -> u32 {
+        let waitcnt = self.io_u16(WAITCNT);
+        match address >> 24 {
+            0x02 => {
+                let wait = 1 + self.ewram_wait_states();
+                if width == 4 { wait * 2 } else { wait }
+            }
+            // 16-bit buses
+            0x05 | 0x06 if width == 4 => 2,
+            0x08..=0x0D => {
+                let state = ((address >> 25) - 4) as usize;
+                let first = 1 + WAIT_STATES_N[((waitcnt >> (2 + state * 3)) & 0x3) as usize];
+                let next = 1 + WAIT_STATES_S[state][((waitcnt >> (4 + state * 3)) & 1) as usize];
+                // a 32-bit access is two 16-bit ones back to back
+                let access = if sequential { next } else { first };
+                if width == 4 { access + next } else { access }
+            }
+            0x0E | 0x0F => 1 + WAIT_STATES_N[(waitcnt & 0x3) as usize],
+            _ => 1,
+        }
+    }
+
+    // The EEPROM answers in the last ROM wait state region, only in its top
+    // 256 bytes when a 32MB ROM needs the rest.
+    pub fn is_eeprom(&self, address: u32) -> bool {
+        self.save_type == SaveType::Eeprom
+            && address >> 24 == 0x0D
+            && (self.rom.len() <= 0x0100_0000 || address >= 0x0DFF_FF00)
+    }
+
+    // bit 0 of the memory control register cuts both work RAMs off the bus
+    fn wram_disabled(&self) -> bool {
+        self.memory_control & 1 != 0
+    }
+
+    fn ewram_mapping(&self) -> WramMapping {
+        if self.wram_disabled() {
+            WramMapping::Disabled
+        } else if self.memory_control & (1 << 5) == 0 {
+            // with the external RAM off its area mirrors IWRAM
+            WramMapping::Iwram
+        } else {
+            WramMapping::Normal
+        }
+    }
+
+    // wait states for EWRAM accesses, 15 minus bits 24-27
+    pub fn ewram_wait_states(&self) -> u32 {
+        15 - ((self.memory_control >> 24) & 0xF)
+    }
+
+    // Raw register access for the hardware side, bypassing CPU write rules
+    pub fn io_u16(&self, offset: usize) -> u16 {
+        self.io[offset] as u16 | ((self.io[offset + 1] as u16) << 8)
+    }
+
+    pub fn io_u32(&self, offset: usize) -> u32 {
+        self.io_u16(offset) as u32 | ((self.io_u16(offset + 2) as u32) << 16)
+    }
+
+    pub fn set_io_u16(&mut self, offset: usize, value: u16) {
+        self.io[offset] = value as u8;
+        self.io[offset + 1] = (value >> 8) as u8;
+    }
+
     pub fn write_u16(&mut self, address: u32, value: u16) {
         self.write_u8(address, value as u8);
         self.write_u8(address + 1, (value >> 8) as u8);
@@ -101,4 +477,69 @@ impl Memory {
         self.write_u16(address, value as u16);
         self.write_u16(address + 2, (value >> 16) as u16);
     }
+
+    // The CPU and DMA call these for each load they make and each store,
+    // before it lands, for watchpoints to see.
+    pub fn watch_read(&mut self, address: u32, size: u32, value: u32) {
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check_read(address, size, value);
+        }
+    }
+
+    pub fn watch_write(&mut self, address: u32, size: u32, value: u32) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+        let old_value = match size {
+            1 => self.read_u8(address) as u32,
+            2 => self.read_u16(address) as u32,
+            _ => self.read_u32(address),
+        };
+        self.watchpoints.check_write(address, size, value, old_value);
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new()
+    }
+}
+
+const SRAM_SIZE: usize = 0x8000;
+
+const WAITCNT: usize = 0x204;
+// WAITCNT settings: first access wait states, shared by SRAM and the three
+// ROM wait state regions, and sequential access wait states per region
+const WAIT_STATES_N: [u32; 4] = [4, 3, 2, 8];
+const WAIT_STATES_S: [[u32; 2]; 3] = [[2, 1], [4, 1], [8, 1]];
+
+// EWRAM enabled with 2 wait states, as the BIOS leaves it
+const MEMORY_CONTROL_DEFAULT: u32 = 0x0D00_0020;
+// bits that hold a value; bits 1-3 are undocumented but writable
+const MEMORY_CONTROL_MASK: u32 = 0xFF00_002F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WramMapping {
+    Normal,
+    Iwram,
+    Disabled,
+}
+
+fn is_memory_control(address: u32) -> bool {
+    address & 0xFF00_FFFC == 0x0400_0800
+}
+
+// VRAM is 96KB mirrored every 128KB, with the upper 32KB of each mirror
+// repeating the OBJ area at 0x10000-0x17FFF
+fn vram_offset(address: u32) -> usize {
+    let offset = (address & 0x1FFFF) as usize;
+    if offset >= 0x18000 { offset - 0x8000 } else { offset }
+}
+
+fn store_video(region: &mut [u8], offset: u32, value: u8, generation: &mut u64) {
+    let slot = &mut region[offset as usize];
+    if *slot != value {
+        *slot = value;
+        *generation += 1;
+    }
 }
@@ -0,0 +1,316 @@
+// Input movies: the buttons held on every frame of a play session from
+// power-on, along with what the console started from, so the session can
+// be played back exactly. The file is the magic, the format version and
+// the length of the bincode header as little endian u32s, the header, and
+// then one little endian u16 per frame run. Every so often a frame is
+// followed by CHECKSUM_RECORD and a u64 checksum of the machine after it,
+// which playback compares against to catch the movie going out of sync.
+//
+// A movie is either read-only, playing back, or being recorded, and can be
+// switched between the two. Recording from partway through a movie replaces
+// everything after that point, and save states made during a movie keep
+// its input up to then, so loading one while recording goes back to that
+// point and records on from it: a re-record, as TAS tools call it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use afterimage::{Gba, KeyState, SaveType};
+use serde::{Deserialize, Serialize};
+
+const MOVIE_MAGIC: &[u8; 8] = b"AFTRMOVI";
+pub const MOVIE_VERSION: u32 = 3;
+// set in a frame's record when the console was reset before it ran; the
+// low bits are the KeyState held during it
+const RESET_FLAG: u16 = 0x8000;
+// can't be a frame, which only uses the reset flag and the ten key bits
+const CHECKSUM_RECORD: u16 = 0xFFFF;
+// frames between checksums
+const CHECKSUM_INTERVAL: usize = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovieHeader {
+    pub rom_hash: u64,
+    pub game_code: Option<String>,
+    pub bios_hash: u64,
+    pub save_type: SaveType,
+    // battery save contents at power-on
+    pub sram: Vec<u8>,
+    // whether the cartridge had a clock, and the Unix time it started
+    // from, so playback sees the same time of day
+    pub has_rtc: bool,
+    pub rtc_seed: u64,
+    // times recording went back to an earlier save state
+    pub rerecords: u32,
+}
+
+impl MovieHeader {
+    // describes gba as it is now, which should be just after power-on
+    pub fn new(gba: &Gba) -> Self {
+        MovieHeader {
+            rom_hash: hash(&gba.memory.rom),
+            game_code: gba.memory.game_code(),
+            bios_hash: hash(&gba.memory.bios),
+            save_type: gba.memory.save_type,
+            sram: gba.memory.sram.clone(),
+            has_rtc: gba.memory.has_rtc,
+            rtc_seed: gba.memory.rtc.start,
+            rerecords: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MovieFrame {
+    keys: KeyState,
+    reset: bool,
+    // of the machine after the frame ran
+    checksum: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Movie {
+    path: PathBuf,
+    pub header: MovieHeader,
+    frames: Vec<MovieFrame>,
+    // frames run so far
+    position: usize,
+    // playing back rather than recording
+    pub read_only: bool,
+    // the last frame with a checksum that matched, 0 for none
+    last_match: usize,
+    desynced: bool,
+}
+
+impl Movie {
+    // Starts recording a new movie from gba as it is now, which should be
+    // just after power-on.
+    pub fn create(path: &Path, gba: &Gba) -> io::Result<Self> {
+        let movie = Movie {
+            path: path.to_path_buf(),
+            header: MovieHeader::new(gba),
+            frames: Vec::new(),
+            position: 0,
+            read_only: false,
+            last_match: 0,
+            desynced: false,
+        };
+        movie.save()?;
+        Ok(movie)
+    }
+
+    // Opens a movie read-only, to be started with start.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let file = fs::read(path)?;
+        let rest = file.strip_prefix(MOVIE_MAGIC.as_slice()).ok_or_else(|| invalid("not a movie file"))?;
+        let (version, rest) = rest.split_first_chunk::<4>().ok_or_else(|| invalid("truncated"))?;
+        if u32::from_le_bytes(*version) != MOVIE_VERSION {
+            return Err(invalid("recorded by an incompatible version"));
+        }
+        let (length, rest) = rest.split_first_chunk::<4>().ok_or_else(|| invalid("truncated"))?;
+        let length = u32::from_le_bytes(*length) as usize;
+        if rest.len() < length {
+            return Err(invalid("truncated"));
+        }
+        let header = bincode::deserialize(&rest[..length]).map_err(|_| invalid("corrupt header"))?;
+        Ok(Movie {
+            path: path.to_path_buf(),
+            header,
+            frames: decode_frames(&rest[length..]),
+            position: 0,
+            read_only: true,
+            last_match: 0,
+            desynced: false,
+        })
+    }
+
+    // Puts gba in the state the movie was recorded from: its save loaded
+    // into the ROM it was recorded with, just powered on with the clock
+    // showing the time it did.
+    pub fn start(&self, gba: &mut Gba) -> io::Result<()> {
+        if hash(&gba.memory.rom) != self.header.rom_hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the movie was recorded with a different ROM"));
+        }
+        if hash(&gba.memory.bios) != self.header.bios_hash {
+            println!("The movie was recorded with a different BIOS and may go out of sync");
+        }
+        gba.memory.save_type = self.header.save_type;
+        gba.memory.sram.clone_from(&self.header.sram);
+        gba.memory.has_rtc = self.header.has_rtc;
+        gba.reset();
+        gba.memory.rtc.start = self.header.rtc_seed;
+        Ok(())
+    }
+
+    // Writes the whole movie out, replacing the file.
+    pub fn save(&self) -> io::Result<()> {
+        let header = bincode::serialize(&self.header).map_err(io::Error::other)?;
+        let mut file = MOVIE_MAGIC.to_vec();
+        file.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        file.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        file.extend_from_slice(&header);
+        file.extend_from_slice(&encode_frames(&self.frames));
+        fs::write(&self.path, file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    // frames run so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn last_match(&self) -> usize {
+        self.last_match
+    }
+
+    // Keys for the next frame and whether to reset before it, or None at
+    // the end of the movie.
+    pub fn next_frame(&self) -> Option<(KeyState, bool)> {
+        let frame = self.frames.get(self.position)?;
+        Some((frame.keys, frame.reset))
+    }
+
+    // Records the frame about to run with keys held, after a reset if
+    // reset is set, dropping any frames that came after it.
+    pub fn record(&mut self, keys: KeyState, reset: bool) {
+        self.frames.truncate(self.position);
+        self.frames.push(MovieFrame {
+            keys,
+            reset,
+            checksum: None,
+        });
+    }
+
+    // Called once the frame from next_frame or record has run. Recording,
+    // this adds a checksum when one is due; playing back, it returns true
+    // the first time the machine no longer matches the movie's checksum.
+    pub fn frame_done(&mut self, gba: &Gba) -> bool {
+        let Some(frame) = self.frames.get_mut(self.position) else {
+            return false;
+        };
+        self.position += 1;
+        if !self.read_only {
+            if self.position.is_multiple_of(CHECKSUM_INTERVAL) {
+                frame.checksum = Some(checksum(gba));
+            }
+            return false;
+        }
+        let Some(expected) = frame.checksum.filter(|_| !self.desynced) else {
+            return false;
+        };
+        if expected == checksum(gba) {
+            self.last_match = self.position;
+            return false;
+        }
+        self.desynced = true;
+        true
+    }
+
+    // The input up to the current frame, kept in save states made during
+    // the movie.
+    pub fn log(&self) -> Vec<u8> {
+        encode_frames(&self.frames[..self.position])
+    }
+
+    // Goes back to where a save state with log was made. Recording, the
+    // log replaces the movie, counting as a re-record. Read-only, the log
+    // has to match the movie up to that point; false if it doesn't.
+    pub fn rewind_to(&mut self, log: &[u8]) -> bool {
+        let frames = decode_frames(log);
+        let position = frames.len();
+        if self.read_only {
+            let matches = frames.len() <= self.frames.len()
+                && frames
+                    .iter()
+                    .zip(&self.frames)
+                    .all(|(state, movie)| state.keys == movie.keys && state.reset == movie.reset);
+            if !matches {
+                return false;
+            }
+        } else {
+            self.frames = frames;
+            self.header.rerecords += 1;
+        }
+        self.position = position;
+        self.last_match = 0;
+        self.desynced = false;
+        true
+    }
+}
+
+fn encode_frames(frames: &[MovieFrame]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(frames.len() * 2);
+    for frame in frames {
+        let record = frame.keys.0 | if frame.reset { RESET_FLAG } else { 0 };
+        bytes.extend_from_slice(&record.to_le_bytes());
+        if let Some(checksum) = frame.checksum {
+            bytes.extend_from_slice(&CHECKSUM_RECORD.to_le_bytes());
+            bytes.extend_from_slice(&checksum.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+// Stops at anything cut off partway through.
+fn decode_frames(bytes: &[u8]) -> Vec<MovieFrame> {
+    let mut frames: Vec<MovieFrame> = Vec::new();
+    let mut records = bytes.chunks_exact(2).map(|record| u16::from_le_bytes([record[0], record[1]]));
+    while let Some(record) = records.next() {
+        if record != CHECKSUM_RECORD {
+            frames.push(MovieFrame {
+                keys: KeyState(record & KeyState::ALL.0),
+                reset: record & RESET_FLAG != 0,
+                checksum: None,
+            });
+            continue;
+        }
+        let words: Vec<u16> = records.by_ref().take(4).collect();
+        if words.len() < 4 {
+            break;
+        }
+        let checksum = words.iter().rev().fold(0, |checksum, &word| checksum << 16 | word as u64);
+        if let Some(frame) = frames.last_mut() {
+            frame.checksum = Some(checksum);
+        }
+    }
+    frames
+}
+
+// Sums up what the game has done so far: the CPU, the clock and RAM,
+// video memory included. Left independent of the save state layout so
+// movies outlive changes to it, and of the frame buffer, which frame
+// skipping leaves behind.
+fn checksum(gba: &Gba) -> u64 {
+    let cpu = &gba.cpu;
+    let mut registers: Vec<u8> = cpu
+        .registers
+        .iter()
+        .chain(&[cpu.sp, cpu.lr, cpu.pc, cpu.cpsr])
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    registers.extend_from_slice(&gba.cycles.to_le_bytes());
+    let memory = &gba.memory;
+    [&registers[..], &memory.ewram, &memory.iwram, &memory.vram, &memory.palette_ram, &memory.oam]
+        .iter()
+        .fold(FNV_OFFSET, |checksum, data| fnv(checksum, data))
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+// FNV-1a, for telling ROM and BIOS images apart
+fn hash(data: &[u8]) -> u64 {
+    fnv(FNV_OFFSET, data)
+}
+
+fn fnv(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3))
+}
@@ -0,0 +1,112 @@
+// Locks emulation to the real GBA frame rate, 16777216 / 280896 = 59.7275
+// Hz. Sleeping alone overshoots by up to a scheduler tick, so the pacer
+// sleeps until just before the deadline and spins the rest of the way.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use afterimage::ppu::{CLOCK_RATE, FRAME_CYCLES};
+
+pub const FRAME_DURATION: Duration = Duration::from_nanos(FRAME_CYCLES * 1_000_000_000 / CLOCK_RATE);
+
+// how early to wake from sleep and start spinning
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+// most frames left undrawn in a row
+pub const MAX_FRAME_SKIP: u32 = 4;
+
+#[derive(Debug)]
+pub struct FramePacer {
+    // run as fast as possible, for benchmarking
+    pub unlocked: bool,
+    // multiple of the real frame rate to run at; infinite for no limit
+    pub speed: f64,
+    deadline: Instant,
+}
+
+impl FramePacer {
+    pub fn new(unlocked: bool) -> Self {
+        FramePacer {
+            unlocked,
+            speed: 1.0,
+            deadline: Instant::now(),
+        }
+    }
+
+    // Blocks until the current frame's time slot is over.
+    pub fn wait(&mut self) {
+        if self.unlocked || !self.speed.is_finite() {
+            return;
+        }
+        self.deadline += FRAME_DURATION.div_f64(self.speed);
+        let now = Instant::now();
+        if self.deadline <= now {
+            // running behind; don't try to catch up with a burst of frames
+            self.deadline = now;
+            return;
+        }
+        if self.deadline - now > SPIN_MARGIN {
+            std::thread::sleep(self.deadline - now - SPIN_MARGIN);
+        }
+        while Instant::now() < self.deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+// Frames to run without drawing between drawn ones: a fixed number, or in
+// auto mode as many as it takes to make up for slow frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSkip {
+    Auto,
+    Fixed(u32),
+}
+
+impl FromStr for FrameSkip {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.eq_ignore_ascii_case("auto") {
+            return Ok(FrameSkip::Auto);
+        }
+        match text.parse() {
+            Ok(frames) if frames <= MAX_FRAME_SKIP => Ok(FrameSkip::Fixed(frames)),
+            _ => Err(format!("expected auto or a number from 0 to {}", MAX_FRAME_SKIP)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameSkipper {
+    mode: FrameSkip,
+    // frames skipped since the last one drawn
+    skipped: u32,
+    // how far emulating and drawing have fallen behind the frame rate
+    lag: Duration,
+}
+
+impl FrameSkipper {
+    pub fn new(mode: FrameSkip) -> Self {
+        FrameSkipper {
+            mode,
+            skipped: 0,
+            lag: Duration::ZERO,
+        }
+    }
+
+    // whether the next frame should be run without drawing it
+    pub fn should_skip(&mut self) -> bool {
+        let skip = match self.mode {
+            FrameSkip::Fixed(frames) => self.skipped < frames,
+            FrameSkip::Auto => !self.lag.is_zero() && self.skipped < MAX_FRAME_SKIP,
+        };
+        self.skipped = if skip { self.skipped + 1 } else { 0 };
+        skip
+    }
+
+    // Records how long a frame took to emulate and draw, leaving out time
+    // spent waiting for the next one.
+    pub fn frame_took(&mut self, time: Duration) {
+        self.lag = (self.lag + time).saturating_sub(FRAME_DURATION).min(FRAME_DURATION * MAX_FRAME_SKIP);
+    }
+}
@@ -1,26 +1,722 @@
+use serde::{Deserialize, Serialize};
+
+use crate::interrupts::Interrupt;
 use crate::memory::Memory;
 
-#[derive(Debug)]
+mod compose;
+pub mod debug;
+pub mod registers;
+
+use compose::{PixelState, TRANSPARENT};
+use registers::{
+    BgAffine, BgCnt, BgOffset, BldAlpha, BldCnt, BldY, Dispcnt, Dispstat, Mosaic, WindowBounds, WindowControl,
+    DISPSTAT, VCOUNT,
+};
+
+pub const SCREEN_WIDTH: usize = 240;
+pub const SCREEN_HEIGHT: usize = 160;
+
+// the CPU clock everything is timed against, in cycles per second
+pub const CLOCK_RATE: u64 = 16_777_216;
+const CYCLES_PER_PIXEL: u64 = 4;
+pub const HDRAW_CYCLES: u64 = 960;
+pub const SCANLINE_CYCLES: u64 = 1232;
+const TOTAL_LINES: u16 = 228;
+pub const FRAME_CYCLES: u64 = SCANLINE_CYCLES * TOTAL_LINES as u64;
+// frames per second, about 59.7275
+pub const FRAME_RATE: f64 = CLOCK_RATE as f64 / FRAME_CYCLES as f64;
+
+// layer ids as used by the BLDCNT target bits
+const OBJ_LAYER: usize = 4;
+const BACKDROP_LAYER: usize = 5;
+
+const OBJ_VRAM_BASE: usize = 0x10000;
+const OBJ_BITMAP_MODE_FIRST_TILE: usize = 512;
+
+// [shape][size] -> (width, height)
+const OBJ_SIZES: [[(i32, i32); 4]; 3] = [
+    [(8, 8), (16, 16), (32, 32), (64, 64)],
+    [(16, 8), (32, 8), (32, 16), (64, 32)],
+    [(8, 16), (8, 32), (16, 32), (32, 64)],
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct ObjPixel {
+    color: u16,
+    priority: u16,
+    semi_transparent: bool,
+}
+
+// Debug switches for hiding layers, independent of what the game enables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayerToggles {
+    pub bg: [bool; 4],
+    pub obj: bool,
+    pub effects: bool,
+}
+
+impl Default for LayerToggles {
+    fn default() -> Self {
+        LayerToggles {
+            bg: [true; 4],
+            obj: true,
+            effects: true,
+        }
+    }
+}
+
+impl LayerToggles {
+    // same bit layout as the WININ/WINOUT enables
+    fn mask(&self) -> u16 {
+        let mut mask = 0;
+        for (bg, &enabled) in self.bg.iter().enumerate() {
+            mask |= (enabled as u16) << bg;
+        }
+        mask | (self.obj as u16) << OBJ_LAYER | (self.effects as u16) << 5
+    }
+}
+
+// what a cached scanline was rendered from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct LineStamp {
+    generation: u64,
+    layers: LayerToggles,
+    affine_ref: [(i32, i32); 2],
+}
+
+// Register state sampled once at the start of each scanline. Everything
+// not latched here (scroll, affine matrix, horizontal window bounds,
+// window enables, blending, palette and VRAM) is read as each pixel is
+// drawn, so mid-line writes take effect from the next pixel on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LineLatch {
+    dispcnt: Dispcnt,
+    bgcnt: [BgCnt; 4],
+    mosaic: Mosaic,
+    // whether the line falls inside each window's vertical span
+    window_rows: [bool; 2],
+    // enabled backgrounds, sorted front to back
+    bgs: Vec<usize>,
+}
+
+impl LineLatch {
+    fn read(memory: &Memory, y: usize) -> Self {
+        let dispcnt = Dispcnt::read(memory);
+        let bgcnt: [BgCnt; 4] = std::array::from_fn(|bg| BgCnt::read(memory, bg));
+        let mut bgs: Vec<usize> = (0..4)
+            .filter(|&bg| dispcnt.bg_enabled(bg) && bg_available(dispcnt.mode(), bg))
+            .collect();
+        bgs.sort_by_key(|&bg| bgcnt[bg].priority());
+
+        LineLatch {
+            dispcnt,
+            bgcnt,
+            mosaic: Mosaic::read(memory),
+            window_rows: std::array::from_fn(|window| WindowBounds::read(memory, window).contains_y(y)),
+            bgs,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Ppu {
     pub vcount: u16,
     pub frame_buffer: Vec<u16>,
+    // kept by the frontend across save state loads
+    #[serde(skip)]
+    pub layers: LayerToggles,
+    // Set for frames that are run but not shown. Lines keep their timing,
+    // status flags and interrupts, but nothing is drawn and the frame
+    // buffer keeps the last frame that was.
+    #[serde(skip)]
+    pub skip_drawing: bool,
+    // the current line is visible and being drawn
+    drawing: bool,
+    // cycle the current scanline started on
+    line_start: u64,
+    // next pixel of the current line draw_layers has to produce
+    next_pixel: usize,
+    latch: LineLatch,
+    // per-layer line buffers, filled pixel by pixel during HDraw and
+    // merged by the compose pass at the end of the line
+    bg_lines: [Vec<u16>; 4],
+    obj_line: Vec<Option<ObjPixel>>,
+    obj_window: Vec<bool>,
+    pixel_states: Vec<PixelState>,
+    // first pixel of the current line the compose pass has to produce
+    compose_from: usize,
+    // left out of save states, so a loaded one redraws every line
+    #[serde(skip, default = "empty_line_stamps")]
+    line_stamps: Vec<Option<LineStamp>>,
+    // stamp taken at the start of the current line
+    line_stamp: LineStamp,
+    // the current line matched its cached stamp and is not being redrawn
+    reusing_line: bool,
+    // internal BG2/BG3 reference points, reloaded from BGxX/BGxY at VBlank
+    // or when written and advanced by PB/PD after every visible line
+    affine_ref: [(i32, i32); 2],
+}
+
+fn empty_line_stamps() -> Vec<Option<LineStamp>> {
+    vec![None; SCREEN_HEIGHT]
 }
 
 impl Ppu {
     pub fn new() -> Self {
+        let layers = LayerToggles::default();
         Ppu {
             vcount: 0,
-            frame_buffer: vec![0; 240 * 160],
+            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            layers,
+            skip_drawing: false,
+            drawing: false,
+            line_start: 0,
+            next_pixel: SCREEN_WIDTH,
+            latch: LineLatch::default(),
+            bg_lines: std::array::from_fn(|_| vec![TRANSPARENT; SCREEN_WIDTH]),
+            obj_line: vec![None; SCREEN_WIDTH],
+            obj_window: vec![false; SCREEN_WIDTH],
+            pixel_states: vec![PixelState::default(); SCREEN_WIDTH],
+            compose_from: 0,
+            line_stamps: empty_line_stamps(),
+            line_stamp: LineStamp {
+                generation: 0,
+                layers,
+                affine_ref: [(0, 0); 2],
+            },
+            reusing_line: false,
+            affine_ref: [(0, 0); 2],
+        }
+    }
+
+    // Starts the scanline in vcount on cycle now. The Gba schedules the
+    // HBlank and line end events that follow it.
+    pub fn begin_line(&mut self, memory: &mut Memory, now: u64) {
+        self.reload_written_affine_refs(memory);
+        self.line_start = now;
+        self.drawing = (self.vcount as usize) < SCREEN_HEIGHT && !self.skip_drawing;
+        if self.drawing {
+            self.start_scanline(memory);
+            self.next_pixel = 0;
+        } else {
+            self.next_pixel = SCREEN_WIDTH;
+        }
+    }
+
+    // Draws every pixel of the current line whose time has come, so a
+    // register write landing mid-line only affects the pixels after it.
+    pub fn catch_up(&mut self, memory: &mut Memory, now: u64) {
+        self.reload_written_affine_refs(memory);
+        if self.next_pixel >= SCREEN_WIDTH {
+            return;
+        }
+        // pixel x is fetched on cycle x * 4 of the line
+        let elapsed = now.saturating_sub(self.line_start);
+        let target = (elapsed.div_ceil(CYCLES_PER_PIXEL) as usize).min(SCREEN_WIDTH);
+        while self.next_pixel < target {
+            self.draw_layers(memory, self.next_pixel);
+            self.next_pixel += 1;
+        }
+    }
+
+    // shifts the line timing for delay cycles spent in STOP mode
+    pub fn postpone(&mut self, delay: u64) {
+        self.line_start += delay;
+    }
+
+    pub fn hblank(&mut self, memory: &mut Memory) {
+        let visible = (self.vcount as usize) < SCREEN_HEIGHT;
+        if self.drawing {
+            self.catch_up(memory, self.line_start + HDRAW_CYCLES);
+            self.finish_scanline(memory);
+        }
+        if visible {
+            self.advance_affine_refs(memory);
         }
+        self.update_status(memory, true);
     }
 
-    pub fn step(&mut self, _memory: &Memory) {
-        self.vcount = (self.vcount + 1) % 228;
-        
-        // TODO: Implement actual rendering logic
-        // - Read background control registers
-        // - Render backgrounds based on mode
-        // - Render sprites
-        // - Handle palette lookups
+    pub fn end_line(&mut self, memory: &mut Memory, now: u64) {
+        self.vcount = (self.vcount + 1) % TOTAL_LINES;
+        memory.set_io_u16(VCOUNT, self.vcount);
+        if self.vcount as usize == SCREEN_HEIGHT {
+            for bg in 2..4 {
+                self.reload_affine_ref(memory, bg);
+            }
+        }
+        self.update_status(memory, false);
+        self.begin_line(memory, now);
     }
+
+    fn update_status(&self, memory: &mut Memory, hblank: bool) {
+        let dispstat = Dispstat::read(memory);
+        let mut stat = dispstat.0 & !0x7;
+        if (160..227).contains(&self.vcount) {
+            stat |= 1;
+        }
+        if hblank {
+            stat |= 1 << 1;
+        }
+        if dispstat.vcount_setting() == self.vcount {
+            stat |= 1 << 2;
+        }
+        memory.set_io_u16(DISPSTAT, stat);
+
+        // each status flag raises its interrupt when it turns on
+        let rising = stat & !dispstat.0;
+        if rising & 1 != 0 && dispstat.vblank_irq() {
+            memory.request_interrupt(Interrupt::VBlank);
+        }
+        if rising & (1 << 1) != 0 && dispstat.hblank_irq() {
+            memory.request_interrupt(Interrupt::HBlank);
+        }
+        if rising & (1 << 2) != 0 && dispstat.vcount_irq() {
+            memory.request_interrupt(Interrupt::VCount);
+        }
+    }
+
+    // Forces every line to be re-rendered, for callers that modify video
+    // memory directly instead of going through the bus.
+    pub fn invalidate_cache(&mut self) {
+        self.line_stamps.fill(None);
+    }
+
+    fn reload_affine_ref(&mut self, memory: &Memory, bg: usize) {
+        let affine = BgAffine::read(memory, bg);
+        self.affine_ref[bg - 2] = (affine.x, affine.y);
+    }
+
+    fn reload_written_affine_refs(&mut self, memory: &mut Memory) {
+        for bg in 2..4 {
+            if std::mem::take(&mut memory.bg_ref_written[bg - 2]) {
+                self.reload_affine_ref(memory, bg);
+            }
+        }
+    }
+
+    fn advance_affine_refs(&mut self, memory: &Memory) {
+        for bg in 2..4 {
+            let affine = BgAffine::read(memory, bg);
+            let (x, y) = &mut self.affine_ref[bg - 2];
+            *x += affine.pb;
+            *y += affine.pd;
+        }
+    }
+
+    fn start_scanline(&mut self, memory: &Memory) {
+        let y = self.vcount as usize;
+        self.latch = LineLatch::read(memory, y);
+        self.render_obj_line(memory, y, self.latch.dispcnt);
+
+        self.line_stamp = LineStamp {
+            generation: memory.video_generation,
+            layers: self.layers,
+            affine_ref: self.affine_ref,
+        };
+        // nothing the renderer reads has changed since this line was last drawn
+        self.reusing_line = self.line_stamps[y] == Some(self.line_stamp);
+        self.compose_from = if self.reusing_line { SCREEN_WIDTH } else { 0 };
+    }
+
+    // renders every enabled layer at one pixel into the line buffers
+    fn draw_layers(&mut self, memory: &Memory, x: usize) {
+        if self.reusing_line {
+            if memory.video_generation == self.line_stamp.generation {
+                return;
+            }
+            // a write landed mid-line, so everything from here on is redrawn
+            self.reusing_line = false;
+            self.compose_from = x;
+        }
+
+        if self.latch.dispcnt.forced_blank() {
+            return;
+        }
+
+        let y = self.vcount as usize;
+        for i in 0..self.latch.bgs.len() {
+            let bg = self.latch.bgs[i];
+            self.bg_lines[bg][x] = self.bg_pixel(memory, bg, x, y).unwrap_or(TRANSPARENT);
+        }
+
+        self.pixel_states[x] = PixelState {
+            window: self.window_mask(memory, x) & self.layers.mask(),
+            backdrop: palette_color(memory, 0),
+            bldcnt: BldCnt::read(memory),
+            bldalpha: BldAlpha::read(memory),
+            bldy: BldY::read(memory),
+        };
+    }
+
+    // merges the layer buffers into the framebuffer
+    fn compose_line(&mut self) {
+        let y = self.vcount as usize;
+        let row = &mut self.frame_buffer[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH];
+
+        if self.latch.dispcnt.forced_blank() {
+            row[self.compose_from..].fill(0x7FFF);
+            return;
+        }
+
+        for (x, pixel) in row.iter_mut().enumerate().skip(self.compose_from) {
+            let bg_colors = std::array::from_fn(|bg| self.bg_lines[bg][x]);
+            *pixel = compose::compose_pixel(&self.latch, &self.pixel_states[x], bg_colors, self.obj_line[x]);
+        }
+    }
+
+    fn finish_scanline(&mut self, memory: &Memory) {
+        self.compose_line();
+
+        // only lines drawn from constant state can be reused next frame
+        let y = self.vcount as usize;
+        let unchanged = memory.video_generation == self.line_stamp.generation;
+        self.line_stamps[y] = unchanged.then_some(self.line_stamp);
+    }
+
+    // returns the WININ/WINOUT enable bits that apply to this pixel
+    fn window_mask(&self, memory: &Memory, x: usize) -> u16 {
+        let dispcnt = self.latch.dispcnt;
+        if !dispcnt.any_window_enabled() {
+            return 0x3F;
+        }
+
+        let control = WindowControl::read(memory);
+
+        for window in 0..2 {
+            if dispcnt.window_enabled(window)
+                && self.latch.window_rows[window]
+                && WindowBounds::read(memory, window).contains_x(x)
+            {
+                return control.inside(window);
+            }
+        }
+
+        if dispcnt.obj_window_enabled() && self.obj_window[x] {
+            return control.obj_window;
+        }
+
+        control.outside
+    }
+
+    fn bg_pixel(&self, memory: &Memory, bg: usize, x: usize, y: usize) -> Option<u16> {
+        let dispcnt = self.latch.dispcnt;
+        let cnt = self.latch.bgcnt[bg];
+        // rows since the top of the current mosaic block
+        let (x, mosaic_rows) = if cnt.mosaic() {
+            let (h, v) = self.latch.mosaic.bg_size();
+            (x - x % h, y % v)
+        } else {
+            (x, 0)
+        };
+
+        match (dispcnt.mode(), bg) {
+            (0, _) | (1, 0..=1) => self.text_bg_pixel(memory, bg, cnt, x, y - mosaic_rows),
+            (1, 2) | (2, _) => self.affine_bg_pixel(memory, bg, cnt, x, mosaic_rows),
+            (3..=5, 2) => self.bitmap_pixel(memory, dispcnt, x, mosaic_rows),
+            _ => None,
+        }
+    }
+
+    fn text_bg_pixel(&self, memory: &Memory, bg: usize, cnt: BgCnt, x: usize, y: usize) -> Option<u16> {
+        let offset = BgOffset::read(memory, bg);
+        let (width, height) = cnt.text_size();
+        text_map_pixel(memory, cnt, (x + offset.h) % width, (y + offset.v) % height)
+    }
+
+    // The line's starting point comes from the internal reference point, so
+    // only the per-pixel PA/PC step is applied here. Vertical mosaic steps
+    // the reference point back to the first row of the block.
+    fn affine_coords(&self, memory: &Memory, bg: usize, x: usize, mosaic_rows: usize) -> (i32, i32) {
+        let affine = BgAffine::read(memory, bg);
+        let (ref_x, ref_y) = self.affine_ref[bg - 2];
+        let (x, rows) = (x as i32, mosaic_rows as i32);
+        (
+            (ref_x - affine.pb * rows + affine.pa * x) >> 8,
+            (ref_y - affine.pd * rows + affine.pc * x) >> 8,
+        )
+    }
+
+    fn affine_bg_pixel(&self, memory: &Memory, bg: usize, cnt: BgCnt, x: usize, mosaic_rows: usize) -> Option<u16> {
+        let (tx, ty) = self.affine_coords(memory, bg, x, mosaic_rows);
+        affine_map_pixel(memory, cnt, tx, ty)
+    }
+
+    fn bitmap_pixel(&self, memory: &Memory, dispcnt: Dispcnt, x: usize, mosaic_rows: usize) -> Option<u16> {
+        let mode = dispcnt.mode();
+        let (tx, ty) = self.affine_coords(memory, 2, x, mosaic_rows);
+        let (width, height) = if mode == 5 { (160, 128) } else { (240, 160) };
+        if tx < 0 || ty < 0 || tx >= width || ty >= height {
+            return None;
+        }
+
+        let offset = (ty * width + tx) as usize;
+        let page = if mode != 3 && dispcnt.frame_select() { 0xA000 } else { 0 };
+
+        match mode {
+            4 => match vram_u8(memory, page + offset) as usize {
+                0 => None,
+                index => Some(palette_color(memory, index)),
+            },
+            _ => Some(vram_u16(memory, page + offset * 2) & 0x7FFF),
+        }
+    }
+
+    fn render_obj_line(&mut self, memory: &Memory, y: usize, dispcnt: Dispcnt) {
+        self.obj_line.fill(None);
+        self.obj_window.fill(false);
+        if !dispcnt.obj_enabled() {
+            return;
+        }
+
+        let one_dimensional = dispcnt.obj_one_dimensional();
+        // in bitmap modes the lower OBJ charblock is framebuffer memory
+        let bitmap_mode = dispcnt.is_bitmap_mode();
+
+        for i in 0..128 {
+            let attr0 = oam_u16(memory, i * 8);
+            let attr1 = oam_u16(memory, i * 8 + 2);
+            let attr2 = oam_u16(memory, i * 8 + 4);
+
+            let affine = attr0 & 0x100 != 0;
+            let double_size = attr0 & 0x200 != 0;
+            if !affine && double_size {
+                // disabled sprite
+                continue;
+            }
+
+            let obj_mode = (attr0 >> 10) & 0x3;
+            let shape = (attr0 >> 14) as usize;
+            if obj_mode == 3 || shape == 3 {
+                continue;
+            }
+
+            let (width, height) = OBJ_SIZES[shape][(attr1 >> 14) as usize];
+            let (box_width, box_height) = if affine && double_size {
+                (width * 2, height * 2)
+            } else {
+                (width, height)
+            };
+
+            let obj_y = (attr0 & 0xFF) as i32;
+            let line = (y as i32 - obj_y) & 0xFF;
+            if line >= box_height {
+                continue;
+            }
+
+            // OBJ mosaic snaps to a screen-aligned grid sized by the OBJ half
+            // of MOSAIC, clamped so blocks never sample outside the sprite
+            let mosaic = attr0 & 0x1000 != 0;
+            let (mosaic_h, mosaic_v) = self.latch.mosaic.obj_size();
+            let line = if mosaic {
+                let sample_y = (y - y % mosaic_v) as i32;
+                let sample_line = (sample_y - obj_y) & 0xFF;
+                if sample_line >= box_height { 0 } else { sample_line }
+            } else {
+                line
+            };
+
+            let mut obj_x = (attr1 & 0x1FF) as i32;
+            if obj_x >= SCREEN_WIDTH as i32 {
+                obj_x -= 512;
+            }
+
+            let tile = (attr2 & 0x3FF) as usize;
+            if bitmap_mode && tile < OBJ_BITMAP_MODE_FIRST_TILE {
+                continue;
+            }
+            let priority = (attr2 >> 10) & 0x3;
+            let palette_bank = (attr2 >> 12) as usize;
+            let color_256 = attr0 & 0x2000 != 0;
+
+            let (pa, pb, pc, pd) = if affine {
+                let group = ((attr1 >> 9) & 0x1F) as usize * 32;
+                (
+                    oam_u16(memory, group + 6) as i16 as i32,
+                    oam_u16(memory, group + 14) as i16 as i32,
+                    oam_u16(memory, group + 22) as i16 as i32,
+                    oam_u16(memory, group + 30) as i16 as i32,
+                )
+            } else {
+                (0x100, 0, 0, 0x100)
+            };
+
+            for sx in 0..box_width {
+                let screen_x = obj_x + sx;
+                if screen_x < 0 || screen_x >= SCREEN_WIDTH as i32 {
+                    continue;
+                }
+                let screen_x = screen_x as usize;
+                let sx = if mosaic {
+                    ((screen_x - screen_x % mosaic_h) as i32 - obj_x).max(0)
+                } else {
+                    sx
+                };
+
+                let is_window = obj_mode == 2;
+                if !is_window && self.obj_line[screen_x].is_some_and(|p| p.priority <= priority) {
+                    continue;
+                }
+
+                let (tx, ty) = if affine {
+                    let cx = sx - box_width / 2;
+                    let cy = line - box_height / 2;
+                    let tx = ((pa * cx + pb * cy) >> 8) + width / 2;
+                    let ty = ((pc * cx + pd * cy) >> 8) + height / 2;
+                    if tx < 0 || ty < 0 || tx >= width || ty >= height {
+                        continue;
+                    }
+                    (tx, ty)
+                } else {
+                    let tx = if attr1 & 0x1000 != 0 { width - 1 - sx } else { sx };
+                    let ty = if attr1 & 0x2000 != 0 { height - 1 - line } else { line };
+                    (tx, ty)
+                };
+
+                let texel = obj_texel(
+                    memory,
+                    tile,
+                    (tx as usize, ty as usize),
+                    width as usize,
+                    color_256,
+                    palette_bank,
+                    one_dimensional,
+                );
+
+                if let Some(color) = texel {
+                    if is_window {
+                        // OBJ window sprites only mark the window region
+                        self.obj_window[screen_x] = true;
+                        continue;
+                    }
+                    self.obj_line[screen_x] = Some(ObjPixel {
+                        color,
+                        priority,
+                        semi_transparent: obj_mode == 1,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Ppu::new()
+    }
+}
+
+// (px, py) are map coordinates, already scrolled and wrapped
+fn text_map_pixel(memory: &Memory, cnt: BgCnt, px: usize, py: usize) -> Option<u16> {
+    let char_base = cnt.char_base();
+    let screen_base = cnt.screen_base();
+    let (width, _) = cnt.text_size();
+
+    let block = px / 256 + (py / 256) * (width / 256);
+    let entry_addr = screen_base + block * 0x800 + ((py % 256) / 8) * 64 + ((px % 256) / 8) * 2;
+    let entry = vram_u16(memory, entry_addr);
+
+    let tile = (entry & 0x3FF) as usize;
+    let tx = if entry & 0x400 != 0 { 7 - px % 8 } else { px % 8 };
+    let ty = if entry & 0x800 != 0 { 7 - py % 8 } else { py % 8 };
+
+    let index = if cnt.color_256() {
+        vram_u8(memory, char_base + tile * 64 + ty * 8 + tx) as usize
+    } else {
+        let byte = vram_u8(memory, char_base + tile * 32 + ty * 4 + tx / 2);
+        let nibble = if tx & 1 != 0 { byte >> 4 } else { byte & 0xF } as usize;
+        if nibble == 0 {
+            return None;
+        }
+        (entry >> 12) as usize * 16 + nibble
+    };
+
+    if index == 0 {
+        None
+    } else {
+        Some(palette_color(memory, index))
+    }
+}
+
+fn affine_map_pixel(memory: &Memory, cnt: BgCnt, mut tx: i32, mut ty: i32) -> Option<u16> {
+    let char_base = cnt.char_base();
+    let screen_base = cnt.screen_base();
+    let size = cnt.affine_size();
+
+    if cnt.affine_wrap() {
+        tx = tx.rem_euclid(size);
+        ty = ty.rem_euclid(size);
+    } else if tx < 0 || ty < 0 || tx >= size || ty >= size {
+        return None;
+    }
+
+    let (tx, ty, size) = (tx as usize, ty as usize, size as usize);
+    let tile = vram_u8(memory, screen_base + (ty / 8) * (size / 8) + tx / 8) as usize;
+    let index = vram_u8(memory, char_base + tile * 64 + (ty % 8) * 8 + tx % 8) as usize;
+
+    if index == 0 {
+        None
+    } else {
+        Some(palette_color(memory, index))
+    }
+}
+
+fn bg_available(mode: u16, bg: usize) -> bool {
+    match mode {
+        0 => true,
+        1 => bg <= 2,
+        2 => bg >= 2,
+        3..=5 => bg == 2,
+        _ => false,
+    }
+}
+
+fn obj_texel(
+    memory: &Memory,
+    tile: usize,
+    (tx, ty): (usize, usize),
+    width: usize,
+    color_256: bool,
+    palette_bank: usize,
+    one_dimensional: bool,
+) -> Option<u16> {
+    let tile_step = if color_256 { 2 } else { 1 };
+    let row_stride = if one_dimensional { (width / 8) * tile_step } else { 32 };
+    let tile_num = (tile + (ty / 8) * row_stride + (tx / 8) * tile_step) & 0x3FF;
+    let tile_addr = OBJ_VRAM_BASE + tile_num * 32;
+    let (px, py) = (tx % 8, ty % 8);
+
+    let index = if color_256 {
+        vram_u8(memory, tile_addr + py * 8 + px) as usize
+    } else {
+        let byte = vram_u8(memory, tile_addr + py * 4 + px / 2);
+        let nibble = if px & 1 != 0 { byte >> 4 } else { byte & 0xF } as usize;
+        if nibble == 0 {
+            return None;
+        }
+        palette_bank * 16 + nibble
+    };
+
+    if index == 0 {
+        None
+    } else {
+        Some(palette_color(memory, 256 + index))
+    }
+}
+
+fn palette_color(memory: &Memory, index: usize) -> u16 {
+    let low = memory.palette_ram[index * 2] as u16;
+    let high = memory.palette_ram[index * 2 + 1] as u16;
+    (low | (high << 8)) & 0x7FFF
+}
+
+fn vram_u8(memory: &Memory, addr: usize) -> u8 {
+    memory.vram.get(addr).copied().unwrap_or(0)
+}
+
+fn vram_u16(memory: &Memory, addr: usize) -> u16 {
+    vram_u8(memory, addr) as u16 | ((vram_u8(memory, addr + 1) as u16) << 8)
+}
+
+fn oam_u16(memory: &Memory, addr: usize) -> u16 {
+    memory.oam[addr] as u16 | ((memory.oam[addr + 1] as u16) << 8)
 }
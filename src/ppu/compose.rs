@@ -0,0 +1,100 @@
+// Final pass of the scanline pipeline: resolves priority between the
+// per-layer line buffers, then applies window enables and color effects.
+
+use serde::{Deserialize, Serialize};
+
+use super::registers::{BlendEffect, BldAlpha, BldCnt, BldY};
+use super::{LineLatch, ObjPixel, BACKDROP_LAYER, OBJ_LAYER};
+
+// marks a transparent pixel in a BG line buffer; real colors are 15-bit
+pub const TRANSPARENT: u16 = 0x8000;
+
+// Inputs to the final pass that may change mid-line, captured as each
+// pixel's layers are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PixelState {
+    // WININ/WINOUT enable bits with the debug layer toggles applied
+    pub window: u16,
+    pub backdrop: u16,
+    pub bldcnt: BldCnt,
+    pub bldalpha: BldAlpha,
+    pub bldy: BldY,
+}
+
+pub fn compose_pixel(latch: &LineLatch, state: &PixelState, bg_colors: [u16; 4], obj: Option<ObjPixel>) -> u16 {
+    let window = state.window;
+
+    // find the two top-most opaque layers, OBJ wins ties against BGs
+    let obj = obj.filter(|_| window & (1 << OBJ_LAYER) != 0);
+    let mut layers: [(usize, u16); 2] = [(BACKDROP_LAYER, state.backdrop); 2];
+    let mut found = 0;
+
+    'search: for priority in 0..4 {
+        if let Some(pixel) = obj.filter(|p| p.priority == priority) {
+            layers[found] = (OBJ_LAYER, pixel.color);
+            found += 1;
+            if found == 2 {
+                break 'search;
+            }
+        }
+        for &bg in &latch.bgs {
+            let color = bg_colors[bg];
+            if color == TRANSPARENT || window & (1 << bg) == 0 || latch.bgcnt[bg].priority() != priority {
+                continue;
+            }
+            layers[found] = (bg, color);
+            found += 1;
+            if found == 2 {
+                break 'search;
+            }
+        }
+    }
+
+    let (top_layer, top) = layers[0];
+    let (bottom_layer, bottom) = layers[1];
+    if window & 0x20 == 0 {
+        return top;
+    }
+
+    let bldcnt = state.bldcnt;
+    let first_target = bldcnt.first_target(top_layer);
+    let second_target = bldcnt.second_target(bottom_layer);
+
+    // semi-transparent sprites always alpha blend onto a second target
+    let semi_transparent = top_layer == OBJ_LAYER && obj.is_some_and(|p| p.semi_transparent);
+    if semi_transparent && second_target {
+        return alpha_blend(state.bldalpha, top, bottom);
+    }
+
+    match bldcnt.effect() {
+        BlendEffect::Alpha if first_target && second_target => alpha_blend(state.bldalpha, top, bottom),
+        BlendEffect::Brighten if first_target => brightness(state.bldy, top, true),
+        BlendEffect::Darken if first_target => brightness(state.bldy, top, false),
+        _ => top,
+    }
+}
+
+fn alpha_blend(bldalpha: BldAlpha, top: u16, bottom: u16) -> u16 {
+    let (eva, evb) = (bldalpha.eva(), bldalpha.evb());
+    blend_channels(top, bottom, |a, b| ((a * eva + b * evb) >> 4).min(31))
+}
+
+fn brightness(bldy: BldY, color: u16, increase: bool) -> u16 {
+    let evy = bldy.evy();
+    blend_channels(color, 0, |c, _| {
+        if increase {
+            c + (((31 - c) * evy) >> 4)
+        } else {
+            c - ((c * evy) >> 4)
+        }
+    })
+}
+
+fn blend_channels(a: u16, b: u16, f: impl Fn(u16, u16) -> u16) -> u16 {
+    let mut result = 0;
+    for shift in [0, 5, 10] {
+        let channel = f((a >> shift) & 0x1F, (b >> shift) & 0x1F);
+        result |= channel << shift;
+    }
+    result
+}
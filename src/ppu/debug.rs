@@ -0,0 +1,328 @@
+// Inspection helpers for debug UIs and tests. None of these touch PPU state.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use super::registers::{BgAffine, BgCnt, BgOffset, Dispcnt, PpuRegisters};
+use super::{
+    affine_map_pixel, oam_u16, palette_color, text_map_pixel, vram_u8, Ppu, OBJ_SIZES, SCREEN_HEIGHT,
+    SCREEN_WIDTH,
+};
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub fn from_bgr555(color: u16) -> Self {
+        // expand 5-bit channels so that 31 maps to 255
+        let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+        Rgb {
+            r: expand(color & 0x1F),
+            g: expand((color >> 5) & 0x1F),
+            b: expand((color >> 10) & 0x1F),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl RgbImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        RgbImage {
+            width,
+            height,
+            pixels: vec![Rgb::default(); width * height],
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Rgb) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data: Vec<u8> = self.pixels.iter().flat_map(|pixel| [pixel.r, pixel.g, pixel.b]).collect();
+        encoder.write_header()?.write_image_data(&data)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    Bpp4,
+    Bpp8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteSource {
+    Bg,
+    Obj,
+}
+
+pub const CHARBLOCK_COUNT: usize = 6;
+const CHARBLOCK_SIZE: usize = 0x4000;
+const TILES_PER_ROW: usize = 32;
+const VIEWPORT_COLOR: Rgb = Rgb { r: 255, g: 0, b: 255 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjMode {
+    Normal,
+    SemiTransparent,
+    Window,
+    Prohibited,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjAffine {
+    pub group: usize,
+    pub double_size: bool,
+    // 8.8 fixed point matrix
+    pub pa: i16,
+    pub pb: i16,
+    pub pc: i16,
+    pub pd: i16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamEntry {
+    pub index: usize,
+    // top-left corner of the sprite, wrapped to negative when it straddles an edge
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub tile: u16,
+    pub palette_bank: u8,
+    pub priority: u8,
+    pub mode: ObjMode,
+    pub color_256: bool,
+    pub mosaic: bool,
+    pub h_flip: bool,
+    pub v_flip: bool,
+    // false for disabled sprites and prohibited shape/mode combinations
+    pub visible: bool,
+    pub affine: Option<ObjAffine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaletteView {
+    // 16 banks of 16 colors each; bank 0 entry 0 of `bg` is the backdrop
+    pub bg: [[Rgb; 16]; 16],
+    pub obj: [[Rgb; 16]; 16],
+}
+
+impl Ppu {
+    // the last frame drawn, as shown on screen
+    pub fn frame_image(&self) -> RgbImage {
+        RgbImage {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: self.frame_buffer.iter().map(|&color| Rgb::from_bgr555(color)).collect(),
+        }
+    }
+
+    pub fn palette_view(&self, memory: &Memory) -> PaletteView {
+        let mut view = PaletteView {
+            bg: [[Rgb::default(); 16]; 16],
+            obj: [[Rgb::default(); 16]; 16],
+        };
+
+        for bank in 0..16 {
+            for entry in 0..16 {
+                let index = bank * 16 + entry;
+                view.bg[bank][entry] = Rgb::from_bgr555(palette_color(memory, index));
+                view.obj[bank][entry] = Rgb::from_bgr555(palette_color(memory, 256 + index));
+            }
+        }
+
+        view
+    }
+
+    pub fn registers(&self, memory: &Memory) -> PpuRegisters {
+        PpuRegisters::read(memory)
+    }
+
+    pub fn oam_entries(&self, memory: &Memory) -> Vec<OamEntry> {
+        (0..128).map(|index| decode_oam_entry(memory, index)).collect()
+    }
+
+    // Renders the whole map of a tiled background as configured for the
+    // current video mode and outlines the area the screen samples from.
+    // Returns None for bitmap layers or layers absent from the mode.
+    pub fn tilemap_view(&self, memory: &Memory, bg: usize) -> Option<RgbImage> {
+        let affine = match (Dispcnt::read(memory).mode(), bg) {
+            (0, 0..=3) | (1, 0..=1) => false,
+            (1, 2) | (2, 2..=3) => true,
+            _ => return None,
+        };
+
+        let cnt = BgCnt::read(memory, bg);
+        let backdrop = Rgb::from_bgr555(palette_color(memory, 0));
+        let to_rgb = |color: Option<u16>| color.map_or(backdrop, Rgb::from_bgr555);
+
+        let image = if affine {
+            let size = cnt.affine_size();
+            let mut image = RgbImage::new(size as usize, size as usize);
+            for y in 0..size {
+                for x in 0..size {
+                    image.set(x as usize, y as usize, to_rgb(affine_map_pixel(memory, cnt, x, y)));
+                }
+            }
+            let transform = BgAffine::read(memory, bg);
+            for (sx, sy) in viewport_edge() {
+                let (tx, ty) = transform.map(sx as i32, sy as i32);
+                if cnt.affine_wrap() {
+                    image.set(tx.rem_euclid(size) as usize, ty.rem_euclid(size) as usize, VIEWPORT_COLOR);
+                } else if (0..size).contains(&tx) && (0..size).contains(&ty) {
+                    image.set(tx as usize, ty as usize, VIEWPORT_COLOR);
+                }
+            }
+            image
+        } else {
+            let (width, height) = cnt.text_size();
+            let mut image = RgbImage::new(width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    image.set(x, y, to_rgb(text_map_pixel(memory, cnt, x, y)));
+                }
+            }
+            let offset = BgOffset::read(memory, bg);
+            for (sx, sy) in viewport_edge() {
+                image.set((sx + offset.h) % width, (sy + offset.v) % height, VIEWPORT_COLOR);
+            }
+            image
+        };
+
+        Some(image)
+    }
+
+    // Charblocks 0-3 hold BG tiles and 4-5 OBJ tiles. The sheet is laid
+    // out 32 tiles wide; `palette_bank` is ignored for 8bpp tiles.
+    pub fn charblock_view(
+        &self,
+        memory: &Memory,
+        block: usize,
+        depth: ColorDepth,
+        source: PaletteSource,
+        palette_bank: usize,
+    ) -> RgbImage {
+        let tile_bytes = match depth {
+            ColorDepth::Bpp4 => 32,
+            ColorDepth::Bpp8 => 64,
+        };
+        let tile_count = CHARBLOCK_SIZE / tile_bytes;
+        let mut image = RgbImage::new(TILES_PER_ROW * 8, tile_count / TILES_PER_ROW * 8);
+        let base = (block % CHARBLOCK_COUNT) * CHARBLOCK_SIZE;
+        let palette_base = match source {
+            PaletteSource::Bg => 0,
+            PaletteSource::Obj => 256,
+        };
+
+        for tile in 0..tile_count {
+            let tile_addr = base + tile * tile_bytes;
+            let origin_x = (tile % TILES_PER_ROW) * 8;
+            let origin_y = (tile / TILES_PER_ROW) * 8;
+
+            for py in 0..8 {
+                for px in 0..8 {
+                    let index = match depth {
+                        ColorDepth::Bpp4 => {
+                            let byte = vram_u8(memory, tile_addr + py * 4 + px / 2);
+                            let nibble = if px & 1 != 0 { byte >> 4 } else { byte & 0xF };
+                            (palette_bank & 0xF) * 16 + nibble as usize
+                        }
+                        ColorDepth::Bpp8 => vram_u8(memory, tile_addr + py * 8 + px) as usize,
+                    };
+                    let color = palette_color(memory, palette_base + index);
+                    image.set(origin_x + px, origin_y + py, Rgb::from_bgr555(color));
+                }
+            }
+        }
+
+        image
+    }
+}
+
+fn decode_oam_entry(memory: &Memory, index: usize) -> OamEntry {
+    let attr0 = oam_u16(memory, index * 8);
+    let attr1 = oam_u16(memory, index * 8 + 2);
+    let attr2 = oam_u16(memory, index * 8 + 4);
+
+    let is_affine = attr0 & 0x100 != 0;
+    let double_size = attr0 & 0x200 != 0;
+    let shape = (attr0 >> 14) as usize;
+    let mode = match (attr0 >> 10) & 0x3 {
+        0 => ObjMode::Normal,
+        1 => ObjMode::SemiTransparent,
+        2 => ObjMode::Window,
+        _ => ObjMode::Prohibited,
+    };
+
+    let (width, height) = if shape < 3 {
+        OBJ_SIZES[shape][(attr1 >> 14) as usize]
+    } else {
+        (0, 0)
+    };
+    let box_height = if is_affine && double_size { height * 2 } else { height };
+
+    let mut x = (attr1 & 0x1FF) as i32;
+    if x >= SCREEN_WIDTH as i32 {
+        x -= 512;
+    }
+    let mut y = (attr0 & 0xFF) as i32;
+    if y + box_height > 256 {
+        y -= 256;
+    }
+
+    let affine = is_affine.then(|| {
+        let group = ((attr1 >> 9) & 0x1F) as usize;
+        let param = |offset: usize| oam_u16(memory, group * 32 + offset) as i16;
+        ObjAffine {
+            group,
+            double_size,
+            pa: param(6),
+            pb: param(14),
+            pc: param(22),
+            pd: param(30),
+        }
+    });
+
+    OamEntry {
+        index,
+        x,
+        y,
+        width,
+        height,
+        tile: attr2 & 0x3FF,
+        palette_bank: (attr2 >> 12) as u8,
+        priority: ((attr2 >> 10) & 0x3) as u8,
+        mode,
+        color_256: attr0 & 0x2000 != 0,
+        mosaic: attr0 & 0x1000 != 0,
+        h_flip: !is_affine && attr1 & 0x1000 != 0,
+        v_flip: !is_affine && attr1 & 0x2000 != 0,
+        visible: (is_affine || !double_size) && shape < 3 && mode != ObjMode::Prohibited,
+        affine,
+    }
+}
+
+// screen coordinates along the border of the visible area
+fn viewport_edge() -> impl Iterator<Item = (usize, usize)> {
+    let horizontal = (0..SCREEN_WIDTH).flat_map(|x| [(x, 0), (x, SCREEN_HEIGHT - 1)]);
+    let vertical = (0..SCREEN_HEIGHT).flat_map(|y| [(0, y), (SCREEN_WIDTH - 1, y)]);
+    horizontal.chain(vertical)
+}
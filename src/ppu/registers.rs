@@ -0,0 +1,401 @@
+// Typed views over the raw LCD I/O registers. Each wrapper holds the raw
+// register value and decodes fields on demand.
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::Memory;
+
+pub const DISPCNT: usize = 0x000;
+pub const DISPSTAT: usize = 0x004;
+pub const VCOUNT: usize = 0x006;
+pub const BG0CNT: usize = 0x008;
+pub const BG0HOFS: usize = 0x010;
+pub const BG2PA: usize = 0x020;
+pub const WIN0H: usize = 0x040;
+pub const WIN0V: usize = 0x044;
+pub const WININ: usize = 0x048;
+pub const WINOUT: usize = 0x04A;
+pub const MOSAIC: usize = 0x04C;
+pub const BLDCNT: usize = 0x050;
+pub const BLDALPHA: usize = 0x052;
+pub const BLDY: usize = 0x054;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Dispcnt(pub u16);
+
+impl Dispcnt {
+    pub fn read(memory: &Memory) -> Self {
+        Dispcnt(memory.io_u16(DISPCNT))
+    }
+
+    pub fn mode(self) -> u16 {
+        self.0 & 0x7
+    }
+
+    pub fn is_bitmap_mode(self) -> bool {
+        self.mode() >= 3
+    }
+
+    pub fn frame_select(self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+
+    pub fn obj_one_dimensional(self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+
+    pub fn forced_blank(self) -> bool {
+        self.0 & (1 << 7) != 0
+    }
+
+    pub fn bg_enabled(self, bg: usize) -> bool {
+        self.0 & (1 << (8 + bg)) != 0
+    }
+
+    pub fn obj_enabled(self) -> bool {
+        self.0 & (1 << 12) != 0
+    }
+
+    pub fn window_enabled(self, window: usize) -> bool {
+        self.0 & (1 << (13 + window)) != 0
+    }
+
+    pub fn obj_window_enabled(self) -> bool {
+        self.0 & (1 << 15) != 0
+    }
+
+    pub fn any_window_enabled(self) -> bool {
+        self.0 & 0xE000 != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dispstat(pub u16);
+
+impl Dispstat {
+    pub fn read(memory: &Memory) -> Self {
+        Dispstat(memory.io_u16(DISPSTAT))
+    }
+
+    pub fn vblank(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    pub fn hblank(self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    pub fn vcount_match(self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    pub fn vblank_irq(self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+
+    pub fn hblank_irq(self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+
+    pub fn vcount_irq(self) -> bool {
+        self.0 & (1 << 5) != 0
+    }
+
+    pub fn vcount_setting(self) -> u16 {
+        self.0 >> 8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BgCnt(pub u16);
+
+impl BgCnt {
+    pub fn read(memory: &Memory, bg: usize) -> Self {
+        BgCnt(memory.io_u16(BG0CNT + bg * 2))
+    }
+
+    pub fn priority(self) -> u16 {
+        self.0 & 0x3
+    }
+
+    // byte offsets into VRAM
+    pub fn char_base(self) -> usize {
+        ((self.0 >> 2) & 0x3) as usize * 0x4000
+    }
+
+    pub fn screen_base(self) -> usize {
+        ((self.0 >> 8) & 0x1F) as usize * 0x800
+    }
+
+    pub fn mosaic(self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+
+    pub fn color_256(self) -> bool {
+        self.0 & (1 << 7) != 0
+    }
+
+    pub fn affine_wrap(self) -> bool {
+        self.0 & (1 << 13) != 0
+    }
+
+    pub fn size(self) -> u16 {
+        self.0 >> 14
+    }
+
+    // map dimensions in pixels for text backgrounds
+    pub fn text_size(self) -> (usize, usize) {
+        match self.size() {
+            0 => (256, 256),
+            1 => (512, 256),
+            2 => (256, 512),
+            _ => (512, 512),
+        }
+    }
+
+    // map edge length in pixels for affine backgrounds
+    pub fn affine_size(self) -> i32 {
+        128 << self.size()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BgOffset {
+    pub h: usize,
+    pub v: usize,
+}
+
+impl BgOffset {
+    pub fn read(memory: &Memory, bg: usize) -> Self {
+        BgOffset {
+            h: (memory.io_u16(BG0HOFS + bg * 4) & 0x1FF) as usize,
+            v: (memory.io_u16(BG0HOFS + bg * 4 + 2) & 0x1FF) as usize,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BgAffine {
+    // 8.8 fixed point matrix and 20.8 fixed point reference point
+    pub pa: i32,
+    pub pb: i32,
+    pub pc: i32,
+    pub pd: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl BgAffine {
+    pub fn read(memory: &Memory, bg: usize) -> Self {
+        let base = BG2PA + (bg - 2) * 0x10;
+        BgAffine {
+            pa: memory.io_u16(base) as i16 as i32,
+            pb: memory.io_u16(base + 2) as i16 as i32,
+            pc: memory.io_u16(base + 4) as i16 as i32,
+            pd: memory.io_u16(base + 6) as i16 as i32,
+            x: sign_extend_28(memory.io_u32(base + 8)),
+            y: sign_extend_28(memory.io_u32(base + 12)),
+        }
+    }
+
+    // texture coordinate for a screen position, ignoring the internal
+    // reference point accumulators the renderer actually uses
+    pub fn map(self, x: i32, y: i32) -> (i32, i32) {
+        (
+            (self.x + self.pa * x + self.pb * y) >> 8,
+            (self.y + self.pc * x + self.pd * y) >> 8,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowBounds {
+    pub left: usize,
+    pub right: usize,
+    pub top: usize,
+    pub bottom: usize,
+}
+
+impl WindowBounds {
+    pub fn read(memory: &Memory, window: usize) -> Self {
+        let h = memory.io_u16(WIN0H + window * 2);
+        let v = memory.io_u16(WIN0V + window * 2);
+        WindowBounds {
+            left: (h >> 8) as usize,
+            right: (h & 0xFF) as usize,
+            top: (v >> 8) as usize,
+            bottom: (v & 0xFF) as usize,
+        }
+    }
+
+    // the right/bottom edges are exclusive and wrap when smaller than left/top
+    pub fn contains(self, x: usize, y: usize) -> bool {
+        self.contains_x(x) && self.contains_y(y)
+    }
+
+    pub fn contains_x(self, x: usize) -> bool {
+        span_contains(self.left, self.right, x)
+    }
+
+    pub fn contains_y(self, y: usize) -> bool {
+        span_contains(self.top, self.bottom, y)
+    }
+}
+
+fn span_contains(start: usize, end: usize, pos: usize) -> bool {
+    if start <= end {
+        pos >= start && pos < end
+    } else {
+        pos >= start || pos < end
+    }
+}
+
+// Layer enable bits as used by WININ/WINOUT: BG0-3, OBJ, color effects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowControl {
+    pub win0: u16,
+    pub win1: u16,
+    pub outside: u16,
+    pub obj_window: u16,
+}
+
+impl WindowControl {
+    pub fn read(memory: &Memory) -> Self {
+        let winin = memory.io_u16(WININ);
+        let winout = memory.io_u16(WINOUT);
+        WindowControl {
+            win0: winin & 0x3F,
+            win1: (winin >> 8) & 0x3F,
+            outside: winout & 0x3F,
+            obj_window: (winout >> 8) & 0x3F,
+        }
+    }
+
+    pub fn inside(self, window: usize) -> u16 {
+        if window == 0 { self.win0 } else { self.win1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Mosaic(pub u16);
+
+impl Mosaic {
+    pub fn read(memory: &Memory) -> Self {
+        Mosaic(memory.io_u16(MOSAIC))
+    }
+
+    // block sizes in pixels
+    pub fn bg_size(self) -> (usize, usize) {
+        ((self.0 & 0xF) as usize + 1, ((self.0 >> 4) & 0xF) as usize + 1)
+    }
+
+    pub fn obj_size(self) -> (usize, usize) {
+        (((self.0 >> 8) & 0xF) as usize + 1, ((self.0 >> 12) & 0xF) as usize + 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendEffect {
+    None,
+    Alpha,
+    Brighten,
+    Darken,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BldCnt(pub u16);
+
+impl BldCnt {
+    pub fn read(memory: &Memory) -> Self {
+        BldCnt(memory.io_u16(BLDCNT))
+    }
+
+    // layer ids: 0-3 BG, 4 OBJ, 5 backdrop
+    pub fn first_target(self, layer: usize) -> bool {
+        self.0 & (1 << layer) != 0
+    }
+
+    pub fn second_target(self, layer: usize) -> bool {
+        self.0 & (1 << (8 + layer)) != 0
+    }
+
+    pub fn effect(self) -> BlendEffect {
+        match (self.0 >> 6) & 0x3 {
+            0 => BlendEffect::None,
+            1 => BlendEffect::Alpha,
+            2 => BlendEffect::Brighten,
+            _ => BlendEffect::Darken,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BldAlpha(pub u16);
+
+impl BldAlpha {
+    pub fn read(memory: &Memory) -> Self {
+        BldAlpha(memory.io_u16(BLDALPHA))
+    }
+
+    // coefficients in sixteenths, clamped to 16 like the hardware
+    pub fn eva(self) -> u16 {
+        (self.0 & 0x1F).min(16)
+    }
+
+    pub fn evb(self) -> u16 {
+        ((self.0 >> 8) & 0x1F).min(16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BldY(pub u16);
+
+impl BldY {
+    pub fn read(memory: &Memory) -> Self {
+        BldY(memory.io_u16(BLDY))
+    }
+
+    pub fn evy(self) -> u16 {
+        (self.0 & 0x1F).min(16)
+    }
+}
+
+// Snapshot of every LCD register, decoded, for debugger views
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PpuRegisters {
+    pub dispcnt: Dispcnt,
+    pub dispstat: Dispstat,
+    pub vcount: u16,
+    pub bgcnt: [BgCnt; 4],
+    pub bg_offset: [BgOffset; 4],
+    pub bg_affine: [BgAffine; 2],
+    pub windows: [WindowBounds; 2],
+    pub window_control: WindowControl,
+    pub mosaic: Mosaic,
+    pub bldcnt: BldCnt,
+    pub bldalpha: BldAlpha,
+    pub bldy: BldY,
+}
+
+impl PpuRegisters {
+    pub fn read(memory: &Memory) -> Self {
+        PpuRegisters {
+            dispcnt: Dispcnt::read(memory),
+            dispstat: Dispstat::read(memory),
+            vcount: memory.io_u16(VCOUNT),
+            bgcnt: std::array::from_fn(|bg| BgCnt::read(memory, bg)),
+            bg_offset: std::array::from_fn(|bg| BgOffset::read(memory, bg)),
+            bg_affine: std::array::from_fn(|i| BgAffine::read(memory, i + 2)),
+            windows: std::array::from_fn(|window| WindowBounds::read(memory, window)),
+            window_control: WindowControl::read(memory),
+            mosaic: Mosaic::read(memory),
+            bldcnt: BldCnt::read(memory),
+            bldalpha: BldAlpha::read(memory),
+            bldy: BldY::read(memory),
+        }
+    }
+}
+
+fn sign_extend_28(value: u32) -> i32 {
+    ((value << 4) as i32) >> 4
+}
@@ -0,0 +1,64 @@
+// The profiler's hot spot report, for --profile and the debugger: where
+// the time went overall, then the functions, or address ranges without a
+// --symbols table, and the single instructions that took the most cycles.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use afterimage::Gba;
+use afterimage::disasm;
+use afterimage::symbols::Symbols;
+
+// functions and instructions listed in the --profile report
+pub const FILE_ROWS: usize = 100;
+
+pub fn load_symbols(gba: &mut Gba, path: &Path) -> io::Result<usize> {
+    let symbols = Symbols::parse(&fs::read_to_string(path)?);
+    if symbols.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no symbols in it"));
+    }
+    let count = symbols.len();
+    gba.profiler.symbols = symbols;
+    Ok(count)
+}
+
+// rows is how many functions and how many instructions to list
+pub fn write_report(gba: &Gba, out: &mut impl Write, rows: usize) -> io::Result<()> {
+    let report = gba.profiler.report();
+    let total = report.total().max(1);
+    let percent = |cycles: u64| cycles as f64 * 100.0 / total as f64;
+    writeln!(
+        out,
+        "{} cycles: {:.1}% running code, {:.1}% halted, {:.1}% stalled by DMA",
+        report.total(),
+        percent(report.running),
+        percent(report.halted),
+        percent(report.dma)
+    )?;
+
+    let by = if gba.profiler.symbols.is_empty() { "range" } else { "function" };
+    writeln!(out, "\n{:>12} {:>6}  {}", "cycles", "%", by)?;
+    for group in report.groups.iter().take(rows) {
+        writeln!(out, "{:>12} {:>5.1}%  {}", group.cycles, percent(group.cycles), group.name)?;
+    }
+
+    writeln!(out, "\n{:>12} {:>6}  instruction", "cycles", "%")?;
+    for spot in report.addresses.iter().take(rows) {
+        let (text, _) = disasm::disassemble(&gba.memory, spot.address, spot.thumb);
+        let place = match gba.profiler.symbols.lookup(spot.address) {
+            Some((name, start)) => format!("{}+{:X}", name, spot.address - start),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            "{:>12} {:>5.1}%  {:08X}  {:<24} {}",
+            spot.cycles,
+            percent(spot.cycles),
+            spot.address,
+            place,
+            text
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+// An exact profiler: while running, every instruction's cycles are added up
+// by the address it ran from, with the time the CPU spends halted and
+// stalled by DMA kept apart. The report groups the counts by function
+// when there is a symbol table, and by fixed size ranges of addresses
+// otherwise.
+
+use std::collections::HashMap;
+
+use crate::symbols::Symbols;
+
+// the ranges cycles are grouped in without symbols
+pub const RANGE_SIZE: u32 = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressCycles {
+    pub address: u32,
+    pub thumb: bool,
+    pub cycles: u64,
+}
+
+// A function, or a range of addresses with no symbols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupCycles {
+    pub name: String,
+    pub start: u32,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    // spent running code
+    pub running: u64,
+    pub halted: u64,
+    pub dma: u64,
+    // the most cycles first
+    pub addresses: Vec<AddressCycles>,
+    pub groups: Vec<GroupCycles>,
+}
+
+impl Report {
+    pub fn total(&self) -> u64 {
+        self.running + self.halted + self.dma
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    running: bool,
+    // by address, with bit 0 set for Thumb code
+    cycles: HashMap<u32, u64>,
+    halted: u64,
+    dma: u64,
+    // names the report's functions
+    pub symbols: Symbols,
+}
+
+impl Profiler {
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    // Carries on adding to the counts there are.
+    pub fn start(&mut self) {
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    pub fn reset(&mut self) {
+        self.cycles.clear();
+        self.halted = 0;
+        self.dma = 0;
+    }
+
+    pub(crate) fn record(&mut self, address: u32, thumb: bool, cycles: u64) {
+        *self.cycles.entry(address | thumb as u32).or_default() += cycles;
+    }
+
+    pub(crate) fn record_halted(&mut self, cycles: u64) {
+        self.halted += cycles;
+    }
+
+    pub(crate) fn record_dma(&mut self, cycles: u64) {
+        self.dma += cycles;
+    }
+
+    pub fn report(&self) -> Report {
+        let mut addresses: Vec<AddressCycles> = self
+            .cycles
+            .iter()
+            .map(|(&key, &cycles)| AddressCycles { address: key & !1, thumb: key & 1 != 0, cycles })
+            .collect();
+        addresses.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.address.cmp(&b.address)));
+
+        let mut groups: HashMap<u32, GroupCycles> = HashMap::new();
+        for spot in &addresses {
+            let (name, start) = match self.symbols.lookup(spot.address) {
+                Some((name, start)) => (name.to_string(), start),
+                None => {
+                    let start = spot.address & !(RANGE_SIZE - 1);
+                    (format!("{:08X}-{:08X}", start, start + (RANGE_SIZE - 1)), start)
+                }
+            };
+            groups.entry(start).or_insert(GroupCycles { name, start, cycles: 0 }).cycles += spot.cycles;
+        }
+        let mut groups: Vec<GroupCycles> = groups.into_values().collect();
+        groups.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.start.cmp(&b.start)));
+
+        Report {
+            running: addresses.iter().map(|spot| spot.cycles).sum(),
+            halted: self.halted,
+            dma: self.dma,
+            addresses,
+            groups,
+        }
+    }
+}
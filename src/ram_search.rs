@@ -0,0 +1,178 @@
+// Searching work RAM for the address a game keeps a value at, by
+// narrowing down every address in EWRAM and IWRAM with comparisons made as
+// the game runs: start a search, lose some health, keep the addresses
+// that decreased, and so on until only a few are left.
+
+use afterimage::Gba;
+
+use crate::cheat::Width;
+
+const EWRAM_BASE: u32 = 0x0200_0000;
+const IWRAM_BASE: u32 = 0x0300_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    // equal to a value now
+    Equals(u32),
+}
+
+impl Comparison {
+    // by the names the console takes, with the value for "="
+    pub fn parse(name: &str, value: Option<u32>) -> Option<Self> {
+        let comparison = match name {
+            "changed" | "ne" => Comparison::Changed,
+            "unchanged" | "eq" => Comparison::Unchanged,
+            "increased" | "gt" => Comparison::Increased,
+            "decreased" | "lt" => Comparison::Decreased,
+            "=" | "value" => Comparison::Equals(value?),
+            _ => return None,
+        };
+        Some(comparison)
+    }
+
+    fn keeps(self, now: u32, before: u32) -> bool {
+        match self {
+            Comparison::Changed => now != before,
+            Comparison::Unchanged => now == before,
+            Comparison::Increased => now > before,
+            Comparison::Decreased => now < before,
+            Comparison::Equals(value) => now == value,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RamSearch {
+    pub width: Width,
+    // addresses still in the running, in order
+    candidates: Vec<u32>,
+    // RAM as of the last step, to compare the next against
+    ewram: Vec<u8>,
+    iwram: Vec<u8>,
+}
+
+impl RamSearch {
+    // Starts with every aligned address in both RAMs.
+    pub fn new(gba: &Gba, width: Width) -> Self {
+        let step = width.bytes() as usize;
+        let addresses = |base: u32, size: usize| (0..size).step_by(step).map(move |offset| base + offset as u32);
+        RamSearch {
+            width,
+            candidates: addresses(EWRAM_BASE, gba.memory.ewram.len())
+                .chain(addresses(IWRAM_BASE, gba.memory.iwram.len()))
+                .collect(),
+            ewram: gba.memory.ewram.clone(),
+            iwram: gba.memory.iwram.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    // Keeps the addresses that pass, comparing against RAM as it was at
+    // the last step, which becomes RAM as it is now.
+    pub fn narrow(&mut self, gba: &Gba, comparison: Comparison) {
+        let width = self.width;
+        let (ewram, iwram) = (&self.ewram, &self.iwram);
+        self.candidates.retain(|&address| {
+            let now = value(&gba.memory.ewram, &gba.memory.iwram, address, width);
+            comparison.keeps(now, value(ewram, iwram, address, width))
+        });
+        self.ewram.clone_from(&gba.memory.ewram);
+        self.iwram.clone_from(&gba.memory.iwram);
+    }
+
+    // The first few addresses left, with their values now and at the last
+    // step.
+    pub fn results(&self, gba: &Gba, count: usize) -> Vec<(u32, u32, u32)> {
+        self.candidates
+            .iter()
+            .take(count)
+            .map(|&address| {
+                let now = value(&gba.memory.ewram, &gba.memory.iwram, address, self.width);
+                (address, now, value(&self.ewram, &self.iwram, address, self.width))
+            })
+            .collect()
+    }
+}
+
+// the little endian value at an address in one of the RAM copies
+fn value(ewram: &[u8], iwram: &[u8], address: u32, width: Width) -> u32 {
+    let (ram, offset) = match address {
+        IWRAM_BASE.. => (iwram, address - IWRAM_BASE),
+        _ => (ewram, address - EWRAM_BASE),
+    };
+    let offset = offset as usize;
+    ram[offset..offset + width.bytes() as usize]
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a byte search narrowed down to three bytes set to 10, then one of
+    // them raised and one lowered
+    fn three_bytes(gba: &mut Gba) -> RamSearch {
+        let mut search = RamSearch::new(gba, Width::Byte);
+        gba.memory.ewram[0x10] = 10;
+        gba.memory.ewram[0x20] = 10;
+        gba.memory.iwram[0x30] = 10;
+        search.narrow(gba, Comparison::Changed);
+        gba.memory.ewram[0x10] = 11;
+        gba.memory.ewram[0x20] = 9;
+        search
+    }
+
+    fn addresses(search: &RamSearch, gba: &Gba) -> Vec<u32> {
+        search.results(gba, usize::MAX).into_iter().map(|(address, _, _)| address).collect()
+    }
+
+    #[test]
+    fn comparisons_keep_the_addresses_that_pass() {
+        let cases = [
+            (Comparison::Changed, vec![0x0200_0010, 0x0200_0020]),
+            (Comparison::Unchanged, vec![0x0300_0030]),
+            (Comparison::Increased, vec![0x0200_0010]),
+            (Comparison::Decreased, vec![0x0200_0020]),
+            (Comparison::Equals(9), vec![0x0200_0020]),
+        ];
+        for (comparison, expected) in cases {
+            let mut gba = Gba::new();
+            let mut search = three_bytes(&mut gba);
+            search.narrow(&gba, comparison);
+            assert_eq!(addresses(&search, &gba), expected, "{:?}", comparison);
+        }
+    }
+
+    #[test]
+    fn each_step_compares_against_the_last() {
+        let mut gba = Gba::new();
+        let mut search = three_bytes(&mut gba);
+        assert_eq!(
+            search.results(&gba, 2),
+            vec![(0x0200_0010, 11, 10), (0x0200_0020, 9, 10)]
+        );
+        search.narrow(&gba, Comparison::Changed);
+        // nothing has moved since that step
+        search.narrow(&gba, Comparison::Unchanged);
+        assert_eq!(search.results(&gba, 2), vec![(0x0200_0010, 11, 11), (0x0200_0020, 9, 9)]);
+    }
+
+    #[test]
+    fn wider_searches_read_aligned_little_endian_values() {
+        let mut gba = Gba::new();
+        let mut search = RamSearch::new(&gba, Width::Half);
+        assert_eq!(search.len(), (gba.memory.ewram.len() + gba.memory.iwram.len()) / 2);
+        gba.memory.ewram[0x40..0x42].copy_from_slice(&[0x34, 0x12]);
+        search.narrow(&gba, Comparison::Equals(0x1234));
+        assert_eq!(addresses(&search, &gba), [0x0200_0040]);
+    }
+}
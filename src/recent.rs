@@ -0,0 +1,59 @@
+// Recently played ROMs, newest first, kept in recent.toml beside the
+// config file so a game can be relaunched by number.
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+pub const RECENT_FILE: &str = "recent.toml";
+const MAX_ENTRIES: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecentRoms {
+    pub roms: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    // A missing file is an empty list.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(toml::from_str(&text)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(RecentRoms::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // Moves rom to the top of the list, by absolute path so the list works
+    // from any directory.
+    pub fn add(&mut self, rom: &Path) {
+        let rom = fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+        self.roms.retain(|recent| *recent != rom);
+        self.roms.insert(0, rom);
+        self.roms.truncate(MAX_ENTRIES);
+    }
+}
+
+// Puts a ROM that just loaded at the top of the recent list at path.
+pub fn remember(path: &Path, rom: &Path) {
+    let result = RecentRoms::load(path).and_then(|mut recent| {
+        recent.add(rom);
+        recent.save(path)
+    });
+    if let Err(err) = result {
+        println!("Could not update {}: {}", path.display(), err);
+    }
+}
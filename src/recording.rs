@@ -0,0 +1,106 @@
+// Gameplay recording through ffmpeg. Frames are piped to an ffmpeg process
+// as raw RGB and encoded losslessly, while the mixer is dumped to a WAV
+// beside them. Finishing runs ffmpeg again to mux the two into the output,
+// whose extension picks the container and codecs.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use afterimage::gba::Gba;
+use afterimage::ppu::debug::Rgb;
+use afterimage::ppu::{CLOCK_RATE, FRAME_CYCLES, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Debug)]
+pub struct Recorder {
+    output: PathBuf,
+    video: PathBuf,
+    audio: PathBuf,
+    ffmpeg: Child,
+    stdin: ChildStdin,
+    // one frame of RGB24 pixels
+    frame: Vec<u8>,
+}
+
+impl Recorder {
+    // Starts ffmpeg and takes over the APU's WAV dump for the audio.
+    pub fn start(gba: &mut Gba, output: &Path) -> io::Result<Self> {
+        if gba.apu.dumping_wav() {
+            return Err(io::Error::other("audio is already being dumped to a WAV file"));
+        }
+        if let Some(dir) = output.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let video = output.with_extension("video.mkv");
+        let audio = output.with_extension("audio.wav");
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pixel_format", "rgb24"])
+            .args(["-video_size", &format!("{}x{}", SCREEN_WIDTH, SCREEN_HEIGHT)])
+            // the exact frame rate, as a fraction ffmpeg takes
+            .args(["-framerate", &format!("{}/{}", CLOCK_RATE, FRAME_CYCLES)])
+            .args(["-i", "-", "-c:v", "ffv1"])
+            .arg(&video)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("could not run ffmpeg: {}", err)))?;
+        let stdin = ffmpeg.stdin.take().expect("ffmpeg stdin is piped");
+        gba.apu.start_wav_dump(&audio, false)?;
+        Ok(Recorder {
+            output: output.to_path_buf(),
+            video,
+            audio,
+            ffmpeg,
+            stdin,
+            frame: Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3),
+        })
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    // Sends a BGR555 frame buffer to ffmpeg. Every emulated frame should
+    // be pushed, shown or not, to keep the picture in step with the audio.
+    pub fn push_frame(&mut self, frame: &[u16]) -> io::Result<()> {
+        self.frame.clear();
+        for &color in frame {
+            let Rgb { r, g, b } = Rgb::from_bgr555(color);
+            self.frame.extend([r, g, b]);
+        }
+        self.stdin.write_all(&self.frame)
+    }
+
+    // Stops the capture and muxes the output, blocking until ffmpeg is
+    // done. The intermediate files are kept if anything fails.
+    pub fn finish(self, gba: &mut Gba) -> io::Result<()> {
+        let Recorder {
+            output,
+            video,
+            audio,
+            mut ffmpeg,
+            stdin,
+            ..
+        } = self;
+        let audio_result = gba.apu.stop_wav_dump();
+        drop(stdin);
+        if !ffmpeg.wait()?.success() {
+            return Err(io::Error::other("ffmpeg failed to encode the video"));
+        }
+        audio_result?;
+
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(&video)
+            .arg("-i")
+            .arg(&audio)
+            .args(["-pix_fmt", "yuv420p", "-shortest"])
+            .arg(&output)
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other("ffmpeg failed to mux the recording"));
+        }
+        fs::remove_file(&video)?;
+        fs::remove_file(&audio)
+    }
+}
@@ -0,0 +1,141 @@
+// Rewinding: a save state is taken every few frames while playing and
+// held back as long as the rewind key is, stepping back one snapshot per
+// frame shown. Only the newest snapshot is kept whole; each older one is
+// stored as its difference from the one after it, with the unchanged runs
+// squeezed out, and the oldest are dropped once the buffer is full.
+
+use std::collections::VecDeque;
+use std::io;
+
+use afterimage::Gba;
+
+// frames between snapshots, and so the speed rewinding plays back at
+const REWIND_INTERVAL: u32 = 2;
+pub const DEFAULT_REWIND_BUFFER_MB: usize = 64;
+// unchanged bytes it takes to end a literal run in a delta
+const MIN_ZERO_RUN: usize = 8;
+
+#[derive(Debug)]
+pub struct Rewind {
+    capacity: usize,
+    // deltas back from each snapshot to the one before it, oldest first
+    deltas: VecDeque<Vec<u8>>,
+    // bytes held by deltas
+    size: usize,
+    newest: Option<Vec<u8>>,
+    frames: u32,
+}
+
+impl Rewind {
+    pub fn new(capacity_mb: usize) -> Self {
+        Rewind {
+            capacity: capacity_mb << 20,
+            deltas: VecDeque::new(),
+            size: 0,
+            newest: None,
+            frames: 0,
+        }
+    }
+
+    // Called after each frame run, taking a snapshot when one is due.
+    pub fn frame_ran(&mut self, gba: &Gba) -> io::Result<()> {
+        self.frames += 1;
+        if self.frames < REWIND_INTERVAL {
+            return Ok(());
+        }
+        self.frames = 0;
+        let state = gba.save_state()?;
+        if let Some(older) = &self.newest {
+            let delta = encode(older, &state);
+            self.size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.newest = Some(state);
+        while self.size > self.capacity
+            && let Some(delta) = self.deltas.pop_front()
+        {
+            self.size -= delta.len();
+        }
+        Ok(())
+    }
+
+    // Loads the snapshot before the newest, which takes its place. False
+    // once there are none left to go back to.
+    pub fn step_back(&mut self, gba: &mut Gba) -> io::Result<bool> {
+        let (Some(delta), Some(newest)) = (self.deltas.pop_back(), &self.newest) else {
+            return Ok(false);
+        };
+        self.size -= delta.len();
+        let older = decode(&delta, newest)?;
+        gba.load_state(&older)?;
+        self.newest = Some(older);
+        self.frames = 0;
+        Ok(true)
+    }
+
+    // Forgets every snapshot, for when they no longer belong to the game
+    // that is running.
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.size = 0;
+        self.newest = None;
+        self.frames = 0;
+    }
+}
+
+// Older XORed with newer, as the length of older followed by runs of an
+// unchanged (zero) byte count, a literal byte count and the literal bytes.
+// Newer is treated as zero padded where older is longer.
+fn encode(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let xor = |index: usize| older[index] ^ newer.get(index).copied().unwrap_or(0);
+    let mut delta = (older.len() as u32).to_le_bytes().to_vec();
+    let mut index = 0;
+    while index < older.len() {
+        let zeros_start = index;
+        while index < older.len() && xor(index) == 0 {
+            index += 1;
+        }
+        let literal_start = index;
+        let mut zero_streak = 0;
+        while index < older.len() && zero_streak < MIN_ZERO_RUN {
+            zero_streak = if xor(index) == 0 { zero_streak + 1 } else { 0 };
+            index += 1;
+        }
+        // a trailing streak of zeros starts the next run
+        index -= zero_streak;
+        delta.extend_from_slice(&((literal_start - zeros_start) as u32).to_le_bytes());
+        delta.extend_from_slice(&((index - literal_start) as u32).to_le_bytes());
+        delta.extend((literal_start..index).map(xor));
+    }
+    delta
+}
+
+fn decode(delta: &[u8], newer: &[u8]) -> io::Result<Vec<u8>> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt rewind snapshot");
+    let mut rest = delta;
+    let take_u32 = |rest: &mut &[u8]| -> io::Result<usize> {
+        let (bytes, tail) = rest.split_first_chunk::<4>().ok_or_else(corrupt)?;
+        *rest = tail;
+        Ok(u32::from_le_bytes(*bytes) as usize)
+    };
+    let length = take_u32(&mut rest)?;
+    let mut xor = Vec::with_capacity(length);
+    while !rest.is_empty() {
+        let zeros = take_u32(&mut rest)?;
+        let literal = take_u32(&mut rest)?;
+        if rest.len() < literal {
+            return Err(corrupt());
+        }
+        xor.resize(xor.len() + zeros, 0);
+        xor.extend_from_slice(&rest[..literal]);
+        rest = &rest[literal..];
+    }
+    if xor.len() != length {
+        return Err(corrupt());
+    }
+    Ok(xor
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ newer.get(index).copied().unwrap_or(0))
+        .collect())
+}
@@ -0,0 +1,276 @@
+// The Seiko S-3511 real time clock some cartridges carry, Pokemon Ruby,
+// Sapphire and Emerald among them, wired to the GPIO port at 0x80000C4 in
+// the ROM area. Three of the port's four pins make a serial bus: SCK, SIO
+// and CS. With CS high the game clocks a command byte into the chip, most
+// significant bit first, then clocks the command's data bytes in or out,
+// least significant bit first, a bit on each rising edge of SCK.
+//
+// The time read back is the emulated time since power-on added to a start
+// time the frontend picks, so two runs from the same start see the same
+// clock. Games setting the date and time are ignored.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ppu::CLOCK_RATE;
+
+// the port's registers, as offsets into the ROM
+const GPIO_DATA: u32 = 0xC4;
+const GPIO_DIRECTION: u32 = 0xC6;
+const GPIO_CONTROL: u32 = 0xC8;
+
+const SCK: u8 = 1;
+const SIO: u8 = 2;
+const CS: u8 = 4;
+
+// commands, from bits 1-3 of the command byte; the top four are always 0110
+const RESET: u8 = 0;
+const STATUS: u8 = 1;
+const DATE_TIME: u8 = 2;
+const TIME: u8 = 3;
+
+// status register bits; only the 24 hour and interrupt bits can be written
+const STATUS_24_HOUR: u8 = 0x40;
+const STATUS_WRITABLE: u8 = 0x6A;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rtc {
+    // unix time the clock showed at cycle 0
+    pub start: u64,
+    // the level on each pin, which of them the GBA drives, and whether the
+    // port can be read back rather than showing the ROM
+    pins: u8,
+    direction: u8,
+    readable: bool,
+    status: u8,
+    // the command under way, once its byte is in
+    command: Option<u8>,
+    // the byte being shifted in or out and how many of its bits have gone
+    byte: u8,
+    bits: u32,
+    // the command's data bytes, those for a read filled in as it starts
+    data: [u8; 7],
+    data_length: usize,
+    data_position: usize,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Rtc {
+            start: 0,
+            pins: 0,
+            direction: 0,
+            readable: false,
+            status: STATUS_24_HOUR,
+            command: None,
+            byte: 0,
+            bits: 0,
+            data: [0; 7],
+            data_length: 0,
+            data_position: 0,
+        }
+    }
+}
+
+impl Rtc {
+    pub fn readable(&self) -> bool {
+        self.readable
+    }
+
+    // A byte of the port's registers, at offset into the ROM.
+    pub fn read(&self, offset: u32) -> u8 {
+        match offset {
+            GPIO_DATA => self.pins,
+            GPIO_DIRECTION => self.direction,
+            GPIO_CONTROL => self.readable as u8,
+            _ => 0,
+        }
+    }
+
+    // now is the current cycle, for commands that read the time.
+    pub fn write(&mut self, offset: u32, value: u8, now: u64) {
+        match offset {
+            GPIO_DATA => {
+                // pins the chip drives keep their level
+                let pins = (self.pins & !self.direction) | (value & self.direction & 0xF);
+                self.set_pins(pins, now);
+            }
+            GPIO_DIRECTION => self.direction = value & 0xF,
+            GPIO_CONTROL => self.readable = value & 1 != 0,
+            _ => {}
+        }
+    }
+
+    fn set_pins(&mut self, pins: u8, now: u64) {
+        let selected = self.pins & CS != 0;
+        let rising = self.pins & SCK == 0 && pins & SCK != 0;
+        self.pins = pins;
+        // raising CS starts a transfer and dropping it ends one
+        if !selected || pins & CS == 0 {
+            self.command = None;
+            self.byte = 0;
+            self.bits = 0;
+            return;
+        }
+        if !rising {
+            return;
+        }
+
+        if self.direction & SIO == 0 {
+            // the chip puts the next bit of the data on SIO
+            let bit = match self.data[..self.data_length].get(self.data_position) {
+                Some(byte) => (byte >> self.bits) & 1,
+                None => 0,
+            };
+            self.pins = (self.pins & !SIO) | (bit << 1);
+        } else {
+            let bit = (pins & SIO) >> 1;
+            match self.command {
+                None => self.byte = (self.byte << 1) | bit,
+                Some(_) => self.byte |= bit << self.bits,
+            }
+        }
+        self.bits += 1;
+        if self.bits < 8 {
+            return;
+        }
+
+        let byte = std::mem::take(&mut self.byte);
+        self.bits = 0;
+        match self.command {
+            None => self.begin_command(byte, now),
+            Some(command) => {
+                if self.direction & SIO != 0 && self.data_position < self.data_length {
+                    self.data[self.data_position] = byte;
+                    if command == STATUS {
+                        self.status = byte & STATUS_WRITABLE;
+                    }
+                }
+                self.data_position += 1;
+            }
+        }
+    }
+
+    fn begin_command(&mut self, byte: u8, now: u64) {
+        if byte >> 4 != 0b0110 {
+            return;
+        }
+        let command = (byte >> 1) & 7;
+        self.command = Some(command);
+        self.data_position = 0;
+        self.data_length = match command {
+            RESET => {
+                self.status = 0;
+                0
+            }
+            STATUS => {
+                self.data[0] = self.status;
+                1
+            }
+            DATE_TIME => {
+                self.data = self.date_time(now);
+                7
+            }
+            TIME => {
+                let date_time = self.date_time(now);
+                self.data[..3].copy_from_slice(&date_time[4..]);
+                3
+            }
+            // the alarm and interrupt commands have nothing behind them
+            _ => 0,
+        };
+    }
+
+    // year, month, day, weekday, hour, minute and second in BCD, as the
+    // chip sends them
+    fn date_time(&self, now: u64) -> [u8; 7] {
+        let seconds = self.start + now / CLOCK_RATE;
+        let days = seconds / 86_400;
+        let (year, month, day) = civil_date(days);
+        // 1970-01-01 was a Thursday, and Sunday is day 0
+        let weekday = (days + 4) % 7;
+        let time = seconds % 86_400;
+        let hour = time / 3600;
+        let hour = if self.status & STATUS_24_HOUR != 0 {
+            bcd(hour)
+        } else {
+            bcd(hour % 12) | if hour >= 12 { 0x80 } else { 0 }
+        };
+        [bcd(year % 100), bcd(month), bcd(day), bcd(weekday), hour, bcd(time / 60 % 60), bcd(time % 60)]
+    }
+}
+
+// year, month and day of a count of days since 1970-01-01, counting years
+// from March so leap days fall at their end
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+    (year, month, day)
+}
+
+fn bcd(value: u64) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // clocks a byte out to the chip as the game's driver does
+    fn send(rtc: &mut Rtc, byte: u8, most_significant_first: bool) {
+        for i in 0..8 {
+            let bit = if most_significant_first { (byte >> (7 - i)) & 1 } else { (byte >> i) & 1 };
+            rtc.write(GPIO_DATA, CS | (bit << 1), 0);
+            rtc.write(GPIO_DATA, CS | (bit << 1) | SCK, 0);
+        }
+    }
+
+    fn receive(rtc: &mut Rtc) -> u8 {
+        (0..8).fold(0, |byte, i| {
+            rtc.write(GPIO_DATA, CS, 0);
+            rtc.write(GPIO_DATA, CS | SCK, 0);
+            byte | (((rtc.read(GPIO_DATA) & SIO) >> 1) << i)
+        })
+    }
+
+    fn command(rtc: &mut Rtc, byte: u8) {
+        rtc.write(GPIO_CONTROL, 1, 0);
+        rtc.write(GPIO_DIRECTION, CS | SIO | SCK, 0);
+        rtc.write(GPIO_DATA, SCK, 0);
+        rtc.write(GPIO_DATA, CS | SCK, 0);
+        send(rtc, byte, true);
+    }
+
+    #[test]
+    fn dates_count_from_1970() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        // a leap day and the day after
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(11_017), (2000, 3, 1));
+    }
+
+    #[test]
+    fn date_and_time_read_back() {
+        // Sunday 2004-11-21 13:05:09
+        let mut rtc = Rtc { start: 1_101_042_309, ..Rtc::default() };
+        command(&mut rtc, 0x65);
+        rtc.write(GPIO_DIRECTION, CS | SCK, 0);
+        let date_time: Vec<u8> = (0..7).map(|_| receive(&mut rtc)).collect();
+        assert_eq!(date_time, [0x04, 0x11, 0x21, 0x00, 0x13, 0x05, 0x09]);
+
+        // on a 12 hour clock the afternoon is flagged
+        rtc.write(GPIO_DATA, 0, 0);
+        command(&mut rtc, 0x62);
+        send(&mut rtc, 0, false);
+        rtc.write(GPIO_DATA, 0, 0);
+        command(&mut rtc, 0x67);
+        rtc.write(GPIO_DIRECTION, CS | SCK, 0);
+        assert_eq!(receive(&mut rtc), 0x81);
+    }
+}
@@ -0,0 +1,88 @@
+// Timestamped hardware events, ordered by the cycle they fire on. The Gba
+// runs the CPU up to the next event instead of ticking every component on
+// every cycle.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    // end of HDraw on the current scanline
+    HBlank,
+    // end of the current scanline
+    LineEnd,
+    // the APU mixes one sample at the PWM rate
+    ApuSample,
+    // 512 Hz step of the PSG frame sequencer
+    FrameSequencer,
+    // a free-running timer counts past 0xFFFF
+    TimerOverflow(usize),
+    // an SIO transfer finishes shifting
+    SerialTransfer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Event {
+    time: u64,
+    // breaks ties so events due on the same cycle fire in scheduling order
+    sequence: u64,
+    kind: EventKind,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<Event>>,
+    sequence: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler::default()
+    }
+
+    pub fn schedule(&mut self, time: u64, kind: EventKind) {
+        self.sequence += 1;
+        self.queue.push(Reverse(Event {
+            time,
+            sequence: self.sequence,
+            kind,
+        }));
+    }
+
+    // drops every pending event of this kind
+    pub fn cancel(&mut self, kind: EventKind) {
+        self.queue.retain(|Reverse(event)| event.kind != kind);
+    }
+
+    pub fn is_scheduled(&self, kind: EventKind) -> bool {
+        self.queue.iter().any(|Reverse(event)| event.kind == kind)
+    }
+
+    // cycle of the earliest pending event, or u64::MAX when idle
+    pub fn next_time(&self) -> u64 {
+        self.queue.peek().map_or(u64::MAX, |Reverse(event)| event.time)
+    }
+
+    // removes and returns the earliest event due at or before now
+    pub fn pop_due(&mut self, now: u64) -> Option<(u64, EventKind)> {
+        if self.next_time() > now {
+            return None;
+        }
+        self.queue.pop().map(|Reverse(event)| (event.time, event.kind))
+    }
+
+    // moves every pending event delay cycles later
+    pub fn postpone(&mut self, delay: u64) {
+        let events = std::mem::take(&mut self.queue).into_vec();
+        self.queue = events
+            .into_iter()
+            .map(|Reverse(event)| Reverse(Event { time: event.time + delay, ..event }))
+            .collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
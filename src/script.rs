@@ -0,0 +1,336 @@
+// Lua scripts, with --script: the script runs once when loaded, setting up
+// callbacks that then run as the game does. What it can use:
+//
+//     emu.on_frame(fn)                   call fn() after each frame
+//     emu.frame()                        frames run since the script loaded
+//     memory.read_u8(address)            also read_u16 and read_u32
+//     memory.write_u8(address, value)    also write_u16 and write_u32
+//     memory.on_read(address, [end,] fn) call fn(address, value, size, pc)
+//     memory.on_write(address, [end,] fn)  when the CPU or DMA reads or
+//                                        writes address, or up to end
+//     memory.on_exec(address, fn)        call fn(address) before the
+//                                        instruction there runs
+//     cpu.register(name)                 r0-r15, sp, lr, pc or cpsr
+//     joypad.get()                       the buttons held, as a table of
+//                                        a, b, select, start, right, left,
+//                                        up, down, r and l
+//     joypad.set(buttons)                hold buttons, a table like get's,
+//                                        for the next frame
+//     gui.text(x, y, text)               show text on the next frame shown
+//
+// The memory hooks are the debugger's breakpoints and watchpoints, stopping
+// the frame for the callback and carrying on after it; a debugger breakpoint
+// on an address the script hooks is replaced. An error in the script stops
+// it, taking its hooks away.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use afterimage::keypad::KeyState;
+use afterimage::watchpoints::{Watchpoint, WatchKind};
+use afterimage::Gba;
+use mlua::{Function, Lua, RegistryKey, Table, Value, Variadic};
+
+// button names for joypad.get and joypad.set
+const BUTTONS: [(&str, KeyState); 10] = [
+    ("a", KeyState::A),
+    ("b", KeyState::B),
+    ("select", KeyState::SELECT),
+    ("start", KeyState::START),
+    ("right", KeyState::RIGHT),
+    ("left", KeyState::LEFT),
+    ("up", KeyState::UP),
+    ("down", KeyState::DOWN),
+    ("r", KeyState::R),
+    ("l", KeyState::L),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HookKind {
+    Read,
+    Write,
+    Exec,
+}
+
+#[derive(Debug)]
+struct Hook {
+    kind: HookKind,
+    start: u32,
+    end: u32,
+    callback: RegistryKey,
+}
+
+impl Hook {
+    fn watchpoint(&self) -> Option<Watchpoint> {
+        let kind = match self.kind {
+            HookKind::Read => WatchKind::Read,
+            HookKind::Write => WatchKind::Write,
+            HookKind::Exec => return None,
+        };
+        Some(Watchpoint { start: self.start, end: self.end, kind })
+    }
+}
+
+// what the script's functions change, for the frontend to pick up
+#[derive(Debug, Default)]
+struct State {
+    frame_callbacks: Vec<RegistryKey>,
+    hooks: Vec<Hook>,
+    // hooks already set in the Gba
+    hooks_set: usize,
+    keys: KeyState,
+    texts: Vec<(usize, usize, String)>,
+    frame: u64,
+}
+
+pub struct Script {
+    lua: Lua,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    pub fn load(path: &Path, gba: &mut Gba) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let script = Script {
+            lua: Lua::new(),
+            state: Rc::new(RefCell::new(State::default())),
+        };
+        script.register().map_err(|err| err.to_string())?;
+        // errors name the file as Lua's own loader would
+        let name = format!("@{}", path.display());
+        script.call(gba, || script.lua.load(&source).set_name(name).exec())?;
+        Ok(script)
+    }
+
+    // The buttons joypad.set asked for, for the frame about to run.
+    pub fn take_keys(&mut self) -> KeyState {
+        std::mem::replace(&mut self.state.borrow_mut().keys, KeyState::NONE)
+    }
+
+    // What gui.text put up since the last call.
+    pub fn take_texts(&mut self) -> Vec<(usize, usize, String)> {
+        std::mem::take(&mut self.state.borrow_mut().texts)
+    }
+
+    // Runs the frame callbacks.
+    pub fn frame_done(&mut self, gba: &mut Gba) -> Result<(), String> {
+        self.state.borrow_mut().frame += 1;
+        let callbacks: Vec<Function> = {
+            let state = self.state.borrow();
+            state
+                .frame_callbacks
+                .iter()
+                .map(|key| self.lua.registry_value(key))
+                .collect::<mlua::Result<_>>()
+                .map_err(|err| err.to_string())?
+        };
+        self.call(gba, || callbacks.iter().try_for_each(|callback| callback.call::<_, ()>(())))
+    }
+
+    // Runs the hooks for the breakpoint or watchpoint the last run stopped
+    // at, returning false if it wasn't one of the script's.
+    pub fn handle_hit(&mut self, gba: &mut Gba) -> Result<bool, String> {
+        // the arguments: address, and for a memory access its value, size
+        // and the instruction's address
+        let (watchpoint, arguments) = if let Some(hit) = gba.memory.watchpoints.hit() {
+            (Some(hit.watchpoint), vec![hit.address, hit.value, hit.size, hit.pc])
+        } else if let Some(hit) = gba.breakpoints.hit() {
+            (None, vec![hit.address])
+        } else {
+            return Ok(false);
+        };
+        let matches = |hook: &Hook| match watchpoint {
+            Some(watchpoint) => hook.watchpoint() == Some(watchpoint),
+            None => hook.kind == HookKind::Exec && hook.start == arguments[0],
+        };
+        let callbacks: Vec<Function> = {
+            let state = self.state.borrow();
+            state
+                .hooks
+                .iter()
+                .filter(|hook| matches(hook))
+                .map(|hook| self.lua.registry_value(&hook.callback))
+                .collect::<mlua::Result<_>>()
+                .map_err(|err| err.to_string())?
+        };
+        if callbacks.is_empty() {
+            return Ok(false);
+        }
+        self.call(gba, || {
+            callbacks
+                .iter()
+                .try_for_each(|callback| callback.call::<_, ()>(Variadic::from_iter(arguments.iter().copied())))
+        })?;
+        Ok(true)
+    }
+
+    // Takes the script's hooks out of the Gba, once it has stopped.
+    pub fn remove_hooks(&self, gba: &mut Gba) {
+        let state = self.state.borrow();
+        for hook in &state.hooks[..state.hooks_set] {
+            match hook.watchpoint() {
+                Some(watchpoint) => {
+                    gba.memory.watchpoints.remove(watchpoint.start, watchpoint.end, Some(watchpoint.kind));
+                }
+                None => {
+                    gba.breakpoints.remove(hook.start);
+                }
+            }
+        }
+    }
+
+    // Runs f with the functions that need the Gba in place, then sets any
+    // hooks it added.
+    fn call(&self, gba: &mut Gba, f: impl FnOnce() -> mlua::Result<()>) -> Result<(), String> {
+        let gba = RefCell::new(gba);
+        self.lua
+            .scope(|scope| {
+                let globals = self.lua.globals();
+                let memory: Table = globals.get("memory")?;
+                memory.set("read_u8", scope.create_function(|_, address: u32| Ok(gba.borrow().memory.read_u8(address)))?)?;
+                memory.set("read_u16", scope.create_function(|_, address: u32| Ok(gba.borrow().memory.read_u16(address)))?)?;
+                memory.set("read_u32", scope.create_function(|_, address: u32| Ok(gba.borrow().memory.read_u32(address)))?)?;
+                memory.set(
+                    "write_u8",
+                    scope.create_function(|_, (address, value): (u32, u8)| {
+                        gba.borrow_mut().memory.write_u8(address, value);
+                        Ok(())
+                    })?,
+                )?;
+                memory.set(
+                    "write_u16",
+                    scope.create_function(|_, (address, value): (u32, u16)| {
+                        gba.borrow_mut().memory.write_u16(address, value);
+                        Ok(())
+                    })?,
+                )?;
+                memory.set(
+                    "write_u32",
+                    scope.create_function(|_, (address, value): (u32, u32)| {
+                        gba.borrow_mut().memory.write_u32(address, value);
+                        Ok(())
+                    })?,
+                )?;
+                let cpu: Table = globals.get("cpu")?;
+                cpu.set(
+                    "register",
+                    scope.create_function(|_, name: String| register(&gba.borrow(), &name))?,
+                )?;
+                let joypad: Table = globals.get("joypad")?;
+                joypad.set(
+                    "get",
+                    scope.create_function(|lua, ()| {
+                        let keys = gba.borrow().keys();
+                        let table = lua.create_table()?;
+                        for (name, button) in BUTTONS {
+                            table.set(name, keys.contains(button))?;
+                        }
+                        Ok(table)
+                    })?,
+                )?;
+                f()
+            })
+            .map_err(|err| err.to_string())?;
+        self.set_hooks(&mut gba.borrow_mut());
+        Ok(())
+    }
+
+    fn set_hooks(&self, gba: &mut Gba) {
+        let mut state = self.state.borrow_mut();
+        for hook in &state.hooks[state.hooks_set..] {
+            match hook.watchpoint() {
+                Some(watchpoint) => gba.memory.watchpoints.add(watchpoint.start, watchpoint.end, watchpoint.kind),
+                None => gba.breakpoints.add(hook.start, None, None),
+            }
+        }
+        state.hooks_set = state.hooks.len();
+    }
+
+    // The functions that don't need the Gba, set up once.
+    fn register(&self) -> mlua::Result<()> {
+        let lua = &self.lua;
+        let globals = lua.globals();
+
+        let emu = lua.create_table()?;
+        let state = self.state.clone();
+        emu.set(
+            "on_frame",
+            lua.create_function(move |lua, callback: Function| {
+                let key = lua.create_registry_value(callback)?;
+                state.borrow_mut().frame_callbacks.push(key);
+                Ok(())
+            })?,
+        )?;
+        let state = self.state.clone();
+        emu.set("frame", lua.create_function(move |_, ()| Ok(state.borrow().frame))?)?;
+        globals.set("emu", emu)?;
+
+        let memory = lua.create_table()?;
+        for (name, kind) in [("on_read", HookKind::Read), ("on_write", HookKind::Write), ("on_exec", HookKind::Exec)] {
+            let state = self.state.clone();
+            memory.set(
+                name,
+                lua.create_function(move |lua, (start, second, third): (u32, Value, Option<Function>)| {
+                    let (end, callback) = match (second, third) {
+                        (Value::Function(callback), None) => (start, callback),
+                        (Value::Integer(end), Some(callback)) if kind != HookKind::Exec => (end as u32, callback),
+                        _ => return Err(mlua::Error::runtime(format!("memory.{} takes an address, then a function", name))),
+                    };
+                    if end < start {
+                        return Err(mlua::Error::runtime("the range ends before it starts"));
+                    }
+                    let callback = lua.create_registry_value(callback)?;
+                    state.borrow_mut().hooks.push(Hook { kind, start, end, callback });
+                    Ok(())
+                })?,
+            )?;
+        }
+        globals.set("memory", memory)?;
+
+        globals.set("cpu", lua.create_table()?)?;
+
+        let joypad = lua.create_table()?;
+        let state = self.state.clone();
+        joypad.set(
+            "set",
+            lua.create_function(move |_, buttons: Table| {
+                let mut keys = KeyState::NONE;
+                for (name, button) in BUTTONS {
+                    keys.set(button, buttons.get::<_, Option<bool>>(name)?.unwrap_or(false));
+                }
+                state.borrow_mut().keys = keys;
+                Ok(())
+            })?,
+        )?;
+        globals.set("joypad", joypad)?;
+
+        let gui = lua.create_table()?;
+        let state = self.state.clone();
+        gui.set(
+            "text",
+            lua.create_function(move |_, (x, y, text): (usize, usize, String)| {
+                state.borrow_mut().texts.push((x, y, text));
+                Ok(())
+            })?,
+        )?;
+        globals.set("gui", gui)?;
+        Ok(())
+    }
+}
+
+fn register(gba: &Gba, name: &str) -> mlua::Result<u32> {
+    let cpu = &gba.cpu;
+    let value = match name {
+        "sp" | "r13" => cpu.sp,
+        "lr" | "r14" => cpu.lr,
+        "pc" | "r15" => cpu.pc,
+        "cpsr" => cpu.cpsr,
+        _ => match name.strip_prefix('r').and_then(|index| index.parse::<usize>().ok()) {
+            Some(index) if index < 13 => cpu.registers[index],
+            _ => return Err(mlua::Error::runtime(format!("there is no register {}", name))),
+        },
+    };
+    Ok(value)
+}
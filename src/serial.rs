@@ -0,0 +1,323 @@
+// Serial I/O port. Normal mode transfers and the SI/SD/SO lines go to an
+// attached SerialDevice. With nothing plugged in every input line reads as
+// pulled high, internally clocked transfers complete shifting in ones, and
+// transfers waiting on another unit's clock never finish. Multiplayer
+// transfers over a LinkCable are finished by the cable.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::interrupts::Interrupt;
+use crate::memory::Memory;
+use crate::ppu::CLOCK_RATE;
+
+pub const SIODATA32: usize = 0x120;
+pub const SIOMULTI0: usize = 0x120;
+pub const SIOCNT: usize = 0x128;
+pub const SIODATA8: usize = 0x12A;
+pub const SIOMLT_SEND: usize = 0x12A;
+pub const RCNT: usize = 0x134;
+
+// multiplayer transfer speeds selected by SIOCNT bits 0-1
+const MULTIPLAYER_BAUD_RATES: [u64; 4] = [9600, 38400, 57600, 115200];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SerialMode {
+    Normal8,
+    Normal32,
+    Multiplayer,
+    Uart,
+    GeneralPurpose,
+    JoyBus,
+}
+
+impl SerialMode {
+    pub fn read(memory: &Memory) -> Self {
+        let rcnt = memory.io_u16(RCNT);
+        if rcnt & 0x8000 != 0 {
+            return if rcnt & 0x4000 != 0 { SerialMode::JoyBus } else { SerialMode::GeneralPurpose };
+        }
+        match (SioCnt::read(memory).0 >> 12) & 0x3 {
+            0 => SerialMode::Normal8,
+            1 => SerialMode::Normal32,
+            2 => SerialMode::Multiplayer,
+            _ => SerialMode::Uart,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SioCnt(pub u16);
+
+impl SioCnt {
+    pub fn read(memory: &Memory) -> Self {
+        SioCnt(memory.io_u16(SIOCNT))
+    }
+
+    // normal mode: this unit drives the clock
+    pub fn internal_clock(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    // normal mode: 2 MHz rather than 256 KHz
+    pub fn fast_clock(self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    pub fn baud_rate(self) -> u64 {
+        MULTIPLAYER_BAUD_RATES[(self.0 & 0x3) as usize]
+    }
+
+    // multiplayer: SI low marks the parent
+    pub fn child(self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    pub fn start(self) -> bool {
+        self.0 & (1 << 7) != 0
+    }
+
+    pub fn irq(self) -> bool {
+        self.0 & (1 << 14) != 0
+    }
+}
+
+// A peripheral plugged into the serial port: link adapters, printers,
+// homebrew hardware. Lines a device doesn't drive are pulled high.
+pub trait SerialDevice: Send {
+    // Normal mode transfer clocked by the GBA. Takes the word shifted out
+    // on SO and returns the one shifted back on SI; bits is 8 or 32.
+    fn exchange(&mut self, sent: u32, bits: u32) -> u32;
+
+    // Normal mode transfer waiting for the device to drive the clock.
+    // Returns the word to shift in once the device clocks it.
+    fn clock_in(&mut self, _sent: u32, _bits: u32) -> Option<u32> {
+        None
+    }
+
+    fn si(&self) -> bool {
+        true
+    }
+
+    fn sd(&self) -> bool {
+        true
+    }
+
+    // SO as driven by the GBA
+    fn set_so(&mut self, _high: bool) {}
+}
+
+impl fmt::Debug for dyn SerialDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SerialDevice")
+    }
+}
+
+// Input line levels seen by the port, refreshed from the attached device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialLines {
+    pub si: bool,
+    pub sd: bool,
+}
+
+impl SerialLines {
+    pub const PULLED_UP: SerialLines = SerialLines { si: true, sd: true };
+}
+
+impl Default for SerialLines {
+    fn default() -> Self {
+        SerialLines::PULLED_UP
+    }
+}
+
+// Refreshes the read-only line states after a write to SIOCNT or RCNT.
+pub fn update_lines(memory: &mut Memory) {
+    let mode = SerialMode::read(memory);
+    let lines = memory.serial_lines;
+    let mut siocnt = memory.io_u16(SIOCNT);
+    match mode {
+        SerialMode::Normal8 | SerialMode::Normal32 => siocnt = (siocnt & !0x0004) | (lines.si as u16) << 2,
+        SerialMode::Multiplayer => match memory.link_id {
+            // with SI and SD high we look like a child with every unit ready
+            None => siocnt = (siocnt & !0x007C) | (lines.si as u16) << 2 | (lines.sd as u16) << 3,
+            // the cable grounds the parent's SI
+            Some(id) => siocnt = (siocnt & !0x0004) | 0x0008 | if id == 0 { 0 } else { 0x0004 },
+        },
+        _ => {}
+    }
+    memory.set_io_u16(SIOCNT, siocnt);
+
+    if mode == SerialMode::GeneralPurpose {
+        // pins set as inputs follow the lines: SC and SO float high
+        let rcnt = memory.io_u16(RCNT);
+        let levels = 0x9 | (lines.sd as u16) << 1 | (lines.si as u16) << 2;
+        let inputs = !(rcnt >> 4) & 0xF;
+        memory.set_io_u16(RCNT, (rcnt & !inputs) | (levels & inputs));
+    }
+}
+
+// level the GBA drives SO to outside of transfers
+fn so_level(memory: &Memory) -> bool {
+    let rcnt = memory.io_u16(RCNT);
+    match SerialMode::read(memory) {
+        SerialMode::GeneralPurpose if rcnt & 0x80 != 0 => rcnt & 0x8 != 0,
+        SerialMode::Normal8 | SerialMode::Normal32 => SioCnt::read(memory).0 & 0x8 != 0,
+        _ => true,
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Serial {
+    // mode of the transfer in flight
+    transfer: Option<SerialMode>,
+    // word shifted in when the normal mode transfer in flight completes
+    received: u32,
+    // word and width of a normal mode transfer waiting on the device's clock
+    awaiting_clock: Option<(u32, u32)>,
+    // a linked multiplayer transfer has finished shifting and is waiting
+    // for the cable to exchange the data words
+    exchange_pending: bool,
+    // not part of a save state, stays plugged in across loads
+    #[serde(skip)]
+    device: Option<Box<dyn SerialDevice>>,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial::default()
+    }
+
+    pub fn attach(&mut self, memory: &mut Memory, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+        self.sync_lines(memory);
+    }
+
+    pub fn detach(&mut self, memory: &mut Memory) -> Option<Box<dyn SerialDevice>> {
+        let device = self.device.take();
+        self.awaiting_clock = None;
+        self.sync_lines(memory);
+        device
+    }
+
+    // Exchanges line levels with the attached device.
+    pub fn sync_lines(&mut self, memory: &mut Memory) {
+        memory.serial_lines = match &mut self.device {
+            Some(device) => {
+                device.set_so(so_level(memory));
+                SerialLines {
+                    si: device.si(),
+                    sd: device.sd(),
+                }
+            }
+            None => SerialLines::PULLED_UP,
+        };
+        update_lines(memory);
+    }
+
+    // Starts the transfer the CPU just requested, returning the cycles
+    // until it completes or None if it is left waiting.
+    pub fn start(&mut self, memory: &mut Memory) -> Option<u64> {
+        let siocnt = SioCnt::read(memory);
+        let mode = SerialMode::read(memory);
+        let cycles = match mode {
+            SerialMode::Normal8 | SerialMode::Normal32 => {
+                let bits = if mode == SerialMode::Normal8 { 8 } else { 32 };
+                let sent = if bits == 8 { memory.io_u16(SIODATA8) as u32 & 0xFF } else { memory.io_u32(SIODATA32) };
+                if !siocnt.internal_clock() {
+                    self.transfer = Some(mode);
+                    self.awaiting_clock = Some((sent, bits));
+                    return self.poll_clock();
+                }
+                self.received = match &mut self.device {
+                    Some(device) => device.exchange(sent, bits),
+                    None => u32::MAX,
+                };
+                let cycles_per_bit = if siocnt.fast_clock() { 8 } else { 64 };
+                bits as u64 * cycles_per_bit
+            }
+            // only the parent can start a multiplayer transfer
+            SerialMode::Multiplayer if !siocnt.child() => {
+                // start bit, 16 data bits and stop bit for each of four units
+                4 * 18 * CLOCK_RATE / siocnt.baud_rate()
+            }
+            _ => return None,
+        };
+        self.transfer = Some(mode);
+        Some(cycles)
+    }
+
+    // Asks the device whether it has clocked a waiting transfer, returning
+    // the cycles until it completes.
+    pub fn poll_clock(&mut self) -> Option<u64> {
+        let (sent, bits) = self.awaiting_clock?;
+        self.received = self.device.as_mut()?.clock_in(sent, bits)?;
+        self.awaiting_clock = None;
+        // devices are assumed to clock at the fast 2 MHz rate
+        Some(bits as u64 * 8)
+    }
+
+    pub fn complete(&mut self, memory: &mut Memory) {
+        let Some(mode) = self.transfer.take() else {
+            return;
+        };
+        if mode == SerialMode::Multiplayer && memory.link_id.is_some() {
+            self.exchange_pending = true;
+            return;
+        }
+        match mode {
+            SerialMode::Normal8 => {
+                let data = (memory.io_u16(SIODATA8) & 0xFF00) | (self.received & 0xFF) as u16;
+                memory.set_io_u16(SIODATA8, data);
+            }
+            SerialMode::Normal32 => {
+                memory.set_io_u16(SIODATA32, self.received as u16);
+                memory.set_io_u16(SIODATA32 + 2, (self.received >> 16) as u16);
+            }
+            SerialMode::Multiplayer => {
+                // our own word comes back in slot 0, the empty slots read all ones
+                let sent = memory.io_u16(SIOMLT_SEND);
+                memory.set_io_u16(SIOMULTI0, sent);
+                for slot in 1..4 {
+                    memory.set_io_u16(SIOMULTI0 + slot * 2, 0xFFFF);
+                }
+            }
+            _ => {}
+        }
+
+        let siocnt = SioCnt::read(memory);
+        memory.set_io_u16(SIOCNT, siocnt.0 & !0x80);
+        if siocnt.irq() {
+            memory.request_interrupt(Interrupt::Serial);
+        }
+    }
+
+    pub fn transfer_in_flight(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    pub fn take_exchange(&mut self) -> bool {
+        std::mem::take(&mut self.exchange_pending)
+    }
+
+    // Ends a linked multiplayer transfer with the words sent by each unit on
+    // the cable; missing units read as all ones.
+    pub fn finish_multiplayer(&mut self, memory: &mut Memory, words: &[u16]) {
+        for slot in 0..4 {
+            let word = words.get(slot).copied().unwrap_or(0xFFFF);
+            memory.set_io_u16(SIOMULTI0 + slot * 2, word);
+        }
+        let id = memory.link_id.unwrap_or(0) as u16;
+        let siocnt = SioCnt::read(memory);
+        memory.set_io_u16(SIOCNT, (siocnt.0 & !0x00F0) | (id << 4));
+        if siocnt.irq() {
+            memory.request_interrupt(Interrupt::Serial);
+        }
+    }
+
+    // children see the busy bit while the parent is transferring
+    pub fn mark_busy(&self, memory: &mut Memory) {
+        let siocnt = memory.io_u16(SIOCNT);
+        memory.set_io_u16(SIOCNT, siocnt | 0x80);
+    }
+}
@@ -0,0 +1,122 @@
+// Save state files as the frontend keeps them: the machine state from
+// Gba::save_state wrapped with when it was saved, which game it belongs
+// to and a small picture of the screen, for showing a slot before it is
+// loaded. Files from another game or another state version are turned
+// away before the running game is touched. A state saved while a movie
+// was running also keeps the movie's input up to that point.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use afterimage::gba::STATE_VERSION;
+use afterimage::{Gba, SCREEN_HEIGHT, SCREEN_WIDTH};
+use serde::{Deserialize, Serialize};
+
+const SLOT_MAGIC: &[u8; 8] = b"AFTRSLOT";
+// version of the wrapper; the state inside carries its own
+const SLOT_VERSION: u32 = 2;
+
+pub const THUMBNAIL_WIDTH: usize = SCREEN_WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = SCREEN_HEIGHT / 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotInfo {
+    // seconds since the Unix epoch
+    pub saved_at: u64,
+    pub game_code: Option<String>,
+    pub state_version: u32,
+    // BGR555, THUMBNAIL_WIDTH x THUMBNAIL_HEIGHT
+    pub thumbnail: Vec<u16>,
+}
+
+impl SlotInfo {
+    // how long ago the slot was saved, as "5 min ago" and the like
+    pub fn age(&self) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+        let seconds = now.saturating_sub(self.saved_at);
+        match seconds {
+            0..60 => "just now".to_string(),
+            60..3600 => format!("{} min ago", seconds / 60),
+            3600..86400 => format!("{} h ago", seconds / 3600),
+            _ => format!("{} days ago", seconds / 86400),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SlotFile {
+    info: SlotInfo,
+    state: Vec<u8>,
+    // from Movie::log
+    movie: Option<Vec<u8>>,
+}
+
+pub fn save(path: &Path, gba: &Gba, movie: Option<Vec<u8>>) -> io::Result<()> {
+    let state = gba.save_state()?;
+    let info = SlotInfo {
+        saved_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs(),
+        game_code: gba.memory.game_code(),
+        state_version: STATE_VERSION,
+        thumbnail: thumbnail(gba.frame_buffer()),
+    };
+    let mut file = SLOT_MAGIC.to_vec();
+    file.extend_from_slice(&SLOT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut file, &SlotFile { info, state, movie }).map_err(io::Error::other)?;
+    fs::write(path, file)
+}
+
+pub fn info(path: &Path) -> io::Result<SlotInfo> {
+    Ok(read(path)?.info)
+}
+
+// Loads the slot into gba if it was saved from the same game by a build
+// with the same state version, returning the movie input it kept if any.
+pub fn load(path: &Path, gba: &mut Gba) -> io::Result<(SlotInfo, Option<Vec<u8>>)> {
+    let SlotFile { info, state, movie } = read(path)?;
+    if info.state_version != STATE_VERSION {
+        return Err(invalid("saved by an incompatible version"));
+    }
+    if let Some(code) = &info.game_code
+        && gba.memory.game_code().is_some_and(|running| running != *code)
+    {
+        return Err(invalid("saved from another game"));
+    }
+    gba.load_state(&state)?;
+    Ok((info, movie))
+}
+
+fn read(path: &Path) -> io::Result<SlotFile> {
+    let file = fs::read(path)?;
+    let file = file
+        .strip_prefix(SLOT_MAGIC.as_slice())
+        .ok_or_else(|| invalid("not a save state"))?;
+    let (version, payload) = file.split_first_chunk::<4>().ok_or_else(|| invalid("truncated"))?;
+    if u32::from_le_bytes(*version) != SLOT_VERSION {
+        return Err(invalid("saved by an incompatible version"));
+    }
+    bincode::deserialize(payload).map_err(|_| invalid("corrupt"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// the frame at half size, each pixel the average of a 2x2 block
+fn thumbnail(frame: &[u16]) -> Vec<u16> {
+    let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT);
+    for y in 0..THUMBNAIL_HEIGHT {
+        for x in 0..THUMBNAIL_WIDTH {
+            let top = 2 * y * SCREEN_WIDTH + 2 * x;
+            let block = [frame[top], frame[top + 1], frame[top + SCREEN_WIDTH], frame[top + SCREEN_WIDTH + 1]];
+            let mut pixel = 0;
+            for shift in [0, 5, 10] {
+                let sum: u16 = block.iter().map(|color| (color >> shift) & 0x1F).sum();
+                pixel |= (sum / 4) << shift;
+            }
+            thumbnail.push(pixel);
+        }
+    }
+    thumbnail
+}
@@ -0,0 +1,55 @@
+// Symbol tables, for putting names to code addresses. Two text formats are
+// read, the ones GBA toolchains and emulators give:
+//
+//     08000124 T main           nm output, of which only code is kept
+//     08000124 main             no$gba .sym files
+//
+// Lines that aren't symbols, like no$gba's .arm and .thumb markers, are
+// skipped. Each symbol covers the addresses up to the next one.
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    // by address
+    list: Vec<(u32, String)>,
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Self {
+        let mut list: Vec<(u32, String)> = text.lines().filter_map(parse_line).collect();
+        list.sort_by_key(|&(address, _)| address);
+        list.dedup_by_key(|&mut (address, _)| address);
+        Symbols { list }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    // The symbol covering address and where it starts.
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        let index = self.list.partition_point(|&(start, _)| start <= address).checked_sub(1)?;
+        let (start, name) = &self.list[index];
+        Some((name, *start))
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u32, String)> {
+    let mut fields = line.split_whitespace();
+    let address = fields.next()?;
+    let address = u32::from_str_radix(address.trim_start_matches("0x"), 16).ok()?;
+    let name = match (fields.next()?, fields.next()) {
+        // nm: text symbols, local, global or weak
+        (kind, Some(name)) if kind.len() == 1 => matches!(kind, "t" | "T" | "w" | "W").then_some(name)?,
+        (name, None) => name,
+        _ => return None,
+    };
+    if name.starts_with('.') {
+        return None;
+    }
+    // Thumb functions have bit 0 set in ELF symbols
+    Some((address & !1, name.to_string()))
+}
@@ -0,0 +1,155 @@
+// The four hardware timers. Counters are not ticked; each timer remembers
+// its value at a point in time and works out the live count from the cycle
+// counter when read. Overflows are delivered as scheduler events.
+
+use serde::{Deserialize, Serialize};
+
+pub const TM0CNT_L: usize = 0x100;
+
+// cycles per count for each TMxCNT_H prescaler setting, as shifts
+const PRESCALER_SHIFTS: [u32; 4] = [0, 6, 8, 10];
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Timer {
+    reload: u16,
+    control: u16,
+    // counter value as of cycle `since`
+    counter: u16,
+    since: u64,
+}
+
+impl Timer {
+    fn running(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    // count-up timers tick on the previous timer's overflow instead of the clock
+    fn cascade(&self) -> bool {
+        self.control & 0x04 != 0
+    }
+
+    fn irq_enabled(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+
+    fn shift(&self) -> u32 {
+        PRESCALER_SHIFTS[(self.control & 0x3) as usize]
+    }
+
+    fn counter_at(&self, now: u64) -> u16 {
+        if !self.running() || self.cascade() {
+            return self.counter;
+        }
+        let ticks = now.saturating_sub(self.since) >> self.shift();
+        let value = self.counter as u64 + ticks;
+        if value < 0x10000 {
+            return value as u16;
+        }
+        // only reachable if an overflow event has not been handled yet
+        let period = 0x10000 - self.reload as u64;
+        (self.reload as u64 + (value - 0x10000) % period) as u16
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Timers {
+    timers: [Timer; 4],
+    // set when a timer's overflow time may have moved, cleared by the Gba
+    // once it has rescheduled the overflow event
+    pub rescheduled: [bool; 4],
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Timers::default()
+    }
+
+    // offset is relative to TM0CNT_L
+    pub fn read_u8(&self, offset: usize, now: u64) -> u8 {
+        let timer = &self.timers[offset / 4];
+        match offset % 4 {
+            0 => timer.counter_at(now) as u8,
+            1 => (timer.counter_at(now) >> 8) as u8,
+            2 => timer.control as u8,
+            _ => (timer.control >> 8) as u8,
+        }
+    }
+
+    pub fn write_u8(&mut self, offset: usize, value: u8, now: u64) {
+        let index = offset / 4;
+        let timer = &mut self.timers[index];
+        match offset % 4 {
+            0 => timer.reload = (timer.reload & 0xFF00) | value as u16,
+            1 => timer.reload = (timer.reload & 0x00FF) | ((value as u16) << 8),
+            2 => self.write_control(index, value as u16, now),
+            _ => {}
+        }
+    }
+
+    fn write_control(&mut self, index: usize, value: u16, now: u64) {
+        let timer = &mut self.timers[index];
+        let was_running = timer.running();
+
+        // settle the count under the old settings before changing them
+        timer.counter = timer.counter_at(now);
+        timer.since = now;
+        timer.control = value & 0xC7;
+        // timer 0 has nothing to count up from
+        if index == 0 {
+            timer.control &= !0x04;
+        }
+
+        // starting a timer loads the reload value
+        if timer.running() && !was_running {
+            timer.counter = timer.reload;
+        }
+        self.rescheduled[index] = true;
+    }
+
+    pub fn counter(&self, index: usize, now: u64) -> u16 {
+        self.timers[index].counter_at(now)
+    }
+
+    // cycle the timer next overflows on, if it is running off the clock
+    pub fn next_overflow(&self, index: usize) -> Option<u64> {
+        let timer = &self.timers[index];
+        if !timer.running() || timer.cascade() {
+            return None;
+        }
+        let remaining = (0x10000 - timer.counter as u64) << timer.shift();
+        Some(timer.since + remaining)
+    }
+
+    // handles the overflow event: the counter restarts from the reload value
+    pub fn overflow(&mut self, index: usize, now: u64) {
+        let timer = &mut self.timers[index];
+        timer.counter = timer.reload;
+        timer.since = now;
+    }
+
+    pub fn irq_enabled(&self, index: usize) -> bool {
+        self.timers[index].irq_enabled()
+    }
+
+    // freezes the counters for delay cycles, as STOP mode does
+    pub fn postpone(&mut self, delay: u64) {
+        for timer in &mut self.timers {
+            timer.since += delay;
+        }
+    }
+
+    // the previous timer overflowed; returns whether this one overflowed in turn
+    pub fn count_up(&mut self, index: usize) -> bool {
+        let timer = &mut self.timers[index];
+        if !timer.running() || !timer.cascade() {
+            return false;
+        }
+        if timer.counter == 0xFFFF {
+            timer.counter = timer.reload;
+            true
+        } else {
+            timer.counter += 1;
+            false
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Data watchpoints over address ranges, seen by the loads and stores the
+// CPU and DMA make on the bus; the debugger's and cheats' own look at
+// memory doesn't count. A hit lets the access finish and stops the Gba
+// after the instruction making it, or once the DMA transfer it was part of
+// is done, leaving the details in hit() like a breakpoint does.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // reads and writes
+    Access,
+    // writes that change a watched byte
+    Change,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// start to end inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u32,
+    pub end: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn overlaps(&self, address: u32, size: u32) -> bool {
+        address <= self.end && address.saturating_add(size - 1) >= self.start
+    }
+
+    fn contains(&self, address: u32) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub watchpoint: Watchpoint,
+    pub access: AccessKind,
+    // the start of the access, which may be outside the range
+    pub address: u32,
+    // in bytes
+    pub size: u32,
+    // read, or written
+    pub value: u32,
+    // what a write replaced
+    pub old_value: Option<u32>,
+    // the instruction that made the access, or the one the CPU was last
+    // running for a DMA transfer
+    pub pc: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    hit: Option<WatchpointHit>,
+    // kept up to date by the Gba while there are any
+    pub(crate) pc: u32,
+}
+
+impl Watchpoints {
+    // A range can be watched for more than one kind of access.
+    pub fn add(&mut self, start: u32, end: u32, kind: WatchKind) {
+        let watchpoint = Watchpoint { start, end, kind };
+        if !self.list.contains(&watchpoint) {
+            self.list.push(watchpoint);
+        }
+    }
+
+    // Removes the watchpoints on exactly that range, of the kind given or
+    // all of them, returning false if there were none.
+    pub fn remove(&mut self, start: u32, end: u32, kind: Option<WatchKind>) -> bool {
+        let before = self.list.len();
+        self.list.retain(|watchpoint| {
+            (watchpoint.start, watchpoint.end) != (start, end) || kind.is_some_and(|kind| kind != watchpoint.kind)
+        });
+        self.list.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // by start address
+    pub fn list(&self) -> Vec<Watchpoint> {
+        let mut list = self.list.clone();
+        list.sort_by_key(|watchpoint| (watchpoint.start, watchpoint.end));
+        list
+    }
+
+    // The watchpoint the last run stopped at, if it stopped at one.
+    pub fn hit(&self) -> Option<WatchpointHit> {
+        self.hit
+    }
+
+    // Called as each run starts, which carries on from any hit.
+    pub(crate) fn resume(&mut self) {
+        self.hit = None;
+    }
+
+    pub(crate) fn check_read(&mut self, address: u32, size: u32, value: u32) {
+        if self.hit.is_some() {
+            return;
+        }
+        let found = self.list.iter().find(|watchpoint| {
+            matches!(watchpoint.kind, WatchKind::Read | WatchKind::Access) && watchpoint.overlaps(address, size)
+        });
+        if let Some(&watchpoint) = found {
+            self.hit = Some(WatchpointHit {
+                watchpoint,
+                access: AccessKind::Read,
+                address,
+                size,
+                value,
+                old_value: None,
+                pc: self.pc,
+            });
+        }
+    }
+
+    pub(crate) fn check_write(&mut self, address: u32, size: u32, value: u32, old_value: u32) {
+        if self.hit.is_some() {
+            return;
+        }
+        let changed = |watchpoint: &Watchpoint| {
+            (0..size).any(|byte| {
+                let shift = byte * 8;
+                watchpoint.contains(address.wrapping_add(byte)) && (value ^ old_value) >> shift & 0xFF != 0
+            })
+        };
+        let found = self.list.iter().find(|watchpoint| {
+            watchpoint.overlaps(address, size)
+                && match watchpoint.kind {
+                    WatchKind::Read => false,
+                    WatchKind::Write | WatchKind::Access => true,
+                    WatchKind::Change => changed(watchpoint),
+                }
+        });
+        if let Some(&watchpoint) = found {
+            self.hit = Some(WatchpointHit {
+                watchpoint,
+                access: AccessKind::Write,
+                address,
+                size,
+                value,
+                old_value: Some(old_value),
+                pc: self.pc,
+            });
+        }
+    }
+}
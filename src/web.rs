@@ -0,0 +1,115 @@
+// The WebAssembly build's interface, for the browser frontend in web/: a
+// Gba wrapped up for JavaScript, with the ROM, save and states passed in
+// and out as bytes since the page has no filesystem to read from. The page
+// draws the frames to a canvas, queues the audio with WebAudio and reads
+// the keyboard.
+
+use wasm_bindgen::prelude::*;
+
+use crate::archive;
+use crate::gba::Gba;
+use crate::keypad::KeyState;
+use crate::memory::SaveType;
+use crate::ppu::debug::Rgb;
+
+// samples pulled from the APU at a time, enough for a few frames
+const AUDIO_CHUNK: usize = 8192;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    gba: Gba,
+    samples: Vec<i16>,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    // name is the ROM file's, which says whether it is in an archive;
+    // sample_rate is the AudioContext's
+    #[wasm_bindgen(constructor)]
+    pub fn new(name: &str, data: Vec<u8>, sample_rate: u32) -> Result<Emulator, JsError> {
+        let mut gba = Gba::new();
+        gba.load_rom_data(archive::unpack_rom(name, data)?);
+        gba.memory.save_type = gba.memory.detect_save_type();
+        gba.memory.has_rtc = gba.memory.detect_rtc();
+        gba.apu.set_output_rate(sample_rate);
+        Ok(Emulator {
+            gba,
+            samples: vec![0; AUDIO_CHUNK],
+        })
+    }
+
+    // four character code from the ROM header, for keeping saves apart
+    pub fn game_code(&self) -> Option<String> {
+        self.gba.memory.game_code()
+    }
+
+    // Unix time, in seconds, for the cartridge clock to start from; the page
+    // passes its own.
+    pub fn set_clock(&mut self, seconds: f64) {
+        self.gba.memory.rtc.start = seconds as u64;
+    }
+
+    pub fn load_bios(&mut self, image: &[u8]) -> Result<(), JsError> {
+        Ok(self.gba.memory.load_bios_data(image)?)
+    }
+
+    pub fn load_save(&mut self, data: &[u8]) {
+        self.gba.memory.load_sram_data(data);
+    }
+
+    // The battery save, if the game has written to it since the last call.
+    pub fn take_save(&mut self) -> Option<Vec<u8>> {
+        let memory = &mut self.gba.memory;
+        if memory.save_type == SaveType::None || !memory.sram_dirty {
+            return None;
+        }
+        memory.sram_dirty = false;
+        Some(memory.save_data().to_vec())
+    }
+
+    // the buttons held, as bits in KEYINPUT's order with 1 for pressed
+    pub fn set_keys(&mut self, keys: u16) {
+        self.gba.set_keys(KeyState(keys & KeyState::ALL.0));
+    }
+
+    pub fn run_frame(&mut self) {
+        self.gba.run_frame();
+    }
+
+    pub fn reset(&mut self) {
+        self.gba.reset();
+    }
+
+    // The last frame as RGBA bytes, for an ImageData.
+    pub fn frame(&self) -> Vec<u8> {
+        self.gba
+            .frame_buffer()
+            .iter()
+            .flat_map(|&color| {
+                let Rgb { r, g, b } = Rgb::from_bgr555(color);
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    // The audio made since the last call, interleaved stereo as WebAudio
+    // takes it.
+    pub fn audio(&mut self) -> Vec<f32> {
+        let mut audio = Vec::new();
+        loop {
+            let count = self.gba.read_audio_samples(&mut self.samples);
+            audio.extend(self.samples[..count].iter().map(|&sample| sample as f32 / 32768.0));
+            if count < self.samples.len() {
+                return audio;
+            }
+        }
+    }
+
+    pub fn save_state(&self) -> Result<Vec<u8>, JsError> {
+        Ok(self.gba.save_state()?)
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsError> {
+        Ok(self.gba.load_state(state)?)
+    }
+}
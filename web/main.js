@@ -0,0 +1,218 @@
+// The browser frontend: runs the Emulator from src/web.rs at the GBA's
+// frame rate off requestAnimationFrame, drawing each frame to the canvas and
+// queueing its audio as WebAudio buffers. Battery saves are kept in
+// localStorage by game, and the one save state slot lasts until the page is
+// closed.
+
+import init, { Emulator } from "./pkg/afterimage.js";
+
+const WIDTH = 240;
+const HEIGHT = 160;
+const FRAME_SECONDS = 280896 / 16777216;
+// frames run at most per animation frame, so a stall doesn't run away
+const MAX_CATCH_UP = 4;
+// how far ahead audio is queued before more is dropped
+const MAX_AUDIO_AHEAD = 0.2;
+// frames between checks for a changed battery save
+const SAVE_CHECK_FRAMES = 60;
+
+// KEYINPUT bits by KeyboardEvent.code, matching the desktop defaults
+const BUTTONS = {
+  KeyX: 1 << 0, // A
+  KeyZ: 1 << 1, // B
+  Backspace: 1 << 2, // Select
+  Enter: 1 << 3, // Start
+  ArrowRight: 1 << 4,
+  ArrowLeft: 1 << 5,
+  ArrowUp: 1 << 6,
+  ArrowDown: 1 << 7,
+  KeyS: 1 << 8, // R
+  KeyA: 1 << 9, // L
+};
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const status = document.getElementById("status");
+
+let emulator = null;
+let saveKey = null;
+let audio = null;
+let audioTime = 0;
+let keys = 0;
+let paused = false;
+let state = null;
+let lastTime = null;
+let owed = 0;
+let frames = 0;
+
+function showStatus(text) {
+  status.textContent = text;
+}
+
+function toBase64(bytes) {
+  let text = "";
+  for (let i = 0; i < bytes.length; i += 0x8000) {
+    text += String.fromCharCode(...bytes.subarray(i, i + 0x8000));
+  }
+  return btoa(text);
+}
+
+function fromBase64(text) {
+  return Uint8Array.from(atob(text), (c) => c.charCodeAt(0));
+}
+
+function writeSave() {
+  const save = emulator && emulator.take_save();
+  if (save) {
+    try {
+      localStorage.setItem(saveKey, toBase64(save));
+    } catch (err) {
+      showStatus(`Could not keep the save: ${err}`);
+    }
+  }
+}
+
+async function start(file) {
+  writeSave();
+  // the old game's memory lives on the WebAssembly heap until freed
+  emulator?.free();
+  emulator = null;
+  // made here, on the file pick, as browsers only allow audio to start
+  // from a user's action
+  audio ??= new AudioContext();
+  const data = new Uint8Array(await file.arrayBuffer());
+  try {
+    emulator = new Emulator(file.name, data, audio.sampleRate);
+    emulator.set_clock(Date.now() / 1000);
+  } catch (err) {
+    emulator = null;
+    showStatus(`Could not load ${file.name}: ${err}`);
+    return;
+  }
+  saveKey = `afterimage-save:${emulator.game_code() ?? file.name}`;
+  const save = localStorage.getItem(saveKey);
+  if (save) {
+    emulator.load_save(fromBase64(save));
+  }
+  state = null;
+  paused = false;
+  lastTime = null;
+  audioTime = 0;
+  showStatus(`Running ${file.name}`);
+}
+
+function queueAudio(samples) {
+  const length = samples.length / 2;
+  const now = audio.currentTime;
+  if (length === 0 || audioTime - now > MAX_AUDIO_AHEAD) {
+    return;
+  }
+  const buffer = audio.createBuffer(2, length, audio.sampleRate);
+  const left = buffer.getChannelData(0);
+  const right = buffer.getChannelData(1);
+  for (let i = 0; i < length; i++) {
+    left[i] = samples[2 * i];
+    right[i] = samples[2 * i + 1];
+  }
+  const source = audio.createBufferSource();
+  source.buffer = buffer;
+  source.connect(audio.destination);
+  // a gap since the last buffer ran out starts the queue again a little ahead
+  audioTime = Math.max(audioTime, now + 0.05);
+  source.start(audioTime);
+  audioTime += buffer.duration;
+}
+
+function tick(time) {
+  requestAnimationFrame(tick);
+  if (!emulator || paused) {
+    lastTime = null;
+    return;
+  }
+  if (lastTime !== null) {
+    owed = Math.min(owed + (time - lastTime) / 1000, MAX_CATCH_UP * FRAME_SECONDS);
+  } else {
+    owed = FRAME_SECONDS;
+  }
+  lastTime = time;
+  if (owed < FRAME_SECONDS) {
+    return;
+  }
+  emulator.set_keys(keys);
+  while (owed >= FRAME_SECONDS) {
+    emulator.run_frame();
+    owed -= FRAME_SECONDS;
+    if (++frames % SAVE_CHECK_FRAMES === 0) {
+      writeSave();
+    }
+  }
+  const pixels = new Uint8ClampedArray(emulator.frame().buffer);
+  context.putImageData(new ImageData(pixels, WIDTH, HEIGHT), 0, 0);
+  queueAudio(emulator.audio());
+}
+
+function hotkey(code) {
+  switch (code) {
+    case "KeyP":
+      paused = !paused;
+      showStatus(paused ? "Paused" : "Resumed");
+      return true;
+    case "F5":
+      state = emulator.save_state();
+      showStatus("Saved state");
+      return true;
+    case "F9":
+      if (state) {
+        try {
+          emulator.load_state(state);
+          showStatus("Loaded state");
+        } catch (err) {
+          showStatus(`Could not load the state: ${err}`);
+        }
+      } else {
+        showStatus("No state saved yet");
+      }
+      return true;
+    case "F10":
+      emulator.reset();
+      showStatus("Reset");
+      return true;
+  }
+  return false;
+}
+
+document.addEventListener("keydown", (event) => {
+  if (!emulator) {
+    return;
+  }
+  if (event.code in BUTTONS) {
+    keys |= BUTTONS[event.code];
+  } else if (event.repeat || !hotkey(event.code)) {
+    return;
+  }
+  event.preventDefault();
+});
+
+document.addEventListener("keyup", (event) => {
+  if (event.code in BUTTONS) {
+    keys &= ~BUTTONS[event.code];
+    event.preventDefault();
+  }
+});
+
+// held buttons would otherwise stay down after switching away
+window.addEventListener("blur", () => {
+  keys = 0;
+});
+
+document.addEventListener("visibilitychange", writeSave);
+
+document.getElementById("rom").addEventListener("change", (event) => {
+  const file = event.target.files[0];
+  if (file) {
+    start(file);
+  }
+});
+
+await init();
+requestAnimationFrame(tick);